@@ -6,11 +6,12 @@ use anyhow::Result;
 use async_trait::async_trait;
 use base64::{engine::general_purpose, Engine as _};
 use buckyos_api::{
-    get_buckyos_api_runtime, init_buckyos_api_runtime, set_buckyos_api_runtime, AccessGroupLevel,
-    AiMessage, AiPayload, AppDoc, AppServiceSpec, AppType, BoxKind, BuckyOSRuntimeType, Capability,
-    CompleteRequest, Contact, ContactQuery, Event, KEventClient, LoginByPasswordResponse,
-    ModelSpec, MsgCenterClient, MsgRecordWithObject, MsgState, RepoListFilter, RepoRecord,
-    Requirements, SendContext, ServiceExposeConfig, ServiceInstallConfig, ServiceState,
+    get_buckyos_api_runtime, init_buckyos_api_runtime, is_privileged_user, set_buckyos_api_runtime,
+    AccessGroupLevel, AiMessage, AiPayload, AppDoc, AppServiceSpec, AppType, BoxKind,
+    BuckyOSRuntimeType, Capability, CompleteRequest, Contact, ContactQuery, Event, KEventClient,
+    LoginByPasswordResponse, ModelSpec, MsgCenterClient, MsgRecordWithObject, MsgState,
+    RepoListFilter, RepoRecord, Requirements, SchedulerClient, SendContext, ServiceExposeConfig,
+    ServiceInstallConfig, ServiceState, SystemConfigChangeAction, SystemConfigChangeEvent,
     SystemConfigClient, UserType, CONTROL_PANEL_SERVICE_NAME, CONTROL_PANEL_SERVICE_PORT,
 };
 use buckyos_kit::*;
@@ -37,7 +38,7 @@ use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::sync::{Arc, OnceLock};
 use std::{
-    net::IpAddr,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     time::{Duration, Instant},
 };
 use sysinfo::{DiskRefreshKind, Disks, Networks, System};
@@ -83,10 +84,16 @@ const LOG_ROOT_DIR: &str = "/opt/buckyos/logs";
 const LOG_DOWNLOAD_TTL_SECS: u64 = 600;
 const DEFAULT_LOG_LIMIT: usize = 200;
 const MAX_LOG_LIMIT: usize = 1000;
+// slog_server indexes the logs of every service, searches go there instead of scanning files.
+// Its address comes from the same env key and default as slog_server's own bind config.
+const SLOG_SERVER_BIND_ENV_KEY: &str = "SLOG_SERVER_BIND";
+const DEFAULT_SLOG_SERVER_BIND: &str = "127.0.0.1:22001";
+const SLOG_QUERY_TIMEOUT_SECS: u64 = 10;
 const METRICS_DISK_REFRESH_INTERVAL_SECS: u64 = 5;
 const NETWORK_TIMELINE_LIMIT: usize = 300;
 const DOCKER_OVERVIEW_CACHE_TTL_SECS: u64 = 15;
 const SYS_CONFIG_TREE_MAX_DEPTH: u64 = 24;
+const SYS_CONFIG_HISTORY_DEFAULT_LIMIT: u64 = 20;
 const GATEWAY_ETC_DIR: &str = "/opt/buckyos/etc";
 const GATEWAY_CONFIG_FILES: [&str; 5] = [
     "cyfs_gateway.json",
@@ -257,6 +264,15 @@ struct LogQueryCursor {
     direction: String,
 }
 
+// Resumes a slog_server search below `before` (unix millis). The records at exactly `before`
+// which earlier pages returned are counted per service, so each page fetches at most one page per service.
+#[derive(Clone, Serialize, Deserialize)]
+struct SlogQueryCursor {
+    before: u64,
+    #[serde(default)]
+    seen_at_before: HashMap<String, usize>,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 struct LogTailCursor {
     file: String,
@@ -1750,56 +1766,6 @@ impl ControlPanelServer {
         value.format("%m-%d %H:%M:%S%.3f").to_string()
     }
 
-    fn rg_available() -> bool {
-        external_command("rg")
-            .arg("--version")
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .status()
-            .map(|status| status.success())
-            .unwrap_or(false)
-    }
-
-    fn rg_search_lines(path: &Path, keyword: &str) -> Result<Vec<(u64, String)>, RPCErrors> {
-        let output = external_command("rg")
-            .arg("--line-number")
-            .arg("--fixed-strings")
-            .arg("--no-heading")
-            .arg("--no-filename")
-            .arg("--color")
-            .arg("never")
-            .arg("-i")
-            .arg(keyword)
-            .arg(path)
-            .output()
-            .map_err(|err| RPCErrors::ReasonError(format!("Failed to run rg: {}", err)))?;
-
-        if !output.status.success() {
-            if output.status.code() == Some(1) {
-                return Ok(Vec::new());
-            }
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(RPCErrors::ReasonError(format!(
-                "rg failed for {}: {}",
-                path.display(),
-                stderr
-            )));
-        }
-
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let mut results = Vec::new();
-        for line in stdout.lines() {
-            let mut parts = line.splitn(2, ':');
-            let line_no = parts.next().unwrap_or("");
-            let content = parts.next().unwrap_or("").to_string();
-            if let Ok(number) = line_no.parse::<u64>() {
-                let line_index = number.saturating_sub(1);
-                results.push((line_index, content));
-            }
-        }
-        Ok(results)
-    }
-
     fn list_log_service_ids(&self) -> Result<Vec<String>, RPCErrors> {
         let mut services = Vec::new();
         let entries = std::fs::read_dir(LOG_ROOT_DIR)
@@ -2331,6 +2297,165 @@ impl ControlPanelServer {
         ))
     }
 
+    fn slog_record_to_entry(record: &Value) -> Value {
+        let log = record.get("log").cloned().unwrap_or(Value::Null);
+        let timestamp = log
+            .get("time")
+            .and_then(|value| value.as_u64())
+            .and_then(|value| Utc.timestamp_millis_opt(value as i64).single())
+            .map(|value| Self::format_log_filter_key(&value))
+            .unwrap_or_default();
+        let level = log
+            .get("level")
+            .and_then(|value| value.as_str())
+            .map(Self::normalize_log_level)
+            .unwrap_or_else(|| "unknown".to_string());
+        let content = log
+            .get("content")
+            .and_then(|value| value.as_str())
+            .unwrap_or_default()
+            .to_string();
+        json!({
+            "timestamp": timestamp,
+            "level": level,
+            "message": content.clone(),
+            "raw": content,
+            "service": record.get("service").cloned().unwrap_or(Value::Null),
+            "node": record.get("node").cloned().unwrap_or(Value::Null),
+            "target": log.get("target").cloned().unwrap_or(Value::Null),
+            "file": log.get("file").and_then(|value| value.as_str()).unwrap_or_default(),
+            "line": log.get("line").cloned().unwrap_or(Value::Null),
+            "fields": log.get("fields").cloned().unwrap_or_else(|| json!({})),
+        })
+    }
+
+    fn slog_server_query_url() -> String {
+        let bind = std::env::var(SLOG_SERVER_BIND_ENV_KEY)
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .unwrap_or_else(|| DEFAULT_SLOG_SERVER_BIND.to_string());
+        // A wildcard bind is reached over loopback
+        let host = match bind.parse::<SocketAddr>() {
+            Ok(addr) if addr.ip().is_unspecified() => {
+                let loopback: IpAddr = if addr.is_ipv4() {
+                    Ipv4Addr::LOCALHOST.into()
+                } else {
+                    Ipv6Addr::LOCALHOST.into()
+                };
+                SocketAddr::new(loopback, addr.port()).to_string()
+            }
+            _ => bind,
+        };
+        format!("http://{}/query", host)
+    }
+
+    fn slog_record_time(record: &Value) -> u64 {
+        record
+            .pointer("/log/time")
+            .and_then(|value| value.as_u64())
+            .unwrap_or(0)
+    }
+
+    // Run a search on slog_server, `filter` holds the query fields except service and paging.
+    // Returns the records (newest first) and the cursor of the next page if more are available.
+    async fn query_slog_server(
+        services: &[String],
+        filter: &Map<String, Value>,
+        cursor: Option<&SlogQueryCursor>,
+        limit: usize,
+    ) -> Result<(Vec<Value>, Option<SlogQueryCursor>), RPCErrors> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(SLOG_QUERY_TIMEOUT_SECS))
+            .build()
+            .map_err(|err| {
+                RPCErrors::ReasonError(format!("Failed to build http client: {}", err))
+            })?;
+        let query_url = Self::slog_server_query_url();
+
+        // Every service is asked for one page below the cursor, the pages are merged by time
+        let mut records: Vec<Value> = Vec::new();
+        let mut has_more = false;
+        for service in services.iter() {
+            let mut body = filter.clone();
+            body.insert("service".to_string(), json!(service));
+            let mut offset = 0;
+            if let Some(cursor) = cursor {
+                let end_time = filter
+                    .get("end_time")
+                    .and_then(|value| value.as_u64())
+                    .map_or(cursor.before, |value| value.min(cursor.before));
+                body.insert("end_time".to_string(), json!(end_time));
+                if end_time == cursor.before {
+                    offset = cursor.seen_at_before.get(service).copied().unwrap_or(0);
+                }
+            }
+            body.insert("offset".to_string(), json!(offset));
+            body.insert("limit".to_string(), json!(limit));
+            let resp = client
+                .post(&query_url)
+                .json(&body)
+                .send()
+                .await
+                .map_err(|err| {
+                    RPCErrors::ReasonError(format!("slog_server query failed: {}", err))
+                })?;
+            let resp: Value = resp.json().await.map_err(|err| {
+                RPCErrors::ReasonError(format!("Invalid slog_server query response: {}", err))
+            })?;
+            if resp.get("ret").and_then(|value| value.as_i64()) != Some(0) {
+                let message = resp
+                    .get("message")
+                    .and_then(|value| value.as_str())
+                    .unwrap_or("unknown error");
+                return Err(RPCErrors::ReasonError(format!(
+                    "slog_server query failed: {}",
+                    message
+                )));
+            }
+            let data = resp.get("data").cloned().unwrap_or(Value::Null);
+            if let Some(items) = data.get("records").and_then(|value| value.as_array()) {
+                records.extend(items.iter().cloned());
+            }
+            has_more |= data
+                .pointer("/page/has_more")
+                .and_then(|value| value.as_bool())
+                .unwrap_or(false);
+        }
+
+        records.sort_by_key(|record| std::cmp::Reverse(Self::slog_record_time(record)));
+        if records.len() > limit {
+            has_more = true;
+            records.truncate(limit);
+        }
+
+        let next_cursor = match records.last() {
+            Some(last) if has_more => {
+                let before = Self::slog_record_time(last);
+                let mut seen_at_before = match cursor {
+                    Some(cursor) if cursor.before == before => cursor.seen_at_before.clone(),
+                    _ => HashMap::new(),
+                };
+                for record in records
+                    .iter()
+                    .filter(|record| Self::slog_record_time(record) == before)
+                {
+                    let service = record
+                        .get("service")
+                        .and_then(|value| value.as_str())
+                        .unwrap_or_default();
+                    *seen_at_before.entry(service.to_string()).or_default() += 1;
+                }
+                Some(SlogQueryCursor {
+                    before,
+                    seen_at_before,
+                })
+            }
+            _ => None,
+        };
+        Ok((records, next_cursor))
+    }
+
     async fn handle_system_logs_query(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
         let mut services: Vec<String> = req
             .params
@@ -2384,6 +2509,82 @@ impl ControlPanelServer {
             .and_then(|value| value.as_u64())
             .unwrap_or(DEFAULT_LOG_LIMIT as u64)
             .clamp(1, MAX_LOG_LIMIT as u64) as usize;
+        let regex_filter = Self::param_str(&req, "regex");
+        let target_filter = Self::param_str(&req, "target");
+        let source_file_filter = Self::param_str(&req, "sourceFile");
+        let source_line_filter = req
+            .params
+            .get("sourceLine")
+            .and_then(|value| value.as_u64());
+        // only slog_server can match these, the log files are scanned for keyword/level/time
+        let needs_index = regex_filter.is_some()
+            || target_filter.is_some()
+            || source_file_filter.is_some()
+            || source_line_filter.is_some();
+
+        // slog_server only pages backward from the newest match and knows nothing about the
+        // log files, a file or forward paging is served by scanning the files
+        let index_pageable = file_filter.is_none() && direction == "backward";
+        if needs_index && !index_pageable {
+            return Err(RPCErrors::ParseRequestError(
+                "regex, target, sourceFile and sourceLine filters only support backward paging without a file filter".to_string(),
+            ));
+        }
+
+        // Searches are answered from the slog_server index. Pages start from the newest match,
+        // entries of a page are in time order.
+        if (keyword_raw.is_some() && index_pageable) || needs_index {
+            let mut filter = Map::new();
+            let optional = [
+                ("keyword", keyword_raw.clone()),
+                ("regex", regex_filter),
+                ("target", target_filter),
+                ("file", source_file_filter),
+                ("level", level_filter.clone()),
+            ];
+            for (key, value) in optional {
+                if let Some(value) = value {
+                    filter.insert(key.to_string(), json!(value));
+                }
+            }
+            if let Some(line) = source_line_filter {
+                filter.insert("line".to_string(), json!(line));
+            }
+            if let Some(since) = since_filter.as_ref() {
+                filter.insert("start_time".to_string(), json!(since.timestamp_millis()));
+            }
+            if let Some(until) = until_filter.as_ref() {
+                filter.insert("end_time".to_string(), json!(until.timestamp_millis()));
+            }
+            let cursor = Self::param_str(&req, "cursor")
+                .and_then(|value| Self::decode_cursor::<SlogQueryCursor>(&value));
+
+            match Self::query_slog_server(&services, &filter, cursor.as_ref(), limit).await {
+                Ok((records, next_cursor)) => {
+                    let entries: Vec<Value> = records
+                        .iter()
+                        .rev()
+                        .map(Self::slog_record_to_entry)
+                        .collect();
+                    let has_more = next_cursor.is_some();
+                    let next_cursor = next_cursor.map(|value| Self::encode_cursor(&value));
+                    return Ok(RPCResponse::new(
+                        RPCResult::Success(json!({
+                            "entries": entries,
+                            "hasMore": has_more,
+                            "nextCursor": next_cursor,
+                        })),
+                        req.seq,
+                    ));
+                }
+                // A plain keyword can still be matched by scanning the local files
+                Err(err) if !needs_index => {
+                    warn!("{}, fallback to scan the log files", err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
         let cursor = Self::param_str(&req, "cursor")
             .and_then(|value| Self::decode_cursor::<LogQueryCursor>(&value));
 
@@ -2411,7 +2612,6 @@ impl ControlPanelServer {
             let mut collected: Vec<Value> = Vec::new();
             let mut has_more = false;
             let mut next_cursor: Option<LogQueryCursor> = None;
-            let use_rg = keyword_raw.as_ref().is_some() && Self::rg_available();
             let cursor_index = cursor.as_ref().and_then(|value| {
                 files
                     .iter()
@@ -2426,97 +2626,55 @@ impl ControlPanelServer {
                 }
 
                 let mut candidates: Vec<(u64, String, String, String, String)> = Vec::new();
-                let mut rg_used = false;
-                if use_rg {
-                    let keyword = keyword_raw.as_ref().unwrap();
-                    match Self::rg_search_lines(&file.path, keyword) {
-                        Ok(matched_lines) => {
-                            rg_used = true;
-                            for (line_index, raw) in matched_lines.into_iter() {
-                                let (ts, level, message) = Self::split_log_line(&raw);
-                                if ts.is_empty() {
-                                    continue;
-                                }
-                                if let Some(filter) = level_filter.as_ref() {
-                                    if &level != filter {
-                                        continue;
-                                    }
-                                }
-                                if since_key.is_some() || until_key.is_some() {
-                                    if ts.is_empty() {
-                                        continue;
-                                    }
-                                    if let Some(since) = since_key.as_ref() {
-                                        if ts < *since {
-                                            continue;
-                                        }
-                                    }
-                                    if let Some(until) = until_key.as_ref() {
-                                        if ts > *until {
-                                            continue;
-                                        }
-                                    }
-                                }
-                                candidates.push((line_index, ts, level, message, raw));
+                let mut last_context: Option<(String, String)> = None;
+                let file_handle = std::fs::File::open(&file.path).map_err(|err| {
+                    RPCErrors::ReasonError(format!(
+                        "Failed to open log file {}: {}",
+                        file.name, err
+                    ))
+                })?;
+                let reader = BufReader::new(file_handle);
+                for (index, line) in reader.lines().enumerate() {
+                    let raw = match line {
+                        Ok(value) => value,
+                        Err(_) => continue,
+                    };
+                    let maybe_entry = Self::extract_log_entry(&raw, last_context.as_ref());
+                    let (ts, level, message, raw_line) = match maybe_entry {
+                        Some((ts, level, message, next_context)) => {
+                            if let Some(context) = next_context {
+                                last_context = Some(context);
                             }
+                            (ts, level, message, raw.trim_end().to_string())
                         }
-                        Err(err) => {
-                            log::warn!("rg failed for {}: {}", file.name, err);
+                        None => continue,
+                    };
+                    if let Some(filter) = level_filter.as_ref() {
+                        if &level != filter {
+                            continue;
                         }
                     }
-                }
-
-                if !rg_used {
-                    let mut last_context: Option<(String, String)> = None;
-                    let file_handle = std::fs::File::open(&file.path).map_err(|err| {
-                        RPCErrors::ReasonError(format!(
-                            "Failed to open log file {}: {}",
-                            file.name, err
-                        ))
-                    })?;
-                    let reader = BufReader::new(file_handle);
-                    for (index, line) in reader.lines().enumerate() {
-                        let raw = match line {
-                            Ok(value) => value,
-                            Err(_) => continue,
-                        };
-                        let maybe_entry = Self::extract_log_entry(&raw, last_context.as_ref());
-                        let (ts, level, message, raw_line) = match maybe_entry {
-                            Some((ts, level, message, next_context)) => {
-                                if let Some(context) = next_context {
-                                    last_context = Some(context);
-                                }
-                                (ts, level, message, raw.trim_end().to_string())
-                            }
-                            None => continue,
-                        };
-                        if let Some(filter) = level_filter.as_ref() {
-                            if &level != filter {
-                                continue;
-                            }
+                    if let Some(filter) = keyword_filter.as_ref() {
+                        if !raw_line.to_lowercase().contains(filter) {
+                            continue;
                         }
-                        if let Some(filter) = keyword_filter.as_ref() {
-                            if !raw_line.to_lowercase().contains(filter) {
+                    }
+                    if since_key.is_some() || until_key.is_some() {
+                        if ts.is_empty() {
+                            continue;
+                        }
+                        if let Some(since) = since_key.as_ref() {
+                            if ts < *since {
                                 continue;
                             }
                         }
-                        if since_key.is_some() || until_key.is_some() {
-                            if ts.is_empty() {
+                        if let Some(until) = until_key.as_ref() {
+                            if ts > *until {
                                 continue;
                             }
-                            if let Some(since) = since_key.as_ref() {
-                                if ts < *since {
-                                    continue;
-                                }
-                            }
-                            if let Some(until) = until_key.as_ref() {
-                                if ts > *until {
-                                    continue;
-                                }
-                            }
                         }
-                        candidates.push((index as u64, ts, level, message, raw_line));
                     }
+                    candidates.push((index as u64, ts, level, message, raw_line));
                 }
 
                 if let Some(cursor) = cursor.as_ref() {
@@ -2568,168 +2726,90 @@ impl ControlPanelServer {
             let mut has_more = false;
             let mut next_cursor: Option<LogQueryCursor> = None;
             let mut reached_cursor = cursor.is_none();
-            let use_rg = keyword_raw.as_ref().is_some() && Self::rg_available();
 
             for file in files.iter() {
-                let mut rg_used = false;
-                if use_rg {
-                    let keyword = keyword_raw.as_ref().unwrap();
-                    match Self::rg_search_lines(&file.path, keyword) {
-                        Ok(matched_lines) => {
-                            rg_used = true;
-                            for (line_index, raw) in matched_lines.into_iter() {
-                                if !reached_cursor {
-                                    if let Some(cursor) = cursor.as_ref() {
-                                        if cursor.service == file.service
-                                            && cursor.file == file.name
-                                        {
-                                            if line_index <= cursor.line_index {
-                                                continue;
-                                            }
-                                            reached_cursor = true;
-                                        } else {
-                                            continue;
-                                        }
-                                    }
-                                }
+                let mut last_context: Option<(String, String)> = None;
+                let file_handle = std::fs::File::open(&file.path).map_err(|err| {
+                    RPCErrors::ReasonError(format!(
+                        "Failed to open log file {}: {}",
+                        file.name, err
+                    ))
+                })?;
+                let reader = BufReader::new(file_handle);
+                for (index, line) in reader.lines().enumerate() {
+                    let line_index = index as u64;
+                    let raw = match line {
+                        Ok(value) => value,
+                        Err(_) => continue,
+                    };
+                    let maybe_entry = Self::extract_log_entry(&raw, last_context.as_ref());
+                    let (ts, level, message, raw_line) = match maybe_entry {
+                        Some((ts, level, message, next_context)) => {
+                            if let Some(context) = next_context {
+                                last_context = Some(context);
+                            }
+                            (ts, level, message, raw.trim_end().to_string())
+                        }
+                        None => continue,
+                    };
 
-                                let (ts, level, message) = Self::split_log_line(&raw);
-                                if ts.is_empty() {
+                    if !reached_cursor {
+                        if let Some(cursor) = cursor.as_ref() {
+                            if cursor.service == file.service && cursor.file == file.name {
+                                if line_index <= cursor.line_index {
                                     continue;
                                 }
-                                if let Some(filter) = level_filter.as_ref() {
-                                    if &level != filter {
-                                        continue;
-                                    }
-                                }
-                                if since_key.is_some() || until_key.is_some() {
-                                    if ts.is_empty() {
-                                        continue;
-                                    }
-                                    if let Some(since) = since_key.as_ref() {
-                                        if ts < *since {
-                                            continue;
-                                        }
-                                    }
-                                    if let Some(until) = until_key.as_ref() {
-                                        if ts > *until {
-                                            continue;
-                                        }
-                                    }
-                                }
-
-                                entries.push(json!({
-                                    "timestamp": ts,
-                                    "level": level,
-                                    "message": message,
-                                    "raw": raw,
-                                    "service": file.service.clone(),
-                                    "file": file.name.clone(),
-                                    "line": line_index,
-                                }));
-
-                                if entries.len() >= limit {
-                                    has_more = true;
-                                    next_cursor = Some(LogQueryCursor {
-                                        service: file.service.clone(),
-                                        file: file.name.clone(),
-                                        line_index,
-                                        direction: direction.clone(),
-                                    });
-                                    break;
-                                }
+                                reached_cursor = true;
+                            } else {
+                                continue;
                             }
                         }
-                        Err(err) => {
-                            log::warn!("rg failed for {}: {}", file.name, err);
+                    }
+                    if let Some(filter) = level_filter.as_ref() {
+                        if &level != filter {
+                            continue;
                         }
                     }
-                }
-
-                if !rg_used {
-                    let mut last_context: Option<(String, String)> = None;
-                    let file_handle = std::fs::File::open(&file.path).map_err(|err| {
-                        RPCErrors::ReasonError(format!(
-                            "Failed to open log file {}: {}",
-                            file.name, err
-                        ))
-                    })?;
-                    let reader = BufReader::new(file_handle);
-                    for (index, line) in reader.lines().enumerate() {
-                        let line_index = index as u64;
-                        let raw = match line {
-                            Ok(value) => value,
-                            Err(_) => continue,
-                        };
-                        let maybe_entry = Self::extract_log_entry(&raw, last_context.as_ref());
-                        let (ts, level, message, raw_line) = match maybe_entry {
-                            Some((ts, level, message, next_context)) => {
-                                if let Some(context) = next_context {
-                                    last_context = Some(context);
-                                }
-                                (ts, level, message, raw.trim_end().to_string())
-                            }
-                            None => continue,
-                        };
-
-                        if !reached_cursor {
-                            if let Some(cursor) = cursor.as_ref() {
-                                if cursor.service == file.service && cursor.file == file.name {
-                                    if line_index <= cursor.line_index {
-                                        continue;
-                                    }
-                                    reached_cursor = true;
-                                } else {
-                                    continue;
-                                }
-                            }
+                    if let Some(filter) = keyword_filter.as_ref() {
+                        if !raw_line.to_lowercase().contains(filter) {
+                            continue;
                         }
-                        if let Some(filter) = level_filter.as_ref() {
-                            if &level != filter {
-                                continue;
-                            }
+                    }
+                    if since_key.is_some() || until_key.is_some() {
+                        if ts.is_empty() {
+                            continue;
                         }
-                        if let Some(filter) = keyword_filter.as_ref() {
-                            if !raw_line.to_lowercase().contains(filter) {
+                        if let Some(since) = since_key.as_ref() {
+                            if ts < *since {
                                 continue;
                             }
                         }
-                        if since_key.is_some() || until_key.is_some() {
-                            if ts.is_empty() {
+                        if let Some(until) = until_key.as_ref() {
+                            if ts > *until {
                                 continue;
                             }
-                            if let Some(since) = since_key.as_ref() {
-                                if ts < *since {
-                                    continue;
-                                }
-                            }
-                            if let Some(until) = until_key.as_ref() {
-                                if ts > *until {
-                                    continue;
-                                }
-                            }
                         }
+                    }
 
-                        entries.push(json!({
-                            "timestamp": ts,
-                            "level": level,
-                            "message": message,
-                            "raw": raw_line,
-                            "service": file.service.clone(),
-                            "file": file.name.clone(),
-                            "line": line_index,
-                        }));
-
-                        if entries.len() >= limit {
-                            has_more = true;
-                            next_cursor = Some(LogQueryCursor {
-                                service: file.service.clone(),
-                                file: file.name.clone(),
-                                line_index,
-                                direction: direction.clone(),
-                            });
-                            break;
-                        }
+                    entries.push(json!({
+                        "timestamp": ts,
+                        "level": level,
+                        "message": message,
+                        "raw": raw_line,
+                        "service": file.service.clone(),
+                        "file": file.name.clone(),
+                        "line": line_index,
+                    }));
+
+                    if entries.len() >= limit {
+                        has_more = true;
+                        next_cursor = Some(LogQueryCursor {
+                            service: file.service.clone(),
+                            file: file.name.clone(),
+                            line_index,
+                            direction: direction.clone(),
+                        });
+                        break;
                     }
                 }
 
@@ -3477,6 +3557,179 @@ impl ControlPanelServer {
         ))
     }
 
+    fn sys_config_history_item(entry: &SystemConfigChangeEvent) -> Value {
+        json!({
+            "version": entry.key_revision,
+            "storeRevision": entry.revision,
+            "action": entry.action,
+            "value": entry.value,
+            "deleted": entry.action == SystemConfigChangeAction::Delete,
+            "timestamp": entry.timestamp,
+        })
+    }
+
+    async fn handle_sys_config_history(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let key = Self::require_param_str(&req, "key")?;
+        let runtime = get_buckyos_api_runtime()?;
+        let client = runtime.get_system_config_client().await?;
+
+        // with version: a single point-in-time read, used by the UI to diff two versions
+        if let Some(version) = Self::param_u64(&req, "version") {
+            let entry = client
+                .get_at(&key, version)
+                .await
+                .map_err(|error| RPCErrors::ReasonError(error.to_string()))?;
+            let items: Vec<Value> = entry.iter().map(Self::sys_config_history_item).collect();
+            return Ok(RPCResponse::new(
+                RPCResult::Success(json!({
+                    "key": key,
+                    "items": items,
+                })),
+                req.seq,
+            ));
+        }
+
+        let limit = Self::param_u64(&req, "limit").unwrap_or(SYS_CONFIG_HISTORY_DEFAULT_LIMIT);
+        let history = client
+            .get_history(&key, Some(limit))
+            .await
+            .map_err(|error| RPCErrors::ReasonError(error.to_string()))?;
+        let items: Vec<Value> = history.iter().map(Self::sys_config_history_item).collect();
+
+        Ok(RPCResponse::new(
+            RPCResult::Success(json!({
+                "key": key,
+                "items": items,
+            })),
+            req.seq,
+        ))
+    }
+
+    async fn handle_sys_config_rollback(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let key = Self::require_param_str(&req, "key")?;
+        let version = Self::param_u64(&req, "version")
+            .ok_or(RPCErrors::ParseRequestError("Missing version".to_string()))?;
+        let runtime = get_buckyos_api_runtime()?;
+        let client = runtime.get_system_config_client().await?;
+        let entry = client
+            .get_at(&key, version)
+            .await
+            .map_err(|error| RPCErrors::ReasonError(error.to_string()))?
+            .ok_or_else(|| {
+                RPCErrors::ReasonError(format!(
+                    "version {} of {} is not in config history",
+                    version, key
+                ))
+            })?;
+
+        match (entry.action, entry.value.as_deref()) {
+            (SystemConfigChangeAction::Delete, _) | (_, None) => {
+                client
+                    .delete(&key)
+                    .await
+                    .map_err(|error| RPCErrors::ReasonError(error.to_string()))?;
+            }
+            (_, Some(value)) => {
+                client
+                    .set(&key, value)
+                    .await
+                    .map_err(|error| RPCErrors::ReasonError(error.to_string()))?;
+            }
+        }
+        info!("sys_config rollback: key={} to version={}", key, version);
+
+        Ok(RPCResponse::new(
+            RPCResult::Success(json!({
+                "ok": true,
+                "key": key,
+                "version": version,
+            })),
+            req.seq,
+        ))
+    }
+
+    // 调度器接口只对管理员开放: 先校验调用者是admin/root,再把调用者自己的token转发给scheduler,
+    // 由scheduler按同一会话再做一次判断,而不是以control panel自身的身份访问
+    async fn admin_scheduler_client(&self, req: &RPCRequest) -> Result<SchedulerClient, RPCErrors> {
+        let token = Self::extract_rpc_session_token(req)
+            .ok_or_else(|| RPCErrors::InvalidToken("missing session token".to_string()))?;
+        let runtime = get_buckyos_api_runtime()?;
+        let parsed = runtime.verify_trusted_session_token(&token).await?;
+        let username = parsed
+            .sub
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+            .ok_or_else(|| RPCErrors::InvalidToken("session token missing subject".to_string()))?;
+        let system_config_client = runtime.get_system_config_client().await?;
+        if !is_privileged_user(&system_config_client, &username).await? {
+            warn!("scheduler access denied: user={}", username);
+            return Err(RPCErrors::NoPermission(format!(
+                "{} is not allowed to access the scheduler",
+                username
+            )));
+        }
+
+        let client = runtime.get_scheduler_client().await?;
+        client
+            .set_context(RPCContext {
+                token: Some(token),
+                ..Default::default()
+            })
+            .await;
+        Ok(client)
+    }
+
+    async fn handle_scheduler_status(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
+        let client = self.admin_scheduler_client(&req).await?;
+        let status = client.get_status().await?;
+        let nodes = client.list_nodes().await?;
+
+        Ok(RPCResponse::new(
+            RPCResult::Success(json!({
+                "status": status,
+                "items": nodes,
+            })),
+            req.seq,
+        ))
+    }
+
+    async fn handle_scheduler_task_list(
+        &self,
+        req: RPCRequest,
+        include_finished: bool,
+    ) -> Result<RPCResponse, RPCErrors> {
+        let node_id = Self::param_str(&req, "node_id");
+        let client = self.admin_scheduler_client(&req).await?;
+        let tasks = client
+            .list_op_tasks(node_id.as_deref(), include_finished)
+            .await?;
+
+        Ok(RPCResponse::new(
+            RPCResult::Success(json!({
+                "items": tasks,
+            })),
+            req.seq,
+        ))
+    }
+
+    async fn handle_scheduler_task_cancel(
+        &self,
+        req: RPCRequest,
+    ) -> Result<RPCResponse, RPCErrors> {
+        let task_id = Self::require_param_str(&req, "task_id")?;
+        let client = self.admin_scheduler_client(&req).await?;
+        let task = client.cancel_op_task(&task_id).await?;
+        info!("scheduler task canceled: task_id={}", task_id);
+
+        Ok(RPCResponse::new(
+            RPCResult::Success(json!({
+                "ok": true,
+                "task": task,
+            })),
+            req.seq,
+        ))
+    }
+
     async fn handle_system_config_test(&self, req: RPCRequest) -> Result<RPCResponse, RPCErrors> {
         let key = req
             .params
@@ -7293,15 +7546,13 @@ impl RPCHandler for ControlPanelServer {
             "sys_config.set" => self.handle_sys_config_set(req).await,
             "sys_config.list" => self.handle_sys_config_list(req).await,
             "sys_config.tree" => self.handle_sys_config_tree(req).await,
-            "sys_config.history" => self.handle_unimplemented(req, "Config history").await,
+            "sys_config.history" => self.handle_sys_config_history(req).await,
+            "sys_config.rollback" => self.handle_sys_config_rollback(req).await,
             // Scheduler
-            "scheduler.status" => self.handle_unimplemented(req, "Scheduler status").await,
-            "scheduler.queue.list" => self.handle_unimplemented(req, "Scheduler queue").await,
-            "scheduler.task.list" => self.handle_unimplemented(req, "Scheduler tasks").await,
-            "scheduler.task.cancel" => {
-                self.handle_unimplemented(req, "Cancel scheduler task")
-                    .await
-            }
+            "scheduler.status" => self.handle_scheduler_status(req).await,
+            "scheduler.queue.list" => self.handle_scheduler_task_list(req, false).await,
+            "scheduler.task.list" => self.handle_scheduler_task_list(req, true).await,
+            "scheduler.task.cancel" => self.handle_scheduler_task_cancel(req).await,
             // Node / Daemon
             "node.list" => self.handle_unimplemented(req, "List nodes").await,
             "node.get" => self.handle_unimplemented(req, "Node detail").await,
@@ -759,6 +759,11 @@ type LogQueryParams = {
   file?: string
   level?: SystemLogLevel
   keyword?: string
+  // searched through slog_server together with keyword
+  regex?: string
+  target?: string
+  sourceFile?: string
+  sourceLine?: number
   since?: string
   until?: string
   limit?: number
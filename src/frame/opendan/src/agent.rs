@@ -2327,6 +2327,7 @@ impl AIAgent {
             other_app_can_write: false,
             other_user_can_read: false,
             other_user_can_write: false,
+            ..Default::default()
         };
 
         self.ensure_session_queue_exists(
@@ -5511,6 +5512,7 @@ mod tests {
             ingress_node: None,
             timestamp: 0,
             data: json!({}),
+            seq: None,
         }
     }
 
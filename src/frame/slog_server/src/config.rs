@@ -1,6 +1,8 @@
+use std::collections::BTreeMap;
 use std::path::PathBuf;
+use std::time::Duration;
 
-use crate::storage::{LogStorageType, PartitionBucket, SqlitePartitionedConfig};
+use crate::storage::{LogStorageType, PartitionBucket, RetentionPolicy, SqlitePartitionedConfig};
 
 /// Environment key for overriding server bind address.
 pub const SLOG_SERVER_BIND_ENV_KEY: &str = "SLOG_SERVER_BIND";
@@ -14,6 +16,15 @@ pub const SLOG_STORAGE_PARTITION_BUCKET_ENV_KEY: &str = "SLOG_STORAGE_PARTITION_
 pub const SLOG_STORAGE_PARTITION_MAX_ROWS_ENV_KEY: &str = "SLOG_STORAGE_PARTITION_MAX_ROWS";
 /// Environment key for max DB size (MB) in one partition DB file.
 pub const SLOG_STORAGE_PARTITION_MAX_SIZE_MB_ENV_KEY: &str = "SLOG_STORAGE_PARTITION_MAX_SIZE_MB";
+/// Environment key for max log age in hours, `0` keeps logs forever.
+pub const SLOG_RETENTION_MAX_AGE_HOURS_ENV_KEY: &str = "SLOG_RETENTION_MAX_AGE_HOURS";
+/// Environment key for max total size (MB) of all partitions, `0` means unlimited.
+pub const SLOG_RETENTION_MAX_TOTAL_SIZE_MB_ENV_KEY: &str = "SLOG_RETENTION_MAX_TOTAL_SIZE_MB";
+/// Environment key for per-service max age in hours, like `svc_a=24,svc_b=720`.
+pub const SLOG_RETENTION_SERVICE_MAX_AGE_HOURS_ENV_KEY: &str =
+    "SLOG_RETENTION_SERVICE_MAX_AGE_HOURS";
+/// Environment key for the interval (seconds) of the background retention job.
+pub const SLOG_RETENTION_INTERVAL_SECS_ENV_KEY: &str = "SLOG_RETENTION_INTERVAL_SECS";
 /// Default bind address when no external config is provided.
 pub const DEFAULT_SERVER_BIND: &str = "127.0.0.1:22001";
 /// Default backend type.
@@ -24,6 +35,12 @@ pub const DEFAULT_STORAGE_PARTITION_BUCKET: PartitionBucket = PartitionBucket::D
 pub const DEFAULT_STORAGE_PARTITION_MAX_ROWS: u64 = 5_000_000;
 /// Default size cap per partition DB, in MB.
 pub const DEFAULT_STORAGE_PARTITION_MAX_SIZE_MB: u64 = 2048;
+/// Default max log age, disabled: retention is opt-in, logs are kept until a max age is configured.
+pub const DEFAULT_RETENTION_MAX_AGE_HOURS: u64 = 0;
+/// Default max total size of all partitions, unlimited.
+pub const DEFAULT_RETENTION_MAX_TOTAL_SIZE_MB: u64 = 0;
+/// Default interval of the background retention job.
+pub const DEFAULT_RETENTION_INTERVAL_SECS: u64 = 3600;
 
 #[derive(Debug, Clone)]
 pub struct NetworkConfig {
@@ -62,11 +79,61 @@ pub struct StoragePartitionConfig {
     pub max_partition_size_mb: u64,
 }
 
+/// Retention of the partitioned storage, `0` disables the age or size limit.
+#[derive(Debug, Clone)]
+pub struct StorageRetentionConfig {
+    pub max_age_hours: u64,
+    pub max_total_size_mb: u64,
+    pub service_max_age_hours: BTreeMap<String, u64>,
+    pub interval_secs: u64,
+}
+
+impl StorageRetentionConfig {
+    pub fn to_policy(&self) -> RetentionPolicy {
+        let hours_to_secs = |hours: u64| hours.saturating_mul(3600);
+        RetentionPolicy {
+            max_age_secs: (self.max_age_hours > 0).then(|| hours_to_secs(self.max_age_hours)),
+            max_total_bytes: (self.max_total_size_mb > 0)
+                .then(|| self.max_total_size_mb.saturating_mul(1024 * 1024)),
+            service_max_age_secs: self
+                .service_max_age_hours
+                .iter()
+                .map(|(service, hours)| (service.clone(), hours_to_secs(*hours)))
+                .collect(),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_secs.max(1))
+    }
+}
+
+/// Parse per-service max age like `svc_a=24,svc_b=720` (hours).
+pub fn parse_service_max_age_hours(raw: &str) -> Result<BTreeMap<String, u64>, String> {
+    let mut ret = BTreeMap::new();
+    for item in raw.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+        let Some((service, hours)) = item.split_once('=') else {
+            return Err(format!("invalid item '{}': expected service=hours", item));
+        };
+        let service = service.trim();
+        if service.is_empty() {
+            return Err(format!("invalid item '{}': empty service name", item));
+        }
+        let hours = hours
+            .trim()
+            .parse::<u64>()
+            .map_err(|e| format!("invalid item '{}': {}", item, e))?;
+        ret.insert(service.to_string(), hours);
+    }
+    Ok(ret)
+}
+
 #[derive(Debug, Clone)]
 pub struct StorageConfig {
     pub storage_dir: PathBuf,
     pub storage_engine: StorageEngine,
     pub partition: StoragePartitionConfig,
+    pub retention: StorageRetentionConfig,
 }
 
 impl StorageConfig {
@@ -102,6 +169,10 @@ pub struct ServerEnvOverrides {
     pub partition_bucket: Option<PartitionBucket>,
     pub partition_max_rows: Option<u64>,
     pub partition_max_size_mb: Option<u64>,
+    pub retention_max_age_hours: Option<u64>,
+    pub retention_max_total_size_mb: Option<u64>,
+    pub retention_service_max_age_hours: Option<BTreeMap<String, u64>>,
+    pub retention_interval_secs: Option<u64>,
 }
 
 impl Default for ServerConfig {
@@ -118,6 +189,12 @@ impl Default for ServerConfig {
                     max_rows_per_partition: DEFAULT_STORAGE_PARTITION_MAX_ROWS,
                     max_partition_size_mb: DEFAULT_STORAGE_PARTITION_MAX_SIZE_MB,
                 },
+                retention: StorageRetentionConfig {
+                    max_age_hours: DEFAULT_RETENTION_MAX_AGE_HOURS,
+                    max_total_size_mb: DEFAULT_RETENTION_MAX_TOTAL_SIZE_MB,
+                    service_max_age_hours: BTreeMap::new(),
+                    interval_secs: DEFAULT_RETENTION_INTERVAL_SECS,
+                },
             },
         }
     }
@@ -153,6 +230,22 @@ impl ServerConfig {
         if let Some(v) = overrides.partition_max_size_mb {
             self.storage.partition.max_partition_size_mb = v;
         }
+
+        if let Some(v) = overrides.retention_max_age_hours {
+            self.storage.retention.max_age_hours = v;
+        }
+
+        if let Some(v) = overrides.retention_max_total_size_mb {
+            self.storage.retention.max_total_size_mb = v;
+        }
+
+        if let Some(v) = overrides.retention_service_max_age_hours {
+            self.storage.retention.service_max_age_hours = v;
+        }
+
+        if let Some(v) = overrides.retention_interval_secs {
+            self.storage.retention.interval_secs = v;
+        }
     }
 }
 
@@ -164,8 +257,10 @@ mod tests {
         SLOG_STORAGE_DIR_ENV_KEY, SLOG_STORAGE_PARTITION_BUCKET_ENV_KEY,
         SLOG_STORAGE_PARTITION_MAX_ROWS_ENV_KEY, SLOG_STORAGE_PARTITION_MAX_SIZE_MB_ENV_KEY,
         SLOG_STORAGE_TYPE_ENV_KEY, ServerConfig, ServerEnvOverrides, StorageEngine,
+        StorageRetentionConfig, parse_service_max_age_hours,
     };
     use crate::storage::{LogStorageType, PartitionBucket};
+    use std::collections::BTreeMap;
     use std::path::PathBuf;
 
     #[test]
@@ -186,6 +281,10 @@ mod tests {
             cfg.storage.partition.max_partition_size_mb,
             DEFAULT_STORAGE_PARTITION_MAX_SIZE_MB
         );
+        assert_eq!(cfg.storage.retention.max_age_hours, 0);
+        assert_eq!(cfg.storage.retention.max_total_size_mb, 0);
+        assert!(cfg.storage.retention.to_policy().is_disabled());
+        assert!(cfg.storage.retention.service_max_age_hours.is_empty());
     }
 
     #[test]
@@ -198,6 +297,10 @@ mod tests {
             partition_bucket: Some(PartitionBucket::Day),
             partition_max_rows: Some(1000),
             partition_max_size_mb: Some(128),
+            retention_max_age_hours: Some(48),
+            retention_max_total_size_mb: Some(512),
+            retention_service_max_age_hours: Some(BTreeMap::from([("svc-a".to_string(), 6)])),
+            retention_interval_secs: Some(60),
         };
         cfg.apply_env_overrides(overrides);
 
@@ -210,6 +313,13 @@ mod tests {
         assert_eq!(cfg.storage.partition.bucket, PartitionBucket::Day);
         assert_eq!(cfg.storage.partition.max_rows_per_partition, 1000);
         assert_eq!(cfg.storage.partition.max_partition_size_mb, 128);
+        assert_eq!(cfg.storage.retention.max_age_hours, 48);
+        assert_eq!(cfg.storage.retention.max_total_size_mb, 512);
+        assert_eq!(
+            cfg.storage.retention.service_max_age_hours.get("svc-a"),
+            Some(&6)
+        );
+        assert_eq!(cfg.storage.retention.interval_secs, 60);
     }
 
     #[test]
@@ -223,6 +333,7 @@ mod tests {
             partition_bucket: None,
             partition_max_rows: None,
             partition_max_size_mb: None,
+            ..Default::default()
         };
         cfg.apply_env_overrides(overrides);
 
@@ -276,4 +387,25 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_retention_config_to_policy() {
+        let cfg = StorageRetentionConfig {
+            max_age_hours: 24,
+            max_total_size_mb: 0,
+            service_max_age_hours: parse_service_max_age_hours(" svc-a=1, svc-b = 48 ,").unwrap(),
+            interval_secs: 0,
+        };
+        let policy = cfg.to_policy();
+        assert_eq!(policy.max_age_secs, Some(24 * 3600));
+        assert_eq!(policy.max_total_bytes, None);
+        assert_eq!(policy.max_age_secs_for("svc-a"), Some(3600));
+        assert_eq!(policy.max_age_secs_for("svc-b"), Some(48 * 3600));
+        assert_eq!(cfg.interval().as_secs(), 1);
+
+        assert!(parse_service_max_age_hours("svc-a").is_err());
+        assert!(parse_service_max_age_hours("=1").is_err());
+        assert!(parse_service_max_age_hours("svc-a=x").is_err());
+        assert_eq!(parse_service_max_age_hours("").unwrap(), BTreeMap::new());
+    }
 }
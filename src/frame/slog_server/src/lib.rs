@@ -1,6 +1,7 @@
 pub mod config;
 pub mod server;
 pub mod storage;
+pub mod tail;
 
 #[macro_use]
 extern crate log;
@@ -3,17 +3,21 @@
 mod config;
 mod server;
 mod storage;
+mod tail;
 
 #[macro_use]
 extern crate log;
 
 use crate::server::LogHttpServer;
-use crate::storage::create_log_storage_with_dir;
+use crate::storage::{create_log_storage_with_dir, start_retention_task};
 use config::{
+    SLOG_RETENTION_INTERVAL_SECS_ENV_KEY, SLOG_RETENTION_MAX_AGE_HOURS_ENV_KEY,
+    SLOG_RETENTION_MAX_TOTAL_SIZE_MB_ENV_KEY, SLOG_RETENTION_SERVICE_MAX_AGE_HOURS_ENV_KEY,
     SLOG_SERVER_BIND_ENV_KEY, SLOG_STORAGE_DIR_ENV_KEY, SLOG_STORAGE_PARTITION_BUCKET_ENV_KEY,
     SLOG_STORAGE_PARTITION_MAX_ROWS_ENV_KEY, SLOG_STORAGE_PARTITION_MAX_SIZE_MB_ENV_KEY,
     SLOG_STORAGE_TYPE_ENV_KEY, ServerConfig, ServerEnvOverrides, StorageEngine,
 };
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use storage::PartitionBucket;
 
@@ -72,6 +76,20 @@ fn read_env_partition_bucket(env_key: &str) -> Option<PartitionBucket> {
     }
 }
 
+fn read_env_service_max_age_hours(env_key: &str) -> Option<BTreeMap<String, u64>> {
+    let value = read_env_nonempty(env_key)?;
+    match config::parse_service_max_age_hours(&value) {
+        Ok(v) => Some(v),
+        Err(e) => {
+            warn!(
+                "ignore invalid env {}='{}': expected service=hours[,service=hours] ({})",
+                env_key, value, e
+            );
+            None
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
     // First init logs output
@@ -112,12 +130,20 @@ async fn main() {
         partition_bucket: read_env_partition_bucket(SLOG_STORAGE_PARTITION_BUCKET_ENV_KEY),
         partition_max_rows: read_env_u64(SLOG_STORAGE_PARTITION_MAX_ROWS_ENV_KEY),
         partition_max_size_mb: read_env_u64(SLOG_STORAGE_PARTITION_MAX_SIZE_MB_ENV_KEY),
+        retention_max_age_hours: read_env_u64(SLOG_RETENTION_MAX_AGE_HOURS_ENV_KEY),
+        retention_max_total_size_mb: read_env_u64(SLOG_RETENTION_MAX_TOTAL_SIZE_MB_ENV_KEY),
+        retention_service_max_age_hours: read_env_service_max_age_hours(
+            SLOG_RETENTION_SERVICE_MAX_AGE_HOURS_ENV_KEY,
+        ),
+        retention_interval_secs: read_env_u64(SLOG_RETENTION_INTERVAL_SECS_ENV_KEY),
     };
     cfg.apply_env_overrides(env_overrides);
 
     let bind_addr = cfg.network.bind_addr;
     let storage_type = cfg.storage.to_storage_type();
     let storage_dir = cfg.storage.storage_dir;
+    let retention_policy = cfg.storage.retention.to_policy();
+    let retention_interval = cfg.storage.retention.interval();
 
     info!(
         "slog_server config: bind_addr={}, storage_dir={}, storage_engine={}, partition_bucket={}, partition_max_rows={}, partition_max_size_mb={}",
@@ -128,6 +154,13 @@ async fn main() {
         cfg.storage.partition.max_rows_per_partition,
         cfg.storage.partition.max_partition_size_mb
     );
+    info!(
+        "slog_server retention: max_age_hours={}, max_total_size_mb={}, service_max_age_hours={:?}, interval_secs={}",
+        cfg.storage.retention.max_age_hours,
+        cfg.storage.retention.max_total_size_mb,
+        cfg.storage.retention.service_max_age_hours,
+        cfg.storage.retention.interval_secs
+    );
 
     let storage = match create_log_storage_with_dir(storage_type, &storage_dir) {
         Ok(s) => s,
@@ -137,6 +170,16 @@ async fn main() {
         }
     };
 
+    // Only the partitioned storage can drop old logs
+    if cfg.storage.storage_engine == StorageEngine::SqlitePartitioned {
+        start_retention_task(storage.clone(), retention_policy, retention_interval);
+    } else {
+        warn!(
+            "log retention is not supported by storage engine {}, logs are kept forever",
+            cfg.storage.storage_engine.as_str()
+        );
+    }
+
     let server = LogHttpServer::new(storage);
     info!("Starting slog server at http://{}", bind_addr);
     if let Err(e) = server.run(&bind_addr).await {
@@ -1,15 +1,27 @@
-use crate::storage::{LogQueryRequest, LogRecords, LogStorageRef};
+use crate::storage::{
+    AggregateGroupBy, LogAggregateBucket, LogAggregateRequest, LogQueryRequest, LogRecords,
+    LogStorageRef, StorageStats,
+};
+use crate::tail::{DEFAULT_TAIL_BUFFER_SIZE, LogTailHub, TailCursor, TailFilter, TailItem};
 use axum::{
     Json, Router,
     extract::Query,
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
     routing::{get, post},
 };
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::sync::Arc;
 
 const DEFAULT_QUERY_LIMIT: usize = 200;
 const MAX_QUERY_LIMIT: usize = 2000;
 const MAX_QUERY_SCAN: usize = 20_000;
+const MAX_AGGREGATE_BUCKETS: usize = 10_000;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct LogResponseMessage {
@@ -33,6 +45,89 @@ pub struct LogQueryHttpRequest {
     pub offset: Option<usize>,
     #[serde(default)]
     pub limit: Option<usize>,
+    #[serde(default)]
+    pub keyword: Option<String>,
+    #[serde(default)]
+    pub regex: Option<String>,
+    #[serde(default)]
+    pub target: Option<String>,
+    #[serde(default)]
+    pub file: Option<String>,
+    #[serde(default)]
+    pub line: Option<u32>,
+    // Comma separated `key=value` pairs of structured fields, like `request_id=r1,user_id=u1`
+    #[serde(default)]
+    pub fields: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LogAggregateHttpRequest {
+    #[serde(default)]
+    pub node: Option<String>,
+    #[serde(default)]
+    pub service: Option<String>,
+    #[serde(default)]
+    pub level: Option<String>,
+    #[serde(default)]
+    pub start_time: Option<u64>,
+    #[serde(default)]
+    pub end_time: Option<u64>,
+    #[serde(default)]
+    pub keyword: Option<String>,
+    #[serde(default)]
+    pub regex: Option<String>,
+    #[serde(default)]
+    pub target: Option<String>,
+    #[serde(default)]
+    pub file: Option<String>,
+    #[serde(default)]
+    pub line: Option<u32>,
+    #[serde(default)]
+    pub fields: Option<String>,
+    // Width of the time buckets in seconds, the whole range is one bucket if not set
+    #[serde(default)]
+    pub bucket_secs: Option<u64>,
+    // Comma separated fields of node/service/level/target, like `service,level`
+    #[serde(default)]
+    pub group_by: Option<String>,
+    // Keep only the top N groups by count in every bucket
+    #[serde(default)]
+    pub top: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogAggregateData {
+    pub buckets: Vec<LogAggregateBucket>,
+    pub bucket_secs: Option<u64>,
+    pub group_by: Vec<AggregateGroupBy>,
+    // More than the max buckets matched, only the first ones are returned
+    pub truncated: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogAggregateResponseMessage {
+    pub ret: i32,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<LogAggregateData>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LogTailHttpRequest {
+    #[serde(default)]
+    pub node: Option<String>,
+    #[serde(default)]
+    pub service: Option<String>,
+    #[serde(default)]
+    pub level: Option<String>,
+    // Resume after this cursor, the `Last-Event-ID` header is used if not set
+    #[serde(default)]
+    pub cursor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TailGapMessage {
+    pub reason: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -66,6 +161,14 @@ pub struct LogQueryResponseMessage {
     pub data: Option<LogQueryData>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StorageStatsResponseMessage {
+    pub ret: i32,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<StorageStats>,
+}
+
 #[derive(Debug, Clone)]
 struct NormalizedLogQueryRequest {
     node: Option<String>,
@@ -76,14 +179,41 @@ struct NormalizedLogQueryRequest {
     offset: usize,
     limit: usize,
     fetch_limit: usize,
+    keyword: Option<String>,
+    regex: Option<String>,
+    target: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+    fields: BTreeMap<String, String>,
+}
+
+impl NormalizedLogQueryRequest {
+    fn to_storage_request(&self, limit: Option<usize>) -> LogQueryRequest {
+        LogQueryRequest {
+            node: self.node.clone(),
+            service: self.service.clone(),
+            level: self.level,
+            start_time: self.start_time,
+            end_time: self.end_time,
+            limit,
+            keyword: self.keyword.clone(),
+            regex: self.regex.clone(),
+            target: self.target.clone(),
+            file: self.file.clone(),
+            line: self.line,
+            fields: self.fields.clone(),
+        }
+    }
 }
 
 pub struct LogHttpServer {
     storage: LogStorageRef,
+    tail: Arc<LogTailHub>,
 }
 
 async fn handle_append_logs(
     storage: LogStorageRef,
+    tail: &LogTailHub,
     records: LogRecords,
 ) -> (StatusCode, Json<LogResponseMessage>) {
     info!(
@@ -93,14 +223,19 @@ async fn handle_append_logs(
         records.logs.len()
     );
 
+    // Only the stored records are pushed to the live tails
+    let tail_records = records.clone();
     match storage.append_logs(records).await {
-        Ok(_) => (
-            StatusCode::OK,
-            Json(LogResponseMessage {
-                ret: 0,
-                message: "Logs stored successfully".to_string(),
-            }),
-        ),
+        Ok(_) => {
+            tail.publish(&tail_records);
+            (
+                StatusCode::OK,
+                Json(LogResponseMessage {
+                    ret: 0,
+                    message: "Logs stored successfully".to_string(),
+                }),
+            )
+        }
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(LogResponseMessage {
@@ -149,12 +284,54 @@ fn parse_level_filter(value: Option<String>) -> Result<Option<slog::LogLevel>, S
     Ok(Some(level))
 }
 
+fn parse_fields_filter(value: Option<String>) -> Result<BTreeMap<String, String>, String> {
+    let mut fields = BTreeMap::new();
+    let Some(raw) = trim_optional_string(value) else {
+        return Ok(fields);
+    };
+
+    for item in raw.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+        let (key, value) = item
+            .split_once('=')
+            .ok_or_else(|| format!("invalid fields '{}': expected key=value", item))?;
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(format!("invalid fields '{}': empty key", item));
+        }
+        let value = value.trim();
+        if let Some(prev) = fields.insert(key.to_string(), value.to_string())
+            && prev != value
+        {
+            return Err(format!(
+                "invalid fields: conflicting values '{}' and '{}' for key '{}'",
+                prev, value, key
+            ));
+        }
+    }
+
+    Ok(fields)
+}
+
 fn normalize_log_query_request(
     request: LogQueryHttpRequest,
 ) -> Result<NormalizedLogQueryRequest, String> {
     let node = trim_optional_string(request.node);
     let service = trim_optional_string(request.service);
     let level = parse_level_filter(request.level)?;
+    let keyword = trim_optional_string(request.keyword);
+    let target = trim_optional_string(request.target);
+    let file = trim_optional_string(request.file);
+    let fields = parse_fields_filter(request.fields)?;
+
+    // Leading and trailing spaces are part of the pattern, only drop empty ones
+    let regex = request.regex.filter(|v| !v.is_empty());
+    if let Some(pattern) = &regex {
+        regex::Regex::new(pattern).map_err(|e| format!("invalid regex '{}': {}", pattern, e))?;
+    }
+
+    if request.line == Some(0) {
+        return Err("invalid line: must be greater than 0".to_string());
+    }
 
     if let (Some(start_time), Some(end_time)) = (request.start_time, request.end_time) {
         if start_time > end_time {
@@ -195,6 +372,12 @@ fn normalize_log_query_request(
         offset,
         limit,
         fetch_limit,
+        keyword,
+        regex,
+        target,
+        file,
+        line: request.line,
+        fields,
     })
 }
 
@@ -249,24 +432,23 @@ async fn handle_query_logs(
     };
 
     info!(
-        "Received log query request: node={:?}, service={:?}, level={:?}, start_time={:?}, end_time={:?}, offset={}, limit={}",
+        "Received log query request: node={:?}, service={:?}, level={:?}, start_time={:?}, end_time={:?}, offset={}, limit={}, keyword={:?}, regex={:?}, target={:?}, file={:?}, line={:?}, fields={:?}",
         normalized.node,
         normalized.service,
         normalized.level,
         normalized.start_time,
         normalized.end_time,
         normalized.offset,
-        normalized.limit
+        normalized.limit,
+        normalized.keyword,
+        normalized.regex,
+        normalized.target,
+        normalized.file,
+        normalized.line,
+        normalized.fields
     );
 
-    let query_request = LogQueryRequest {
-        node: normalized.node.clone(),
-        service: normalized.service.clone(),
-        level: normalized.level,
-        start_time: normalized.start_time,
-        end_time: normalized.end_time,
-        limit: Some(normalized.fetch_limit),
-    };
+    let query_request = normalized.to_storage_request(Some(normalized.fetch_limit));
 
     let queried = match storage.query_logs(query_request).await {
         Ok(v) => v,
@@ -319,21 +501,233 @@ async fn handle_query_logs(
     )
 }
 
+fn normalize_log_aggregate_request(
+    request: LogAggregateHttpRequest,
+) -> Result<LogAggregateRequest, String> {
+    // The filters are the same as the query, without pagination
+    let filter = normalize_log_query_request(LogQueryHttpRequest {
+        node: request.node,
+        service: request.service,
+        level: request.level,
+        start_time: request.start_time,
+        end_time: request.end_time,
+        keyword: request.keyword,
+        regex: request.regex,
+        target: request.target,
+        file: request.file,
+        line: request.line,
+        fields: request.fields,
+        ..Default::default()
+    })?;
+
+    if request.bucket_secs == Some(0) {
+        return Err("invalid bucket_secs: must be greater than 0".to_string());
+    }
+    if request.top == Some(0) {
+        return Err("invalid top: must be greater than 0".to_string());
+    }
+
+    let mut group_by = Vec::new();
+    if let Some(raw) = trim_optional_string(request.group_by) {
+        for item in raw.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+            let field = AggregateGroupBy::parse(item).ok_or_else(|| {
+                format!(
+                    "invalid group_by '{}': expected node/service/level/target",
+                    item
+                )
+            })?;
+            if !group_by.contains(&field) {
+                group_by.push(field);
+            }
+        }
+    }
+
+    Ok(LogAggregateRequest {
+        filter: filter.to_storage_request(None),
+        bucket_secs: request.bucket_secs,
+        group_by,
+        top: request.top,
+    })
+}
+
+async fn handle_aggregate_logs(
+    storage: LogStorageRef,
+    request: LogAggregateHttpRequest,
+) -> (StatusCode, Json<LogAggregateResponseMessage>) {
+    let request = match normalize_log_aggregate_request(request) {
+        Ok(v) => v,
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(LogAggregateResponseMessage {
+                    ret: 1,
+                    message: e,
+                    data: None,
+                }),
+            );
+        }
+    };
+
+    info!(
+        "Received log aggregate request: filter={:?}, bucket_secs={:?}, group_by={:?}, top={:?}",
+        request.filter, request.bucket_secs, request.group_by, request.top
+    );
+
+    let bucket_secs = request.bucket_secs;
+    let group_by = request.group_by.clone();
+    let mut buckets = match storage.aggregate_logs(request).await {
+        Ok(v) => v,
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(LogAggregateResponseMessage {
+                    ret: 1,
+                    message: format!("Failed to aggregate logs: {}", e),
+                    data: None,
+                }),
+            );
+        }
+    };
+
+    let truncated = buckets.len() > MAX_AGGREGATE_BUCKETS;
+    buckets.truncate(MAX_AGGREGATE_BUCKETS);
+
+    (
+        StatusCode::OK,
+        Json(LogAggregateResponseMessage {
+            ret: 0,
+            message: "Logs aggregated successfully".to_string(),
+            data: Some(LogAggregateData {
+                buckets,
+                bucket_secs,
+                group_by,
+                truncated,
+            }),
+        }),
+    )
+}
+
+fn tail_event(epoch: u64, item: TailItem) -> Event {
+    let event = match item {
+        TailItem::Record(record) => {
+            let record = record.to_record(epoch);
+            Event::default()
+                .event("log")
+                .id(record.cursor.clone())
+                .json_data(record)
+        }
+        TailItem::Gap { reason } => Event::default()
+            .event("gap")
+            .json_data(TailGapMessage { reason }),
+    };
+
+    event.unwrap_or_else(|e| {
+        error!("Failed to serialize tail event: {}", e);
+        Event::default().event("error").data(e.to_string())
+    })
+}
+
+async fn handle_tail_logs(
+    tail: Arc<LogTailHub>,
+    request: LogTailHttpRequest,
+    last_event_id: Option<String>,
+) -> Response {
+    let filter = match parse_level_filter(request.level) {
+        Ok(level) => TailFilter {
+            node: trim_optional_string(request.node),
+            service: trim_optional_string(request.service),
+            level,
+        },
+        Err(e) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(LogResponseMessage { ret: 1, message: e }),
+            )
+                .into_response();
+        }
+    };
+
+    let cursor = match trim_optional_string(request.cursor.or(last_event_id)) {
+        Some(raw) => match TailCursor::parse(&raw) {
+            Ok(cursor) => Some(cursor),
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(LogResponseMessage { ret: 1, message: e }),
+                )
+                    .into_response();
+            }
+        },
+        None => None,
+    };
+
+    info!(
+        "Received log tail request: node={:?}, service={:?}, level={:?}, cursor={:?}",
+        filter.node, filter.service, filter.level, cursor
+    );
+
+    let subscription = tail.subscribe(cursor);
+    let stream = futures::stream::unfold(
+        (subscription, filter),
+        |(mut subscription, filter)| async move {
+            let item = subscription.next(&filter).await?;
+            let event = tail_event(subscription.epoch(), item);
+            Some((Ok::<Event, Infallible>(event), (subscription, filter)))
+        },
+    );
+
+    Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+async fn handle_storage_stats(
+    storage: LogStorageRef,
+) -> (StatusCode, Json<StorageStatsResponseMessage>) {
+    match storage.storage_stats().await {
+        Ok(stats) => (
+            StatusCode::OK,
+            Json(StorageStatsResponseMessage {
+                ret: 0,
+                message: "Storage stats collected successfully".to_string(),
+                data: Some(stats),
+            }),
+        ),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(StorageStatsResponseMessage {
+                ret: 1,
+                message: format!("Failed to collect storage stats: {}", e),
+                data: None,
+            }),
+        ),
+    }
+}
+
 impl LogHttpServer {
     pub fn new(storage: LogStorageRef) -> Self {
-        Self { storage }
+        Self {
+            storage,
+            tail: Arc::new(LogTailHub::new(DEFAULT_TAIL_BUFFER_SIZE)),
+        }
     }
 
     pub async fn run(&self, addr: &str) -> Result<(), String> {
         let append_storage = self.storage.clone();
+        let append_tail = self.tail.clone();
+        let tail_hub = self.tail.clone();
         let query_get_storage = self.storage.clone();
         let query_post_storage = self.storage.clone();
+        let stats_storage = self.storage.clone();
+        let aggregate_get_storage = self.storage.clone();
+        let aggregate_post_storage = self.storage.clone();
         let app = Router::new()
             .route(
                 "/logs",
                 post(move |log_records: Json<LogRecords>| {
                     let storage = append_storage.clone();
-                    async move { handle_append_logs(storage, log_records.0).await }
+                    let tail = append_tail.clone();
+                    async move { handle_append_logs(storage, &tail, log_records.0).await }
                 }),
             )
             .route(
@@ -346,6 +740,37 @@ impl LogHttpServer {
                     let storage = query_post_storage.clone();
                     async move { handle_query_logs(storage, request.0).await }
                 }),
+            )
+            .route(
+                "/aggregate",
+                get(move |request: Query<LogAggregateHttpRequest>| {
+                    let storage = aggregate_get_storage.clone();
+                    async move { handle_aggregate_logs(storage, request.0).await }
+                })
+                .post(move |request: Json<LogAggregateHttpRequest>| {
+                    let storage = aggregate_post_storage.clone();
+                    async move { handle_aggregate_logs(storage, request.0).await }
+                }),
+            )
+            .route(
+                "/tail",
+                get(
+                    move |headers: HeaderMap, request: Query<LogTailHttpRequest>| {
+                        let tail = tail_hub.clone();
+                        let last_event_id = headers
+                            .get("last-event-id")
+                            .and_then(|v| v.to_str().ok())
+                            .map(|v| v.to_string());
+                        async move { handle_tail_logs(tail, request.0, last_event_id).await }
+                    },
+                ),
+            )
+            .route(
+                "/admin/stats",
+                get(move || {
+                    let storage = stats_storage.clone();
+                    async move { handle_storage_stats(storage).await }
+                }),
             );
 
         let listener = tokio::net::TcpListener::bind(addr).await.map_err(|e| {
@@ -388,6 +813,21 @@ mod tests {
             *self.captured_query.lock().unwrap() = Some(request);
             self.query_result.clone()
         }
+
+        async fn aggregate_logs(
+            &self,
+            request: LogAggregateRequest,
+        ) -> Result<Vec<LogAggregateBucket>, String> {
+            *self.captured_query.lock().unwrap() = Some(request.filter);
+            Ok(vec![LogAggregateBucket {
+                bucket_start: Some(3_600_000),
+                node: None,
+                service: Some("svc-a".to_string()),
+                level: Some(LogLevel::Error),
+                target: None,
+                count: 7,
+            }])
+        }
     }
 
     fn test_log(time: u64, level: LogLevel, content: &str) -> SystemLogRecord {
@@ -398,6 +838,7 @@ mod tests {
             file: Some("test.rs".to_string()),
             line: Some(1),
             content: content.to_string(),
+            fields: Default::default(),
         }
     }
 
@@ -417,27 +858,38 @@ mod tests {
     #[tokio::test]
     async fn test_handle_append_logs_returns_ok_when_append_succeeds() {
         let (storage, _) = make_storage(Ok(()), Ok(vec![]));
+        let tail = Arc::new(LogTailHub::new(16));
+        let mut subscription = tail.subscribe(None);
         let (status, body) = handle_append_logs(
             storage,
+            &tail,
             LogRecords {
                 node: "node-1".to_string(),
                 service: "svc-a".to_string(),
                 batch_id: None,
                 record_ids: vec![],
-                logs: vec![],
+                logs: vec![test_log(1000, LogLevel::Info, "appended")],
             },
         )
         .await;
 
         assert_eq!(status, StatusCode::OK);
         assert_eq!(body.ret, 0);
+
+        let TailItem::Record(record) = subscription.next(&TailFilter::default()).await.unwrap()
+        else {
+            panic!("expected appended record in tail");
+        };
+        assert_eq!(record.to_record(tail.epoch()).log.content, "appended");
     }
 
     #[tokio::test]
     async fn test_handle_append_logs_returns_500_when_append_fails() {
         let (storage, _) = make_storage(Err("db write failed".to_string()), Ok(vec![]));
+        let tail = Arc::new(LogTailHub::new(16));
         let (status, body) = handle_append_logs(
             storage,
+            &tail,
             LogRecords {
                 node: "node-1".to_string(),
                 service: "svc-a".to_string(),
@@ -488,6 +940,7 @@ mod tests {
                 end_time: Some(2000),
                 offset: Some(1),
                 limit: Some(2),
+                ..Default::default()
             },
         )
         .await;
@@ -555,6 +1008,172 @@ mod tests {
         assert!(body.message.contains("invalid time range"));
     }
 
+    #[tokio::test]
+    async fn test_handle_query_logs_forwards_search_filters() {
+        let (storage, captured_query) = make_storage(Ok(()), Ok(vec![]));
+        let (status, body) = handle_query_logs(
+            storage,
+            LogQueryHttpRequest {
+                keyword: Some("  connection refused ".to_string()),
+                regex: Some(r"peer \d+".to_string()),
+                target: Some(" kmsg ".to_string()),
+                file: Some("server.rs".to_string()),
+                line: Some(42),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.ret, 0);
+
+        let forwarded = captured_query
+            .lock()
+            .unwrap()
+            .clone()
+            .expect("forwarded query should be captured");
+        assert_eq!(forwarded.keyword.as_deref(), Some("connection refused"));
+        assert_eq!(forwarded.regex.as_deref(), Some(r"peer \d+"));
+        assert_eq!(forwarded.target.as_deref(), Some("kmsg"));
+        assert_eq!(forwarded.file.as_deref(), Some("server.rs"));
+        assert_eq!(forwarded.line, Some(42));
+    }
+
+    #[tokio::test]
+    async fn test_handle_query_logs_forwards_fields_filter() {
+        let (storage, captured_query) = make_storage(Ok(()), Ok(vec![]));
+        let (status, body) = handle_query_logs(
+            storage.clone(),
+            LogQueryHttpRequest {
+                fields: Some(" request_id = r-1 , app_id=app=2,".to_string()),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.ret, 0);
+        let forwarded = captured_query.lock().unwrap().clone().unwrap();
+        assert_eq!(forwarded.fields.len(), 2);
+        assert_eq!(
+            forwarded.fields.get("request_id").map(String::as_str),
+            Some("r-1")
+        );
+        assert_eq!(
+            forwarded.fields.get("app_id").map(String::as_str),
+            Some("app=2")
+        );
+
+        for bad in ["request_id", "=r-1", "request_id=a,request_id=b"] {
+            let (status, body) = handle_query_logs(
+                storage.clone(),
+                LogQueryHttpRequest {
+                    fields: Some(bad.to_string()),
+                    ..Default::default()
+                },
+            )
+            .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert!(body.message.contains("invalid fields"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_query_logs_rejects_invalid_regex() {
+        let (storage, _) = make_storage(Ok(()), Ok(vec![]));
+        let (status, body) = handle_query_logs(
+            storage,
+            LogQueryHttpRequest {
+                regex: Some("(unclosed".to_string()),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body.ret, 1);
+        assert!(body.message.contains("invalid regex"));
+    }
+
+    #[tokio::test]
+    async fn test_handle_aggregate_logs_forwards_filters_and_group_by() {
+        let (storage, captured_query) = make_storage(Ok(()), Ok(vec![]));
+        let (status, body) = handle_aggregate_logs(
+            storage.clone(),
+            LogAggregateHttpRequest {
+                level: Some("error".to_string()),
+                start_time: Some(100),
+                end_time: Some(2000),
+                bucket_secs: Some(3600),
+                group_by: Some("service, level,service".to_string()),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        let data = body.0.data.expect("aggregate data should exist");
+        assert_eq!(data.bucket_secs, Some(3600));
+        assert_eq!(
+            data.group_by,
+            vec![AggregateGroupBy::Service, AggregateGroupBy::Level]
+        );
+        assert_eq!(data.buckets.len(), 1);
+        assert_eq!(data.buckets[0].count, 7);
+        assert!(!data.truncated);
+
+        let forwarded = captured_query.lock().unwrap().clone().unwrap();
+        assert_eq!(forwarded.level, Some(LogLevel::Error));
+        assert_eq!(forwarded.start_time, Some(100));
+        assert_eq!(forwarded.end_time, Some(2000));
+        assert_eq!(forwarded.limit, None);
+
+        let (status, body) = handle_aggregate_logs(
+            storage,
+            LogAggregateHttpRequest {
+                group_by: Some("file".to_string()),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.message.contains("invalid group_by"));
+    }
+
+    #[tokio::test]
+    async fn test_handle_tail_logs_rejects_invalid_cursor() {
+        let tail = Arc::new(LogTailHub::new(16));
+        let response = handle_tail_logs(
+            tail.clone(),
+            LogTailHttpRequest::default(),
+            Some("not-a-cursor".to_string()),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = handle_tail_logs(
+            tail,
+            LogTailHttpRequest {
+                level: Some("warn".to_string()),
+                ..Default::default()
+            },
+            None,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_handle_storage_stats_returns_500_when_not_supported() {
+        let (storage, _) = make_storage(Ok(()), Ok(vec![]));
+        let (status, body) = handle_storage_stats(storage).await;
+
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body.ret, 1);
+        assert!(body.data.is_none());
+        assert!(body.message.contains("not supported"));
+    }
+
     #[tokio::test]
     async fn test_handle_query_logs_returns_500_when_storage_query_fails() {
         let (storage, _) = make_storage(Ok(()), Err("db query failed".to_string()));
@@ -0,0 +1,243 @@
+use super::storage::LogQueryRequest;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AggregateGroupBy {
+    Node,
+    Service,
+    Level,
+    Target,
+}
+
+impl AggregateGroupBy {
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "node" => Some(Self::Node),
+            "service" => Some(Self::Service),
+            "level" => Some(Self::Level),
+            "target" => Some(Self::Target),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Node => "node",
+            Self::Service => "service",
+            Self::Level => "level",
+            Self::Target => "target",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
+pub struct LogAggregateRequest {
+    // Same filters as the query, the limit is ignored
+    pub filter: LogQueryRequest,
+    // Width of the time buckets in seconds, None counts the whole range as one bucket
+    pub bucket_secs: Option<u64>,
+    pub group_by: Vec<AggregateGroupBy>,
+    // Keep only the top N groups by count in every bucket
+    pub top: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogAggregateBucket {
+    // Start of the time bucket in millis, None if not bucketed by time
+    pub bucket_start: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<slog::LogLevel>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub count: u64,
+}
+
+type AggregateKey = (
+    Option<u64>,
+    Option<String>,
+    Option<String>,
+    Option<slog::LogLevel>,
+    Option<String>,
+);
+
+// Column names of the grouped fields in the sql of a storage
+pub(crate) struct AggregateColumns {
+    pub node: &'static str,
+    pub service: &'static str,
+    pub level: &'static str,
+    pub target: &'static str,
+    pub timestamp: &'static str,
+}
+
+impl AggregateColumns {
+    fn column(&self, group_by: AggregateGroupBy) -> &'static str {
+        match group_by {
+            AggregateGroupBy::Node => self.node,
+            AggregateGroupBy::Service => self.service,
+            AggregateGroupBy::Level => self.level,
+            AggregateGroupBy::Target => self.target,
+        }
+    }
+
+    // `SELECT <bucket>, <group columns...>, COUNT(*)`, the FROM and WHERE parts are added by the storage.
+    // Timestamps may be stored in seconds or millis, the buckets are always in millis.
+    pub fn select_sql(&self, request: &LogAggregateRequest) -> String {
+        let bucket = match request.bucket_secs {
+            Some(secs) => {
+                let bucket_millis = secs.saturating_mul(1000).max(1);
+                format!(
+                    "((CASE WHEN {0} < 10000000000 THEN {0} * 1000 ELSE {0} END) / {1}) * {1}",
+                    self.timestamp, bucket_millis
+                )
+            }
+            None => "NULL".to_string(),
+        };
+
+        let mut columns = vec![bucket];
+        for group_by in &request.group_by {
+            columns.push(self.column(*group_by).to_string());
+        }
+        format!("SELECT {}, COUNT(*)", columns.join(", "))
+    }
+
+    // `GROUP BY 1, 2, ...` matching the select columns
+    pub fn group_by_sql(&self, request: &LogAggregateRequest) -> String {
+        let positions: Vec<String> = (1..=request.group_by.len() + 1)
+            .map(|i| i.to_string())
+            .collect();
+        format!(" GROUP BY {}", positions.join(", "))
+    }
+}
+
+pub(crate) fn read_aggregate_row(
+    row: &rusqlite::Row<'_>,
+    group_by: &[AggregateGroupBy],
+) -> rusqlite::Result<Result<LogAggregateBucket, String>> {
+    let mut bucket = LogAggregateBucket {
+        bucket_start: row.get::<_, Option<i64>>(0)?.map(|v| v.max(0) as u64),
+        node: None,
+        service: None,
+        level: None,
+        target: None,
+        count: 0,
+    };
+
+    for (i, item) in group_by.iter().enumerate() {
+        let idx = i + 1;
+        match item {
+            AggregateGroupBy::Node => bucket.node = Some(row.get(idx)?),
+            AggregateGroupBy::Service => bucket.service = Some(row.get(idx)?),
+            AggregateGroupBy::Target => bucket.target = Some(row.get(idx)?),
+            AggregateGroupBy::Level => {
+                let level = row.get::<_, i32>(idx)? as u32;
+                match slog::LogLevel::try_from(level) {
+                    Ok(level) => bucket.level = Some(level),
+                    Err(e) => return Ok(Err(e)),
+                }
+            }
+        }
+    }
+    bucket.count = row.get::<_, i64>(group_by.len() + 1)?.max(0) as u64;
+
+    Ok(Ok(bucket))
+}
+
+// Sum the counts of the same bucket and group from different partitions, then sort by
+// bucket ascending and count descending, keeping the top N groups of every bucket
+pub(crate) fn merge_aggregate_buckets(
+    buckets: Vec<LogAggregateBucket>,
+    top: Option<usize>,
+) -> Vec<LogAggregateBucket> {
+    let mut merged: BTreeMap<AggregateKey, u64> = BTreeMap::new();
+    for bucket in buckets {
+        let key = (
+            bucket.bucket_start,
+            bucket.node,
+            bucket.service,
+            bucket.level,
+            bucket.target,
+        );
+        *merged.entry(key).or_default() += bucket.count;
+    }
+
+    let mut ret: Vec<LogAggregateBucket> = merged
+        .into_iter()
+        .map(
+            |((bucket_start, node, service, level, target), count)| LogAggregateBucket {
+                bucket_start,
+                node,
+                service,
+                level,
+                target,
+                count,
+            },
+        )
+        .collect();
+    ret.sort_by(|a, b| {
+        a.bucket_start
+            .cmp(&b.bucket_start)
+            .then_with(|| b.count.cmp(&a.count))
+    });
+
+    if let Some(top) = top {
+        let mut kept_in_bucket: BTreeMap<Option<u64>, usize> = BTreeMap::new();
+        ret.retain(|bucket| {
+            let kept = kept_in_bucket.entry(bucket.bucket_start).or_default();
+            *kept += 1;
+            *kept <= top
+        });
+    }
+
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::LogLevel;
+
+    fn bucket(start: u64, service: &str, level: LogLevel, count: u64) -> LogAggregateBucket {
+        LogAggregateBucket {
+            bucket_start: Some(start),
+            node: None,
+            service: Some(service.to_string()),
+            level: Some(level),
+            target: None,
+            count,
+        }
+    }
+
+    #[test]
+    fn test_merge_aggregate_buckets_sums_sorts_and_keeps_top() {
+        let merged = merge_aggregate_buckets(
+            vec![
+                bucket(2000, "svc-a", LogLevel::Error, 1),
+                bucket(1000, "svc-a", LogLevel::Error, 2),
+                bucket(1000, "svc-b", LogLevel::Error, 4),
+                bucket(1000, "svc-a", LogLevel::Error, 3),
+                bucket(1000, "svc-c", LogLevel::Error, 1),
+            ],
+            Some(2),
+        );
+
+        assert_eq!(
+            merged,
+            vec![
+                bucket(1000, "svc-a", LogLevel::Error, 5),
+                bucket(1000, "svc-b", LogLevel::Error, 4),
+                bucket(2000, "svc-a", LogLevel::Error, 1),
+            ]
+        );
+        assert_eq!(
+            AggregateGroupBy::parse(" Level "),
+            Some(AggregateGroupBy::Level)
+        );
+        assert_eq!(AggregateGroupBy::parse("file"), None);
+    }
+}
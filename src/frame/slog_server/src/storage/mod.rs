@@ -1,7 +1,12 @@
+mod aggregate;
+mod retention;
+mod search;
 mod sqlite;
 mod sqlite_partitioned;
 mod storage;
 
+pub use aggregate::{AggregateGroupBy, LogAggregateBucket, LogAggregateRequest};
+pub use retention::*;
 pub use sqlite_partitioned::{PartitionBucket, SqlitePartitionedConfig};
 pub use storage::*;
 // pub use sqlite::*;
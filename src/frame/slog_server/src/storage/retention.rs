@@ -0,0 +1,167 @@
+use super::storage::LogStorageRef;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+// How many non-empty prune reports the storage keeps for the admin stats
+pub const MAX_PRUNE_HISTORY: usize = 32;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    // Logs older than this are pruned, None keeps them forever
+    pub max_age_secs: Option<u64>,
+    // The oldest partitions are dropped until the total size fits, None means unlimited
+    pub max_total_bytes: Option<u64>,
+    // Per-service max age, overrides `max_age_secs` for the logs of that service
+    #[serde(default)]
+    pub service_max_age_secs: BTreeMap<String, u64>,
+}
+
+impl RetentionPolicy {
+    pub fn is_disabled(&self) -> bool {
+        self.max_age_secs.is_none()
+            && self.max_total_bytes.is_none()
+            && self.service_max_age_secs.is_empty()
+    }
+
+    pub fn max_age_secs_for(&self, service: &str) -> Option<u64> {
+        self.service_max_age_secs
+            .get(service)
+            .copied()
+            .or(self.max_age_secs)
+    }
+
+    // The smallest max age of any service, rows newer than this never expire
+    pub fn min_max_age_secs(&self) -> Option<u64> {
+        self.service_max_age_secs
+            .values()
+            .copied()
+            .chain(self.max_age_secs)
+            .min()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PruneReason {
+    // Every log in the partition is older than the max age of its service
+    MaxAge,
+    // The total size of all partitions is over the limit
+    MaxTotalBytes,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PartitionInfo {
+    pub file_name: String,
+    pub bucket_key: String,
+    pub part_seq: i64,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub row_count: u64,
+    pub size_bytes: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PrunedPartition {
+    pub partition: PartitionInfo,
+    pub reason: PruneReason,
+}
+
+// Expired rows removed from a partition that is still kept for other services
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrimmedServiceLogs {
+    pub file_name: String,
+    pub service: String,
+    pub removed_rows: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PruneReport {
+    // Unix time in seconds
+    pub run_at: u64,
+    pub policy: RetentionPolicy,
+    pub dropped: Vec<PrunedPartition>,
+    pub trimmed: Vec<TrimmedServiceLogs>,
+    pub freed_bytes: u64,
+    pub total_bytes_after: u64,
+}
+
+impl PruneReport {
+    pub fn is_empty(&self) -> bool {
+        self.dropped.is_empty() && self.trimmed.is_empty()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StorageStats {
+    pub partitions: Vec<PartitionInfo>,
+    pub total_rows: u64,
+    pub total_bytes: u64,
+    // Unix time in seconds of the last prune run, None if never run
+    pub last_prune_at: Option<u64>,
+    // The most recent prune runs which removed something, newest first
+    pub recent_prunes: Vec<PruneReport>,
+}
+
+// Enforce the retention policy on the storage every `interval`, the first run is immediate
+pub fn start_retention_task(storage: LogStorageRef, policy: RetentionPolicy, interval: Duration) {
+    if policy.is_disabled() {
+        info!("Log retention is disabled, skip the retention task");
+        return;
+    }
+
+    info!(
+        "Starting log retention task: policy={:?}, interval={:?}",
+        policy, interval
+    );
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            match storage.prune(&policy).await {
+                Ok(report) if report.is_empty() => {
+                    debug!("Log retention run finished, nothing to prune");
+                }
+                Ok(report) => {
+                    info!(
+                        "Log retention run finished: dropped_partitions={}, trimmed={}, freed_bytes={}, total_bytes_after={}",
+                        report.dropped.len(),
+                        report.trimmed.len(),
+                        report.freed_bytes,
+                        report.total_bytes_after
+                    );
+                }
+                Err(e) => {
+                    error!("Log retention run failed: {}", e);
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retention_policy_service_override() {
+        let mut policy = RetentionPolicy {
+            max_age_secs: Some(3600),
+            ..Default::default()
+        };
+        policy.service_max_age_secs.insert("svc-a".to_string(), 60);
+        policy
+            .service_max_age_secs
+            .insert("svc-b".to_string(), 7200);
+
+        assert!(!policy.is_disabled());
+        assert_eq!(policy.max_age_secs_for("svc-a"), Some(60));
+        assert_eq!(policy.max_age_secs_for("svc-b"), Some(7200));
+        assert_eq!(policy.max_age_secs_for("svc-c"), Some(3600));
+        assert_eq!(policy.min_max_age_secs(), Some(60));
+
+        assert!(RetentionPolicy::default().is_disabled());
+        assert_eq!(RetentionPolicy::default().min_max_age_secs(), None);
+    }
+}
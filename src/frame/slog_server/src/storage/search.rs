@@ -0,0 +1,236 @@
+use super::storage::LogQueryRequest;
+use regex::Regex;
+use rusqlite::Connection;
+use rusqlite::functions::FunctionFlags;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+// The trigram tokenizer makes MATCH a case-insensitive substring search,
+// but it can only match keywords with at least 3 characters.
+const FTS_MIN_KEYWORD_CHARS: usize = 3;
+
+// External content FTS5 index over logs.content, kept in sync by triggers.
+// Both storages use a `logs` table with `log_id` as the rowid and a `content` column.
+const LOGS_FTS_SCHEMA: &str = "
+    CREATE VIRTUAL TABLE IF NOT EXISTS logs_fts USING fts5(
+        content,
+        content = 'logs',
+        content_rowid = 'log_id',
+        tokenize = 'trigram'
+    );
+
+    CREATE TRIGGER IF NOT EXISTS logs_fts_insert AFTER INSERT ON logs BEGIN
+        INSERT INTO logs_fts (rowid, content) VALUES (new.log_id, new.content);
+    END;
+
+    CREATE TRIGGER IF NOT EXISTS logs_fts_delete AFTER DELETE ON logs BEGIN
+        INSERT INTO logs_fts (logs_fts, rowid, content) VALUES ('delete', old.log_id, old.content);
+    END;
+
+    CREATE TRIGGER IF NOT EXISTS logs_fts_update AFTER UPDATE OF content ON logs BEGIN
+        INSERT INTO logs_fts (logs_fts, rowid, content) VALUES ('delete', old.log_id, old.content);
+        INSERT INTO logs_fts (rowid, content) VALUES (new.log_id, new.content);
+    END;";
+
+// The structured fields are stored as a json object in logs.fields, and every key-value
+// pair is copied into log_fields by triggers, so `key = value` filters use an index.
+const LOG_FIELDS_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS log_fields (
+        log_id  INTEGER NOT NULL,
+        key     TEXT NOT NULL,
+        value   TEXT
+    );
+
+    CREATE INDEX IF NOT EXISTS idx_log_fields_key_value
+    ON log_fields (key, value);
+
+    CREATE INDEX IF NOT EXISTS idx_log_fields_log_id
+    ON log_fields (log_id);
+
+    CREATE TRIGGER IF NOT EXISTS log_fields_insert AFTER INSERT ON logs
+    WHEN new.fields IS NOT NULL BEGIN
+        INSERT INTO log_fields (log_id, key, value)
+        SELECT new.log_id, key, value FROM json_each(new.fields);
+    END;
+
+    CREATE TRIGGER IF NOT EXISTS log_fields_delete AFTER DELETE ON logs
+    WHEN old.fields IS NOT NULL BEGIN
+        DELETE FROM log_fields WHERE log_id = old.log_id;
+    END;
+
+    CREATE TRIGGER IF NOT EXISTS log_fields_update AFTER UPDATE OF fields ON logs BEGIN
+        DELETE FROM log_fields WHERE log_id = old.log_id;
+        INSERT INTO log_fields (log_id, key, value)
+        SELECT new.log_id, key, value FROM json_each(new.fields) WHERE new.fields IS NOT NULL;
+    END;";
+
+// Add the fields column to databases created before it existed, and create its index.
+// Old rows have no fields, so there is nothing to backfill.
+pub(crate) fn ensure_fields_index(conn: &Connection) -> Result<(), String> {
+    let has_column: bool = conn
+        .query_row(
+            "SELECT EXISTS (SELECT 1 FROM pragma_table_info('logs') WHERE name = 'fields')",
+            [],
+            |row| row.get(0),
+        )
+        .map_err(|e| {
+            let msg = format!("Failed to check logs fields column: {}", e);
+            error!("{}", msg);
+            msg
+        })?;
+
+    if !has_column {
+        conn.execute_batch("ALTER TABLE logs ADD COLUMN fields TEXT;")
+            .map_err(|e| {
+                let msg = format!("Failed to add logs fields column: {}", e);
+                error!("{}", msg);
+                msg
+            })?;
+    }
+
+    conn.execute_batch(LOG_FIELDS_SCHEMA).map_err(|e| {
+        let msg = format!("Failed to create log fields index: {}", e);
+        error!("{}", msg);
+        msg
+    })
+}
+
+// None for records without fields, so the column stays NULL and the trigger is skipped
+pub(crate) fn fields_to_json(fields: &BTreeMap<String, String>) -> Option<String> {
+    if fields.is_empty() {
+        None
+    } else {
+        serde_json::to_string(fields).ok()
+    }
+}
+
+pub(crate) fn fields_from_json(value: Option<String>) -> Result<BTreeMap<String, String>, String> {
+    match value {
+        Some(value) => serde_json::from_str(&value)
+            .map_err(|e| format!("invalid log fields json '{}': {}", value, e)),
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+pub(crate) fn ensure_fts_index(conn: &Connection) -> Result<(), String> {
+    let exists: bool = conn
+        .query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'logs_fts')",
+            [],
+            |row| row.get(0),
+        )
+        .map_err(|e| {
+            let msg = format!("Failed to check logs fts index: {}", e);
+            error!("{}", msg);
+            msg
+        })?;
+
+    conn.execute_batch(LOGS_FTS_SCHEMA).map_err(|e| {
+        let msg = format!("Failed to create logs fts index: {}", e);
+        error!("{}", msg);
+        msg
+    })?;
+
+    // Databases created before the index existed need their rows indexed once
+    if !exists {
+        conn.execute_batch("INSERT INTO logs_fts (logs_fts) VALUES ('rebuild');")
+            .map_err(|e| {
+                let msg = format!("Failed to rebuild logs fts index: {}", e);
+                error!("{}", msg);
+                msg
+            })?;
+        info!("Built logs fts index for existing rows");
+    }
+
+    Ok(())
+}
+
+// SQLite has the REGEXP operator but no implementation, provide one backed by the regex crate.
+// The compiled pattern is cached by sqlite for the whole statement.
+pub(crate) fn register_regexp_function(conn: &Connection) -> Result<(), String> {
+    conn.create_scalar_function(
+        "regexp",
+        2,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let regex: Arc<Regex> = ctx.get_or_create_aux(0, |value| -> Result<_, BoxError> {
+                Ok(Regex::new(value.as_str()?)?)
+            })?;
+            let text = ctx.get::<Option<String>>(1)?;
+            Ok(text.is_some_and(|text| regex.is_match(&text)))
+        },
+    )
+    .map_err(|e| {
+        let msg = format!("Failed to register regexp function: {}", e);
+        error!("{}", msg);
+        msg
+    })
+}
+
+// Quote the keyword as a single fts5 phrase, so operators in user input are matched literally
+fn fts_phrase(keyword: &str) -> String {
+    format!("\"{}\"", keyword.replace('"', "\"\""))
+}
+
+// Append the keyword/regex/target/file/line/fields conditions of the request.
+// `column_prefix` is the table alias of the logs table in the query, like "l." or "".
+pub(crate) fn push_search_filters(
+    query: &mut String,
+    params: &mut Vec<Box<dyn rusqlite::ToSql>>,
+    request: &LogQueryRequest,
+    column_prefix: &str,
+) {
+    if let Some(keyword) = &request.keyword {
+        if keyword.chars().count() >= FTS_MIN_KEYWORD_CHARS {
+            query.push_str(&format!(
+                " AND {}log_id IN (SELECT rowid FROM logs_fts WHERE logs_fts MATCH ?) ",
+                column_prefix
+            ));
+            params.push(Box::new(fts_phrase(keyword)));
+        } else {
+            query.push_str(&format!(
+                " AND instr(lower({}content), lower(?)) > 0 ",
+                column_prefix
+            ));
+            params.push(Box::new(keyword.clone()));
+        }
+    }
+    if let Some(target) = &request.target {
+        let prefix = format!("{}::", target);
+        query.push_str(&format!(
+            " AND ({0}target = ? OR substr({0}target, 1, ?) = ?) ",
+            column_prefix
+        ));
+        params.push(Box::new(target.clone()));
+        params.push(Box::new(prefix.chars().count() as i64));
+        params.push(Box::new(prefix));
+    }
+    if let Some(file) = &request.file {
+        let suffix = format!("/{}", file);
+        query.push_str(&format!(
+            " AND ({0}file = ? OR substr({0}file, -?) = ?) ",
+            column_prefix
+        ));
+        params.push(Box::new(file.clone()));
+        params.push(Box::new(suffix.chars().count() as i64));
+        params.push(Box::new(suffix));
+    }
+    if let Some(line) = request.line {
+        query.push_str(&format!(" AND {}line = ? ", column_prefix));
+        params.push(Box::new(line as i64));
+    }
+    for (key, value) in &request.fields {
+        query.push_str(&format!(
+            " AND {}log_id IN (SELECT log_id FROM log_fields WHERE key = ? AND value = ?) ",
+            column_prefix
+        ));
+        params.push(Box::new(key.clone()));
+        params.push(Box::new(value.clone()));
+    }
+    if let Some(regex) = &request.regex {
+        query.push_str(&format!(" AND {}content REGEXP ? ", column_prefix));
+        params.push(Box::new(regex.clone()));
+    }
+}
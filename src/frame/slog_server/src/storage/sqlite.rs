@@ -1,3 +1,8 @@
+use super::aggregate::{
+    AggregateColumns, LogAggregateBucket, LogAggregateRequest, merge_aggregate_buckets,
+    read_aggregate_row,
+};
+use super::search;
 use super::storage::{LogQueryRequest, LogRecords, LogStorage};
 use rusqlite::Connection;
 use slog::SystemLogRecord;
@@ -11,6 +16,7 @@ struct SystemLogRecordResult {
     pub file: Option<String>,
     pub line: Option<u32>,
     pub content: String,
+    pub fields: Option<String>,
 }
 
 impl TryInto<SystemLogRecord> for SystemLogRecordResult {
@@ -24,6 +30,7 @@ impl TryInto<SystemLogRecord> for SystemLogRecordResult {
             file: self.file,
             line: self.line,
             content: self.content,
+            fields: search::fields_from_json(self.fields)?,
         })
     }
 }
@@ -129,6 +136,11 @@ impl SqliteLogStorage {
             msg
         })?;
 
+        // Full text index on content for keyword search, and REGEXP for regex search
+        search::ensure_fts_index(&conn)?;
+        search::ensure_fields_index(&conn)?;
+        search::register_regexp_function(&conn)?;
+
         info!("Initialized SQLite log storage at {:?}", db_path);
 
         let ret = Self {
@@ -182,8 +194,8 @@ impl SqliteLogStorage {
         let mut log_insert_stmt = tx
             .prepare(
                 "INSERT OR IGNORE INTO logs (
-                    source_fk, timestamp, level, target, file, line, content, batch_id, record_index, record_id
-                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10);",
+                    source_fk, timestamp, level, target, file, line, content, batch_id, record_index, record_id, fields
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11);",
             )
             .map_err(|e| {
                 let msg = format!("Failed to prepare log insert statement: {}", e);
@@ -211,6 +223,7 @@ impl SqliteLogStorage {
         // Insert log record list
         for (record_index, record) in logs.into_iter().enumerate() {
             let record_id = record_ids.get(record_index).map(|s| s.as_str());
+            let fields = search::fields_to_json(&record.fields);
             log_insert_stmt
                 .execute(rusqlite::params![
                     source_id,
@@ -223,6 +236,7 @@ impl SqliteLogStorage {
                     batch_id.as_deref(),
                     record_index as i64,
                     record_id,
+                    fields,
                 ])
                 .map_err(|e| {
                     let msg = format!("Failed to insert log record: {}", e);
@@ -245,25 +259,18 @@ impl SqliteLogStorage {
         Ok(())
     }
 
-    fn query(&self, request: LogQueryRequest) -> Result<Vec<LogRecords>, String> {
-        let conn_lock = self.conn.lock().unwrap();
-
-        // Build the query dynamically based on the request parameters
-        let mut query = String::from(
-            "SELECT ls.node_id, ls.service_name, l.timestamp, l.level, l.target, l.file, l.line, l.content
-             FROM logs l
-             JOIN log_sources ls ON l.source_fk = ls.source_id
-             WHERE 1=1",
-        );
-        let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
-
-        if let Some(node) = request.node {
+    fn push_query_filters(
+        query: &mut String,
+        params: &mut Vec<Box<dyn rusqlite::ToSql>>,
+        request: &LogQueryRequest,
+    ) {
+        if let Some(node) = &request.node {
             query.push_str(" AND ls.node_id = ? ");
-            params.push(Box::new(node));
+            params.push(Box::new(node.clone()));
         }
-        if let Some(service) = request.service {
+        if let Some(service) = &request.service {
             query.push_str(" AND ls.service_name = ? ");
-            params.push(Box::new(service));
+            params.push(Box::new(service.clone()));
         }
         if let Some(level) = request.level {
             query.push_str(" AND l.level = ? ");
@@ -277,6 +284,73 @@ impl SqliteLogStorage {
             query.push_str(" AND l.timestamp <= ? ");
             params.push(Box::new(end_time as i64));
         }
+        search::push_search_filters(query, params, request, "l.");
+    }
+
+    fn aggregate(&self, request: LogAggregateRequest) -> Result<Vec<LogAggregateBucket>, String> {
+        let conn_lock = self.conn.lock().unwrap();
+
+        let columns = AggregateColumns {
+            node: "ls.node_id",
+            service: "ls.service_name",
+            level: "l.level",
+            target: "l.target",
+            timestamp: "l.timestamp",
+        };
+        let mut query = columns.select_sql(&request);
+        query.push_str(
+            " FROM logs l
+             JOIN log_sources ls ON l.source_fk = ls.source_id
+             WHERE 1=1",
+        );
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+        Self::push_query_filters(&mut query, &mut params, &request.filter);
+        query.push_str(&columns.group_by_sql(&request));
+
+        let mut stmt = conn_lock.prepare(&query).map_err(|e| {
+            let msg = format!("Failed to prepare log aggregate statement: {}", e);
+            error!("{}", msg);
+            msg
+        })?;
+        let rows = stmt
+            .query_map(
+                rusqlite::params_from_iter(params.iter().map(|p| &**p)),
+                |row| read_aggregate_row(row, &request.group_by),
+            )
+            .map_err(|e| {
+                let msg = format!("Failed to execute log aggregate query: {}", e);
+                error!("{}", msg);
+                msg
+            })?;
+
+        let mut buckets = Vec::new();
+        for row in rows {
+            let bucket = row.map_err(|e| {
+                let msg = format!("Failed to map log aggregate row: {}", e);
+                error!("{}", msg);
+                msg
+            })?;
+            match bucket {
+                Ok(bucket) => buckets.push(bucket),
+                Err(e) => warn!("Failed to convert aggregated log row: {}", e),
+            }
+        }
+
+        Ok(merge_aggregate_buckets(buckets, request.top))
+    }
+
+    fn query(&self, request: LogQueryRequest) -> Result<Vec<LogRecords>, String> {
+        let conn_lock = self.conn.lock().unwrap();
+
+        // Build the query dynamically based on the request parameters
+        let mut query = String::from(
+            "SELECT ls.node_id, ls.service_name, l.timestamp, l.level, l.target, l.file, l.line, l.content, l.fields
+             FROM logs l
+             JOIN log_sources ls ON l.source_fk = ls.source_id
+             WHERE 1=1",
+        );
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+        Self::push_query_filters(&mut query, &mut params, &request);
 
         query.push_str(" ORDER BY l.timestamp DESC ");
 
@@ -305,6 +379,7 @@ impl SqliteLogStorage {
                             file: row.get::<_, Option<String>>(5)?,
                             line: row.get::<_, Option<i64>>(6)?.map(|v| v as u32),
                             content: row.get::<_, String>(7)?,
+                            fields: row.get::<_, Option<String>>(8)?,
                         },
                     ))
                 },
@@ -359,11 +434,19 @@ impl LogStorage for SqliteLogStorage {
     async fn query_logs(&self, request: LogQueryRequest) -> Result<Vec<LogRecords>, String> {
         self.query(request)
     }
+
+    async fn aggregate_logs(
+        &self,
+        request: LogAggregateRequest,
+    ) -> Result<Vec<LogAggregateBucket>, String> {
+        self.aggregate(request)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::AggregateGroupBy;
     use slog::{LogLevel, SystemLogRecord};
     use std::collections::HashMap;
     use std::time::{SystemTime, UNIX_EPOCH};
@@ -391,6 +474,7 @@ mod tests {
             file: None,
             line: None,
             content: content.to_string(),
+            fields: Default::default(),
         }
     }
 
@@ -440,6 +524,7 @@ mod tests {
                 start_time: None,
                 end_time: None,
                 limit: None,
+                ..Default::default()
             })
             .unwrap();
         assert_eq!(all.len(), 2);
@@ -452,6 +537,7 @@ mod tests {
                 start_time: None,
                 end_time: None,
                 limit: Some(10),
+                ..Default::default()
             })
             .unwrap();
         assert_eq!(only_error.len(), 1);
@@ -491,6 +577,7 @@ mod tests {
                 start_time: None,
                 end_time: None,
                 limit: None,
+                ..Default::default()
             })
             .unwrap();
         assert_eq!(queried.len(), 1);
@@ -531,6 +618,7 @@ mod tests {
                 start_time: None,
                 end_time: None,
                 limit: None,
+                ..Default::default()
             })
             .unwrap();
         assert_eq!(node_a.len(), 1);
@@ -547,6 +635,7 @@ mod tests {
                 start_time: None,
                 end_time: None,
                 limit: None,
+                ..Default::default()
             })
             .unwrap();
         assert_eq!(node_b.len(), 1);
@@ -563,6 +652,7 @@ mod tests {
                 start_time: None,
                 end_time: None,
                 limit: None,
+                ..Default::default()
             })
             .unwrap();
         assert_eq!(shared_service_all_nodes.len(), 2);
@@ -619,6 +709,7 @@ mod tests {
                 start_time: Some(3010),
                 end_time: Some(3020),
                 limit: None,
+                ..Default::default()
             })
             .unwrap();
         assert_eq!(in_range.len(), 1);
@@ -639,6 +730,7 @@ mod tests {
                 start_time: None,
                 end_time: None,
                 limit: Some(2),
+                ..Default::default()
             })
             .unwrap();
         assert_eq!(limited.len(), 1);
@@ -700,6 +792,7 @@ mod tests {
                 start_time: None,
                 end_time: None,
                 limit: None,
+                ..Default::default()
             })
             .unwrap();
         assert_eq!(q1.len(), 1);
@@ -713,6 +806,7 @@ mod tests {
                 start_time: None,
                 end_time: None,
                 limit: None,
+                ..Default::default()
             })
             .unwrap();
         assert_eq!(q2.len(), 1);
@@ -727,6 +821,7 @@ mod tests {
                 start_time: None,
                 end_time: None,
                 limit: None,
+                ..Default::default()
             })
             .unwrap();
         let q3_count: usize = q3.iter().map(|item| item.logs.len()).sum();
@@ -740,6 +835,7 @@ mod tests {
                 start_time: Some(1010),
                 end_time: Some(1040),
                 limit: None,
+                ..Default::default()
             })
             .unwrap();
         let q4_count: usize = q4.iter().map(|item| item.logs.len()).sum();
@@ -753,6 +849,7 @@ mod tests {
                 start_time: Some(1000),
                 end_time: Some(2000),
                 limit: Some(1),
+                ..Default::default()
             })
             .unwrap();
         assert_eq!(q5.len(), 1);
@@ -761,4 +858,283 @@ mod tests {
 
         cleanup_db_path(&db_path);
     }
+
+    #[test]
+    fn test_sqlite_storage_query_with_search_filters() {
+        let db_path = temp_db_path("search_filters");
+        let storage = SqliteLogStorage::open(&db_path).unwrap();
+
+        let record =
+            |target: &str, file: &str, line: u32, time: u64, content: &str| SystemLogRecord {
+                level: LogLevel::Info,
+                target: target.to_string(),
+                time,
+                file: Some(file.to_string()),
+                line: Some(line),
+                content: content.to_string(),
+                fields: Default::default(),
+            };
+        storage
+            .append(LogRecords {
+                node: "node-1".to_string(),
+                service: "svc-a".to_string(),
+                batch_id: Some("batch-search-1".to_string()),
+                record_ids: vec![],
+                logs: vec![
+                    record(
+                        "kmsg",
+                        "src/kmsg/server.rs",
+                        10,
+                        1000,
+                        "Connection REFUSED by peer 7",
+                    ),
+                    record(
+                        "kmsg::queue",
+                        "src/kmsg/queue.rs",
+                        20,
+                        1010,
+                        "queue compacted",
+                    ),
+                    record(
+                        "kmsg_ext",
+                        "src/ext/server.rs",
+                        10,
+                        1020,
+                        "connection refused by peer x",
+                    ),
+                    record("klog", "src/klog/myserver.rs", 30, 1030, "ok"),
+                ],
+            })
+            .unwrap();
+
+        let contents = |request: LogQueryRequest| -> Vec<String> {
+            let mut contents: Vec<String> = storage
+                .query(request)
+                .unwrap()
+                .into_iter()
+                .flat_map(|item| item.logs.into_iter().map(|log| log.content))
+                .collect();
+            contents.sort();
+            contents
+        };
+
+        // Case-insensitive substring through the fts index, and the short keyword fallback
+        let by_keyword = contents(LogQueryRequest {
+            keyword: Some("connection refused".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(by_keyword.len(), 2);
+        let by_short_keyword = contents(LogQueryRequest {
+            keyword: Some("OK".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(by_short_keyword, vec!["ok".to_string()]);
+
+        let by_regex = contents(LogQueryRequest {
+            regex: Some(r"peer \d+$".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(by_regex, vec!["Connection REFUSED by peer 7".to_string()]);
+
+        // Sub modules match, but targets sharing only the name prefix don't
+        let by_target = contents(LogQueryRequest {
+            target: Some("kmsg".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(
+            by_target,
+            vec![
+                "Connection REFUSED by peer 7".to_string(),
+                "queue compacted".to_string()
+            ]
+        );
+
+        let by_file_and_line = contents(LogQueryRequest {
+            file: Some("server.rs".to_string()),
+            line: Some(10),
+            ..Default::default()
+        });
+        assert_eq!(by_file_and_line.len(), 2);
+
+        // Databases created before the fts index existed are indexed on open
+        drop(storage);
+        {
+            let conn = Connection::open(&db_path).unwrap();
+            conn.execute_batch(
+                "DROP TRIGGER logs_fts_insert;
+                 DROP TRIGGER logs_fts_delete;
+                 DROP TRIGGER logs_fts_update;
+                 DROP TABLE logs_fts;",
+            )
+            .unwrap();
+        }
+        let storage = SqliteLogStorage::open(&db_path).unwrap();
+        let reopened = storage
+            .query(LogQueryRequest {
+                keyword: Some("compacted".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(reopened.len(), 1);
+        assert_eq!(reopened[0].logs[0].content, "queue compacted");
+
+        cleanup_db_path(&db_path);
+    }
+
+    #[test]
+    fn test_sqlite_storage_store_and_filter_by_fields() {
+        let db_path = temp_db_path("fields");
+        let storage = SqliteLogStorage::open(&db_path).unwrap();
+
+        let record = |time: u64, content: &str, fields: &[(&str, &str)]| SystemLogRecord {
+            fields: fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            ..sample_record(LogLevel::Info, time, content)
+        };
+        storage
+            .append(LogRecords {
+                node: "node-1".to_string(),
+                service: "svc-a".to_string(),
+                batch_id: Some("batch-fields-1".to_string()),
+                record_ids: vec![],
+                logs: vec![
+                    record(1000, "r1-app1", &[("request_id", "r1"), ("app_id", "app1")]),
+                    record(1010, "r2-app1", &[("request_id", "r2"), ("app_id", "app1")]),
+                    record(1020, "no-fields", &[]),
+                ],
+            })
+            .unwrap();
+
+        let query = |fields: &[(&str, &str)]| -> Vec<SystemLogRecord> {
+            let mut logs: Vec<SystemLogRecord> = storage
+                .query(LogQueryRequest {
+                    fields: fields
+                        .iter()
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .collect(),
+                    ..Default::default()
+                })
+                .unwrap()
+                .into_iter()
+                .flat_map(|item| item.logs)
+                .collect();
+            logs.sort_by_key(|log| log.time);
+            logs
+        };
+
+        // The fields are returned as stored, records without fields have none
+        let all = query(&[]);
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].field("request_id"), Some("r1"));
+        assert_eq!(all[0].field("app_id"), Some("app1"));
+        assert!(all[2].fields.is_empty());
+
+        let by_app = query(&[("app_id", "app1")]);
+        assert_eq!(by_app.len(), 2);
+        let by_app_and_request = query(&[("app_id", "app1"), ("request_id", "r2")]);
+        assert_eq!(by_app_and_request.len(), 1);
+        assert_eq!(by_app_and_request[0].content, "r2-app1");
+        assert!(query(&[("request_id", "app1")]).is_empty());
+
+        cleanup_db_path(&db_path);
+    }
+
+    #[test]
+    fn test_sqlite_storage_aggregate_by_service_and_level() {
+        let db_path = temp_db_path("aggregate");
+        let storage = SqliteLogStorage::open(&db_path).unwrap();
+
+        let record = |level: LogLevel, time: u64| SystemLogRecord {
+            level,
+            target: "test".to_string(),
+            time,
+            file: None,
+            line: None,
+            content: format!("log-{}", time),
+            fields: Default::default(),
+        };
+        let append = |service: &str, logs: Vec<SystemLogRecord>| {
+            storage
+                .append(LogRecords {
+                    node: "node-1".to_string(),
+                    service: service.to_string(),
+                    batch_id: None,
+                    record_ids: vec![],
+                    logs,
+                })
+                .unwrap();
+        };
+        // Mixed seconds and millis timestamps fall into the same millis buckets
+        append(
+            "svc-a",
+            vec![
+                record(LogLevel::Error, 1_721_001_600),
+                record(LogLevel::Error, 1_721_001_601_000),
+                record(LogLevel::Info, 1_721_001_700),
+                record(LogLevel::Error, 1_721_005_300),
+            ],
+        );
+        append("svc-b", vec![record(LogLevel::Error, 1_721_001_650_000)]);
+
+        let buckets = storage
+            .aggregate(LogAggregateRequest {
+                bucket_secs: Some(3600),
+                group_by: vec![AggregateGroupBy::Service, AggregateGroupBy::Level],
+                ..Default::default()
+            })
+            .unwrap();
+        let summary: Vec<_> = buckets
+            .into_iter()
+            .map(|b| (b.bucket_start, b.service, b.level, b.count))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    Some(1_721_001_600_000),
+                    Some("svc-a".to_string()),
+                    Some(LogLevel::Error),
+                    2
+                ),
+                (
+                    Some(1_721_001_600_000),
+                    Some("svc-a".to_string()),
+                    Some(LogLevel::Info),
+                    1
+                ),
+                (
+                    Some(1_721_001_600_000),
+                    Some("svc-b".to_string()),
+                    Some(LogLevel::Error),
+                    1
+                ),
+                (
+                    Some(1_721_005_200_000),
+                    Some("svc-a".to_string()),
+                    Some(LogLevel::Error),
+                    1
+                ),
+            ]
+        );
+
+        // Filters apply before counting, and no bucket counts the whole range
+        let total = storage
+            .aggregate(LogAggregateRequest {
+                filter: LogQueryRequest {
+                    level: Some(LogLevel::Error),
+                    ..Default::default()
+                },
+                group_by: vec![AggregateGroupBy::Node],
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(total.len(), 1);
+        assert_eq!(total[0].bucket_start, None);
+        assert_eq!(total[0].node.as_deref(), Some("node-1"));
+        assert_eq!(total[0].count, 4);
+
+        cleanup_db_path(&db_path);
+    }
 }
@@ -1,7 +1,16 @@
+use super::aggregate::{
+    AggregateColumns, LogAggregateBucket, LogAggregateRequest, merge_aggregate_buckets,
+    read_aggregate_row,
+};
+use super::retention::{
+    MAX_PRUNE_HISTORY, PartitionInfo, PruneReason, PruneReport, PrunedPartition, RetentionPolicy,
+    StorageStats, TrimmedServiceLogs,
+};
+use super::search;
 use super::storage::{LogQueryRequest, LogRecords, LogStorage};
-use rusqlite::{Connection, params};
+use rusqlite::{Connection, OpenFlags, params};
 use slog::SystemLogRecord;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
@@ -9,6 +18,8 @@ const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
 const SECOND_THRESHOLD: u64 = 10_000_000_000;
 const DEFAULT_PARTITION_MAX_ROWS: u64 = 5_000_000;
 const DEFAULT_PARTITION_MAX_SIZE_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+// Batch mappings of dropped partitions are kept this long, so a late replay of the batch is still skipped
+const DROPPED_BATCH_MAPPING_TTL_SECS: i64 = 7 * 24 * 3600;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum PartitionBucket {
@@ -87,6 +98,7 @@ struct SystemLogRecordResult {
     pub file: Option<String>,
     pub line: Option<u32>,
     pub content: String,
+    pub fields: Option<String>,
 }
 
 impl TryInto<SystemLogRecord> for SystemLogRecordResult {
@@ -100,14 +112,25 @@ impl TryInto<SystemLogRecord> for SystemLogRecordResult {
             file: self.file,
             line: self.line,
             content: self.content,
+            fields: search::fields_from_json(self.fields)?,
         })
     }
 }
 
+#[derive(Default)]
+struct PruneHistory {
+    last_run_at: Option<u64>,
+    // Newest first, only the runs which removed something
+    reports: VecDeque<PruneReport>,
+}
+
+// Cheap to clone, the clones share the manifest connection and the prune history
+#[derive(Clone)]
 pub struct SqlitePartitionedLogStorage {
     partitions_dir: PathBuf,
     manifest_conn: Arc<Mutex<Connection>>,
     config: SqlitePartitionedConfig,
+    prune_history: Arc<Mutex<PruneHistory>>,
 }
 
 impl SqlitePartitionedLogStorage {
@@ -189,6 +212,7 @@ impl SqlitePartitionedLogStorage {
             })?;
 
         Self::reconcile_manifest_with_partitions(&manifest_conn, &partitions_dir)?;
+        Self::migrate_partitions(&manifest_conn, &partitions_dir)?;
 
         info!(
             "Initialized sqlite partitioned storage at {}, bucket={}, max_rows_per_partition={}, max_partition_size_bytes={}",
@@ -202,6 +226,7 @@ impl SqlitePartitionedLogStorage {
             partitions_dir,
             manifest_conn: Arc::new(Mutex::new(manifest_conn)),
             config,
+            prune_history: Arc::new(Mutex::new(PruneHistory::default())),
         })
     }
 
@@ -241,6 +266,8 @@ impl SqlitePartitionedLogStorage {
             error!("{}", msg);
             msg
         })?;
+        search::ensure_fts_index(conn)?;
+        search::ensure_fields_index(conn)?;
         Ok(())
     }
 
@@ -268,6 +295,18 @@ impl SqlitePartitionedLogStorage {
         Ok(())
     }
 
+    // Bring partitions written by older versions to the current schema (fts and fields
+    // index), once at startup so the query path never has to run DDL
+    fn migrate_partitions(manifest: &Connection, partitions_dir: &Path) -> Result<(), String> {
+        for partition in Self::list_all_partitions(manifest)? {
+            let partition_path = partitions_dir.join(&partition.file_name);
+            if partition_path.exists() {
+                Self::ensure_partition_database(&partition_path)?;
+            }
+        }
+        Ok(())
+    }
+
     fn parse_partition_file_name(file_name: &str) -> Option<(String, i64)> {
         if !file_name.starts_with("logs_") || !file_name.ends_with(".db") {
             return None;
@@ -762,27 +801,6 @@ impl SqlitePartitionedLogStorage {
         Ok(())
     }
 
-    fn clear_batch_partition_mapping(
-        manifest: &Connection,
-        node: &str,
-        service: &str,
-        batch_id: &str,
-        bucket_key: &str,
-    ) -> Result<(), String> {
-        manifest
-            .execute(
-                "DELETE FROM batch_partition_map
-                 WHERE node_id = ?1 AND service_name = ?2 AND batch_id = ?3 AND bucket_key = ?4",
-                params![node, service, batch_id, bucket_key],
-            )
-            .map_err(|e| {
-                let msg = format!("Failed to delete stale batch partition mapping: {}", e);
-                error!("{}", msg);
-                msg
-            })?;
-        Ok(())
-    }
-
     fn resolve_target_partition(
         &self,
         manifest: &Connection,
@@ -794,7 +812,7 @@ impl SqlitePartitionedLogStorage {
         max_time: u64,
         incoming_rows: u64,
         incoming_bytes: u64,
-    ) -> Result<PartitionMeta, String> {
+    ) -> Result<Option<PartitionMeta>, String> {
         if let Some(batch_id) = batch_id {
             if let Some(mapped_file) =
                 Self::read_batch_partition_mapping(manifest, node, service, batch_id, bucket_key)?
@@ -802,14 +820,16 @@ impl SqlitePartitionedLogStorage {
                 if let Some(partition) =
                     Self::get_partition_by_file(manifest, bucket_key, &mapped_file)?
                 {
-                    return Ok(partition);
+                    return Ok(Some(partition));
                 }
 
-                warn!(
-                    "Stale batch partition mapping found for node={}, service={}, batch_id={}, bucket_key={}, file_name={}; will remap",
+                // Reconcile clears the mappings of lost partitions, so the batch was stored
+                // in a partition dropped by retention and must not be written again
+                debug!(
+                    "Skip replayed batch of dropped partition: node={}, service={}, batch_id={}, bucket_key={}, file_name={}",
                     node, service, batch_id, bucket_key, mapped_file
                 );
-                Self::clear_batch_partition_mapping(manifest, node, service, batch_id, bucket_key)?;
+                return Ok(None);
             }
         }
 
@@ -841,7 +861,7 @@ impl SqlitePartitionedLogStorage {
             )?;
         }
 
-        Ok(target)
+        Ok(Some(target))
     }
 
     fn append_records_to_partition(
@@ -875,8 +895,8 @@ impl SqlitePartitionedLogStorage {
         let mut stmt = tx
             .prepare(
                 "INSERT OR IGNORE INTO logs (
-                    node_id, service_name, timestamp, level, target, file, line, content, batch_id, record_index, record_id
-                 ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                    node_id, service_name, timestamp, level, target, file, line, content, batch_id, record_index, record_id, fields
+                 ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
             )
             .map_err(|e| {
                 let msg = format!("Failed to prepare partition log insert statement: {}", e);
@@ -899,6 +919,7 @@ impl SqlitePartitionedLogStorage {
                     batch_id,
                     item.record_index as i64,
                     item.record_id.as_deref(),
+                    search::fields_to_json(&item.record.fields),
                 ])
                 .map_err(|e| {
                     let msg = format!("Failed to append log row to partition db: {}", e);
@@ -1009,7 +1030,7 @@ impl SqlitePartitionedLogStorage {
             let min_time = records.iter().map(|r| r.record.time).min().unwrap_or(0);
             let max_time = records.iter().map(|r| r.record.time).max().unwrap_or(0);
 
-            let target_partition = self.resolve_target_partition(
+            let Some(target_partition) = self.resolve_target_partition(
                 manifest,
                 &node,
                 &service,
@@ -1019,7 +1040,10 @@ impl SqlitePartitionedLogStorage {
                 max_time,
                 incoming_rows,
                 incoming_bytes,
-            )?;
+            )?
+            else {
+                continue;
+            };
 
             let partition_path = self.partition_path(&target_partition.file_name);
             let inserted_rows = Self::append_records_to_partition(
@@ -1091,26 +1115,27 @@ impl SqlitePartitionedLogStorage {
         Ok(ret)
     }
 
-    fn query_single_partition(
+    // Open a partition for query, None if its file is gone
+    fn open_partition_for_read(
         &self,
         partition_file_name: &str,
-        node: Option<&str>,
-        service: Option<&str>,
-        level: Option<slog::LogLevel>,
-        start_time: Option<u64>,
-        end_time: Option<u64>,
-        limit: Option<usize>,
-    ) -> Result<Vec<(String, String, SystemLogRecord)>, String> {
+        request: &LogQueryRequest,
+    ) -> Result<Option<Connection>, String> {
         let partition_path = self.partition_path(partition_file_name);
         if !partition_path.exists() {
             warn!(
                 "Skipping missing partition database file during query: {}",
                 partition_path.display()
             );
-            return Ok(vec![]);
+            return Ok(None);
         }
 
-        let conn = Connection::open(&partition_path).map_err(|e| {
+        // The schema was migrated at startup, queries only read
+        let conn = Connection::open_with_flags(
+            &partition_path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .map_err(|e| {
             let msg = format!(
                 "Failed to open partition database {} for query: {}",
                 partition_path.display(),
@@ -1120,36 +1145,147 @@ impl SqlitePartitionedLogStorage {
             msg
         })?;
 
-        let mut query = String::from(
-            "SELECT node_id, service_name, timestamp, level, target, file, line, content
-             FROM logs
-             WHERE 1=1",
-        );
-        let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+        if request.regex.is_some() {
+            search::register_regexp_function(&conn)?;
+        }
+
+        Ok(Some(conn))
+    }
 
-        if let Some(node) = node {
+    fn push_query_filters(
+        query: &mut String,
+        params: &mut Vec<Box<dyn rusqlite::ToSql>>,
+        request: &LogQueryRequest,
+    ) {
+        if let Some(node) = &request.node {
             query.push_str(" AND node_id = ? ");
-            params.push(Box::new(node.to_string()));
+            params.push(Box::new(node.clone()));
         }
-        if let Some(service) = service {
+        if let Some(service) = &request.service {
             query.push_str(" AND service_name = ? ");
-            params.push(Box::new(service.to_string()));
+            params.push(Box::new(service.clone()));
         }
-        if let Some(level) = level {
+        if let Some(level) = request.level {
             query.push_str(" AND level = ? ");
             params.push(Box::new(level as i32));
         }
-        if let Some(start_time) = start_time {
+        if let Some(start_time) = request.start_time {
             query.push_str(" AND timestamp >= ? ");
             params.push(Box::new(start_time as i64));
         }
-        if let Some(end_time) = end_time {
+        if let Some(end_time) = request.end_time {
             query.push_str(" AND timestamp <= ? ");
             params.push(Box::new(end_time as i64));
         }
+        search::push_search_filters(query, params, request, "");
+    }
+
+    fn aggregate_single_partition(
+        &self,
+        partition_file_name: &str,
+        request: &LogAggregateRequest,
+    ) -> Result<Vec<LogAggregateBucket>, String> {
+        let Some(conn) = self.open_partition_for_read(partition_file_name, &request.filter)? else {
+            return Ok(vec![]);
+        };
+
+        let columns = AggregateColumns {
+            node: "node_id",
+            service: "service_name",
+            level: "level",
+            target: "target",
+            timestamp: "timestamp",
+        };
+        let mut query = columns.select_sql(request);
+        query.push_str(" FROM logs WHERE 1=1");
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+        Self::push_query_filters(&mut query, &mut params, &request.filter);
+        query.push_str(&columns.group_by_sql(request));
+
+        let mut stmt = conn.prepare(&query).map_err(|e| {
+            let msg = format!(
+                "Failed to prepare partition aggregate statement for {}: {}",
+                partition_file_name, e
+            );
+            error!("{}", msg);
+            msg
+        })?;
+        let rows = stmt
+            .query_map(
+                rusqlite::params_from_iter(params.iter().map(|p| &**p)),
+                |row| read_aggregate_row(row, &request.group_by),
+            )
+            .map_err(|e| {
+                let msg = format!(
+                    "Failed to execute partition aggregate query for {}: {}",
+                    partition_file_name, e
+                );
+                error!("{}", msg);
+                msg
+            })?;
+
+        let mut output = Vec::new();
+        for row in rows {
+            let bucket = row.map_err(|e| {
+                let msg = format!("Failed to map partition aggregate row: {}", e);
+                error!("{}", msg);
+                msg
+            })?;
+            match bucket {
+                Ok(bucket) => output.push(bucket),
+                Err(e) => warn!("Failed to convert aggregated log row: {}", e),
+            }
+        }
+
+        Ok(output)
+    }
+
+    fn aggregate(&self, request: LogAggregateRequest) -> Result<Vec<LogAggregateBucket>, String> {
+        let candidate_partitions = {
+            let manifest_lock = self.manifest_conn.lock().map_err(|e| {
+                let msg = format!(
+                    "Failed to lock partition manifest db mutex for aggregate: {}",
+                    e
+                );
+                error!("{}", msg);
+                msg
+            })?;
+            Self::list_candidate_partitions(
+                &manifest_lock,
+                request.filter.start_time,
+                request.filter.end_time,
+            )?
+        };
+
+        let mut buckets = Vec::new();
+        for partition in candidate_partitions {
+            let mut chunk = self.aggregate_single_partition(&partition.file_name, &request)?;
+            buckets.append(&mut chunk);
+        }
+
+        Ok(merge_aggregate_buckets(buckets, request.top))
+    }
+
+    fn query_single_partition(
+        &self,
+        partition_file_name: &str,
+        request: &LogQueryRequest,
+    ) -> Result<Vec<(String, String, SystemLogRecord)>, String> {
+        let partition_path = self.partition_path(partition_file_name);
+        let Some(conn) = self.open_partition_for_read(partition_file_name, request)? else {
+            return Ok(vec![]);
+        };
+
+        let mut query = String::from(
+            "SELECT node_id, service_name, timestamp, level, target, file, line, content, fields
+             FROM logs
+             WHERE 1=1",
+        );
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+        Self::push_query_filters(&mut query, &mut params, request);
 
         query.push_str(" ORDER BY timestamp DESC ");
-        if let Some(limit) = limit {
+        if let Some(limit) = request.limit {
             query.push_str(" LIMIT ? ");
             params.push(Box::new(limit as i64));
         }
@@ -1178,6 +1314,7 @@ impl SqlitePartitionedLogStorage {
                             file: row.get::<_, Option<String>>(5)?,
                             line: row.get::<_, Option<i64>>(6)?.map(|v| v as u32),
                             content: row.get::<_, String>(7)?,
+                            fields: row.get::<_, Option<String>>(8)?,
                         },
                     ))
                 },
@@ -1208,81 +1345,473 @@ impl SqlitePartitionedLogStorage {
         Ok(output)
     }
 
-    fn query(&self, request: LogQueryRequest) -> Result<Vec<LogRecords>, String> {
-        let LogQueryRequest {
-            node,
-            service,
-            level,
-            start_time,
-            end_time,
-            limit,
-        } = request;
+    // All partitions, the oldest first
+    fn list_all_partitions(manifest: &Connection) -> Result<Vec<PartitionInfo>, String> {
+        let mut stmt = manifest
+            .prepare(
+                "SELECT file_name, bucket_key, part_seq, start_time, end_time, row_count, size_bytes
+                 FROM partitions",
+            )
+            .map_err(|e| {
+                let msg = format!("Failed to prepare partitions list query: {}", e);
+                error!("{}", msg);
+                msg
+            })?;
 
-        let candidate_partitions = {
-            let manifest_lock = self.manifest_conn.lock().map_err(|e| {
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(PartitionInfo {
+                    file_name: row.get::<_, String>(0)?,
+                    bucket_key: row.get::<_, String>(1)?,
+                    part_seq: row.get::<_, i64>(2)?,
+                    start_time: row.get::<_, i64>(3)?.max(0) as u64,
+                    end_time: row.get::<_, i64>(4)?.max(0) as u64,
+                    row_count: row.get::<_, i64>(5)?.max(0) as u64,
+                    size_bytes: row.get::<_, i64>(6)?.max(0) as u64,
+                })
+            })
+            .map_err(|e| {
+                let msg = format!("Failed to execute partitions list query: {}", e);
+                error!("{}", msg);
+                msg
+            })?;
+
+        let mut ret = Vec::new();
+        for row in rows {
+            let info = row.map_err(|e| {
+                let msg = format!("Failed to map partition row: {}", e);
+                error!("{}", msg);
+                msg
+            })?;
+            ret.push(info);
+        }
+
+        // Timestamps may be stored in seconds or millis, compare them normalized
+        ret.sort_by_key(|p| (normalize_to_millis(p.end_time), p.part_seq));
+        Ok(ret)
+    }
+
+    // Remove the partition from the manifest, then delete its database files
+    fn drop_partition(&self, manifest: &Connection, file_name: &str) -> Result<(), String> {
+        manifest
+            .execute(
+                "DELETE FROM partitions WHERE file_name = ?1",
+                params![file_name],
+            )
+            .map_err(|e| {
                 let msg = format!(
-                    "Failed to lock partition manifest db mutex for query: {}",
+                    "Failed to delete partition manifest entry {}: {}",
+                    file_name, e
+                );
+                error!("{}", msg);
+                msg
+            })?;
+        // The batch mappings stay as tombstones, see prune_dropped_batch_mappings
+
+        let partition_path = self.partition_path(file_name);
+        for suffix in ["", "-wal", "-shm"] {
+            let path = PathBuf::from(format!("{}{}", partition_path.display(), suffix));
+            match std::fs::remove_file(&path) {
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    let msg = format!("Failed to delete partition file {}: {}", path.display(), e);
+                    error!("{}", msg);
+                    return Err(msg);
+                }
+            }
+        }
+
+        info!("Dropped log partition {}", file_name);
+        Ok(())
+    }
+
+    // Forget the batches of dropped partitions once their replay window has passed
+    fn prune_dropped_batch_mappings(manifest: &Connection, now_secs: i64) -> Result<(), String> {
+        manifest
+            .execute(
+                "DELETE FROM batch_partition_map
+                 WHERE created_at < ?1
+                   AND file_name NOT IN (SELECT file_name FROM partitions)",
+                params![now_secs.saturating_sub(DROPPED_BATCH_MAPPING_TTL_SECS)],
+            )
+            .map_err(|e| {
+                let msg = format!(
+                    "Failed to prune batch mappings of dropped partitions: {}",
                     e
                 );
                 error!("{}", msg);
                 msg
             })?;
-            Self::list_candidate_partitions(&manifest_lock, start_time, end_time)?
-        };
+        Ok(())
+    }
 
-        if candidate_partitions.is_empty() {
-            return Ok(vec![]);
-        }
+    // Delete the rows older than the max age of their service.
+    // Returns None if every row in the partition has expired, so the whole partition can be dropped.
+    fn expire_partition_logs(
+        &self,
+        file_name: &str,
+        policy: &RetentionPolicy,
+        now_millis: u64,
+    ) -> Result<Option<Vec<TrimmedServiceLogs>>, String> {
+        let partition_path = self.partition_path(file_name);
+        let conn = Connection::open(&partition_path).map_err(|e| {
+            let msg = format!(
+                "Failed to open partition database {} for retention: {}",
+                partition_path.display(),
+                e
+            );
+            error!("{}", msg);
+            msg
+        })?;
 
-        let mut rows: Vec<(String, String, SystemLogRecord)> = Vec::new();
-        for partition in candidate_partitions {
-            let mut chunk = self.query_single_partition(
-                &partition.file_name,
-                node.as_deref(),
-                service.as_deref(),
-                level,
-                start_time,
-                end_time,
-                limit,
-            )?;
-            rows.append(&mut chunk);
-        }
+        let normalized_time = format!(
+            "CASE WHEN timestamp < {0} THEN timestamp * 1000 ELSE timestamp END",
+            SECOND_THRESHOLD
+        );
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT service_name, MIN({0}), MAX({0}) FROM logs GROUP BY service_name",
+                normalized_time
+            ))
+            .map_err(|e| {
+                let msg = format!(
+                    "Failed to prepare partition service time range query: {}",
+                    e
+                );
+                error!("{}", msg);
+                msg
+            })?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?.max(0) as u64,
+                    row.get::<_, i64>(2)?.max(0) as u64,
+                ))
+            })
+            .map_err(|e| {
+                let msg = format!("Failed to query partition service time range: {}", e);
+                error!("{}", msg);
+                msg
+            })?;
 
-        rows.sort_by(|a, b| b.2.time.cmp(&a.2.time));
+        let mut has_rows = false;
+        let mut all_expired = true;
+        let mut expired_services = Vec::new();
+        for row in rows {
+            let (service, min_time, max_time) = row.map_err(|e| {
+                let msg = format!("Failed to map partition service time range row: {}", e);
+                error!("{}", msg);
+                msg
+            })?;
+            has_rows = true;
 
-        if let Some(limit) = limit {
-            rows.truncate(limit);
+            let Some(max_age_secs) = policy.max_age_secs_for(&service) else {
+                all_expired = false;
+                continue;
+            };
+            let cutoff = now_millis.saturating_sub(max_age_secs.saturating_mul(1000));
+            if max_time >= cutoff {
+                all_expired = false;
+            }
+            if min_time < cutoff {
+                expired_services.push((service, cutoff));
+            }
         }
+        drop(stmt);
 
-        let mut records_map: HashMap<(String, String), Vec<SystemLogRecord>> = HashMap::new();
-        for (node, service, record) in rows {
-            records_map.entry((node, service)).or_default().push(record);
+        if has_rows && all_expired {
+            return Ok(None);
         }
 
-        let mut result = Vec::new();
-        for ((node, service), logs) in records_map {
-            result.push(LogRecords {
-                node,
-                service,
-                batch_id: None,
-                record_ids: vec![],
-                logs,
-            });
+        let mut trimmed = Vec::new();
+        for (service, cutoff) in expired_services {
+            let removed_rows = conn
+                .execute(
+                    &format!(
+                        "DELETE FROM logs WHERE service_name = ?1 AND {} < ?2",
+                        normalized_time
+                    ),
+                    params![&service, cutoff as i64],
+                )
+                .map_err(|e| {
+                    let msg = format!(
+                        "Failed to delete expired logs of service {} from partition {}: {}",
+                        service, file_name, e
+                    );
+                    error!("{}", msg);
+                    msg
+                })?;
+            if removed_rows > 0 {
+                trimmed.push(TrimmedServiceLogs {
+                    file_name: file_name.to_string(),
+                    service,
+                    removed_rows: removed_rows as u64,
+                });
+            }
         }
-        Ok(result)
-    }
-}
 
-#[async_trait::async_trait]
-impl LogStorage for SqlitePartitionedLogStorage {
-    async fn append_logs(&self, logs: LogRecords) -> Result<(), String> {
-        self.append(logs)
-    }
+        if !trimmed.is_empty() {
+            Self::vacuum_partition(&conn, file_name)?;
+        }
 
-    async fn query_logs(&self, request: LogQueryRequest) -> Result<Vec<LogRecords>, String> {
-        self.query(request)
+        Ok(Some(trimmed))
     }
-}
+
+    // DELETE only frees pages inside the file, rebuild it and truncate the wal to give the space back
+    fn vacuum_partition(conn: &Connection, file_name: &str) -> Result<(), String> {
+        conn.execute_batch("VACUUM;").map_err(|e| {
+            let msg = format!("Failed to vacuum partition {}: {}", file_name, e);
+            error!("{}", msg);
+            msg
+        })?;
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+            .map_err(|e| {
+                let msg = format!("Failed to checkpoint partition {} wal: {}", file_name, e);
+                error!("{}", msg);
+                msg
+            })?;
+        Ok(())
+    }
+
+    // Bytes on disk of the partition database, including the rows still in its wal
+    fn partition_file_size(&self, file_name: &str) -> u64 {
+        let partition_path = self.partition_path(file_name);
+        ["", "-wal"]
+            .iter()
+            .map(|suffix| {
+                std::fs::metadata(format!("{}{}", partition_path.display(), suffix))
+                    .map(|m| m.len())
+                    .unwrap_or_default()
+            })
+            .sum()
+    }
+
+    fn lock_manifest_for_prune(&self) -> Result<std::sync::MutexGuard<'_, Connection>, String> {
+        self.manifest_conn.lock().map_err(|e| {
+            let msg = format!(
+                "Failed to lock partition manifest db mutex for prune: {}",
+                e
+            );
+            error!("{}", msg);
+            msg
+        })
+    }
+
+    fn prune(&self, policy: &RetentionPolicy) -> Result<PruneReport, String> {
+        let now_secs = now_unix_secs().max(0) as u64;
+        let now_millis = now_secs.saturating_mul(1000);
+        let mut report = PruneReport {
+            run_at: now_secs,
+            policy: policy.clone(),
+            dropped: vec![],
+            trimmed: vec![],
+            freed_bytes: 0,
+            total_bytes_after: 0,
+        };
+
+        let partitions = Self::list_all_partitions(&*self.lock_manifest_for_prune()?)?;
+        let mut kept = VecDeque::new();
+        let min_cutoff = policy
+            .min_max_age_secs()
+            .map(|age| now_millis.saturating_sub(age.saturating_mul(1000)));
+        for mut partition in partitions {
+            // Nothing in the partition can have expired yet
+            let Some(min_cutoff) = min_cutoff else {
+                kept.push_back(partition);
+                continue;
+            };
+            if partition.row_count == 0 || normalize_to_millis(partition.start_time) >= min_cutoff {
+                kept.push_back(partition);
+                continue;
+            }
+
+            // Lock the manifest per partition, appends only wait for the partition being pruned
+            // and never write to a partition after it is dropped
+            let manifest_lock = self.lock_manifest_for_prune()?;
+            let manifest = &*manifest_lock;
+            let size_before = self.partition_file_size(&partition.file_name);
+            match self.expire_partition_logs(&partition.file_name, policy, now_millis)? {
+                None => {
+                    self.drop_partition(manifest, &partition.file_name)?;
+                    report.freed_bytes = report.freed_bytes.saturating_add(size_before);
+                    report.dropped.push(PrunedPartition {
+                        partition,
+                        reason: PruneReason::MaxAge,
+                    });
+                }
+                Some(trimmed) => {
+                    if !trimmed.is_empty() {
+                        let stats = Self::collect_partition_stats(
+                            &self.partition_path(&partition.file_name),
+                        )?;
+                        Self::update_manifest_partition_stats(
+                            manifest,
+                            &partition.file_name,
+                            &stats,
+                        )?;
+                        let size_after = self.partition_file_size(&partition.file_name);
+                        report.freed_bytes = report
+                            .freed_bytes
+                            .saturating_add(size_before.saturating_sub(size_after));
+                        partition.start_time = stats.start_time;
+                        partition.end_time = stats.end_time;
+                        partition.row_count = stats.row_count;
+                        partition.size_bytes = stats.size_bytes;
+                        report.trimmed.extend(trimmed);
+                    }
+                    kept.push_back(partition);
+                }
+            }
+        }
+
+        let mut total_bytes: u64 = kept.iter().map(|p| p.size_bytes).sum();
+        if let Some(max_total_bytes) = policy.max_total_bytes {
+            // Always keep the newest partition, it is the one receiving logs
+            while total_bytes > max_total_bytes && kept.len() > 1 {
+                let Some(partition) = kept.pop_front() else {
+                    break;
+                };
+                let manifest_lock = self.lock_manifest_for_prune()?;
+                let size_before = self.partition_file_size(&partition.file_name);
+                self.drop_partition(&manifest_lock, &partition.file_name)?;
+                drop(manifest_lock);
+                total_bytes = total_bytes.saturating_sub(partition.size_bytes);
+                report.freed_bytes = report.freed_bytes.saturating_add(size_before);
+                report.dropped.push(PrunedPartition {
+                    partition,
+                    reason: PruneReason::MaxTotalBytes,
+                });
+            }
+        }
+        report.total_bytes_after = total_bytes;
+        Self::prune_dropped_batch_mappings(&*self.lock_manifest_for_prune()?, now_secs as i64)?;
+
+        let mut history = self.prune_history.lock().map_err(|e| {
+            let msg = format!("Failed to lock prune history mutex: {}", e);
+            error!("{}", msg);
+            msg
+        })?;
+        history.last_run_at = Some(now_secs);
+        if !report.is_empty() {
+            history.reports.push_front(report.clone());
+            history.reports.truncate(MAX_PRUNE_HISTORY);
+        }
+
+        Ok(report)
+    }
+
+    fn storage_stats(&self) -> Result<StorageStats, String> {
+        let partitions = {
+            let manifest_lock = self.manifest_conn.lock().map_err(|e| {
+                let msg = format!(
+                    "Failed to lock partition manifest db mutex for stats: {}",
+                    e
+                );
+                error!("{}", msg);
+                msg
+            })?;
+            Self::list_all_partitions(&manifest_lock)?
+        };
+
+        let history = self.prune_history.lock().map_err(|e| {
+            let msg = format!("Failed to lock prune history mutex: {}", e);
+            error!("{}", msg);
+            msg
+        })?;
+
+        Ok(StorageStats {
+            total_rows: partitions.iter().map(|p| p.row_count).sum(),
+            total_bytes: partitions.iter().map(|p| p.size_bytes).sum(),
+            partitions,
+            last_prune_at: history.last_run_at,
+            recent_prunes: history.reports.iter().cloned().collect(),
+        })
+    }
+
+    fn query(&self, request: LogQueryRequest) -> Result<Vec<LogRecords>, String> {
+        let candidate_partitions = {
+            let manifest_lock = self.manifest_conn.lock().map_err(|e| {
+                let msg = format!(
+                    "Failed to lock partition manifest db mutex for query: {}",
+                    e
+                );
+                error!("{}", msg);
+                msg
+            })?;
+            Self::list_candidate_partitions(&manifest_lock, request.start_time, request.end_time)?
+        };
+
+        if candidate_partitions.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut rows: Vec<(String, String, SystemLogRecord)> = Vec::new();
+        for partition in candidate_partitions {
+            let mut chunk = self.query_single_partition(&partition.file_name, &request)?;
+            rows.append(&mut chunk);
+        }
+
+        rows.sort_by(|a, b| b.2.time.cmp(&a.2.time));
+
+        if let Some(limit) = request.limit {
+            rows.truncate(limit);
+        }
+
+        let mut records_map: HashMap<(String, String), Vec<SystemLogRecord>> = HashMap::new();
+        for (node, service, record) in rows {
+            records_map.entry((node, service)).or_default().push(record);
+        }
+
+        let mut result = Vec::new();
+        for ((node, service), logs) in records_map {
+            result.push(LogRecords {
+                node,
+                service,
+                batch_id: None,
+                record_ids: vec![],
+                logs,
+            });
+        }
+        Ok(result)
+    }
+}
+
+#[async_trait::async_trait]
+impl LogStorage for SqlitePartitionedLogStorage {
+    async fn append_logs(&self, logs: LogRecords) -> Result<(), String> {
+        self.append(logs)
+    }
+
+    async fn query_logs(&self, request: LogQueryRequest) -> Result<Vec<LogRecords>, String> {
+        self.query(request)
+    }
+
+    async fn aggregate_logs(
+        &self,
+        request: LogAggregateRequest,
+    ) -> Result<Vec<LogAggregateBucket>, String> {
+        self.aggregate(request)
+    }
+
+    async fn prune(&self, policy: &RetentionPolicy) -> Result<PruneReport, String> {
+        // Deleting rows and vacuuming partitions is slow blocking sqlite work
+        let storage = self.clone();
+        let policy = policy.clone();
+        tokio::task::spawn_blocking(move || SqlitePartitionedLogStorage::prune(&storage, &policy))
+            .await
+            .map_err(|e| {
+                let msg = format!("Log prune task failed: {}", e);
+                error!("{}", msg);
+                msg
+            })?
+    }
+
+    async fn storage_stats(&self) -> Result<StorageStats, String> {
+        SqlitePartitionedLogStorage::storage_stats(self)
+    }
+}
 
 fn now_unix_secs() -> i64 {
     std::time::SystemTime::now()
@@ -1302,6 +1831,7 @@ fn normalize_to_millis(timestamp: u64) -> u64 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::storage::AggregateGroupBy;
     use slog::{LogLevel, SystemLogRecord};
     use std::time::{SystemTime, UNIX_EPOCH};
 
@@ -1334,6 +1864,7 @@ mod tests {
             file: None,
             line: None,
             content: content.to_string(),
+            fields: Default::default(),
         }
     }
 
@@ -1425,6 +1956,7 @@ mod tests {
                 start_time: None,
                 end_time: None,
                 limit: None,
+                ..Default::default()
             })
             .unwrap();
         assert_eq!(queried.len(), 1);
@@ -1472,6 +2004,7 @@ mod tests {
                 start_time: None,
                 end_time: None,
                 limit: None,
+                ..Default::default()
             })
             .unwrap();
         assert_eq!(queried.len(), 1);
@@ -1530,6 +2063,7 @@ mod tests {
                 start_time: Some(day1),
                 end_time: Some(day2 + 40),
                 limit: Some(3),
+                ..Default::default()
             })
             .unwrap();
         assert_eq!(queried.len(), 1);
@@ -1541,6 +2075,242 @@ mod tests {
         cleanup_storage_dir(&storage_dir);
     }
 
+    #[test]
+    fn test_partitioned_storage_query_cross_day_with_search_filters() {
+        let storage_dir = temp_storage_dir("cross_day_search");
+        let storage = SqlitePartitionedLogStorage::open(
+            &storage_dir,
+            SqlitePartitionedConfig {
+                bucket: PartitionBucket::Day,
+                max_rows_per_partition: 100,
+                max_partition_size_bytes: 1024 * 1024 * 1024,
+            },
+        )
+        .unwrap();
+
+        let day1 = 1_721_000_000_000_u64;
+        let day2 = day1 + DAY_MILLIS;
+        storage
+            .append(payload(
+                "node-1",
+                "svc-a",
+                "batch-search-1",
+                vec![
+                    record(day1 + 10, "upload Timeout after 3 retries"),
+                    record(day1 + 20, "upload done"),
+                ],
+            ))
+            .unwrap();
+        storage
+            .append(payload(
+                "node-1",
+                "svc-a",
+                "batch-search-2",
+                vec![
+                    record(day2 + 10, "download timeout after 5 retries"),
+                    record(day2 + 20, "download done"),
+                ],
+            ))
+            .unwrap();
+
+        let by_keyword = storage
+            .query(LogQueryRequest {
+                keyword: Some("timeout".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(by_keyword.len(), 1);
+        assert_eq!(by_keyword[0].logs.len(), 2);
+        assert_eq!(
+            by_keyword[0].logs[0].content,
+            "download timeout after 5 retries"
+        );
+        assert_eq!(
+            by_keyword[0].logs[1].content,
+            "upload Timeout after 3 retries"
+        );
+
+        let by_keyword_and_regex = storage
+            .query(LogQueryRequest {
+                keyword: Some("timeout".to_string()),
+                regex: Some(r"after [0-3] retries".to_string()),
+                target: Some("test-target".to_string()),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(by_keyword_and_regex.len(), 1);
+        assert_eq!(by_keyword_and_regex[0].logs.len(), 1);
+        assert_eq!(
+            by_keyword_and_regex[0].logs[0].content,
+            "upload Timeout after 3 retries"
+        );
+
+        cleanup_storage_dir(&storage_dir);
+    }
+
+    #[test]
+    fn test_partitioned_storage_fields_filter_and_old_partition_upgrade() {
+        let storage_dir = temp_storage_dir("fields");
+        let storage = SqlitePartitionedLogStorage::open(
+            &storage_dir,
+            SqlitePartitionedConfig {
+                bucket: PartitionBucket::Day,
+                max_rows_per_partition: 100,
+                max_partition_size_bytes: 1024 * 1024 * 1024,
+            },
+        )
+        .unwrap();
+
+        let day1 = 1_721_000_000_000_u64;
+        let day2 = day1 + DAY_MILLIS;
+        storage
+            .append(payload(
+                "node-1",
+                "svc-a",
+                "batch-fields-1",
+                vec![record(day1 + 10, "old-partition")],
+            ))
+            .unwrap();
+
+        // Simulate a partition written before the fields column existed
+        let old_file = query_first_partition_file_name(&storage);
+        {
+            let conn = Connection::open(storage.partition_path(&old_file)).unwrap();
+            conn.execute_batch(
+                "DROP TRIGGER log_fields_insert;
+                 DROP TRIGGER log_fields_delete;
+                 DROP TRIGGER log_fields_update;
+                 DROP TABLE log_fields;
+                 ALTER TABLE logs DROP COLUMN fields;",
+            )
+            .unwrap();
+        }
+        // The upgrade happens when the server starts
+        drop(storage);
+        let storage = SqlitePartitionedLogStorage::open(
+            &storage_dir,
+            SqlitePartitionedConfig {
+                bucket: PartitionBucket::Day,
+                max_rows_per_partition: 100,
+                max_partition_size_bytes: 1024 * 1024 * 1024,
+            },
+        )
+        .unwrap();
+
+        let mut with_fields = record(day2 + 10, "new-partition");
+        with_fields
+            .fields
+            .insert("request_id".to_string(), "r1".to_string());
+        storage
+            .append(payload(
+                "node-1",
+                "svc-a",
+                "batch-fields-2",
+                vec![with_fields, record(day2 + 20, "new-no-fields")],
+            ))
+            .unwrap();
+
+        let all = storage.query(LogQueryRequest::default()).unwrap();
+        assert_eq!(all.len(), 1);
+        assert_eq!(all[0].logs.len(), 3);
+
+        let mut fields = BTreeMap::new();
+        fields.insert("request_id".to_string(), "r1".to_string());
+        let by_fields = storage
+            .query(LogQueryRequest {
+                fields,
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(by_fields.len(), 1);
+        assert_eq!(by_fields[0].logs.len(), 1);
+        assert_eq!(by_fields[0].logs[0].content, "new-partition");
+        assert_eq!(by_fields[0].logs[0].field("request_id"), Some("r1"));
+
+        cleanup_storage_dir(&storage_dir);
+    }
+
+    #[test]
+    fn test_partitioned_storage_aggregate_cross_day_merges_partitions() {
+        let storage_dir = temp_storage_dir("cross_day_aggregate");
+        let storage = SqlitePartitionedLogStorage::open(
+            &storage_dir,
+            SqlitePartitionedConfig {
+                bucket: PartitionBucket::Day,
+                max_rows_per_partition: 2,
+                max_partition_size_bytes: 1024 * 1024 * 1024,
+            },
+        )
+        .unwrap();
+
+        let day1 = 1_721_001_600_000_u64;
+        let day2 = day1 + DAY_MILLIS;
+        // The row limit splits day1 of svc-a into two partitions
+        storage
+            .append(payload(
+                "node-1",
+                "svc-a",
+                "batch-agg-1",
+                vec![record(day1 + 10, "a-1"), record(day1 + 20, "a-2")],
+            ))
+            .unwrap();
+        storage
+            .append(payload(
+                "node-1",
+                "svc-a",
+                "batch-agg-2",
+                vec![record(day1 + 30, "a-3")],
+            ))
+            .unwrap();
+        storage
+            .append(payload(
+                "node-2",
+                "svc-b",
+                "batch-agg-3",
+                vec![record(day1 + 40, "b-1"), record(day2 + 10, "b-2")],
+            ))
+            .unwrap();
+        assert!(query_partition_count(&storage) >= 3);
+
+        let buckets = storage
+            .aggregate(LogAggregateRequest {
+                bucket_secs: Some(86_400),
+                group_by: vec![AggregateGroupBy::Service],
+                ..Default::default()
+            })
+            .unwrap();
+        let summary: Vec<(Option<u64>, Option<String>, u64)> = buckets
+            .into_iter()
+            .map(|b| (b.bucket_start, b.service, b.count))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Some(day1), Some("svc-a".to_string()), 3),
+                (Some(day1), Some("svc-b".to_string()), 1),
+                (Some(day2), Some("svc-b".to_string()), 1),
+            ]
+        );
+
+        let top = storage
+            .aggregate(LogAggregateRequest {
+                filter: LogQueryRequest {
+                    keyword: Some("a-".to_string()),
+                    ..Default::default()
+                },
+                group_by: vec![AggregateGroupBy::Node, AggregateGroupBy::Level],
+                top: Some(1),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].node.as_deref(), Some("node-1"));
+        assert_eq!(top[0].level, Some(LogLevel::Info));
+        assert_eq!(top[0].count, 3);
+
+        cleanup_storage_dir(&storage_dir);
+    }
+
     #[test]
     fn test_partitioned_storage_rollover_by_size_limit() {
         let storage_dir = temp_storage_dir("size_rollover");
@@ -1618,6 +2388,7 @@ mod tests {
                 start_time: Some(day1_secs),
                 end_time: Some(day2_secs + 60),
                 limit: None,
+                ..Default::default()
             })
             .unwrap();
         assert_eq!(queried.len(), 1);
@@ -1666,6 +2437,7 @@ mod tests {
                 start_time: None,
                 end_time: None,
                 limit: None,
+                ..Default::default()
             })
             .unwrap();
         assert_eq!(node_a.len(), 1);
@@ -1685,6 +2457,7 @@ mod tests {
                 start_time: None,
                 end_time: None,
                 limit: None,
+                ..Default::default()
             })
             .unwrap();
         assert_eq!(node_b.len(), 1);
@@ -1751,6 +2524,7 @@ mod tests {
                 start_time: Some(second_time),
                 end_time: Some(second_time),
                 limit: None,
+                ..Default::default()
             })
             .unwrap();
         assert_eq!(queried.len(), 1);
@@ -1818,6 +2592,7 @@ mod tests {
                 start_time: None,
                 end_time: None,
                 limit: None,
+                ..Default::default()
             })
             .unwrap();
         assert_eq!(queried.len(), 1);
@@ -1826,4 +2601,247 @@ mod tests {
 
         cleanup_storage_dir(&storage_dir);
     }
+
+    fn service_contents(storage: &SqlitePartitionedLogStorage, service: &str) -> Vec<String> {
+        storage
+            .query(LogQueryRequest {
+                service: Some(service.to_string()),
+                ..Default::default()
+            })
+            .unwrap()
+            .into_iter()
+            .flat_map(|item| item.logs.into_iter().map(|log| log.content))
+            .collect()
+    }
+
+    #[test]
+    fn test_partitioned_storage_prune_by_max_age_with_service_override() {
+        let storage_dir = temp_storage_dir("prune_max_age");
+        let storage =
+            SqlitePartitionedLogStorage::open(&storage_dir, SqlitePartitionedConfig::default())
+                .unwrap();
+
+        let now = now_unix_secs() as u64 * 1000;
+        let day10 = now - 10 * DAY_MILLIS;
+        let day8 = now - 8 * DAY_MILLIS;
+        storage
+            .append(payload(
+                "node-1",
+                "svc-a",
+                "a-old",
+                vec![record(day10, "a-day10")],
+            ))
+            .unwrap();
+        storage
+            .append(payload(
+                "node-1",
+                "svc-b",
+                "b-old",
+                vec![record(day10 + 1, "b-day10")],
+            ))
+            .unwrap();
+        storage
+            .append(payload(
+                "node-1",
+                "svc-a",
+                "a-day8",
+                vec![record(day8, "a-day8")],
+            ))
+            .unwrap();
+        storage
+            .append(payload(
+                "node-1",
+                "svc-a",
+                "a-new",
+                vec![record(now, "a-now")],
+            ))
+            .unwrap();
+        assert_eq!(query_partition_count(&storage), 3);
+
+        let mut policy = RetentionPolicy {
+            max_age_secs: Some(5 * 24 * 3600),
+            ..Default::default()
+        };
+        policy
+            .service_max_age_secs
+            .insert("svc-b".to_string(), 20 * 24 * 3600);
+
+        // The day8 partition only has expired logs, the day10 one is kept for svc-b
+        let report = storage.prune(&policy).unwrap();
+        assert_eq!(report.dropped.len(), 1);
+        assert_eq!(report.dropped[0].reason, PruneReason::MaxAge);
+        assert_eq!(report.dropped[0].partition.row_count, 1);
+        assert_eq!(report.trimmed.len(), 1);
+        assert_eq!(report.trimmed[0].service, "svc-a");
+        assert_eq!(report.trimmed[0].removed_rows, 1);
+        assert_eq!(query_partition_count(&storage), 2);
+
+        assert_eq!(service_contents(&storage, "svc-a"), vec!["a-now"]);
+        assert_eq!(service_contents(&storage, "svc-b"), vec!["b-day10"]);
+
+        // Nothing more to prune, and only the non-empty run is kept in the history
+        let report = storage.prune(&policy).unwrap();
+        assert!(report.is_empty());
+
+        let stats = storage.storage_stats().unwrap();
+        assert_eq!(stats.partitions.len(), 2);
+        assert_eq!(stats.total_rows, 2);
+        assert_eq!(stats.last_prune_at, Some(report.run_at));
+        assert_eq!(stats.recent_prunes.len(), 1);
+        assert_eq!(stats.recent_prunes[0].dropped.len(), 1);
+
+        cleanup_storage_dir(&storage_dir);
+    }
+
+    #[test]
+    fn test_partitioned_storage_prune_by_max_total_bytes_keeps_newest() {
+        let storage_dir = temp_storage_dir("prune_max_bytes");
+        let storage =
+            SqlitePartitionedLogStorage::open(&storage_dir, SqlitePartitionedConfig::default())
+                .unwrap();
+
+        let day1 = 1_721_000_000_000_u64;
+        for day in 0..3 {
+            let time = day1 + day * DAY_MILLIS;
+            storage
+                .append(payload(
+                    "node-1",
+                    "svc-a",
+                    &format!("batch-{}", day),
+                    vec![record(time, &format!("day-{}", day))],
+                ))
+                .unwrap();
+        }
+        assert_eq!(query_partition_count(&storage), 3);
+        let newest = storage.storage_stats().unwrap().partitions[2]
+            .file_name
+            .clone();
+
+        let report = storage
+            .prune(&RetentionPolicy {
+                max_total_bytes: Some(1),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(report.dropped.len(), 2);
+        assert!(
+            report
+                .dropped
+                .iter()
+                .all(|item| item.reason == PruneReason::MaxTotalBytes)
+        );
+        assert!(report.freed_bytes > 0);
+
+        let stats = storage.storage_stats().unwrap();
+        assert_eq!(stats.partitions.len(), 1);
+        assert_eq!(stats.partitions[0].file_name, newest);
+        assert_eq!(stats.total_bytes, report.total_bytes_after);
+        assert!(
+            !storage
+                .partition_path(&report.dropped[0].partition.file_name)
+                .exists()
+        );
+        assert_eq!(service_contents(&storage, "svc-a"), vec!["day-2"]);
+
+        cleanup_storage_dir(&storage_dir);
+    }
+
+    #[tokio::test]
+    async fn test_partitioned_storage_prune_trim_vacuums_partition() {
+        let storage_dir = temp_storage_dir("prune_trim_vacuum");
+        let storage =
+            SqlitePartitionedLogStorage::open(&storage_dir, SqlitePartitionedConfig::default())
+                .unwrap();
+
+        // Both services share one partition, only svc-a has a max age
+        let time = (now_unix_secs() as u64 - 2 * 3600) * 1000;
+        let content = "x".repeat(4096);
+        let rows = (0..64)
+            .map(|idx| record(time + idx, &content))
+            .collect::<Vec<_>>();
+        storage
+            .append(payload("node-1", "svc-a", "a-old", rows))
+            .unwrap();
+        storage
+            .append(payload(
+                "node-1",
+                "svc-b",
+                "b-old",
+                vec![record(time, "b-old")],
+            ))
+            .unwrap();
+        let file_name = query_first_partition_file_name(&storage);
+        let size_before = storage.partition_file_size(&file_name);
+
+        let mut policy = RetentionPolicy::default();
+        policy
+            .service_max_age_secs
+            .insert("svc-a".to_string(), 3600);
+        let report = LogStorage::prune(&storage, &policy).await.unwrap();
+        assert!(report.dropped.is_empty());
+        assert_eq!(report.trimmed.len(), 1);
+        assert_eq!(report.trimmed[0].removed_rows, 64);
+
+        let size_after = storage.partition_file_size(&file_name);
+        assert!(size_after < size_before);
+        assert_eq!(report.freed_bytes, size_before - size_after);
+        assert_eq!(
+            storage.storage_stats().unwrap().partitions[0].size_bytes,
+            size_after
+        );
+        assert!(service_contents(&storage, "svc-a").is_empty());
+        assert_eq!(service_contents(&storage, "svc-b"), vec!["b-old"]);
+
+        cleanup_storage_dir(&storage_dir);
+    }
+
+    #[test]
+    fn test_partitioned_storage_skips_replayed_batch_of_dropped_partition() {
+        let storage_dir = temp_storage_dir("prune_replayed_batch");
+        let storage =
+            SqlitePartitionedLogStorage::open(&storage_dir, SqlitePartitionedConfig::default())
+                .unwrap();
+
+        let day1 = 1_721_000_000_000_u64;
+        let old_batch = payload("node-1", "svc-a", "batch-old", vec![record(day1, "old")]);
+        storage.append(old_batch.clone()).unwrap();
+        storage
+            .append(payload(
+                "node-1",
+                "svc-a",
+                "batch-new",
+                vec![record(day1 + DAY_MILLIS, "new")],
+            ))
+            .unwrap();
+
+        let report = storage
+            .prune(&RetentionPolicy {
+                max_total_bytes: Some(1),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(report.dropped.len(), 1);
+
+        // The daemon retries a batch it never got the ack for, it must not come back
+        storage.append(old_batch).unwrap();
+        assert_eq!(query_partition_count(&storage), 1);
+        assert_eq!(service_contents(&storage, "svc-a"), vec!["new"]);
+
+        // The tombstone goes away once the replay window has passed
+        let manifest = storage.manifest_conn.lock().unwrap();
+        SqlitePartitionedLogStorage::prune_dropped_batch_mappings(
+            &manifest,
+            now_unix_secs() + DROPPED_BATCH_MAPPING_TTL_SECS + 1,
+        )
+        .unwrap();
+        let mappings: i64 = manifest
+            .query_row("SELECT COUNT(*) FROM batch_partition_map", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(mappings, 1);
+        drop(manifest);
+
+        cleanup_storage_dir(&storage_dir);
+    }
 }
@@ -1,5 +1,8 @@
+use super::aggregate::{LogAggregateBucket, LogAggregateRequest};
+use super::retention::{PruneReport, RetentionPolicy, StorageStats};
 use serde::{Deserialize, Serialize};
 use slog::SystemLogRecord;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -17,11 +20,28 @@ pub struct LogRecords {
 pub trait LogStorage: Sync + Send {
     async fn append_logs(&self, records: LogRecords) -> Result<(), String>;
     async fn query_logs(&self, request: LogQueryRequest) -> Result<Vec<LogRecords>, String>;
+
+    // Time-bucketed counts grouped by the requested fields, computed by the storage
+    async fn aggregate_logs(
+        &self,
+        _request: LogAggregateRequest,
+    ) -> Result<Vec<LogAggregateBucket>, String> {
+        Err("aggregation is not supported by this storage".to_string())
+    }
+
+    // Drop or trim the logs out of the retention policy, only partitioned storages support it
+    async fn prune(&self, _policy: &RetentionPolicy) -> Result<PruneReport, String> {
+        Err("retention is not supported by this storage".to_string())
+    }
+
+    async fn storage_stats(&self) -> Result<StorageStats, String> {
+        Err("storage stats are not supported by this storage".to_string())
+    }
 }
 
 pub type LogStorageRef = Arc<Box<dyn LogStorage>>;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Default)]
 pub struct LogQueryRequest {
     pub node: Option<String>,
     pub service: Option<String>,
@@ -29,4 +49,15 @@ pub struct LogQueryRequest {
     pub start_time: Option<u64>,
     pub end_time: Option<u64>,
     pub limit: Option<usize>,
+    // Case-insensitive substring of content, served by the FTS5 index
+    pub keyword: Option<String>,
+    // Regular expression on content, evaluated after the indexed filters
+    pub regex: Option<String>,
+    // Matches the target itself and its sub modules, e.g. "kmsg" matches "kmsg::server"
+    pub target: Option<String>,
+    // Matches the full file path or a trailing path segment, e.g. "server.rs"
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    // Structured fields which must all equal, e.g. request_id=xxx, served by the log_fields index
+    pub fields: BTreeMap<String, String>,
 }
@@ -0,0 +1,395 @@
+use crate::storage::LogRecords;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+/// How many of the latest appended records are kept for resuming tails.
+pub const DEFAULT_TAIL_BUFFER_SIZE: usize = 10_000;
+// Appends retried with the same batch id are published only once
+const RECENT_BATCH_CAPACITY: usize = 4096;
+const TAIL_CHANNEL_CAPACITY: usize = 1024;
+
+/// Position in the tail stream, formatted as `<epoch>-<seq>`.
+/// The epoch changes on every server start, so cursors from a previous run are detected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TailCursor {
+    pub epoch: u64,
+    pub seq: u64,
+}
+
+impl TailCursor {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let raw = raw.trim();
+        let (epoch, seq) = raw
+            .split_once('-')
+            .ok_or_else(|| format!("invalid cursor '{}': expected <epoch>-<seq>", raw))?;
+        let epoch = epoch
+            .parse::<u64>()
+            .map_err(|e| format!("invalid cursor '{}': {}", raw, e))?;
+        let seq = seq
+            .parse::<u64>()
+            .map_err(|e| format!("invalid cursor '{}': {}", raw, e))?;
+        Ok(Self { epoch, seq })
+    }
+}
+
+impl fmt::Display for TailCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.epoch, self.seq)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TailLogRecord {
+    pub cursor: String,
+    pub node: String,
+    pub service: String,
+    pub log: slog::SystemLogRecord,
+}
+
+#[derive(Debug, Clone)]
+pub enum TailItem {
+    Record(TailLogRecordRef),
+    // Some records between the resume cursor and the next record are lost,
+    // the client should backfill them through `/query`
+    Gap { reason: String },
+}
+
+#[derive(Debug, Clone)]
+pub struct TailLogRecordRef(Arc<TailEvent>);
+
+impl TailLogRecordRef {
+    pub fn to_record(&self, epoch: u64) -> TailLogRecord {
+        TailLogRecord {
+            cursor: TailCursor {
+                epoch,
+                seq: self.0.seq,
+            }
+            .to_string(),
+            node: self.0.node.clone(),
+            service: self.0.service.clone(),
+            log: self.0.log.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TailEvent {
+    seq: u64,
+    node: String,
+    service: String,
+    log: slog::SystemLogRecord,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TailFilter {
+    pub node: Option<String>,
+    pub service: Option<String>,
+    pub level: Option<slog::LogLevel>,
+}
+
+impl TailFilter {
+    fn matches(&self, event: &TailEvent) -> bool {
+        self.node.as_ref().is_none_or(|node| *node == event.node)
+            && self
+                .service
+                .as_ref()
+                .is_none_or(|service| *service == event.service)
+            && self.level.is_none_or(|level| level == event.log.level)
+    }
+}
+
+struct TailState {
+    // Seq of the next published record, starts from 1 so 0 means "before everything"
+    next_seq: u64,
+    buffer: VecDeque<Arc<TailEvent>>,
+    recent_batches: HashSet<(String, String, String)>,
+    recent_batch_order: VecDeque<(String, String, String)>,
+}
+
+impl TailState {
+    fn oldest_seq(&self) -> u64 {
+        self.buffer.front().map(|e| e.seq).unwrap_or(self.next_seq)
+    }
+
+    // Buffered events after `seq`, with a gap first if some of them were evicted
+    fn replay_after(&self, seq: u64, out: &mut VecDeque<TailItem>) {
+        if seq.saturating_add(1) < self.oldest_seq() {
+            out.push_back(TailItem::Gap {
+                reason: "cursor is older than the tail buffer".to_string(),
+            });
+        }
+        for event in self.buffer.iter().filter(|e| e.seq > seq) {
+            out.push_back(TailItem::Record(TailLogRecordRef(event.clone())));
+        }
+    }
+}
+
+/// Fan out the appended records to the live tail subscribers.
+pub struct LogTailHub {
+    epoch: u64,
+    buffer_size: usize,
+    state: Mutex<TailState>,
+    sender: broadcast::Sender<Arc<TailEvent>>,
+}
+
+impl LogTailHub {
+    pub fn new(buffer_size: usize) -> Self {
+        let epoch = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        let (sender, _) = broadcast::channel(TAIL_CHANNEL_CAPACITY);
+        Self {
+            epoch,
+            buffer_size: buffer_size.max(1),
+            state: Mutex::new(TailState {
+                next_seq: 1,
+                buffer: VecDeque::new(),
+                recent_batches: HashSet::new(),
+                recent_batch_order: VecDeque::new(),
+            }),
+            sender,
+        }
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    // Called after the records are stored successfully
+    pub fn publish(&self, records: &LogRecords) {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(batch_id) = &records.batch_id {
+            let key = (
+                records.node.clone(),
+                records.service.clone(),
+                batch_id.clone(),
+            );
+            if state.recent_batches.contains(&key) {
+                debug!(
+                    "skip tail publish for retried batch: node={}, service={}, batch_id={}",
+                    records.node, records.service, batch_id
+                );
+                return;
+            }
+            state.recent_batches.insert(key.clone());
+            state.recent_batch_order.push_back(key);
+            while state.recent_batch_order.len() > RECENT_BATCH_CAPACITY {
+                if let Some(old) = state.recent_batch_order.pop_front() {
+                    state.recent_batches.remove(&old);
+                }
+            }
+        }
+
+        for log in &records.logs {
+            let event = Arc::new(TailEvent {
+                seq: state.next_seq,
+                node: records.node.clone(),
+                service: records.service.clone(),
+                log: log.clone(),
+            });
+            state.next_seq += 1;
+            state.buffer.push_back(event.clone());
+            if state.buffer.len() > self.buffer_size {
+                state.buffer.pop_front();
+            }
+
+            // No receivers is fine, the event stays in the buffer for resuming
+            let _ = self.sender.send(event);
+        }
+    }
+
+    // Subscribe from the record after `cursor`, or from now on if None
+    pub fn subscribe(self: &Arc<Self>, cursor: Option<TailCursor>) -> TailSubscription {
+        let state = self.state.lock().unwrap();
+        let mut pending = VecDeque::new();
+
+        let last_seq = match cursor {
+            None => state.next_seq - 1,
+            Some(cursor) if cursor.epoch != self.epoch => {
+                pending.push_back(TailItem::Gap {
+                    reason: "server restarted since the cursor".to_string(),
+                });
+                for event in state.buffer.iter() {
+                    pending.push_back(TailItem::Record(TailLogRecordRef(event.clone())));
+                }
+                state.next_seq - 1
+            }
+            Some(cursor) if cursor.seq >= state.next_seq => {
+                pending.push_back(TailItem::Gap {
+                    reason: "cursor is ahead of the server".to_string(),
+                });
+                state.next_seq - 1
+            }
+            Some(cursor) => {
+                state.replay_after(cursor.seq, &mut pending);
+                state.next_seq - 1
+            }
+        };
+
+        // Subscribe while holding the state lock, so no record falls between replay and live
+        let receiver = self.sender.subscribe();
+        drop(state);
+
+        TailSubscription {
+            hub: self.clone(),
+            receiver,
+            pending,
+            last_seq,
+        }
+    }
+}
+
+pub struct TailSubscription {
+    hub: Arc<LogTailHub>,
+    receiver: broadcast::Receiver<Arc<TailEvent>>,
+    pending: VecDeque<TailItem>,
+    // Seq of the last record handed out or replayed, matched by the filter or not
+    last_seq: u64,
+}
+
+impl TailSubscription {
+    pub fn epoch(&self) -> u64 {
+        self.hub.epoch
+    }
+
+    // Wait for the next record matching the filter, None if the hub is gone
+    pub async fn next(&mut self, filter: &TailFilter) -> Option<TailItem> {
+        loop {
+            if let Some(item) = self.pending.pop_front() {
+                match &item {
+                    TailItem::Record(record) => {
+                        self.last_seq = self.last_seq.max(record.0.seq);
+                        if !filter.matches(&record.0) {
+                            continue;
+                        }
+                    }
+                    TailItem::Gap { .. } => {}
+                }
+                return Some(item);
+            }
+
+            match self.receiver.recv().await {
+                Ok(event) => {
+                    // Already replayed after a lag
+                    if event.seq <= self.last_seq {
+                        continue;
+                    }
+                    self.pending
+                        .push_back(TailItem::Record(TailLogRecordRef(event)));
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(
+                        "tail subscriber lagged behind by {} records, replay from buffer",
+                        skipped
+                    );
+                    let state = self.hub.state.lock().unwrap();
+                    state.replay_after(self.last_seq, &mut self.pending);
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use slog::{LogLevel, SystemLogRecord};
+
+    fn records(service: &str, batch_id: Option<&str>, contents: &[&str]) -> LogRecords {
+        LogRecords {
+            node: "node-1".to_string(),
+            service: service.to_string(),
+            batch_id: batch_id.map(|v| v.to_string()),
+            record_ids: vec![],
+            logs: contents
+                .iter()
+                .map(|content| SystemLogRecord {
+                    level: LogLevel::Info,
+                    target: "test".to_string(),
+                    time: 1000,
+                    file: None,
+                    line: None,
+                    content: content.to_string(),
+                    fields: Default::default(),
+                })
+                .collect(),
+        }
+    }
+
+    async fn next_content(sub: &mut TailSubscription, filter: &TailFilter) -> String {
+        match sub.next(filter).await.unwrap() {
+            TailItem::Record(record) => record.to_record(sub.epoch()).log.content,
+            TailItem::Gap { reason } => format!("gap: {}", reason),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tail_hub_live_filter_and_batch_dedup() {
+        let hub = Arc::new(LogTailHub::new(16));
+        hub.publish(&records("svc-a", None, &["before"]));
+
+        let filter = TailFilter {
+            service: Some("svc-a".to_string()),
+            ..Default::default()
+        };
+        let mut sub = hub.subscribe(None);
+        hub.publish(&records("svc-b", None, &["other"]));
+        hub.publish(&records("svc-a", Some("b1"), &["a-1", "a-2"]));
+        hub.publish(&records("svc-a", Some("b1"), &["a-1", "a-2"]));
+        hub.publish(&records("svc-a", Some("b2"), &["a-3"]));
+
+        assert_eq!(next_content(&mut sub, &filter).await, "a-1");
+        assert_eq!(next_content(&mut sub, &filter).await, "a-2");
+        assert_eq!(next_content(&mut sub, &filter).await, "a-3");
+    }
+
+    #[tokio::test]
+    async fn test_tail_hub_resume_from_cursor() {
+        let hub = Arc::new(LogTailHub::new(3));
+        let filter = TailFilter::default();
+        hub.publish(&records("svc-a", None, &["r-1", "r-2"]));
+
+        let mut sub = hub.subscribe(None);
+        hub.publish(&records("svc-a", None, &["r-3"]));
+        let TailItem::Record(record) = sub.next(&filter).await.unwrap() else {
+            panic!("expected record");
+        };
+        let cursor = TailCursor::parse(&record.to_record(hub.epoch()).cursor).unwrap();
+        assert_eq!(cursor.seq, 3);
+        drop(sub);
+
+        // Records published while disconnected are replayed exactly once
+        hub.publish(&records("svc-a", None, &["r-4", "r-5"]));
+        let mut sub = hub.subscribe(Some(cursor));
+        hub.publish(&records("svc-a", None, &["r-6"]));
+        assert_eq!(next_content(&mut sub, &filter).await, "r-4");
+        assert_eq!(next_content(&mut sub, &filter).await, "r-5");
+        assert_eq!(next_content(&mut sub, &filter).await, "r-6");
+
+        // r-3 was evicted from the buffer of 3, so resuming from r-2 reports a gap
+        let mut sub = hub.subscribe(Some(TailCursor {
+            epoch: hub.epoch(),
+            seq: 2,
+        }));
+        assert!(next_content(&mut sub, &filter).await.starts_with("gap"));
+        assert_eq!(next_content(&mut sub, &filter).await, "r-4");
+
+        // A cursor from another server run reports a gap, then replays the buffer
+        let mut sub = hub.subscribe(Some(TailCursor {
+            epoch: hub.epoch() + 1,
+            seq: 1,
+        }));
+        assert!(next_content(&mut sub, &filter).await.starts_with("gap"));
+        assert_eq!(next_content(&mut sub, &filter).await, "r-4");
+
+        assert!(TailCursor::parse("abc").is_err());
+        assert_eq!(TailCursor::parse(&cursor.to_string()).unwrap(), cursor);
+    }
+}
@@ -1,6 +1,6 @@
 //system control panel client
 
-use crate::AppDoc;
+use crate::{AppDoc, NodeAffinity, ReplicaSpread};
 use ::kRPC::*;
 use log::warn;
 use name_lib::DID;
@@ -8,6 +8,9 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 pub const SERVICE_INSTANCE_INFO_UPDATE_INTERVAL: u64 = 30;
+// instance reports are written with a sys_config lease of this ttl, renewed on every update.
+// a few missed updates are tolerated before the report key expires.
+pub const SERVICE_INSTANCE_LEASE_TTL: u64 = SERVICE_INSTANCE_INFO_UPDATE_INTERVAL * 3;
 
 pub const KNOWN_SERVICE_WWW: (&str, u16) = ("www", 80);
 pub const KNOWN_SERVICE_HTTP: (&str, u16) = ("http", 80);
@@ -60,6 +63,9 @@ pub struct ServiceInstanceReportInfo {
     pub last_update_time: u64,
     pub start_time: u64,
     pub pid: u32,
+    //expire time of the sys_config lease holding this report, None for reports without lease
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lease_expire_time: Option<u64>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -118,6 +124,12 @@ pub struct ServiceInstallConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub start_param: Option<String>,
     pub res_pool_id: String,
+
+    //调度约束:按node标签选择节点,以及副本按拓扑域分散,为None时不限制
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_affinity: Option<NodeAffinity>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replica_spread: Option<ReplicaSpread>,
 }
 
 impl Default for ServiceInstallConfig {
@@ -131,6 +143,8 @@ impl Default for ServiceInstallConfig {
             container_param: None,
             start_param: None,
             res_pool_id: "default".to_string(),
+            node_affinity: None,
+            replica_spread: None,
         }
     }
 }
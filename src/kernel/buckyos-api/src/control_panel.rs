@@ -120,6 +120,67 @@ impl UserSettings {
     }
 }
 
+pub fn is_privileged_user_type(user_type: &str) -> bool {
+    matches!(
+        user_type.trim().to_ascii_lowercase().as_str(),
+        "admin" | "root"
+    )
+}
+
+// "g, <user>, admin" style grouping lines of system/rbac/policy
+pub fn user_has_privileged_role_in_policy(policy: &str, user_name: &str) -> bool {
+    policy.lines().any(|line| {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return false;
+        }
+
+        let parts: Vec<&str> = line.split(',').map(|part| part.trim()).collect();
+        if parts.len() < 3 || parts[0] != "g" || parts[1] != user_name {
+            return false;
+        }
+
+        is_privileged_user_type(parts[2])
+    })
+}
+
+// a user is admin/root by the type in users/{user}/settings or by its role in the rbac policy,
+// the same check system_config uses for privileged users. None means the key is not set.
+pub fn is_privileged_user_by_config(
+    user_settings: Option<&str>,
+    rbac_policy: Option<&str>,
+    user_name: &str,
+) -> Result<bool> {
+    if let Some(user_settings) = user_settings {
+        let user_settings: serde_json::Value =
+            serde_json::from_str(user_settings).map_err(|err| {
+                RPCErrors::ReasonError(format!("parse user settings failed: {}", err))
+            })?;
+        let user_type = user_settings
+            .get("type")
+            .or_else(|| user_settings.get("user_type"))
+            .and_then(|value| value.as_str());
+        if user_type.is_some_and(is_privileged_user_type) {
+            return Ok(true);
+        }
+    }
+    Ok(rbac_policy.is_some_and(|policy| user_has_privileged_role_in_policy(policy, user_name)))
+}
+
+pub async fn is_privileged_user(client: &SystemConfigClient, user_name: &str) -> Result<bool> {
+    let user_settings = match client.get(&format!("users/{}/settings", user_name)).await {
+        Ok(value) => Some(value.value),
+        Err(SystemConfigError::KeyNotFound(_)) => None,
+        Err(err) => return Err(RPCErrors::ReasonError(err.to_string())),
+    };
+    let rbac_policy = match client.get("system/rbac/policy").await {
+        Ok(value) => Some(value.value),
+        Err(SystemConfigError::KeyNotFound(_)) => None,
+        Err(err) => return Err(RPCErrors::ReasonError(err.to_string())),
+    };
+    is_privileged_user_by_config(user_settings.as_deref(), rbac_policy.as_deref(), user_name)
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "lowercase")]
 pub enum NodeState {
@@ -393,6 +454,70 @@ impl ControlPanelClient {
         Ok(0)
     }
 
+    // Write the instance report attached to a sys_config lease, so the report disappears when
+    // the instance stops updating it. `lease` is the one returned by the previous call; it is
+    // renewed if still alive, otherwise a new lease is granted.
+    pub async fn update_service_instance_info_with_lease(
+        &self,
+        service_name: &str,
+        node_name: &str,
+        instance_info: &ServiceInstanceReportInfo,
+        lease: Option<&SystemConfigLease>,
+    ) -> Result<SystemConfigLease> {
+        let renewed = match lease {
+            Some(lease) => self
+                .system_config_client
+                .keep_alive(lease.lease_id.as_str(), Some(SERVICE_INSTANCE_LEASE_TTL))
+                .await
+                .map_err(|e| {
+                    warn!(
+                        "renew instance lease {} of {}@{} failed, will grant a new one: {}",
+                        lease.lease_id, service_name, node_name, e
+                    );
+                    e
+                })
+                .ok(),
+            None => None,
+        };
+        let lease = match renewed {
+            Some(lease) => lease,
+            None => self
+                .system_config_client
+                .grant_lease(SERVICE_INSTANCE_LEASE_TTL)
+                .await
+                .map_err(|e| {
+                    RPCErrors::ReasonError(format!(
+                        "grant instance lease for {}@{} failed, err:{}",
+                        service_name, node_name, e
+                    ))
+                })?,
+        };
+
+        let mut instance_info = instance_info.clone();
+        instance_info.lease_expire_time = Some(lease.expire_at);
+        let service_info_path = format!("services/{}/instances/{}", service_name, node_name);
+        let service_info_str = serde_json::to_string(&instance_info).map_err(|error| {
+            RPCErrors::ReasonError(format!(
+                "serialize service instance info {}@{} failed: {}",
+                service_name, node_name, error
+            ))
+        })?;
+        self.system_config_client
+            .set_with_lease(
+                service_info_path.as_str(),
+                service_info_str.as_str(),
+                lease.lease_id.as_str(),
+            )
+            .await
+            .map_err(|e| {
+                RPCErrors::ReasonError(format!(
+                    "update service instance info {}@{} failed, err:{}",
+                    service_name, node_name, e
+                ))
+            })?;
+        Ok(lease)
+    }
+
     pub async fn get_services_info(&self, service_name: &str) -> Result<ServiceInfo> {
         let service_info_path = format!("services/{}/info", service_name);
         let service_info = self
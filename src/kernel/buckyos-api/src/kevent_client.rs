@@ -18,7 +18,13 @@ pub const KEVENT_SERVICE_NAME: &str = "kevent";
 pub const KEVENT_SERVICE_MAIN_PORT: u16 = 4041;
 pub const DEFAULT_READER_CAPACITY: usize = 1024;
 pub const MAX_EVENT_DATA_SIZE_BYTES: usize = 64 * 1024;
+/// Delivered by the daemon to a resumed reader when the requested seq was
+/// already trimmed from the durable log.  data: {"requested_seq", "first_seq"}.
+pub const KEVENT_GAP_EVENTID: &str = "/kevent/reader/gap";
 const SHARED_RING_DRAIN_BATCH: usize = 128;
+/// Long-poll timeout used when pulling the events of a resumed reader from the
+/// daemon bridge.
+const BRIDGE_READER_PULL_TIMEOUT_MS: u64 = 30 * 1000;
 /// Maximum time the ShmDispatch thread blocks in futex/ulock before
 /// re-checking (acts as a heartbeat / fallback interval).
 ///
@@ -66,6 +72,8 @@ pub enum KEventError {
     NotSupported(String),
     #[error("READER_CLOSED: {0}")]
     ReaderClosed(String),
+    #[error("PERMISSION_DENIED: {0}")]
+    PermissionDenied(String),
     #[error("INTERNAL: {0}")]
     Internal(String),
 }
@@ -82,6 +90,7 @@ impl KEventError {
             KEventError::TimerNotFound(_) => "TIMER_NOT_FOUND",
             KEventError::NotSupported(_) => "NOT_SUPPORTED",
             KEventError::ReaderClosed(_) => "READER_CLOSED",
+            KEventError::PermissionDenied(_) => "PERMISSION_DENIED",
             KEventError::Internal(_) => "INTERNAL",
         }
     }
@@ -96,6 +105,9 @@ pub struct Event {
     pub ingress_node: Option<String>,
     pub timestamp: u64,
     pub data: Value,
+    /// Assigned by the daemon when the event is appended to its durable log.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -142,6 +154,9 @@ pub enum KEventDaemonRequest {
     RegisterReader {
         reader_id: String,
         patterns: Vec<String>,
+        /// Replay durable events from this seq (0 = oldest retained) before live events.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        from_seq: Option<u64>,
     },
     UnregisterReader {
         reader_id: String,
@@ -171,6 +186,30 @@ pub enum KEventDaemonResponse {
 #[async_trait]
 pub trait KEventDaemonBridge: Send + Sync {
     async fn register_reader(&self, reader_id: &str, patterns: &[String]) -> KEventResult<()>;
+    /// Register a reader that resumes from the daemon durable log.  All global
+    /// events of the reader, replayed and live, are then taken with
+    /// `pull_reader_event`.
+    async fn register_reader_from(
+        &self,
+        _reader_id: &str,
+        _patterns: &[String],
+        _from_seq: u64,
+    ) -> KEventResult<()> {
+        Err(KEventError::NotSupported(
+            "daemon bridge does not support durable replay".to_string(),
+        ))
+    }
+    /// Next event of a reader registered with `register_reader_from`, None on
+    /// timeout.  Fails with `ReaderClosed` once the reader is unregistered.
+    async fn pull_reader_event(
+        &self,
+        _reader_id: &str,
+        _timeout_ms: Option<u64>,
+    ) -> KEventResult<Option<Event>> {
+        Err(KEventError::NotSupported(
+            "daemon bridge does not support durable replay".to_string(),
+        ))
+    }
     async fn unregister_reader(&self, reader_id: &str) -> KEventResult<()>;
     async fn publish_global(&self, event: &Event) -> KEventResult<()>;
 }
@@ -203,16 +242,27 @@ struct ReaderState {
     queue: Mutex<VecDeque<Event>>,
     notify: Notify,
     capacity: usize,
+    /// Resumed from the durable log: the daemon delivers every global event of
+    /// this reader through the bridge, local dispatch skips them to avoid duplicates.
+    replay: bool,
 }
 
 impl ReaderState {
-    fn new(patterns: Vec<String>, capacity: usize) -> Self {
+    fn new(patterns: Vec<String>, capacity: usize, replay: bool) -> Self {
         Self {
             patterns,
             queue: Mutex::new(VecDeque::new()),
             notify: Notify::new(),
             capacity,
+            replay,
+        }
+    }
+
+    fn accepts(&self, event: &Event) -> bool {
+        if self.replay && is_global_eventid(&event.eventid) {
+            return false;
         }
+        match_event_patterns(&self.patterns, &event.eventid)
     }
 
     async fn push(&self, event: Event) {
@@ -246,7 +296,7 @@ impl KEventClientInner {
     async fn dispatch_event(&self, event: &Event) {
         let snapshot: Vec<Arc<ReaderState>> = self.readers.read().await.values().cloned().collect();
         for reader in snapshot {
-            if match_event_patterns(&reader.patterns, &event.eventid) {
+            if reader.accepts(event) {
                 reader.push(event.clone()).await;
             }
         }
@@ -259,7 +309,7 @@ impl KEventClientInner {
         let snapshot: Vec<Arc<ReaderState>> =
             self.readers.blocking_read().values().cloned().collect();
         for reader in snapshot {
-            if match_event_patterns(&reader.patterns, &event.eventid) {
+            if reader.accepts(event) {
                 reader.push_sync(event.clone());
             }
         }
@@ -393,6 +443,26 @@ impl KEventClient {
     }
 
     pub async fn create_event_reader(&self, patterns: Vec<String>) -> KEventResult<EventReader> {
+        self.create_event_reader_impl(patterns, None).await
+    }
+
+    /// Create a reader that first replays durable events with seq >= from_seq
+    /// from the daemon, then continues with live events.  If from_seq was
+    /// already trimmed, the first event is a `KEVENT_GAP_EVENTID` notification.
+    pub async fn create_event_reader_from(
+        &self,
+        patterns: Vec<String>,
+        from_seq: u64,
+    ) -> KEventResult<EventReader> {
+        self.create_event_reader_impl(patterns, Some(from_seq))
+            .await
+    }
+
+    async fn create_event_reader_impl(
+        &self,
+        patterns: Vec<String>,
+        from_seq: Option<u64>,
+    ) -> KEventResult<EventReader> {
         if patterns.is_empty() {
             return Err(KEventError::InvalidPattern(
                 "patterns must not be empty".to_string(),
@@ -415,6 +485,19 @@ impl KEventClient {
             }
         }
 
+        if from_seq.is_some() {
+            if self.mode != KEventClientMode::Full || !has_global_patterns {
+                return Err(KEventError::NotSupported(
+                    "durable replay requires global patterns in full mode".to_string(),
+                ));
+            }
+            if self.bridge.is_none() {
+                return Err(KEventError::DaemonUnavailable(
+                    "durable replay requires daemon bridge".to_string(),
+                ));
+            }
+        }
+
         if self.mode == KEventClientMode::Full
             && has_global_patterns
             && self.bridge.is_none()
@@ -433,6 +516,7 @@ impl KEventClient {
         let state = Arc::new(ReaderState::new(
             patterns.clone(),
             self.inner.reader_capacity.max(1),
+            from_seq.is_some(),
         ));
         self.inner
             .readers
@@ -448,10 +532,25 @@ impl KEventClient {
 
         if self.mode == KEventClientMode::Full && has_global_patterns {
             if let Some(bridge) = &self.bridge {
-                if let Err(err) = bridge.register_reader(&reader_id, &patterns).await {
+                let result = match from_seq {
+                    Some(from_seq) => {
+                        bridge
+                            .register_reader_from(&reader_id, &patterns, from_seq)
+                            .await
+                    }
+                    None => bridge.register_reader(&reader_id, &patterns).await,
+                };
+                if let Err(err) = result {
                     self.inner.readers.write().await.remove(&reader_id);
                     return Err(err);
                 }
+                if from_seq.is_some() {
+                    tokio::spawn(pump_bridge_reader(
+                        Arc::downgrade(&self.inner),
+                        bridge.clone(),
+                        reader_id.clone(),
+                    ));
+                }
             }
         }
 
@@ -480,6 +579,7 @@ impl KEventClient {
             },
             timestamp: now_millis(),
             data,
+            seq: None,
         };
 
         let is_global = is_global_eventid(eventid);
@@ -543,6 +643,26 @@ impl KEventClient {
         Ok(())
     }
 
+    // Called by daemon bridge receiver for events pulled on behalf of one reader
+    // (durable replay and gap notifications), which must not fan out to other readers.
+    pub async fn ingest_reader_event(&self, reader_id: &str, event: Event) -> KEventResult<()> {
+        if !is_global_eventid(&event.eventid) {
+            return Err(KEventError::InvalidEventId(
+                "ingest_reader_event only accepts global eventid".to_string(),
+            ));
+        }
+        let state = self
+            .inner
+            .readers
+            .read()
+            .await
+            .get(reader_id)
+            .cloned()
+            .ok_or_else(|| KEventError::ReaderClosed(reader_id.to_string()))?;
+        state.push(event).await;
+        Ok(())
+    }
+
     pub async fn create_timer(
         &self,
         eventid: &str,
@@ -638,6 +758,38 @@ impl KEventClient {
     }
 }
 
+// Moves the events of a resumed reader from the daemon bridge into its queue,
+// until the reader is closed or the client is dropped.
+async fn pump_bridge_reader(
+    weak: Weak<KEventClientInner>,
+    bridge: Arc<dyn KEventDaemonBridge>,
+    reader_id: String,
+) {
+    loop {
+        let result = bridge
+            .pull_reader_event(&reader_id, Some(BRIDGE_READER_PULL_TIMEOUT_MS))
+            .await;
+        let Some(inner) = weak.upgrade() else {
+            return;
+        };
+        let state = inner.readers.read().await.get(&reader_id).cloned();
+        let Some(state) = state else {
+            return;
+        };
+        match result {
+            Ok(Some(event)) => state.push(event).await,
+            Ok(None) => {}
+            Err(err) => {
+                warn!(
+                    "pull events of reader {} from bridge failed: {}",
+                    reader_id, err
+                );
+                return;
+            }
+        }
+    }
+}
+
 // ---------------------------------------------------------------------------
 // ShmDispatch background thread  (design doc §6)
 //
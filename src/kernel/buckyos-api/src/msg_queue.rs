@@ -1,10 +1,12 @@
 use ::kRPC::*;
 use async_trait::async_trait;
+use futures::Stream;
 use name_lib::DID;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::net::IpAddr;
+use std::time::Duration;
 
 use crate::{AppDoc, AppType, SelectorType};
 
@@ -28,6 +30,25 @@ pub fn generate_kmsg_service_doc() -> AppDoc {
     .unwrap()
 }
 
+/// 长轮询 fetch 的最长等待时间，超过的 wait_ms 会被截断
+pub const MAX_FETCH_WAIT_MS: u64 = 30_000;
+/// message_stream 每次长轮询的等待时间
+pub const STREAM_FETCH_WAIT_MS: u64 = 10_000;
+/// 消费组内消息租约的默认可见性超时，超时未 ack 的消息会重新分配给组内其他消费者
+pub const DEFAULT_VISIBILITY_TIMEOUT_MS: u64 = 30_000;
+
+/// 死信消息上记录来源的 headers
+pub const DLQ_HEADER_SOURCE_QUEUE: &str = "x-dlq-source-queue";
+pub const DLQ_HEADER_SOURCE_INDEX: &str = "x-dlq-source-index";
+pub const DLQ_HEADER_SUB_ID: &str = "x-dlq-sub-id";
+pub const DLQ_HEADER_ATTEMPTS: &str = "x-dlq-attempts";
+/// 消费组内转入死信时记录组名，重放时交还给整个组
+pub const DLQ_HEADER_GROUP: &str = "x-dlq-group";
+
+/// 从死信队列重放的消息只投递给原来的订阅或消费组，其他订阅视为已处理
+pub const REPLAY_HEADER_SUB_ID: &str = "x-replay-sub-id";
+pub const REPLAY_HEADER_GROUP: &str = "x-replay-group";
+
 /// 全局唯一的队列标识符 (Uniform Resource Name)
 /// 格式通常为: "appid::owner::name"
 pub type QueueUrn = String;
@@ -62,6 +83,75 @@ impl Message {
     }
 }
 
+/// 单个 header 匹配条件，header 不存在时不匹配
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum HeaderMatch {
+    Eq { key: String, value: String },
+    Prefix { key: String, prefix: String },
+    In { key: String, values: Vec<String> },
+}
+
+impl HeaderMatch {
+    pub fn matches(&self, headers: &HashMap<String, String>) -> bool {
+        match self {
+            Self::Eq { key, value } => headers.get(key) == Some(value),
+            Self::Prefix { key, prefix } => headers
+                .get(key)
+                .is_some_and(|value| value.starts_with(prefix.as_str())),
+            Self::In { key, values } => {
+                headers.get(key).is_some_and(|value| values.contains(value))
+            }
+        }
+    }
+}
+
+/// 订阅的 header 过滤表达式，所有条件都满足 (AND) 的消息才会投递给订阅者
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HeaderFilter {
+    pub all: Vec<HeaderMatch>,
+}
+
+impl HeaderFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn eq(mut self, key: &str, value: &str) -> Self {
+        self.all.push(HeaderMatch::Eq {
+            key: key.to_string(),
+            value: value.to_string(),
+        });
+        self
+    }
+
+    pub fn prefix(mut self, key: &str, prefix: &str) -> Self {
+        self.all.push(HeaderMatch::Prefix {
+            key: key.to_string(),
+            prefix: prefix.to_string(),
+        });
+        self
+    }
+
+    pub fn one_of(mut self, key: &str, values: &[&str]) -> Self {
+        self.all.push(HeaderMatch::In {
+            key: key.to_string(),
+            values: values.iter().map(|value| value.to_string()).collect(),
+        });
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.all.is_empty()
+    }
+
+    pub fn matches(&self, message: &Message) -> bool {
+        self.all
+            .iter()
+            .all(|condition| condition.matches(&message.headers))
+    }
+}
+
 /// 队列配置
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueueConfig {
@@ -69,6 +159,12 @@ pub struct QueueConfig {
     pub max_messages: Option<u64>,
     /// 消息过期时间 (秒)
     pub retention_seconds: Option<u64>,
+    /// 队列消息总字节数上限 (只计算 payload，None 表示不限制)
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+    /// 超出上述限制时是否允许删除订阅尚未确认的消息，默认只删除所有订阅都已确认的消息
+    #[serde(default)]
+    pub drop_unacked: bool,
     /// 是否需要同步落盘 (Write-Ahead-Log 语义)
     pub sync_write: bool,
 
@@ -76,6 +172,13 @@ pub struct QueueConfig {
     pub other_app_can_write: bool,
     pub other_user_can_read: bool,
     pub other_user_can_write: bool,
+
+    /// 同一订阅对单条消息的最大投递次数，用尽后转入死信队列 (None 表示不限制)
+    #[serde(default)]
+    pub max_delivery_attempts: Option<u32>,
+    /// 死信队列 URN，必须是已存在的队列；未设置时用尽投递次数的消息直接跳过
+    #[serde(default)]
+    pub dead_letter_queue: Option<QueueUrn>,
 }
 
 impl Default for QueueConfig {
@@ -83,11 +186,15 @@ impl Default for QueueConfig {
         Self {
             max_messages: None,
             retention_seconds: None,
+            max_bytes: None,
+            drop_unacked: false,
             sync_write: false,
             other_app_can_read: true,
             other_app_can_write: false,
             other_user_can_read: false,
             other_user_can_write: false,
+            max_delivery_attempts: None,
+            dead_letter_queue: None,
         }
     }
 }
@@ -224,6 +331,15 @@ pub struct MsgQueueSubscribeReq {
     pub app_id: String,
     pub sub_id: Option<String>,
     pub position: SubPosition,
+    /// 消费组名称，同组的订阅者分摊消息而不是各自收到全部消息
+    #[serde(default)]
+    pub group: Option<String>,
+    /// 消费组的可见性超时 (毫秒)，只在创建消费组时生效
+    #[serde(default)]
+    pub visibility_timeout_ms: Option<u64>,
+    /// 服务端按 header 过滤消息，不匹配的消息不会投递给该订阅
+    #[serde(default)]
+    pub filter: Option<HeaderFilter>,
 }
 
 impl MsgQueueSubscribeReq {
@@ -240,9 +356,23 @@ impl MsgQueueSubscribeReq {
             app_id,
             sub_id,
             position,
+            group: None,
+            visibility_timeout_ms: None,
+            filter: None,
         }
     }
 
+    pub fn with_filter(mut self, filter: HeaderFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    pub fn with_group(mut self, group: String, visibility_timeout_ms: Option<u64>) -> Self {
+        self.group = Some(group);
+        self.visibility_timeout_ms = visibility_timeout_ms;
+        self
+    }
+
     pub fn from_json(value: Value) -> std::result::Result<Self, RPCErrors> {
         serde_json::from_value(value).map_err(|e| {
             RPCErrors::ParseRequestError(format!("Failed to parse MsgQueueSubscribeReq: {}", e))
@@ -272,6 +402,9 @@ pub struct MsgQueueFetchMessagesReq {
     pub sub_id: SubscriptionId,
     pub length: usize,
     pub auto_commit: bool,
+    /// 没有新消息时最多等待的毫秒数，0 表示立即返回
+    #[serde(default)]
+    pub wait_ms: u64,
 }
 
 impl MsgQueueFetchMessagesReq {
@@ -280,9 +413,15 @@ impl MsgQueueFetchMessagesReq {
             sub_id,
             length,
             auto_commit,
+            wait_ms: 0,
         }
     }
 
+    pub fn with_wait(mut self, wait_ms: u64) -> Self {
+        self.wait_ms = wait_ms;
+        self
+    }
+
     pub fn from_json(value: Value) -> std::result::Result<Self, RPCErrors> {
         serde_json::from_value(value).map_err(|e| {
             RPCErrors::ParseRequestError(format!("Failed to parse MsgQueueFetchMessagesReq: {}", e))
@@ -331,6 +470,53 @@ impl MsgQueueCommitAckReq {
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MsgQueueNackReq {
+    pub sub_id: SubscriptionId,
+    pub index: MsgIndex,
+    /// 重新投递前的等待时间 (毫秒)
+    #[serde(default)]
+    pub requeue_after: u64,
+}
+
+impl MsgQueueNackReq {
+    pub fn new(sub_id: SubscriptionId, index: MsgIndex, requeue_after: u64) -> Self {
+        Self {
+            sub_id,
+            index,
+            requeue_after,
+        }
+    }
+
+    pub fn from_json(value: Value) -> std::result::Result<Self, RPCErrors> {
+        serde_json::from_value(value).map_err(|e| {
+            RPCErrors::ParseRequestError(format!("Failed to parse MsgQueueNackReq: {}", e))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MsgQueueReplayDeadLetterReq {
+    /// 死信队列 URN
+    pub queue_urn: QueueUrn,
+    pub index: MsgIndex,
+}
+
+impl MsgQueueReplayDeadLetterReq {
+    pub fn new(queue_urn: QueueUrn, index: MsgIndex) -> Self {
+        Self { queue_urn, index }
+    }
+
+    pub fn from_json(value: Value) -> std::result::Result<Self, RPCErrors> {
+        serde_json::from_value(value).map_err(|e| {
+            RPCErrors::ParseRequestError(format!(
+                "Failed to parse MsgQueueReplayDeadLetterReq: {}",
+                e
+            ))
+        })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MsgQueueSeekReq {
     pub sub_id: SubscriptionId,
@@ -569,6 +755,106 @@ impl MsgQueueClient {
         }
     }
 
+    /// 带 header 过滤条件的订阅，不匹配的消息在服务端被跳过
+    pub async fn subscribe_filtered(
+        &self,
+        queue_urn: &str,
+        user_id: &str,
+        app_id: &str,
+        sub_id: Option<String>,
+        position: SubPosition,
+        filter: HeaderFilter,
+    ) -> std::result::Result<SubscriptionId, RPCErrors> {
+        match self {
+            Self::InProcess(handler) => {
+                let ctx = RPCContext::default();
+                handler
+                    .handle_subscribe_filtered(
+                        queue_urn, user_id, app_id, sub_id, position, filter, ctx,
+                    )
+                    .await
+            }
+            Self::KRPC(client) => {
+                let req = MsgQueueSubscribeReq::new(
+                    queue_urn.to_string(),
+                    user_id.to_string(),
+                    app_id.to_string(),
+                    sub_id,
+                    position,
+                )
+                .with_filter(filter);
+                let req_json = serde_json::to_value(&req).map_err(|e| {
+                    RPCErrors::ReasonError(format!(
+                        "Failed to serialize MsgQueueSubscribeReq: {}",
+                        e
+                    ))
+                })?;
+                let result = client.call("subscribe", req_json).await?;
+                result
+                    .as_str()
+                    .map(|value| value.to_string())
+                    .ok_or_else(|| {
+                        RPCErrors::ParserResponseError("Expected SubscriptionId string".to_string())
+                    })
+            }
+        }
+    }
+
+    /// 以消费组成员的身份订阅。组内的消息按条租给成员，成员需要逐条 commit_ack，
+    /// 租约在 visibility_timeout_ms 内未确认会重新分配给其他成员
+    #[allow(clippy::too_many_arguments)]
+    pub async fn subscribe_group(
+        &self,
+        queue_urn: &str,
+        user_id: &str,
+        app_id: &str,
+        group: &str,
+        sub_id: Option<String>,
+        position: SubPosition,
+        visibility_timeout_ms: Option<u64>,
+    ) -> std::result::Result<SubscriptionId, RPCErrors> {
+        match self {
+            Self::InProcess(handler) => {
+                let ctx = RPCContext::default();
+                handler
+                    .handle_subscribe_group(
+                        queue_urn,
+                        user_id,
+                        app_id,
+                        group,
+                        sub_id,
+                        position,
+                        visibility_timeout_ms.unwrap_or(DEFAULT_VISIBILITY_TIMEOUT_MS),
+                        ctx,
+                    )
+                    .await
+            }
+            Self::KRPC(client) => {
+                let req = MsgQueueSubscribeReq::new(
+                    queue_urn.to_string(),
+                    user_id.to_string(),
+                    app_id.to_string(),
+                    sub_id,
+                    position,
+                )
+                .with_group(group.to_string(), visibility_timeout_ms);
+                let req_json = serde_json::to_value(&req).map_err(|e| {
+                    RPCErrors::ReasonError(format!(
+                        "Failed to serialize MsgQueueSubscribeReq: {}",
+                        e
+                    ))
+                })?;
+                let result = client.call("subscribe", req_json).await?;
+                result
+                    .as_str()
+                    .map(|value| value.to_string())
+                    .ok_or_else(|| {
+                        RPCErrors::ParserResponseError("Expected SubscriptionId string".to_string())
+                    })
+            }
+        }
+    }
+
     pub async fn unsubscribe(&self, sub_id: &str) -> std::result::Result<(), RPCErrors> {
         match self {
             Self::InProcess(handler) => {
@@ -594,16 +880,35 @@ impl MsgQueueClient {
         sub_id: &str,
         length: usize,
         auto_commit: bool,
+    ) -> std::result::Result<Vec<Message>, RPCErrors> {
+        self.fetch_messages_wait(sub_id, length, auto_commit, 0)
+            .await
+    }
+
+    /// 长轮询：没有可投递的消息时最多等待 wait_ms 毫秒，期间有新消息写入会立即返回
+    pub async fn fetch_messages_wait(
+        &self,
+        sub_id: &str,
+        length: usize,
+        auto_commit: bool,
+        wait_ms: u64,
     ) -> std::result::Result<Vec<Message>, RPCErrors> {
         match self {
             Self::InProcess(handler) => {
                 let ctx = RPCContext::default();
-                handler
-                    .handle_fetch_messages(sub_id, length, auto_commit, ctx)
-                    .await
+                if wait_ms == 0 {
+                    handler
+                        .handle_fetch_messages(sub_id, length, auto_commit, ctx)
+                        .await
+                } else {
+                    handler
+                        .handle_fetch_messages_wait(sub_id, length, auto_commit, wait_ms, ctx)
+                        .await
+                }
             }
             Self::KRPC(client) => {
-                let req = MsgQueueFetchMessagesReq::new(sub_id.to_string(), length, auto_commit);
+                let req = MsgQueueFetchMessagesReq::new(sub_id.to_string(), length, auto_commit)
+                    .with_wait(wait_ms);
                 let req_json = serde_json::to_value(&req).map_err(|e| {
                     RPCErrors::ReasonError(format!(
                         "Failed to serialize MsgQueueFetchMessagesReq: {}",
@@ -621,6 +926,53 @@ impl MsgQueueClient {
         }
     }
 
+    /// 把订阅变成消息流，内部用长轮询等待新消息。
+    /// 拉取下一条消息时会提交上一条的 ack，消费者中途退出时未处理完的消息会被重新投递 (at-least-once)。
+    /// 出错时产出一次 Err 后流结束。
+    pub fn message_stream<'a>(
+        &'a self,
+        sub_id: &'a str,
+        batch: usize,
+    ) -> impl Stream<Item = std::result::Result<Message, RPCErrors>> + Send + 'a {
+        struct StreamState {
+            buffer: VecDeque<Message>,
+            pending_ack: Option<MsgIndex>,
+            done: bool,
+        }
+
+        let state = StreamState {
+            buffer: VecDeque::new(),
+            pending_ack: None,
+            done: false,
+        };
+        futures::stream::unfold(state, move |mut state| async move {
+            if state.done {
+                return None;
+            }
+            if let Some(index) = state.pending_ack.take() {
+                if let Err(err) = self.commit_ack(sub_id, index).await {
+                    state.done = true;
+                    return Some((Err(err), state));
+                }
+            }
+            while state.buffer.is_empty() {
+                match self
+                    .fetch_messages_wait(sub_id, batch.max(1), false, STREAM_FETCH_WAIT_MS)
+                    .await
+                {
+                    Ok(messages) => state.buffer.extend(messages),
+                    Err(err) => {
+                        state.done = true;
+                        return Some((Err(err), state));
+                    }
+                }
+            }
+            let message = state.buffer.pop_front()?;
+            state.pending_ack = Some(message.index);
+            Some((Ok(message), state))
+        })
+    }
+
     pub async fn read_message(
         &self,
         queue_urn: &str,
@@ -677,6 +1029,57 @@ impl MsgQueueClient {
         }
     }
 
+    pub async fn nack(
+        &self,
+        sub_id: &str,
+        index: MsgIndex,
+        requeue_after: u64,
+    ) -> std::result::Result<(), RPCErrors> {
+        match self {
+            Self::InProcess(handler) => {
+                let ctx = RPCContext::default();
+                handler.handle_nack(sub_id, index, requeue_after, ctx).await
+            }
+            Self::KRPC(client) => {
+                let req = MsgQueueNackReq::new(sub_id.to_string(), index, requeue_after);
+                let req_json = serde_json::to_value(&req).map_err(|e| {
+                    RPCErrors::ReasonError(format!("Failed to serialize MsgQueueNackReq: {}", e))
+                })?;
+                client.call("nack", req_json).await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// 将死信队列中的一条消息重新投递回来源队列，返回在来源队列中的新索引
+    pub async fn replay_dead_letter(
+        &self,
+        dead_letter_queue: &str,
+        index: MsgIndex,
+    ) -> std::result::Result<MsgIndex, RPCErrors> {
+        match self {
+            Self::InProcess(handler) => {
+                let ctx = RPCContext::default();
+                handler
+                    .handle_replay_dead_letter(dead_letter_queue, index, ctx)
+                    .await
+            }
+            Self::KRPC(client) => {
+                let req = MsgQueueReplayDeadLetterReq::new(dead_letter_queue.to_string(), index);
+                let req_json = serde_json::to_value(&req).map_err(|e| {
+                    RPCErrors::ReasonError(format!(
+                        "Failed to serialize MsgQueueReplayDeadLetterReq: {}",
+                        e
+                    ))
+                })?;
+                let result = client.call("replay_dead_letter", req_json).await?;
+                result.as_u64().ok_or_else(|| {
+                    RPCErrors::ParserResponseError("Expected MsgIndex u64".to_string())
+                })
+            }
+        }
+    }
+
     pub async fn seek(
         &self,
         sub_id: &str,
@@ -774,6 +1177,46 @@ pub trait MsgQueueHandler: Send + Sync {
         ctx: RPCContext,
     ) -> std::result::Result<SubscriptionId, RPCErrors>;
 
+    /// 带 header 过滤条件的订阅，空的过滤条件等同于 handle_subscribe
+    #[allow(clippy::too_many_arguments)]
+    async fn handle_subscribe_filtered(
+        &self,
+        queue_urn: &str,
+        user_id: &str,
+        app_id: &str,
+        sub_id: Option<String>,
+        position: SubPosition,
+        filter: HeaderFilter,
+        ctx: RPCContext,
+    ) -> std::result::Result<SubscriptionId, RPCErrors> {
+        if !filter.is_empty() {
+            return Err(RPCErrors::ReasonError(
+                "subscription filter is not supported".to_string(),
+            ));
+        }
+        self.handle_subscribe(queue_urn, user_id, app_id, sub_id, position, ctx)
+            .await
+    }
+
+    /// 加入 (不存在时创建) 队列上的消费组。
+    /// 组成员的 fetch 会领取消息租约，commit_ack 只确认单条消息，nack 立即释放租约
+    #[allow(clippy::too_many_arguments)]
+    async fn handle_subscribe_group(
+        &self,
+        _queue_urn: &str,
+        _user_id: &str,
+        _app_id: &str,
+        _group: &str,
+        _sub_id: Option<String>,
+        _position: SubPosition,
+        _visibility_timeout_ms: u64,
+        _ctx: RPCContext,
+    ) -> std::result::Result<SubscriptionId, RPCErrors> {
+        Err(RPCErrors::ReasonError(
+            "consumer group is not supported".to_string(),
+        ))
+    }
+
     async fn handle_unsubscribe(
         &self,
         sub_id: &str,
@@ -788,6 +1231,30 @@ pub trait MsgQueueHandler: Send + Sync {
         ctx: RPCContext,
     ) -> std::result::Result<Vec<Message>, RPCErrors>;
 
+    /// 长轮询版本的 fetch。默认实现按固定间隔重试，存储实现可以在新消息写入时直接唤醒
+    async fn handle_fetch_messages_wait(
+        &self,
+        sub_id: &str,
+        length: usize,
+        auto_commit: bool,
+        wait_ms: u64,
+        ctx: RPCContext,
+    ) -> std::result::Result<Vec<Message>, RPCErrors> {
+        const POLL_INTERVAL_MS: u64 = 100;
+        let deadline =
+            tokio::time::Instant::now() + Duration::from_millis(wait_ms.min(MAX_FETCH_WAIT_MS));
+        loop {
+            let messages = self
+                .handle_fetch_messages(sub_id, length, auto_commit, ctx.clone())
+                .await?;
+            let now = tokio::time::Instant::now();
+            if !messages.is_empty() || now >= deadline {
+                return Ok(messages);
+            }
+            tokio::time::sleep((deadline - now).min(Duration::from_millis(POLL_INTERVAL_MS))).await;
+        }
+    }
+
     async fn handle_read_message(
         &self,
         queue_urn: &str,
@@ -810,6 +1277,28 @@ pub trait MsgQueueHandler: Send + Sync {
         ctx: RPCContext,
     ) -> std::result::Result<(), RPCErrors>;
 
+    /// 消费失败：index 在 requeue_after 毫秒后重新投递，投递次数用尽时转入死信队列
+    async fn handle_nack(
+        &self,
+        _sub_id: &str,
+        _index: MsgIndex,
+        _requeue_after: u64,
+        _ctx: RPCContext,
+    ) -> std::result::Result<(), RPCErrors> {
+        Err(RPCErrors::ReasonError("nack is not supported".to_string()))
+    }
+
+    async fn handle_replay_dead_letter(
+        &self,
+        _queue_urn: &str,
+        _index: MsgIndex,
+        _ctx: RPCContext,
+    ) -> std::result::Result<MsgIndex, RPCErrors> {
+        Err(RPCErrors::ReasonError(
+            "replay_dead_letter is not supported".to_string(),
+        ))
+    }
+
     async fn handle_delete_message_before(
         &self,
         queue_urn: &str,
@@ -886,17 +1375,57 @@ impl<T: MsgQueueHandler> RPCHandler for MsgQueueServerHandler<T> {
             }
             "subscribe" => {
                 let subscribe_req = MsgQueueSubscribeReq::from_json(req.params)?;
-                let result = self
-                    .0
-                    .handle_subscribe(
-                        &subscribe_req.queue_urn,
-                        &subscribe_req.user_id,
-                        &subscribe_req.app_id,
-                        subscribe_req.sub_id,
-                        subscribe_req.position,
-                        ctx,
-                    )
-                    .await?;
+                let filter = subscribe_req.filter.filter(|filter| !filter.is_empty());
+                let result = match subscribe_req.group.as_deref() {
+                    Some(_) if filter.is_some() => {
+                        return Err(RPCErrors::ReasonError(
+                            "subscription filter is not supported for consumer groups".to_string(),
+                        ));
+                    }
+                    Some(group) => {
+                        self.0
+                            .handle_subscribe_group(
+                                &subscribe_req.queue_urn,
+                                &subscribe_req.user_id,
+                                &subscribe_req.app_id,
+                                group,
+                                subscribe_req.sub_id,
+                                subscribe_req.position,
+                                subscribe_req
+                                    .visibility_timeout_ms
+                                    .unwrap_or(DEFAULT_VISIBILITY_TIMEOUT_MS),
+                                ctx,
+                            )
+                            .await?
+                    }
+                    None => match filter {
+                        Some(filter) => {
+                            self.0
+                                .handle_subscribe_filtered(
+                                    &subscribe_req.queue_urn,
+                                    &subscribe_req.user_id,
+                                    &subscribe_req.app_id,
+                                    subscribe_req.sub_id,
+                                    subscribe_req.position,
+                                    filter,
+                                    ctx,
+                                )
+                                .await?
+                        }
+                        None => {
+                            self.0
+                                .handle_subscribe(
+                                    &subscribe_req.queue_urn,
+                                    &subscribe_req.user_id,
+                                    &subscribe_req.app_id,
+                                    subscribe_req.sub_id,
+                                    subscribe_req.position,
+                                    ctx,
+                                )
+                                .await?
+                        }
+                    },
+                };
                 RPCResult::Success(json!(result))
             }
             "unsubscribe" => {
@@ -909,15 +1438,26 @@ impl<T: MsgQueueHandler> RPCHandler for MsgQueueServerHandler<T> {
             }
             "fetch_messages" => {
                 let fetch_req = MsgQueueFetchMessagesReq::from_json(req.params)?;
-                let result = self
-                    .0
-                    .handle_fetch_messages(
-                        &fetch_req.sub_id,
-                        fetch_req.length,
-                        fetch_req.auto_commit,
-                        ctx,
-                    )
-                    .await?;
+                let result = if fetch_req.wait_ms == 0 {
+                    self.0
+                        .handle_fetch_messages(
+                            &fetch_req.sub_id,
+                            fetch_req.length,
+                            fetch_req.auto_commit,
+                            ctx,
+                        )
+                        .await?
+                } else {
+                    self.0
+                        .handle_fetch_messages_wait(
+                            &fetch_req.sub_id,
+                            fetch_req.length,
+                            fetch_req.auto_commit,
+                            fetch_req.wait_ms,
+                            ctx,
+                        )
+                        .await?
+                };
                 RPCResult::Success(json!(result))
             }
             "read_message" => {
@@ -936,6 +1476,27 @@ impl<T: MsgQueueHandler> RPCHandler for MsgQueueServerHandler<T> {
                     .await?;
                 RPCResult::Success(json!(result))
             }
+            "nack" => {
+                let nack_req = MsgQueueNackReq::from_json(req.params)?;
+                let result = self
+                    .0
+                    .handle_nack(
+                        &nack_req.sub_id,
+                        nack_req.index,
+                        nack_req.requeue_after,
+                        ctx,
+                    )
+                    .await?;
+                RPCResult::Success(json!(result))
+            }
+            "replay_dead_letter" => {
+                let replay_req = MsgQueueReplayDeadLetterReq::from_json(req.params)?;
+                let result = self
+                    .0
+                    .handle_replay_dead_letter(&replay_req.queue_urn, replay_req.index, ctx)
+                    .await?;
+                RPCResult::Success(json!(result))
+            }
             "seek" => {
                 let seek_req = MsgQueueSeekReq::from_json(req.params)?;
                 let result = self
@@ -1506,4 +2067,44 @@ mod tests {
         assert_eq!(messages.len(), 1);
         assert_eq!(messages[0].payload, b"path-message".to_vec());
     }
+
+    #[tokio::test]
+    async fn test_message_stream_waits_and_acks() {
+        use futures::StreamExt;
+
+        let client = build_client();
+        let queue_urn = client
+            .create_queue(Some("stream"), "app", "owner", QueueConfig::default())
+            .await
+            .unwrap();
+        let sub_id = client
+            .subscribe(&queue_urn, "user", "app", None, SubPosition::Earliest)
+            .await
+            .unwrap();
+
+        let consumer = async {
+            let stream = client.message_stream(&sub_id, 4);
+            futures::pin_mut!(stream);
+            let first = stream.next().await.unwrap().unwrap();
+            let second = stream.next().await.unwrap().unwrap();
+            (first, second)
+        };
+        let producer = async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            for payload in [b"one".to_vec(), b"two".to_vec()] {
+                client
+                    .post_message(&queue_urn, Message::new(payload))
+                    .await
+                    .unwrap();
+            }
+        };
+        let ((first, second), _) = tokio::join!(consumer, producer);
+        assert_eq!(first.payload, b"one".to_vec());
+        assert_eq!(second.payload, b"two".to_vec());
+
+        // only the message the consumer moved past is acked, "two" is redelivered
+        let pending = client.fetch_messages(&sub_id, 2, false).await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].payload, b"two".to_vec());
+    }
 }
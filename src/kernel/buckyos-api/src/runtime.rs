@@ -178,6 +178,7 @@ pub struct BuckyOSRuntime {
     pub refresh_token: Arc<RwLock<String>>,
     trust_keys: Arc<RwLock<HashMap<String, DecodingKey>>>,
     last_update_service_info_time: RwLock<u64>,
+    service_instance_lease: RwLock<Option<SystemConfigLease>>,
     named_store_mgr: OnceCell<NamedStoreMgr>,
     system_config_client: OnceCell<Arc<SystemConfigClient>>,
     background_task_status:
@@ -231,6 +232,7 @@ impl BuckyOSRuntime {
             node_gateway_port: DEFAULT_NODE_GATEWAY_PORT,
             trust_keys: Arc::new(RwLock::new(HashMap::new())),
             last_update_service_info_time: RwLock::new(0),
+            service_instance_lease: RwLock::new(None),
             named_store_mgr: OnceCell::new(),
             system_config_client: OnceCell::new(),
             background_task_status: Arc::new(RwLock::new(HashMap::from([
@@ -952,12 +954,21 @@ impl BuckyOSRuntime {
             last_update_time: buckyos_get_unix_timestamp(),
             start_time: 0,
             pid: std::process::id(),
+            lease_expire_time: None,
         };
 
         let control_panel_client = self.get_control_panel_client().await?;
-        control_panel_client
-            .update_service_instance_info(&self.app_id, &node_id, &service_instance_info)
+        let mut service_instance_lease = self.service_instance_lease.write().await;
+        let lease = control_panel_client
+            .update_service_instance_info_with_lease(
+                &self.app_id,
+                &node_id,
+                &service_instance_info,
+                service_instance_lease.as_ref(),
+            )
             .await?;
+        *service_instance_lease = Some(lease);
+        drop(service_instance_lease);
         let mut last_update_service_info_time = self.last_update_service_info_time.write().await;
         *last_update_service_info_time = now;
         info!("update service instance info,app_id:{}", self.app_id);
@@ -1,17 +1,672 @@
 use crate::{AppDoc, AppType, SelectorType};
 use ::kRPC::*;
+use async_trait::async_trait;
 use name_lib::DID;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::IpAddr;
 
 pub const SCHEDULER_SERVICE_UNIQUE_ID: &str = "scheduler";
 pub const SCHEDULER_SERVICE_SERVICE_NAME: &str = "scheduler";
 pub const SCHEDULER_SERVICE_SERVICE_PORT: u16 = 3220;
-pub struct SchedulerClient {
-    rpc_client: kRPC,
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SchedulerNodeInfo {
+    pub id: String,
+    pub node_type: String,
+    pub state: String,
+    pub labels: Vec<String>,
+    pub network_zone: String,
+    pub support_container: bool,
+    pub available_cpu_mhz: u32,
+    pub total_cpu_mhz: u32,
+    pub available_memory: u64,
+    pub total_memory: u64,
+    pub available_gpu_memory: u64,
+    pub total_gpu_memory: u64,
+    pub instance_count: usize,
+    pub pending_op_task_count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SchedulerSpecInfo {
+    pub id: String,
+    pub app_id: String,
+    pub owner_id: String,
+    pub spec_type: String,
+    pub state: String,
+    pub best_instance_count: u32,
+    pub instance_count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SchedulerInstanceInfo {
+    pub instance_id: String,
+    pub spec_id: String,
+    pub node_id: String,
+    pub state: String,
+    pub last_update_time: u64,
+    #[serde(default)]
+    pub lease_expire_time: Option<u64>,
+    #[serde(default)]
+    pub service_ports: HashMap<String, u16>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SchedulerOpTaskInfo {
+    pub id: String,
+    pub node_id: String,
+    #[serde(default)]
+    pub creator_id: Option<String>,
+    pub body: String,
+    pub status: String,
+    pub create_time: u64,
+    pub start_time: u64,
+    pub max_timeout_sec: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SchedulerStatus {
+    pub has_snapshot: bool,
+    pub schedule_time: u64,
+    pub node_count: usize,
+    pub spec_count: usize,
+    pub instance_count: usize,
+    pub pending_op_task_count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchedulerGetSnapshotResult {
+    //NodeScheduler的原始json,调度器还没有完成过调度时为None
+    pub snapshot: Option<Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchedulerListNodesResult {
+    pub nodes: Vec<SchedulerNodeInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchedulerListSpecsResult {
+    pub specs: Vec<SchedulerSpecInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchedulerListInstancesResult {
+    pub instances: Vec<SchedulerInstanceInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchedulerListOpTasksResult {
+    pub tasks: Vec<SchedulerOpTaskInfo>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchedulerTriggerScheduleResult {
+    pub accepted: bool,
+}
+
+//节点标签选择表达式。标签形如"key=value",只有"key"的标签其value为空字符串。
+//调度器还会为每个node提供隐含标签: node_id / node_type / network_zone
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LabelSelectorOperator {
+    In,
+    NotIn,
+    Exists,
+    DoesNotExist,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LabelSelectorRequirement {
+    pub key: String,
+    pub operator: LabelSelectorOperator,
+    #[serde(default)]
+    pub values: Vec<String>,
+}
+
+impl LabelSelectorRequirement {
+    pub fn new(key: &str, operator: LabelSelectorOperator, values: Vec<String>) -> Self {
+        Self {
+            key: key.to_string(),
+            operator,
+            values,
+        }
+    }
+
+    pub fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        let value = labels.get(&self.key);
+        match self.operator {
+            LabelSelectorOperator::In => value.is_some_and(|value| self.values.contains(value)),
+            LabelSelectorOperator::NotIn => value.is_none_or(|value| !self.values.contains(value)),
+            LabelSelectorOperator::Exists => value.is_some(),
+            LabelSelectorOperator::DoesNotExist => value.is_none(),
+        }
+    }
+}
+
+//一组表达式之间是AND关系
+pub fn match_label_selectors(
+    selectors: &[LabelSelectorRequirement],
+    labels: &HashMap<String, String>,
+) -> bool {
+    selectors.iter().all(|selector| selector.matches(labels))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PreferredLabelSelector {
+    //匹配时给node加的分数
+    pub weight: u32,
+    pub match_expressions: Vec<LabelSelectorRequirement>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct NodeAffinity {
+    //必须全部满足,不满足的node会被过滤掉
+    #[serde(default)]
+    pub required: Vec<LabelSelectorRequirement>,
+    //尽量满足,影响node打分
+    #[serde(default)]
+    pub preferred: Vec<PreferredLabelSelector>,
+}
+
+impl NodeAffinity {
+    //最常见的用法: 要求node带有某个标签
+    pub fn required_label(key: &str) -> Self {
+        Self {
+            required: vec![LabelSelectorRequirement::new(
+                key,
+                LabelSelectorOperator::Exists,
+                vec![],
+            )],
+            preferred: vec![],
+        }
+    }
+}
+
+//副本反亲和/分散约束: 同一个spec的副本按topology_key(node标签key)划分的拓扑域分散
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ReplicaSpread {
+    pub topology_key: String,
+    //true: 同一拓扑域最多一个副本; false: 尽量分散,同域已有副本时降低打分
+    #[serde(default)]
+    pub required: bool,
+}
+
+//dry run时在调度快照副本上应用的假设变更
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SchedulerDryRunMutation {
+    AddNode {
+        node_id: String,
+        #[serde(default)]
+        node_type: Option<String>, //默认为ood
+        #[serde(default)]
+        state: Option<String>, //默认为New
+        #[serde(default)]
+        labels: Vec<String>,
+        #[serde(default)]
+        network_zone: Option<String>,
+        #[serde(default)]
+        support_container: bool,
+        cpu_mhz: u32,
+        memory: u64,
+        #[serde(default)]
+        gpu_memory: u64,
+        #[serde(default)]
+        gpu_tflops: f32,
+    },
+    //把node标记为Removing,预览调度器移除node时的动作
+    RemoveNode {
+        node_id: String,
+    },
+    SetSpecResources {
+        spec_id: String,
+        #[serde(default)]
+        required_cpu_mhz: Option<u32>,
+        #[serde(default)]
+        required_memory: Option<u64>,
+        #[serde(default)]
+        required_gpu_tflops: Option<f32>,
+        #[serde(default)]
+        required_gpu_mem: Option<u64>,
+    },
+    SetSpecAffinity {
+        spec_id: String,
+        #[serde(default)]
+        node_affinity: Option<NodeAffinity>,
+        #[serde(default)]
+        network_affinity: Option<String>,
+        #[serde(default)]
+        replica_spread: Option<ReplicaSpread>,
+    },
+    SetBestInstanceCount {
+        spec_id: String,
+        count: u32,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SchedulerPlannedAction {
+    pub action: String,
+    pub target: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SchedulerDryRunResult {
+    pub actions: Vec<SchedulerPlannedAction>,
+    //每个mutation被应用后的描述
+    pub notes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SchedulerListInstancesReq {
+    #[serde(default)]
+    pub spec_id: Option<String>,
+    #[serde(default)]
+    pub node_id: Option<String>,
+}
+
+impl SchedulerListInstancesReq {
+    pub fn new(spec_id: Option<String>, node_id: Option<String>) -> Self {
+        Self { spec_id, node_id }
+    }
+
+    pub fn from_json(value: Value) -> Result<Self> {
+        serde_json::from_value(value).map_err(|e| {
+            RPCErrors::ParseRequestError(format!(
+                "Failed to parse SchedulerListInstancesReq: {}",
+                e
+            ))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SchedulerListOpTasksReq {
+    #[serde(default)]
+    pub node_id: Option<String>,
+    //为false时只返回New/Running状态的任务
+    #[serde(default)]
+    pub include_finished: bool,
+}
+
+impl SchedulerListOpTasksReq {
+    pub fn new(node_id: Option<String>, include_finished: bool) -> Self {
+        Self {
+            node_id,
+            include_finished,
+        }
+    }
+
+    pub fn from_json(value: Value) -> Result<Self> {
+        serde_json::from_value(value).map_err(|e| {
+            RPCErrors::ParseRequestError(format!("Failed to parse SchedulerListOpTasksReq: {}", e))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchedulerCancelOpTaskReq {
+    pub task_id: String,
+}
+
+impl SchedulerCancelOpTaskReq {
+    pub fn new(task_id: String) -> Self {
+        Self { task_id }
+    }
+
+    pub fn from_json(value: Value) -> Result<Self> {
+        serde_json::from_value(value).map_err(|e| {
+            RPCErrors::ParseRequestError(format!("Failed to parse SchedulerCancelOpTaskReq: {}", e))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SchedulerDryRunReq {
+    #[serde(default)]
+    pub mutations: Vec<SchedulerDryRunMutation>,
+}
+
+impl SchedulerDryRunReq {
+    pub fn new(mutations: Vec<SchedulerDryRunMutation>) -> Self {
+        Self { mutations }
+    }
+
+    pub fn from_json(value: Value) -> Result<Self> {
+        serde_json::from_value(value).map_err(|e| {
+            RPCErrors::ParseRequestError(format!("Failed to parse SchedulerDryRunReq: {}", e))
+        })
+    }
+}
+
+pub enum SchedulerClient {
+    InProcess(Box<dyn SchedulerHandler>),
+    KRPC(Box<kRPC>),
 }
 
 impl SchedulerClient {
     pub fn new(rpc_client: kRPC) -> Self {
-        Self { rpc_client }
+        Self::KRPC(Box::new(rpc_client))
+    }
+
+    pub fn new_in_process(handler: Box<dyn SchedulerHandler>) -> Self {
+        Self::InProcess(handler)
+    }
+
+    pub fn new_krpc(client: Box<kRPC>) -> Self {
+        Self::KRPC(client)
+    }
+
+    pub async fn set_context(&self, context: RPCContext) {
+        match self {
+            Self::InProcess(_) => {}
+            Self::KRPC(client) => client.set_context(context).await,
+        }
+    }
+
+    pub async fn get_status(&self) -> Result<SchedulerStatus> {
+        match self {
+            Self::InProcess(handler) => handler.handle_get_status(RPCContext::default()).await,
+            Self::KRPC(client) => {
+                let result = client.call("get_status", json!({})).await?;
+                serde_json::from_value::<SchedulerStatus>(result).map_err(|e| {
+                    RPCErrors::ParserResponseError(format!(
+                        "Expected SchedulerStatus response: {}",
+                        e
+                    ))
+                })
+            }
+        }
+    }
+
+    pub async fn get_snapshot(&self) -> Result<Option<Value>> {
+        match self {
+            Self::InProcess(handler) => handler.handle_get_snapshot(RPCContext::default()).await,
+            Self::KRPC(client) => {
+                let result = client.call("get_snapshot", json!({})).await?;
+                let response = serde_json::from_value::<SchedulerGetSnapshotResult>(result)
+                    .map_err(|e| {
+                        RPCErrors::ParserResponseError(format!(
+                            "Expected SchedulerGetSnapshotResult response: {}",
+                            e
+                        ))
+                    })?;
+                Ok(response.snapshot)
+            }
+        }
+    }
+
+    pub async fn list_nodes(&self) -> Result<Vec<SchedulerNodeInfo>> {
+        match self {
+            Self::InProcess(handler) => handler.handle_list_nodes(RPCContext::default()).await,
+            Self::KRPC(client) => {
+                let result = client.call("list_nodes", json!({})).await?;
+                let response =
+                    serde_json::from_value::<SchedulerListNodesResult>(result).map_err(|e| {
+                        RPCErrors::ParserResponseError(format!(
+                            "Expected SchedulerListNodesResult response: {}",
+                            e
+                        ))
+                    })?;
+                Ok(response.nodes)
+            }
+        }
+    }
+
+    pub async fn list_specs(&self) -> Result<Vec<SchedulerSpecInfo>> {
+        match self {
+            Self::InProcess(handler) => handler.handle_list_specs(RPCContext::default()).await,
+            Self::KRPC(client) => {
+                let result = client.call("list_specs", json!({})).await?;
+                let response =
+                    serde_json::from_value::<SchedulerListSpecsResult>(result).map_err(|e| {
+                        RPCErrors::ParserResponseError(format!(
+                            "Expected SchedulerListSpecsResult response: {}",
+                            e
+                        ))
+                    })?;
+                Ok(response.specs)
+            }
+        }
+    }
+
+    pub async fn list_instances(
+        &self,
+        spec_id: Option<&str>,
+        node_id: Option<&str>,
+    ) -> Result<Vec<SchedulerInstanceInfo>> {
+        let req = SchedulerListInstancesReq::new(
+            spec_id.map(|value| value.to_string()),
+            node_id.map(|value| value.to_string()),
+        );
+        match self {
+            Self::InProcess(handler) => {
+                handler
+                    .handle_list_instances(req, RPCContext::default())
+                    .await
+            }
+            Self::KRPC(client) => {
+                let req_json = serde_json::to_value(&req).map_err(|e| {
+                    RPCErrors::ReasonError(format!("Failed to serialize request: {}", e))
+                })?;
+                let result = client.call("list_instances", req_json).await?;
+                let response = serde_json::from_value::<SchedulerListInstancesResult>(result)
+                    .map_err(|e| {
+                        RPCErrors::ParserResponseError(format!(
+                            "Expected SchedulerListInstancesResult response: {}",
+                            e
+                        ))
+                    })?;
+                Ok(response.instances)
+            }
+        }
+    }
+
+    pub async fn list_op_tasks(
+        &self,
+        node_id: Option<&str>,
+        include_finished: bool,
+    ) -> Result<Vec<SchedulerOpTaskInfo>> {
+        let req =
+            SchedulerListOpTasksReq::new(node_id.map(|value| value.to_string()), include_finished);
+        match self {
+            Self::InProcess(handler) => {
+                handler
+                    .handle_list_op_tasks(req, RPCContext::default())
+                    .await
+            }
+            Self::KRPC(client) => {
+                let req_json = serde_json::to_value(&req).map_err(|e| {
+                    RPCErrors::ReasonError(format!("Failed to serialize request: {}", e))
+                })?;
+                let result = client.call("list_op_tasks", req_json).await?;
+                let response = serde_json::from_value::<SchedulerListOpTasksResult>(result)
+                    .map_err(|e| {
+                        RPCErrors::ParserResponseError(format!(
+                            "Expected SchedulerListOpTasksResult response: {}",
+                            e
+                        ))
+                    })?;
+                Ok(response.tasks)
+            }
+        }
+    }
+
+    //请求调度器立即执行一轮调度,不等待本轮调度完成
+    pub async fn trigger_schedule(&self) -> Result<bool> {
+        match self {
+            Self::InProcess(handler) => {
+                handler.handle_trigger_schedule(RPCContext::default()).await
+            }
+            Self::KRPC(client) => {
+                let result = client.call("trigger_schedule", json!({})).await?;
+                let response = serde_json::from_value::<SchedulerTriggerScheduleResult>(result)
+                    .map_err(|e| {
+                        RPCErrors::ParserResponseError(format!(
+                            "Expected SchedulerTriggerScheduleResult response: {}",
+                            e
+                        ))
+                    })?;
+                Ok(response.accepted)
+            }
+        }
+    }
+
+    pub async fn cancel_op_task(&self, task_id: &str) -> Result<SchedulerOpTaskInfo> {
+        match self {
+            Self::InProcess(handler) => {
+                handler
+                    .handle_cancel_op_task(task_id, RPCContext::default())
+                    .await
+            }
+            Self::KRPC(client) => {
+                let req = SchedulerCancelOpTaskReq::new(task_id.to_string());
+                let req_json = serde_json::to_value(&req).map_err(|e| {
+                    RPCErrors::ReasonError(format!("Failed to serialize request: {}", e))
+                })?;
+                let result = client.call("cancel_op_task", req_json).await?;
+                serde_json::from_value::<SchedulerOpTaskInfo>(result).map_err(|e| {
+                    RPCErrors::ParserResponseError(format!(
+                        "Expected SchedulerOpTaskInfo response: {}",
+                        e
+                    ))
+                })
+            }
+        }
+    }
+
+    //在调度快照副本上应用mutations并执行一次调度,返回将要产生的动作,不会修改系统状态
+    pub async fn dry_run(
+        &self,
+        mutations: Vec<SchedulerDryRunMutation>,
+    ) -> Result<SchedulerDryRunResult> {
+        let req = SchedulerDryRunReq::new(mutations);
+        match self {
+            Self::InProcess(handler) => handler.handle_dry_run(req, RPCContext::default()).await,
+            Self::KRPC(client) => {
+                let req_json = serde_json::to_value(&req).map_err(|e| {
+                    RPCErrors::ReasonError(format!("Failed to serialize request: {}", e))
+                })?;
+                let result = client.call("dry_run", req_json).await?;
+                serde_json::from_value::<SchedulerDryRunResult>(result).map_err(|e| {
+                    RPCErrors::ParserResponseError(format!(
+                        "Expected SchedulerDryRunResult response: {}",
+                        e
+                    ))
+                })
+            }
+        }
+    }
+}
+
+#[async_trait]
+pub trait SchedulerHandler: Send + Sync {
+    async fn handle_get_status(&self, ctx: RPCContext) -> Result<SchedulerStatus>;
+
+    async fn handle_get_snapshot(&self, ctx: RPCContext) -> Result<Option<Value>>;
+
+    async fn handle_list_nodes(&self, ctx: RPCContext) -> Result<Vec<SchedulerNodeInfo>>;
+
+    async fn handle_list_specs(&self, ctx: RPCContext) -> Result<Vec<SchedulerSpecInfo>>;
+
+    async fn handle_list_instances(
+        &self,
+        req: SchedulerListInstancesReq,
+        ctx: RPCContext,
+    ) -> Result<Vec<SchedulerInstanceInfo>>;
+
+    async fn handle_list_op_tasks(
+        &self,
+        req: SchedulerListOpTasksReq,
+        ctx: RPCContext,
+    ) -> Result<Vec<SchedulerOpTaskInfo>>;
+
+    async fn handle_trigger_schedule(&self, ctx: RPCContext) -> Result<bool>;
+
+    async fn handle_cancel_op_task(
+        &self,
+        task_id: &str,
+        ctx: RPCContext,
+    ) -> Result<SchedulerOpTaskInfo>;
+
+    async fn handle_dry_run(
+        &self,
+        req: SchedulerDryRunReq,
+        ctx: RPCContext,
+    ) -> Result<SchedulerDryRunResult>;
+}
+
+pub struct SchedulerServerHandler<T: SchedulerHandler>(pub T);
+
+impl<T: SchedulerHandler> SchedulerServerHandler<T> {
+    pub fn new(handler: T) -> Self {
+        Self(handler)
+    }
+}
+
+#[async_trait]
+impl<T: SchedulerHandler> RPCHandler for SchedulerServerHandler<T> {
+    async fn handle_rpc_call(&self, req: RPCRequest, ip_from: IpAddr) -> Result<RPCResponse> {
+        let seq = req.seq;
+        let trace_id = req.trace_id.clone();
+        let ctx = RPCContext::from_request(&req, ip_from);
+
+        let result = match req.method.as_str() {
+            "get_status" => {
+                let status = self.0.handle_get_status(ctx).await?;
+                RPCResult::Success(json!(status))
+            }
+            "get_snapshot" => {
+                let snapshot = self.0.handle_get_snapshot(ctx).await?;
+                RPCResult::Success(json!(SchedulerGetSnapshotResult { snapshot }))
+            }
+            "list_nodes" => {
+                let nodes = self.0.handle_list_nodes(ctx).await?;
+                RPCResult::Success(json!(SchedulerListNodesResult { nodes }))
+            }
+            "list_specs" => {
+                let specs = self.0.handle_list_specs(ctx).await?;
+                RPCResult::Success(json!(SchedulerListSpecsResult { specs }))
+            }
+            "list_instances" => {
+                let list_req = SchedulerListInstancesReq::from_json(req.params)?;
+                let instances = self.0.handle_list_instances(list_req, ctx).await?;
+                RPCResult::Success(json!(SchedulerListInstancesResult { instances }))
+            }
+            "list_op_tasks" => {
+                let list_req = SchedulerListOpTasksReq::from_json(req.params)?;
+                let tasks = self.0.handle_list_op_tasks(list_req, ctx).await?;
+                RPCResult::Success(json!(SchedulerListOpTasksResult { tasks }))
+            }
+            "trigger_schedule" => {
+                let accepted = self.0.handle_trigger_schedule(ctx).await?;
+                RPCResult::Success(json!(SchedulerTriggerScheduleResult { accepted }))
+            }
+            "cancel_op_task" => {
+                let cancel_req = SchedulerCancelOpTaskReq::from_json(req.params)?;
+                let task = self
+                    .0
+                    .handle_cancel_op_task(cancel_req.task_id.as_str(), ctx)
+                    .await?;
+                RPCResult::Success(json!(task))
+            }
+            "dry_run" => {
+                let dry_run_req = SchedulerDryRunReq::from_json(req.params)?;
+                let result = self.0.handle_dry_run(dry_run_req, ctx).await?;
+                RPCResult::Success(json!(result))
+            }
+            _ => return Err(RPCErrors::UnknownMethod(req.method.clone())),
+        };
+
+        Ok(RPCResponse {
+            result,
+            seq,
+            trace_id,
+        })
     }
 }
 
@@ -33,6 +688,75 @@ pub fn generate_scheduler_service_doc() -> AppDoc {
 
 mod tests {
 
+    #[test]
+    fn test_dry_run_mutation_json_format() {
+        use super::SchedulerDryRunMutation;
+        let mutation: SchedulerDryRunMutation = serde_json::from_value(serde_json::json!({
+            "type": "set_best_instance_count",
+            "spec_id": "repo-service",
+            "count": 2
+        }))
+        .unwrap();
+        assert_eq!(
+            mutation,
+            SchedulerDryRunMutation::SetBestInstanceCount {
+                spec_id: "repo-service".to_string(),
+                count: 2,
+            }
+        );
+
+        let mutation: SchedulerDryRunMutation = serde_json::from_value(serde_json::json!({
+            "type": "add_node",
+            "node_id": "ood2",
+            "cpu_mhz": 4000,
+            "memory": 8589934592u64
+        }))
+        .unwrap();
+        match mutation {
+            SchedulerDryRunMutation::AddNode {
+                node_id,
+                node_type,
+                labels,
+                ..
+            } => {
+                assert_eq!(node_id, "ood2");
+                assert_eq!(node_type, None);
+                assert!(labels.is_empty());
+            }
+            other => panic!("unexpected mutation: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_label_selector_matches_node_labels() {
+        use super::*;
+        let labels: HashMap<String, String> = [("ssd", ""), ("rack", "r1"), ("node_type", "ood")]
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        let affinity: NodeAffinity = serde_json::from_value(serde_json::json!({
+            "required": [
+                {"key": "ssd", "operator": "exists"},
+                {"key": "node_type", "operator": "not_in", "values": ["mobile", "sensor"]}
+            ]
+        }))
+        .unwrap();
+        assert!(affinity.preferred.is_empty());
+        assert!(match_label_selectors(&affinity.required, &labels));
+
+        let in_rack =
+            LabelSelectorRequirement::new("rack", LabelSelectorOperator::In, vec!["r2".into()]);
+        assert!(!in_rack.matches(&labels));
+        let no_gpu =
+            LabelSelectorRequirement::new("gpu", LabelSelectorOperator::DoesNotExist, vec![]);
+        assert!(no_gpu.matches(&labels));
+        let not_in_missing_key =
+            LabelSelectorRequirement::new("zone", LabelSelectorOperator::NotIn, vec!["z1".into()]);
+        assert!(not_in_missing_key.matches(&labels));
+        assert!(!match_label_selectors(&[no_gpu, in_rack], &labels));
+    }
+
     #[test]
     fn test_generate_scheduler_service_doc() {
         use super::generate_scheduler_service_doc;
@@ -1,13 +1,16 @@
 //TODO:
-//  add WATCH,and load cached value automatically when the value is changed.
+//  load cached value automatically when the value is changed.
 
 use ::kRPC::{kRPC, RPCContext};
 use buckyos_kit::buckyos_get_unix_timestamp;
+use futures::Stream;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
 use log::*;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Map, Value};
 use thiserror::Error;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use tokio::sync::{OnceCell, RwLock};
 
 use crate::KVAction;
@@ -25,6 +28,8 @@ pub enum SystemConfigError {
     NoPermission(String),
     #[error("Timeout: {0}")]
     Timeout(String),
+    #[error("revision {requested} compacted, oldest available revision is {oldest}")]
+    Compacted { requested: u64, oldest: u64 },
 }
 
 pub type SytemConfigResult<T> = std::result::Result<T, SystemConfigError>;
@@ -41,6 +46,139 @@ pub struct SystemConfigValue {
     pub is_changed: bool,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SystemConfigChangeAction {
+    Create,
+    Set,
+    Delete,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SystemConfigChangeEvent {
+    //store-wide revision, pass revision + 1 to watch() to resume after this event
+    pub revision: u64,
+    pub key: String,
+    pub action: SystemConfigChangeAction,
+    #[serde(default)]
+    pub value: Option<String>,
+    //revision of the key after this change, same as SystemConfigValue.version
+    pub key_revision: u64,
+    #[serde(default)]
+    pub timestamp: u64,
+}
+
+pub const SYSTEM_CONFIG_ARCHIVE_FORMAT: &str = "buckyos.sys_config.archive";
+pub const SYSTEM_CONFIG_ARCHIVE_VERSION: u32 = 1;
+
+// Snapshot of the config tree produced by sys_config_export.
+// It is only accepted by sys_config_import after being signed by the zone owner key,
+// see sign_system_config_archive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SystemConfigArchive {
+    pub format: String,
+    pub version: u32,
+    //empty prefix means the whole config tree
+    pub prefix: String,
+    //store revision when the snapshot was taken
+    pub store_revision: u64,
+    pub created_at: u64,
+    pub items: HashMap<String, String>,
+}
+
+// encode the archive as an EdDSA JWT signed by the owner private key
+pub fn sign_system_config_archive(
+    archive: &SystemConfigArchive,
+    kid: Option<String>,
+    private_key: &EncodingKey,
+) -> SytemConfigResult<String> {
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = kid;
+    jsonwebtoken::encode(&header, archive, private_key).map_err(|error| {
+        SystemConfigError::ReasonError(format!("sign sys_config archive failed: {}", error))
+    })
+}
+
+pub struct SystemConfigImportResult {
+    //keys created or updated
+    pub updated: u64,
+    //keys removed because they were not in the archive (replace mode only)
+    pub removed: u64,
+}
+
+// A TTL lease on sys_config: keys written with the lease_id are removed when the lease
+// expires, unless keep_alive is called before expire_at.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SystemConfigLease {
+    pub lease_id: String,
+    //seconds
+    pub ttl: u64,
+    //unix timestamp (seconds) on the sys_config service clock
+    pub expire_at: u64,
+}
+
+pub struct SystemConfigWatchResult {
+    pub events: Vec<SystemConfigChangeEvent>,
+    //revision to pass as from_revision in the next watch call
+    pub next_revision: u64,
+}
+
+// Watch changes under a key prefix. Each call to next_event long-polls sys_config_watch
+// when the local buffer is empty, so the watcher never misses changes between calls.
+// into_stream turns the watcher into a futures::Stream of the same events.
+pub struct SystemConfigWatcher<'a> {
+    client: &'a SystemConfigClient,
+    key_prefix: String,
+    next_revision: u64,
+    pending: VecDeque<SystemConfigChangeEvent>,
+}
+
+impl<'a> SystemConfigWatcher<'a> {
+    pub fn key_prefix(&self) -> &str {
+        self.key_prefix.as_str()
+    }
+
+    // the revision to resume from if the watcher is re-created (e.g. after a restart)
+    pub fn next_revision(&self) -> u64 {
+        match self.pending.front() {
+            Some(event) => event.revision,
+            None => self.next_revision,
+        }
+    }
+
+    pub async fn next_event(
+        &mut self,
+        timeout_ms: Option<u64>,
+    ) -> SytemConfigResult<Option<SystemConfigChangeEvent>> {
+        if self.pending.is_empty() {
+            let result = self
+                .client
+                .watch_once(&self.key_prefix, self.next_revision, timeout_ms)
+                .await?;
+            self.next_revision = result.next_revision;
+            self.pending.extend(result.events);
+        }
+        Ok(self.pending.pop_front())
+    }
+
+    // Poll timeouts without changes are skipped, the stream ends after yielding an error
+    pub fn into_stream(
+        self,
+        timeout_ms: Option<u64>,
+    ) -> impl Stream<Item = SytemConfigResult<SystemConfigChangeEvent>> + Send + 'a {
+        futures::stream::unfold(Some(self), move |watcher| async move {
+            let mut watcher = watcher?;
+            loop {
+                match watcher.next_event(timeout_ms).await {
+                    Ok(Some(event)) => return Some((Ok(event), Some(watcher))),
+                    Ok(None) => continue,
+                    Err(err) => return Some((Err(err), None)),
+                }
+            }
+        })
+    }
+}
+
 fn summarize_session_token(session_token: Option<&str>) -> String {
     match session_token {
         Some(token) => format!("present(len={})", token.len()),
@@ -245,6 +383,23 @@ impl SystemConfigClient {
         Ok(0)
     }
 
+    // dry run of set: check write permission and the schema registered for key without
+    // writing. Returns the matched schema pattern, None if key has no schema.
+    pub async fn validate(&self, key: &str, value: &str) -> SytemConfigResult<Option<String>> {
+        let client = self.get_krpc_client()?;
+        let result = client
+            .call(
+                "sys_config_set",
+                json!({"key": key, "value": value, "dry_run": true}),
+            )
+            .await
+            .map_err(|error| SystemConfigError::ReasonError(error.to_string()))?;
+        Ok(result
+            .get("schema")
+            .and_then(|schema| schema.as_str())
+            .map(|schema| schema.to_string()))
+    }
+
     pub async fn set_by_json_path(
         &self,
         key: &str,
@@ -398,6 +553,290 @@ impl SystemConfigClient {
         Ok(0)
     }
 
+    // Long-poll changes under key_prefix with revision >= from_revision.
+    // from_revision == 0 means "only changes made after this call".
+    // Returns an empty batch when timeout_ms expires without changes,
+    // and SystemConfigError::Compacted when from_revision is too old to replay.
+    pub async fn watch_once(
+        &self,
+        key_prefix: &str,
+        from_revision: u64,
+        timeout_ms: Option<u64>,
+    ) -> SytemConfigResult<SystemConfigWatchResult> {
+        let mut params = json!({
+            "key": key_prefix,
+            "from_revision": from_revision,
+        });
+        if let Some(timeout_ms) = timeout_ms {
+            params["timeout_ms"] = json!(timeout_ms);
+        }
+
+        let client = self.get_krpc_client()?;
+        let result = client
+            .call("sys_config_watch", params)
+            .await
+            .map_err(|error| SystemConfigError::ReasonError(error.to_string()))?;
+
+        if result
+            .get("compacted")
+            .and_then(|value| value.as_bool())
+            .unwrap_or(false)
+        {
+            let oldest = result
+                .get("oldest_revision")
+                .and_then(|value| value.as_u64())
+                .unwrap_or(0);
+            return Err(SystemConfigError::Compacted {
+                requested: from_revision,
+                oldest,
+            });
+        }
+
+        let next_revision = result
+            .get("revision")
+            .and_then(|value| value.as_u64())
+            .ok_or_else(|| {
+                SystemConfigError::ReasonError(
+                    "sys_config_watch missing numeric revision".to_string(),
+                )
+            })?;
+        let events: Vec<SystemConfigChangeEvent> = match result.get("events") {
+            Some(events) => serde_json::from_value(events.clone()).map_err(|error| {
+                SystemConfigError::ReasonError(format!(
+                    "parse sys_config_watch events failed: {}",
+                    error
+                ))
+            })?,
+            None => Vec::new(),
+        };
+
+        for event in events.iter() {
+            self.remove_config_cache(&event.key).await;
+        }
+
+        Ok(SystemConfigWatchResult {
+            events,
+            next_revision,
+        })
+    }
+
+    // retained revisions of key, newest first
+    pub async fn get_history(
+        &self,
+        key: &str,
+        limit: Option<u64>,
+    ) -> SytemConfigResult<Vec<SystemConfigChangeEvent>> {
+        let mut params = json!({"key": key});
+        if let Some(limit) = limit {
+            params["limit"] = json!(limit);
+        }
+        let client = self.get_krpc_client()?;
+        let result = client
+            .call("sys_config_get_history", params)
+            .await
+            .map_err(|error| SystemConfigError::ReasonError(error.to_string()))?;
+        let history = result.get("history").cloned().unwrap_or(json!([]));
+        serde_json::from_value(history).map_err(|error| {
+            SystemConfigError::ReasonError(format!(
+                "parse sys_config_get_history result failed: {}",
+                error
+            ))
+        })
+    }
+
+    // the change that produced `revision` (a key version) of key, None if it is no longer retained
+    pub async fn get_at(
+        &self,
+        key: &str,
+        revision: u64,
+    ) -> SytemConfigResult<Option<SystemConfigChangeEvent>> {
+        let client = self.get_krpc_client()?;
+        let result = client
+            .call(
+                "sys_config_get_at",
+                json!({"key": key, "revision": revision}),
+            )
+            .await
+            .map_err(|error| SystemConfigError::ReasonError(error.to_string()))?;
+        if result.is_null() {
+            return Ok(None);
+        }
+        let entry = serde_json::from_value(result).map_err(|error| {
+            SystemConfigError::ReasonError(format!(
+                "parse sys_config_get_at result failed: {}",
+                error
+            ))
+        })?;
+        Ok(Some(entry))
+    }
+
+    // unsigned snapshot of all keys under prefix (empty prefix = whole tree)
+    pub async fn export_archive(&self, prefix: &str) -> SytemConfigResult<SystemConfigArchive> {
+        let client = self.get_krpc_client()?;
+        let result = client
+            .call("sys_config_export", json!({"prefix": prefix}))
+            .await
+            .map_err(|error| SystemConfigError::ReasonError(error.to_string()))?;
+        serde_json::from_value(result).map_err(|error| {
+            SystemConfigError::ReasonError(format!("parse sys_config archive failed: {}", error))
+        })
+    }
+
+    // apply a signed archive in one transaction. With replace, keys under the archive prefix
+    // that are not in the archive are removed as well.
+    pub async fn import_archive(
+        &self,
+        signed_archive: &str,
+        replace: bool,
+    ) -> SytemConfigResult<SystemConfigImportResult> {
+        let client = self.get_krpc_client()?;
+        let result = client
+            .call(
+                "sys_config_import",
+                json!({"archive": signed_archive, "replace": replace}),
+            )
+            .await
+            .map_err(|error| SystemConfigError::ReasonError(error.to_string()))?;
+
+        // the import can touch any key, drop the whole cache
+        self.config_cache.write().await.clear();
+
+        Ok(SystemConfigImportResult {
+            updated: result
+                .get("updated")
+                .and_then(|value| value.as_u64())
+                .unwrap_or(0),
+            removed: result
+                .get("removed")
+                .and_then(|value| value.as_u64())
+                .unwrap_or(0),
+        })
+    }
+
+    fn parse_lease(method: &str, result: Value) -> SytemConfigResult<SystemConfigLease> {
+        serde_json::from_value(result).map_err(|error| {
+            SystemConfigError::ReasonError(format!("parse {} result failed: {}", method, error))
+        })
+    }
+
+    pub async fn grant_lease(&self, ttl: u64) -> SytemConfigResult<SystemConfigLease> {
+        let client = self.get_krpc_client()?;
+        let result = client
+            .call("sys_config_grant_lease", json!({"ttl": ttl}))
+            .await
+            .map_err(|error| SystemConfigError::ReasonError(error.to_string()))?;
+        Self::parse_lease("sys_config_grant_lease", result)
+    }
+
+    // renew the lease for another ttl (or a new ttl), fails if it already expired
+    pub async fn keep_alive(
+        &self,
+        lease_id: &str,
+        ttl: Option<u64>,
+    ) -> SytemConfigResult<SystemConfigLease> {
+        let mut params = json!({"lease_id": lease_id});
+        if let Some(ttl) = ttl {
+            params["ttl"] = json!(ttl);
+        }
+        let client = self.get_krpc_client()?;
+        let result = client
+            .call("sys_config_keep_alive", params)
+            .await
+            .map_err(|error| SystemConfigError::ReasonError(error.to_string()))?;
+        Self::parse_lease("sys_config_keep_alive", result)
+    }
+
+    // drop the lease now, its keys are removed; returns the removed keys
+    pub async fn revoke_lease(&self, lease_id: &str) -> SytemConfigResult<Vec<String>> {
+        let client = self.get_krpc_client()?;
+        let result = client
+            .call("sys_config_revoke_lease", json!({"lease_id": lease_id}))
+            .await
+            .map_err(|error| SystemConfigError::ReasonError(error.to_string()))?;
+        let removed: Vec<String> = result
+            .get("removed")
+            .cloned()
+            .map(serde_json::from_value)
+            .transpose()
+            .map_err(|error| SystemConfigError::ReasonError(error.to_string()))?
+            .unwrap_or_default();
+        for key in removed.iter() {
+            self.remove_config_cache(key).await;
+        }
+        Ok(removed)
+    }
+
+    // set key and attach it to lease_id, the key is removed when the lease expires
+    pub async fn set_with_lease(
+        &self,
+        key: &str,
+        value: &str,
+        lease_id: &str,
+    ) -> SytemConfigResult<u64> {
+        let client = self.get_krpc_client()?;
+        client
+            .call(
+                "sys_config_set",
+                json!({"key": key, "value": value, "lease_id": lease_id}),
+            )
+            .await
+            .map_err(|error| SystemConfigError::ReasonError(error.to_string()))?;
+        self.remove_config_cache(key).await;
+        Ok(0)
+    }
+
+    pub async fn create_with_lease(
+        &self,
+        key: &str,
+        value: &str,
+        lease_id: &str,
+    ) -> SytemConfigResult<u64> {
+        let client = self.get_krpc_client()?;
+        client
+            .call(
+                "sys_config_create",
+                json!({"key": key, "value": value, "lease_id": lease_id}),
+            )
+            .await
+            .map_err(|error| SystemConfigError::ReasonError(error.to_string()))?;
+        self.remove_config_cache(key).await;
+        Ok(0)
+    }
+
+    // Take key as an exclusive lock. The lock is held by the returned lease: keep it alive
+    // while working and call unlock when done; if the holder dies the lock frees itself after ttl.
+    pub async fn lock(&self, key: &str, ttl: u64) -> SytemConfigResult<SystemConfigLease> {
+        let client = self.get_krpc_client()?;
+        let result = client
+            .call("sys_config_lock", json!({"key": key, "ttl": ttl}))
+            .await
+            .map_err(|error| SystemConfigError::ReasonError(error.to_string()))?;
+        self.remove_config_cache(key).await;
+        Self::parse_lease("sys_config_lock", result)
+    }
+
+    pub async fn unlock(&self, key: &str, lease_id: &str) -> SytemConfigResult<()> {
+        let client = self.get_krpc_client()?;
+        client
+            .call(
+                "sys_config_unlock",
+                json!({"key": key, "lease_id": lease_id}),
+            )
+            .await
+            .map_err(|error| SystemConfigError::ReasonError(error.to_string()))?;
+        self.remove_config_cache(key).await;
+        Ok(())
+    }
+
+    pub fn watch(&self, key_prefix: &str, from_revision: u64) -> SystemConfigWatcher<'_> {
+        SystemConfigWatcher {
+            client: self,
+            key_prefix: key_prefix.to_string(),
+            next_revision: from_revision,
+            pending: VecDeque::new(),
+        }
+    }
+
     pub async fn dump_configs_for_scheduler(&self) -> SytemConfigResult<Value> {
         let client = self.get_krpc_client()?;
         let result = client
@@ -459,4 +898,42 @@ mod tests {
             Some("token-b".to_string())
         );
     }
+
+    #[test]
+    fn change_event_parses_watch_payload() {
+        let events: Vec<SystemConfigChangeEvent> = serde_json::from_value(json!([
+            {"revision": 7, "key": "services/demo/spec", "action": "set", "value": "{}", "key_revision": 3},
+            {"revision": 8, "key": "services/demo/spec", "action": "delete", "key_revision": 4}
+        ]))
+        .expect("parse events");
+
+        assert_eq!(events[0].action, SystemConfigChangeAction::Set);
+        assert_eq!(events[0].value.as_deref(), Some("{}"));
+        assert_eq!(events[1].action, SystemConfigChangeAction::Delete);
+        assert_eq!(events[1].value, None);
+        assert_eq!(events[1].key_revision, 4);
+    }
+
+    #[tokio::test]
+    async fn watcher_stream_yields_buffered_events_in_order() {
+        use futures::StreamExt;
+
+        let client = SystemConfigClient::new(None, None);
+        let mut watcher = client.watch("services/", 7);
+        let events: Vec<SystemConfigChangeEvent> = serde_json::from_value(json!([
+            {"revision": 7, "key": "services/a", "action": "set", "value": "1", "key_revision": 1},
+            {"revision": 8, "key": "services/b", "action": "delete", "key_revision": 2}
+        ]))
+        .expect("parse events");
+        watcher.pending.extend(events);
+        watcher.next_revision = 9;
+
+        let stream = watcher.into_stream(Some(10));
+        futures::pin_mut!(stream);
+        let first = stream.next().await.unwrap().unwrap();
+        let second = stream.next().await.unwrap().unwrap();
+        assert_eq!(first.key, "services/a");
+        assert_eq!(second.revision, 8);
+        assert_eq!(second.action, SystemConfigChangeAction::Delete);
+    }
 }
@@ -0,0 +1,296 @@
+use buckyos_api::{
+    match_event_patterns, validate_pattern, Event, KEventError, KEventResult, KEVENT_GAP_EVENTID,
+};
+use serde_json::json;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+pub const DEFAULT_DURABLE_LOG_CAPACITY: usize = 64 * 1024;
+
+pub struct DurableLogConfig {
+    // events matching these global patterns are persisted and get a seq
+    pub patterns: Vec<String>,
+    // None keeps the log in memory only (survives reader restarts, not daemon restarts)
+    pub path: Option<PathBuf>,
+    pub max_events: usize,
+}
+
+impl DurableLogConfig {
+    pub fn new(patterns: Vec<String>, path: Option<PathBuf>) -> Self {
+        Self {
+            patterns,
+            path,
+            max_events: DEFAULT_DURABLE_LOG_CAPACITY,
+        }
+    }
+}
+
+pub enum DurableRead {
+    Event(Event),
+    // requested seq was already trimmed, reader continues from first_seq
+    Gap { requested_seq: u64, first_seq: u64 },
+    Empty,
+}
+
+// Append-only event log, one json encoded event per line.
+// seq starts from 1 and never goes back, even after the oldest events are trimmed.
+pub struct DurableEventLog {
+    patterns: Vec<String>,
+    path: Option<PathBuf>,
+    max_events: usize,
+    entries: VecDeque<Event>,
+    next_seq: u64,
+    // lines in file, the file is rewritten when it grows to twice max_events
+    file_lines: usize,
+}
+
+impl DurableEventLog {
+    pub fn open(config: DurableLogConfig) -> KEventResult<Self> {
+        if config.patterns.is_empty() {
+            return Err(KEventError::InvalidPattern(
+                "durable patterns must not be empty".to_string(),
+            ));
+        }
+        for pattern in &config.patterns {
+            validate_pattern(pattern)?;
+        }
+
+        let mut log = Self {
+            patterns: config.patterns,
+            path: config.path,
+            max_events: config.max_events.max(1),
+            entries: VecDeque::new(),
+            next_seq: 1,
+            file_lines: 0,
+        };
+        log.load()?;
+        Ok(log)
+    }
+
+    fn load(&mut self) -> KEventResult<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if !path.exists() {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent).map_err(|err| {
+                    KEventError::Internal(format!("create durable log dir failed: {}", err))
+                })?;
+            }
+            return Ok(());
+        }
+
+        let file = File::open(path)
+            .map_err(|err| KEventError::Internal(format!("open durable log failed: {}", err)))?;
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(|err| {
+                KEventError::Internal(format!("read durable log failed: {}", err))
+            })?;
+            self.file_lines += 1;
+            // a crash can leave a torn last line, skip anything that does not parse
+            let Ok(event) = serde_json::from_str::<Event>(&line) else {
+                continue;
+            };
+            let Some(seq) = event.seq else {
+                continue;
+            };
+            if seq < self.next_seq {
+                continue;
+            }
+            self.next_seq = seq + 1;
+            self.entries.push_back(event);
+            if self.entries.len() > self.max_events {
+                self.entries.pop_front();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+
+    pub fn should_persist(&self, eventid: &str) -> bool {
+        match_event_patterns(&self.patterns, eventid)
+    }
+
+    // oldest seq that can still be read
+    pub fn first_seq(&self) -> u64 {
+        self.entries
+            .front()
+            .and_then(|event| event.seq)
+            .unwrap_or(self.next_seq)
+    }
+
+    pub fn last_seq(&self) -> u64 {
+        self.next_seq - 1
+    }
+
+    pub fn append(&mut self, event: &Event) -> KEventResult<u64> {
+        let seq = self.next_seq;
+        let mut event = event.clone();
+        event.seq = Some(seq);
+
+        if let Some(path) = &self.path {
+            let mut line = serde_json::to_string(&event)
+                .map_err(|err| KEventError::Internal(format!("encode event failed: {}", err)))?;
+            line.push('\n');
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|err| {
+                    KEventError::Internal(format!("open durable log failed: {}", err))
+                })?;
+            file.write_all(line.as_bytes()).map_err(|err| {
+                KEventError::Internal(format!("write durable log failed: {}", err))
+            })?;
+            // the seq is handed out only after the event reached the disk, a crash must not
+            // lose an event a reader may already have seen
+            file.sync_data().map_err(|err| {
+                KEventError::Internal(format!("sync durable log failed: {}", err))
+            })?;
+            self.file_lines += 1;
+        }
+
+        self.next_seq += 1;
+        self.entries.push_back(event);
+        if self.entries.len() > self.max_events {
+            self.entries.pop_front();
+        }
+        if self.file_lines >= self.max_events * 2 {
+            self.compact()?;
+        }
+        Ok(seq)
+    }
+
+    fn compact(&mut self) -> KEventResult<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let tmp_path = path.with_extension("compact");
+        let mut content = String::new();
+        for event in &self.entries {
+            let line = serde_json::to_string(event)
+                .map_err(|err| KEventError::Internal(format!("encode event failed: {}", err)))?;
+            content.push_str(&line);
+            content.push('\n');
+        }
+        File::create(&tmp_path)
+            .and_then(|mut file| {
+                file.write_all(content.as_bytes())?;
+                file.sync_data()
+            })
+            .and_then(|_| std::fs::rename(&tmp_path, path))
+            .map_err(|err| KEventError::Internal(format!("compact durable log failed: {}", err)))?;
+        self.file_lines = self.entries.len();
+        Ok(())
+    }
+
+    // Read the first event with seq >= from_seq matching patterns.
+    // from_seq 0 means the oldest retained event.
+    pub fn read_from(&self, from_seq: u64, patterns: &[String]) -> DurableRead {
+        let first_seq = self.first_seq();
+        if from_seq != 0 && from_seq < first_seq {
+            return DurableRead::Gap {
+                requested_seq: from_seq,
+                first_seq,
+            };
+        }
+
+        let skip = from_seq.saturating_sub(first_seq) as usize;
+        self.entries
+            .iter()
+            .skip(skip)
+            .find(|event| match_event_patterns(patterns, &event.eventid))
+            .cloned()
+            .map(DurableRead::Event)
+            .unwrap_or(DurableRead::Empty)
+    }
+}
+
+pub fn build_gap_event(source_node: &str, requested_seq: u64, first_seq: u64) -> Event {
+    Event {
+        eventid: KEVENT_GAP_EVENTID.to_string(),
+        source_node: source_node.to_string(),
+        source_pid: std::process::id(),
+        ingress_node: Some(source_node.to_string()),
+        timestamp: crate::now_millis(),
+        data: json!({
+            "requested_seq": requested_seq,
+            "first_seq": first_seq,
+        }),
+        seq: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_event(eventid: &str) -> Event {
+        Event {
+            eventid: eventid.to_string(),
+            source_node: "node_a".to_string(),
+            source_pid: 1,
+            ingress_node: None,
+            timestamp: 0,
+            data: json!({}),
+            seq: None,
+        }
+    }
+
+    fn test_log_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "buckyos_kevent_durable_{}_{}.log",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_durable_log_reload_and_compact() {
+        let path = test_log_path("reload");
+        let config = || DurableLogConfig {
+            patterns: vec!["/taskmgr/**".to_string()],
+            path: Some(path.clone()),
+            max_events: 4,
+        };
+
+        let mut log = DurableEventLog::open(config()).unwrap();
+        assert!(!log.should_persist("/system/node/online"));
+        for index in 0..10 {
+            let seq = log
+                .append(&make_event(&format!("/taskmgr/task_{}", index)))
+                .unwrap();
+            assert_eq!(seq, index + 1);
+        }
+        assert_eq!(log.first_seq(), 7);
+        assert_eq!(log.last_seq(), 10);
+        drop(log);
+
+        let log = DurableEventLog::open(config()).unwrap();
+        assert_eq!(log.first_seq(), 7);
+        assert_eq!(log.last_seq(), 10);
+        assert!(log.file_lines < 8);
+
+        let patterns = vec!["/taskmgr/**".to_string()];
+        match log.read_from(3, &patterns) {
+            DurableRead::Gap {
+                requested_seq,
+                first_seq,
+            } => assert_eq!((requested_seq, first_seq), (3, 7)),
+            _ => panic!("expect gap"),
+        }
+        match log.read_from(9, &patterns) {
+            DurableRead::Event(event) => assert_eq!(event.seq, Some(9)),
+            _ => panic!("expect event"),
+        }
+        assert!(matches!(log.read_from(11, &patterns), DurableRead::Empty));
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,788 @@
+use crate::{now_millis, KEventPeerPublisher, KEventService};
+use async_trait::async_trait;
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::post,
+    Json, Router,
+};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine as _,
+};
+use buckyos_api::{is_global_eventid, Event, KEventError, KEventResult, SystemConfigClient};
+use ed25519_dalek::pkcs8::DecodePrivateKey;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use log::{debug, info, warn};
+use name_lib::{DeviceConfig, EncodedDocument};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::{Mutex, Notify, RwLock};
+
+// Route exposed by the kevent daemon behind the zone gateway.
+pub const KEVENT_PEER_PATH: &str = "/kapi/kevent/peer";
+pub const KEVENT_PEER_NODE_HEADER: &str = "x-kevent-node";
+pub const KEVENT_PEER_SIGNATURE_HEADER: &str = "x-kevent-signature";
+// batches older than this are rejected, so a captured request can not be replayed later
+pub const DEFAULT_PEER_MAX_CLOCK_SKEW_MS: u64 = 5 * 60 * 1000;
+const DEFAULT_PEER_DEDUPE_CAPACITY: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PeerEvent {
+    // unique per event and stable across retries: {ingress_node}-{boot_millis}-{counter}
+    pub id: String,
+    pub event: Event,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PeerEventBatch {
+    pub from_node: String,
+    pub timestamp: u64,
+    pub events: Vec<PeerEvent>,
+}
+
+// Device identity of this node, the signing key is the node private key (node_private_key.pem).
+pub struct KEventNodeIdentity {
+    node_id: String,
+    signing_key: SigningKey,
+}
+
+impl KEventNodeIdentity {
+    pub fn new(node_id: impl Into<String>, signing_key: SigningKey) -> Self {
+        Self {
+            node_id: node_id.into(),
+            signing_key,
+        }
+    }
+
+    pub fn from_pkcs8_pem(node_id: impl Into<String>, pem: &str) -> KEventResult<Self> {
+        let signing_key = SigningKey::from_pkcs8_pem(pem).map_err(|err| {
+            KEventError::Internal(format!("load node private key failed: {}", err))
+        })?;
+        Ok(Self::new(node_id, signing_key))
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.node_id
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        self.signing_key.verifying_key()
+    }
+
+    fn sign(&self, body: &[u8]) -> String {
+        STANDARD.encode(self.signing_key.sign(body).to_bytes())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PeerPublisherConfig {
+    pub queue_capacity: usize,
+    pub batch_size: usize,
+    pub request_timeout_ms: u64,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for PeerPublisherConfig {
+    fn default() -> Self {
+        Self {
+            queue_capacity: 4096,
+            batch_size: 128,
+            request_timeout_ms: 5000,
+            initial_backoff_ms: 200,
+            max_backoff_ms: 30_000,
+        }
+    }
+}
+
+// Forwards global events to one peer daemon over http.
+// broadcast only enqueues; a background task sends batches and retries the same
+// batch with exponential backoff until the peer is reachable again.
+pub struct HttpPeerPublisher {
+    identity: Arc<KEventNodeIdentity>,
+    peer_node: String,
+    url: String,
+    config: PeerPublisherConfig,
+    client: reqwest::Client,
+    queue: Mutex<VecDeque<PeerEvent>>,
+    notify: Arc<Notify>,
+    id_prefix: String,
+    id_seq: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl HttpPeerPublisher {
+    // peer_url is the peer daemon base url, e.g. http://ood2:3180 (zone gateway)
+    pub fn start(
+        identity: Arc<KEventNodeIdentity>,
+        peer_node: impl Into<String>,
+        peer_url: &str,
+        config: PeerPublisherConfig,
+    ) -> KEventResult<Arc<Self>> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.request_timeout_ms))
+            .build()
+            .map_err(|err| KEventError::Internal(format!("create http client failed: {}", err)))?;
+        let publisher = Arc::new(Self {
+            id_prefix: format!("{}-{}", identity.node_id(), now_millis()),
+            identity,
+            peer_node: peer_node.into(),
+            url: format!("{}{}", peer_url.trim_end_matches('/'), KEVENT_PEER_PATH),
+            config,
+            client,
+            queue: Mutex::new(VecDeque::new()),
+            notify: Arc::new(Notify::new()),
+            id_seq: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        });
+
+        let weak = Arc::downgrade(&publisher);
+        let notify = publisher.notify.clone();
+        tokio::spawn(async move {
+            run_peer_sender(weak, notify).await;
+        });
+        Ok(publisher)
+    }
+
+    pub fn peer_node(&self) -> &str {
+        &self.peer_node
+    }
+
+    // events dropped because the peer was unreachable long enough to fill the queue
+    pub fn dropped_count(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub async fn pending_count(&self) -> usize {
+        self.queue.lock().await.len()
+    }
+
+    async fn take_batch(&self) -> Vec<PeerEvent> {
+        let mut queue = self.queue.lock().await;
+        let count = queue.len().min(self.config.batch_size.max(1));
+        queue.drain(..count).collect()
+    }
+
+    async fn send_batch(&self, events: &[PeerEvent]) -> KEventResult<()> {
+        let batch = PeerEventBatch {
+            from_node: self.identity.node_id().to_string(),
+            timestamp: now_millis(),
+            events: events.to_vec(),
+        };
+        let body = serde_json::to_vec(&batch)
+            .map_err(|err| KEventError::Internal(format!("encode peer batch failed: {}", err)))?;
+        let resp = self
+            .client
+            .post(&self.url)
+            .header(KEVENT_PEER_NODE_HEADER, self.identity.node_id())
+            .header(KEVENT_PEER_SIGNATURE_HEADER, self.identity.sign(&body))
+            .header("content-type", "application/json")
+            .body(body)
+            .send()
+            .await
+            .map_err(|err| KEventError::DaemonUnavailable(format!("{}: {}", self.url, err)))?;
+        if !resp.status().is_success() {
+            let status = resp.status();
+            let message = resp.text().await.unwrap_or_default();
+            return Err(KEventError::DaemonUnavailable(format!(
+                "{} returned {}: {}",
+                self.url, status, message
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl KEventPeerPublisher for HttpPeerPublisher {
+    async fn broadcast(&self, event: &Event) -> KEventResult<()> {
+        let id = format!(
+            "{}-{}",
+            self.id_prefix,
+            self.id_seq.fetch_add(1, Ordering::Relaxed) + 1
+        );
+        let mut event = event.clone();
+        event.seq = None;
+
+        let mut queue = self.queue.lock().await;
+        let overflow = queue.len() >= self.config.queue_capacity.max(1);
+        if overflow {
+            queue.pop_front();
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        queue.push_back(PeerEvent { id, event });
+        drop(queue);
+        self.notify.notify_one();
+
+        if overflow {
+            return Err(KEventError::DaemonUnavailable(format!(
+                "peer {} queue is full, oldest event dropped",
+                self.peer_node
+            )));
+        }
+        Ok(())
+    }
+}
+
+async fn run_peer_sender(weak: Weak<HttpPeerPublisher>, notify: Arc<Notify>) {
+    let mut inflight: Vec<PeerEvent> = Vec::new();
+    let mut backoff_ms = 0u64;
+    loop {
+        if backoff_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(backoff_ms)).await;
+        }
+
+        let Some(publisher) = weak.upgrade() else {
+            return;
+        };
+        if inflight.is_empty() {
+            inflight = publisher.take_batch().await;
+        }
+        if inflight.is_empty() {
+            drop(publisher);
+            // wake up periodically to notice the publisher has been dropped
+            let _ = tokio::time::timeout(Duration::from_secs(1), notify.notified()).await;
+            continue;
+        }
+
+        match publisher.send_batch(&inflight).await {
+            Ok(_) => {
+                if backoff_ms > 0 {
+                    debug!("kevent peer {} reconnected", publisher.peer_node);
+                }
+                inflight.clear();
+                backoff_ms = 0;
+            }
+            Err(err) => {
+                backoff_ms = if backoff_ms == 0 {
+                    publisher.config.initial_backoff_ms.max(1)
+                } else {
+                    (backoff_ms * 2).min(publisher.config.max_backoff_ms)
+                };
+                warn!(
+                    "forward {} events to kevent peer {} failed, retry in {}ms: {}",
+                    inflight.len(),
+                    publisher.peer_node,
+                    backoff_ms,
+                    err
+                );
+            }
+        }
+    }
+}
+
+// Remembers recently seen peer event ids, the oldest are forgotten first.
+struct DedupeWindow {
+    capacity: usize,
+    order: VecDeque<String>,
+    seen: HashSet<String>,
+}
+
+impl DedupeWindow {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            order: VecDeque::new(),
+            seen: HashSet::new(),
+        }
+    }
+
+    // true if id is new
+    fn insert(&mut self, id: &str) -> bool {
+        if self.seen.contains(id) {
+            return false;
+        }
+        if self.order.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        self.order.push_back(id.to_string());
+        self.seen.insert(id.to_string());
+        true
+    }
+}
+
+// Accepts batches from trusted peers and dispatches them locally via publish_from_peer.
+pub struct KEventPeerReceiver {
+    service: KEventService,
+    trusted_peers: RwLock<HashMap<String, VerifyingKey>>,
+    dedupe: Mutex<DedupeWindow>,
+    max_clock_skew_ms: u64,
+}
+
+impl KEventPeerReceiver {
+    pub fn new(service: KEventService) -> Self {
+        Self {
+            service,
+            trusted_peers: RwLock::new(HashMap::new()),
+            dedupe: Mutex::new(DedupeWindow::new(DEFAULT_PEER_DEDUPE_CAPACITY)),
+            max_clock_skew_ms: DEFAULT_PEER_MAX_CLOCK_SKEW_MS,
+        }
+    }
+
+    // Daemon startup: the receiver trusts every other device of the zone.
+    // The service node id is the device name, as in devices/{name}/doc.
+    pub async fn from_zone(
+        service: KEventService,
+        client: &SystemConfigClient,
+    ) -> KEventResult<Self> {
+        let receiver = Self::new(service);
+        receiver.trust_zone_devices(client).await?;
+        Ok(receiver)
+    }
+
+    // Returns how many peers are trusted. A device doc without a usable key is skipped,
+    // batches from that device are rejected.
+    pub async fn trust_zone_devices(&self, client: &SystemConfigClient) -> KEventResult<usize> {
+        let devices = client
+            .list("devices")
+            .await
+            .map_err(|err| KEventError::Internal(format!("list zone devices failed: {}", err)))?;
+        let mut trusted = 0;
+        for device in devices {
+            if device == self.service.source_node {
+                continue;
+            }
+            match load_device_key(client, &device).await {
+                Ok(public_key) => {
+                    self.trust_peer(&device, public_key).await;
+                    trusted += 1;
+                }
+                Err(err) => warn!("skip kevent peer {}: {}", device, err),
+            }
+        }
+        info!("kevent receiver trusts {} zone peers", trusted);
+        Ok(trusted)
+    }
+
+    // public key comes from the peer device doc
+    pub async fn trust_peer(&self, node_id: &str, public_key: VerifyingKey) {
+        self.trusted_peers
+            .write()
+            .await
+            .insert(node_id.to_string(), public_key);
+    }
+
+    pub async fn untrust_peer(&self, node_id: &str) {
+        self.trusted_peers.write().await.remove(node_id);
+    }
+
+    // Returns how many events were dispatched, duplicates are skipped.
+    pub async fn handle_batch(
+        &self,
+        from_node: &str,
+        signature: &str,
+        body: &[u8],
+    ) -> KEventResult<usize> {
+        let public_key = self
+            .trusted_peers
+            .read()
+            .await
+            .get(from_node)
+            .cloned()
+            .ok_or_else(|| {
+                KEventError::PermissionDenied(format!("peer {} is not trusted", from_node))
+            })?;
+        let signature = STANDARD
+            .decode(signature)
+            .ok()
+            .and_then(|bytes| Signature::from_slice(&bytes).ok())
+            .ok_or_else(|| KEventError::PermissionDenied("invalid peer signature".to_string()))?;
+        public_key
+            .verify(body, &signature)
+            .map_err(|_| KEventError::PermissionDenied("peer signature mismatch".to_string()))?;
+
+        let batch: PeerEventBatch = serde_json::from_slice(body)
+            .map_err(|err| KEventError::Internal(format!("decode peer batch failed: {}", err)))?;
+        if batch.from_node != from_node {
+            return Err(KEventError::PermissionDenied(format!(
+                "batch from {} signed by {}",
+                batch.from_node, from_node
+            )));
+        }
+        if now_millis().abs_diff(batch.timestamp) > self.max_clock_skew_ms {
+            return Err(KEventError::PermissionDenied(
+                "peer batch timestamp out of range".to_string(),
+            ));
+        }
+
+        let mut dispatched = 0;
+        for peer_event in batch.events {
+            if !is_global_eventid(&peer_event.event.eventid) {
+                continue;
+            }
+            // a peer may only relay its own events, the signature does not vouch for other nodes
+            if peer_event.event.source_node != from_node {
+                warn!(
+                    "drop event {} from peer {}: source node is {}",
+                    peer_event.id, from_node, peer_event.event.source_node
+                );
+                continue;
+            }
+            if !self.dedupe.lock().await.insert(&peer_event.id) {
+                continue;
+            }
+            match self.service.publish_from_peer(peer_event.event).await {
+                Ok(_) => dispatched += 1,
+                Err(err) => warn!(
+                    "drop event {} from peer {}: {}",
+                    peer_event.id, from_node, err
+                ),
+            }
+        }
+        Ok(dispatched)
+    }
+
+    pub fn router(self: Arc<Self>) -> Router {
+        Router::new()
+            .route(KEVENT_PEER_PATH, post(handle_peer_post))
+            .with_state(self)
+    }
+}
+
+async fn load_device_key(client: &SystemConfigClient, device: &str) -> KEventResult<VerifyingKey> {
+    let doc_path = format!("devices/{}/doc", device);
+    let doc = client
+        .get(doc_path.as_str())
+        .await
+        .map_err(|err| KEventError::Internal(format!("get {} failed: {}", doc_path, err)))?;
+    let doc = EncodedDocument::from_str(doc.value)
+        .map_err(|err| KEventError::Internal(format!("parse {} failed: {}", doc_path, err)))?;
+    let doc = DeviceConfig::decode(&doc, None)
+        .map_err(|err| KEventError::Internal(format!("decode {} failed: {}", doc_path, err)))?;
+    let jwk = doc
+        .get_default_key()
+        .ok_or_else(|| KEventError::Internal(format!("{} has no default key", doc_path)))?;
+    let jwk = serde_json::to_value(&jwk)
+        .map_err(|err| KEventError::Internal(format!("encode device key failed: {}", err)))?;
+    verifying_key_from_jwk(&jwk)
+}
+
+// device keys are Ed25519 jwks, x is the base64url encoded public key
+fn verifying_key_from_jwk(jwk: &serde_json::Value) -> KEventResult<VerifyingKey> {
+    let field = |name: &str| {
+        jwk.get(name)
+            .and_then(|value| value.as_str())
+            .unwrap_or_default()
+    };
+    if field("kty") != "OKP" || field("crv") != "Ed25519" {
+        return Err(KEventError::Internal(format!(
+            "unsupported device key type {}/{}",
+            field("kty"),
+            field("crv")
+        )));
+    }
+    let bytes: [u8; 32] = URL_SAFE_NO_PAD
+        .decode(field("x"))
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| {
+            KEventError::Internal("device key is not a 32 byte ed25519 key".to_string())
+        })?;
+    VerifyingKey::from_bytes(&bytes)
+        .map_err(|err| KEventError::Internal(format!("invalid device key: {}", err)))
+}
+
+async fn handle_peer_post(
+    State(receiver): State<Arc<KEventPeerReceiver>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string()
+    };
+    let from_node = header(KEVENT_PEER_NODE_HEADER);
+    let signature = header(KEVENT_PEER_SIGNATURE_HEADER);
+    match receiver.handle_batch(&from_node, &signature, &body).await {
+        Ok(dispatched) => (
+            StatusCode::OK,
+            Json(json!({"status": "ok", "dispatched": dispatched})),
+        ),
+        Err(err) => {
+            let status = match err {
+                KEventError::PermissionDenied(_) => StatusCode::UNAUTHORIZED,
+                _ => StatusCode::BAD_REQUEST,
+            };
+            (
+                status,
+                Json(json!({"status": "err", "code": err.code(), "message": err.to_string()})),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn test_identity(node_id: &str, seed: u8) -> Arc<KEventNodeIdentity> {
+        Arc::new(KEventNodeIdentity::new(
+            node_id,
+            SigningKey::from_bytes(&[seed; 32]),
+        ))
+    }
+
+    async fn serve(receiver: Arc<KEventPeerReceiver>, listener: TcpListener) {
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, receiver.router()).await;
+        });
+    }
+
+    fn fast_config() -> PeerPublisherConfig {
+        PeerPublisherConfig {
+            initial_backoff_ms: 20,
+            max_backoff_ms: 100,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_federation_between_two_services() {
+        let identity_a = test_identity("node_a", 1);
+        let identity_b = test_identity("node_b", 2);
+        let service_a = KEventService::new("node_a");
+        let service_b = KEventService::new("node_b");
+
+        let receiver_a = Arc::new(KEventPeerReceiver::new(service_a.clone()));
+        receiver_a
+            .trust_peer("node_b", identity_b.verifying_key())
+            .await;
+        let receiver_b = Arc::new(KEventPeerReceiver::new(service_b.clone()));
+        receiver_b
+            .trust_peer("node_a", identity_a.verifying_key())
+            .await;
+
+        let listener_a = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let listener_b = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url_a = format!("http://{}", listener_a.local_addr().unwrap());
+        let url_b = format!("http://{}", listener_b.local_addr().unwrap());
+        serve(receiver_a, listener_a).await;
+        serve(receiver_b, listener_b).await;
+
+        service_a
+            .add_peer_publisher(
+                HttpPeerPublisher::start(identity_a, "node_b", &url_b, fast_config()).unwrap(),
+            )
+            .await;
+        service_b
+            .add_peer_publisher(
+                HttpPeerPublisher::start(identity_b, "node_a", &url_a, fast_config()).unwrap(),
+            )
+            .await;
+
+        service_a
+            .register_reader("ra", vec!["/taskmgr/**".to_string()])
+            .await
+            .unwrap();
+        service_b
+            .register_reader("rb", vec!["/taskmgr/**".to_string()])
+            .await
+            .unwrap();
+
+        service_a
+            .publish_local_global("/taskmgr/new/task_001", json!({"from": "a"}))
+            .await
+            .unwrap();
+        let local = service_a
+            .pull_event("ra", Some(100))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(local.eventid, "/taskmgr/new/task_001");
+        let remote = service_b
+            .pull_event("rb", Some(2000))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(remote.eventid, "/taskmgr/new/task_001");
+        assert_eq!(remote.ingress_node.as_deref(), Some("node_a"));
+
+        // peer events are not forwarded back, so node_a sees its event only once
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(service_a.pull_event("ra", Some(0)).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_receiver_rejects_untrusted_and_dedupes() {
+        let identity_a = test_identity("node_a", 1);
+        let intruder = test_identity("node_a", 9);
+        let service_b = KEventService::new("node_b");
+        service_b
+            .register_reader("rb", vec!["/taskmgr/**".to_string()])
+            .await
+            .unwrap();
+        let receiver_b = KEventPeerReceiver::new(service_b.clone());
+        receiver_b
+            .trust_peer("node_a", identity_a.verifying_key())
+            .await;
+
+        let batch = PeerEventBatch {
+            from_node: "node_a".to_string(),
+            timestamp: now_millis(),
+            events: vec![PeerEvent {
+                id: "node_a-1-1".to_string(),
+                event: Event {
+                    eventid: "/taskmgr/new/task_001".to_string(),
+                    source_node: "node_a".to_string(),
+                    source_pid: 1,
+                    ingress_node: Some("node_a".to_string()),
+                    timestamp: now_millis(),
+                    data: json!({}),
+                    seq: None,
+                },
+            }],
+        };
+        let body = serde_json::to_vec(&batch).unwrap();
+
+        assert!(receiver_b
+            .handle_batch("node_a", &intruder.sign(&body), &body)
+            .await
+            .is_err());
+        assert!(receiver_b
+            .handle_batch("node_c", &identity_a.sign(&body), &body)
+            .await
+            .is_err());
+        assert_eq!(
+            receiver_b
+                .handle_batch("node_a", &identity_a.sign(&body), &body)
+                .await
+                .unwrap(),
+            1
+        );
+        // a retried batch is not dispatched twice
+        assert_eq!(
+            receiver_b
+                .handle_batch("node_a", &identity_a.sign(&body), &body)
+                .await
+                .unwrap(),
+            0
+        );
+        assert!(service_b.pull_event("rb", Some(0)).await.unwrap().is_some());
+        assert!(service_b.pull_event("rb", Some(0)).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_receiver_rejects_events_from_other_source_nodes() {
+        let identity_a = test_identity("node_a", 1);
+        let service_b = KEventService::new("node_b");
+        service_b
+            .register_reader("rb", vec!["/taskmgr/**".to_string()])
+            .await
+            .unwrap();
+        let receiver_b = KEventPeerReceiver::new(service_b.clone());
+        receiver_b
+            .trust_peer("node_a", identity_a.verifying_key())
+            .await;
+
+        let peer_event = |id: &str, source_node: &str| PeerEvent {
+            id: id.to_string(),
+            event: Event {
+                eventid: "/taskmgr/new/task_001".to_string(),
+                source_node: source_node.to_string(),
+                source_pid: 1,
+                ingress_node: Some(source_node.to_string()),
+                timestamp: now_millis(),
+                data: json!({}),
+                seq: None,
+            },
+        };
+        let batch = PeerEventBatch {
+            from_node: "node_a".to_string(),
+            timestamp: now_millis(),
+            events: vec![
+                peer_event("node_a-1-1", "node_c"),
+                peer_event("node_a-1-2", "node_a"),
+            ],
+        };
+        let body = serde_json::to_vec(&batch).unwrap();
+        assert_eq!(
+            receiver_b
+                .handle_batch("node_a", &identity_a.sign(&body), &body)
+                .await
+                .unwrap(),
+            1
+        );
+        let event = service_b.pull_event("rb", Some(0)).await.unwrap().unwrap();
+        assert_eq!(event.source_node, "node_a");
+        assert!(service_b.pull_event("rb", Some(0)).await.unwrap().is_none());
+
+        let err = receiver_b
+            .handle_batch("node_c", &identity_a.sign(&body), &body)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, KEventError::PermissionDenied(_)));
+    }
+
+    #[tokio::test]
+    async fn test_publisher_retries_until_peer_is_up() {
+        let identity_a = test_identity("node_a", 1);
+        // reserve a port, then start the peer only after the first send failed
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let publisher = HttpPeerPublisher::start(
+            identity_a.clone(),
+            "node_b",
+            &format!("http://{}", addr),
+            fast_config(),
+        )
+        .unwrap();
+        let service_a = KEventService::new("node_a");
+        service_a.add_peer_publisher(publisher.clone()).await;
+        service_a
+            .publish_local_global("/taskmgr/new/task_001", json!({}))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let service_b = KEventService::new("node_b");
+        service_b
+            .register_reader("rb", vec!["/taskmgr/**".to_string()])
+            .await
+            .unwrap();
+        let receiver_b = Arc::new(KEventPeerReceiver::new(service_b.clone()));
+        receiver_b
+            .trust_peer("node_a", identity_a.verifying_key())
+            .await;
+        serve(receiver_b, TcpListener::bind(addr).await.unwrap()).await;
+
+        let event = service_b
+            .pull_event("rb", Some(3000))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.eventid, "/taskmgr/new/task_001");
+        assert_eq!(publisher.pending_count().await, 0);
+        assert_eq!(publisher.dropped_count(), 0);
+    }
+
+    #[test]
+    fn test_verifying_key_from_device_jwk() {
+        let identity = test_identity("node_b", 2);
+        let public_key = identity.verifying_key();
+        let jwk = json!({
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "x": URL_SAFE_NO_PAD.encode(public_key.to_bytes()),
+        });
+        assert_eq!(verifying_key_from_jwk(&jwk).unwrap(), public_key);
+
+        let short = json!({"kty": "OKP", "crv": "Ed25519", "x": "AAAA"});
+        assert!(verifying_key_from_jwk(&short).is_err());
+        let rsa = json!({"kty": "RSA", "n": "AAAA", "e": "AQAB"});
+        assert!(verifying_key_from_jwk(&rsa).is_err());
+    }
+}
@@ -1,18 +1,55 @@
+mod durable_log;
+mod federation;
+
+pub use durable_log::*;
+pub use federation::*;
+
 use async_trait::async_trait;
 use buckyos_api::{
     is_global_eventid, is_global_pattern, match_event_patterns, validate_event_data_size,
-    validate_eventid, validate_pattern, Event, KEventDaemonRequest, KEventDaemonResponse,
-    KEventError, KEventResult,
+    validate_eventid, validate_pattern, Event, KEventDaemonBridge, KEventDaemonRequest,
+    KEventDaemonResponse, KEventError, KEventResult,
 };
+use log::warn;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::collections::{HashMap, VecDeque};
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex as StdMutex, MutexGuard as StdMutexGuard};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::{Mutex, Notify, RwLock};
 use tokio::time::{timeout, Instant};
 
 pub const DEFAULT_DAEMON_READER_CAPACITY: usize = 1024;
 
+// "durable" section of the kevent daemon config
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KEventDurableConfig {
+    pub patterns: Vec<String>,
+    pub path: PathBuf,
+    #[serde(default = "default_durable_max_events")]
+    pub max_events: usize,
+}
+
+fn default_durable_max_events() -> usize {
+    DEFAULT_DURABLE_LOG_CAPACITY
+}
+
+// kevent daemon config, read at daemon startup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KEventDaemonConfig {
+    pub node_id: String,
+    #[serde(default = "default_reader_capacity")]
+    pub reader_capacity: usize,
+    // None: durable mode off, readers can not resume by seq
+    #[serde(default)]
+    pub durable: Option<KEventDurableConfig>,
+}
+
+fn default_reader_capacity() -> usize {
+    DEFAULT_DAEMON_READER_CAPACITY
+}
+
 #[async_trait]
 pub trait KEventPeerPublisher: Send + Sync {
     async fn broadcast(&self, event: &Event) -> KEventResult<()>;
@@ -24,6 +61,10 @@ pub struct KEventService {
     reader_capacity: usize,
     readers: Arc<RwLock<HashMap<String, Arc<ServiceReaderState>>>>,
     peers: Arc<RwLock<Vec<Arc<dyn KEventPeerPublisher>>>>,
+    // appends write and fsync the file, every access runs in spawn_blocking
+    durable_log: Option<Arc<StdMutex<DurableEventLog>>>,
+    // copy of the log patterns, so publishers never wait on the log mutex held across fsync
+    durable_patterns: Arc<Vec<String>>,
 }
 
 struct ServiceReaderState {
@@ -31,15 +72,19 @@ struct ServiceReaderState {
     queue: Mutex<VecDeque<Event>>,
     notify: Notify,
     capacity: usize,
+    // next durable seq to read. Durable events for resumed readers are read from
+    // the log instead of the queue, so they are never dropped on overflow.
+    cursor: Mutex<Option<u64>>,
 }
 
 impl ServiceReaderState {
-    fn new(patterns: Vec<String>, capacity: usize) -> Self {
+    fn new(patterns: Vec<String>, capacity: usize, from_seq: Option<u64>) -> Self {
         Self {
             patterns,
             queue: Mutex::new(VecDeque::new()),
             notify: Notify::new(),
             capacity,
+            cursor: Mutex::new(from_seq),
         }
     }
 
@@ -70,9 +115,43 @@ impl KEventService {
             reader_capacity: reader_capacity.max(1),
             readers: Arc::new(RwLock::new(HashMap::new())),
             peers: Arc::new(RwLock::new(Vec::new())),
+            durable_log: None,
+            durable_patterns: Arc::new(Vec::new()),
         }
     }
 
+    // Daemon startup: opens the durable log when the config enables it.
+    pub fn from_config(config: KEventDaemonConfig) -> KEventResult<Self> {
+        let service = Self::new_with_capacity(config.node_id, config.reader_capacity);
+        let Some(durable) = config.durable else {
+            return Ok(service);
+        };
+        let log = DurableEventLog::open(DurableLogConfig {
+            patterns: durable.patterns,
+            path: Some(durable.path),
+            max_events: durable.max_events,
+        })?;
+        Ok(service.with_durable_log(log))
+    }
+
+    // Persist events matching the log patterns so readers can resume by seq.
+    pub fn with_durable_log(mut self, log: DurableEventLog) -> Self {
+        self.durable_patterns = Arc::new(log.patterns().to_vec());
+        self.durable_log = Some(Arc::new(StdMutex::new(log)));
+        self
+    }
+
+    // (first_seq, last_seq) of the durable log, None if durable mode is off.
+    pub async fn durable_seq_range(&self) -> Option<(u64, u64)> {
+        let log = self.durable_log.as_ref()?.clone();
+        tokio::task::spawn_blocking(move || {
+            let log = lock_log(&log);
+            (log.first_seq(), log.last_seq())
+        })
+        .await
+        .ok()
+    }
+
     pub async fn add_peer_publisher(&self, peer: Arc<dyn KEventPeerPublisher>) {
         self.peers.write().await.push(peer);
     }
@@ -82,6 +161,32 @@ impl KEventService {
         reader_id: &str,
         patterns: Vec<String>,
     ) -> KEventResult<()> {
+        self.register_reader_impl(reader_id, patterns, None).await
+    }
+
+    // Replay durable events from from_seq (0 = oldest retained) before live events.
+    // A gap event is delivered first if from_seq was already trimmed.
+    pub async fn register_reader_from(
+        &self,
+        reader_id: &str,
+        patterns: Vec<String>,
+        from_seq: u64,
+    ) -> KEventResult<()> {
+        self.register_reader_impl(reader_id, patterns, Some(from_seq))
+            .await
+    }
+
+    async fn register_reader_impl(
+        &self,
+        reader_id: &str,
+        patterns: Vec<String>,
+        from_seq: Option<u64>,
+    ) -> KEventResult<()> {
+        if from_seq.is_some() && self.durable_log.is_none() {
+            return Err(KEventError::NotSupported(
+                "durable log is not enabled".to_string(),
+            ));
+        }
         if reader_id.is_empty() {
             return Err(KEventError::InvalidPattern(
                 "reader_id must not be empty".to_string(),
@@ -103,7 +208,11 @@ impl KEventService {
 
         self.readers.write().await.insert(
             reader_id.to_string(),
-            Arc::new(ServiceReaderState::new(patterns, self.reader_capacity)),
+            Arc::new(ServiceReaderState::new(
+                patterns,
+                self.reader_capacity,
+                from_seq,
+            )),
         );
         Ok(())
     }
@@ -129,6 +238,7 @@ impl KEventService {
             ingress_node: Some(self.source_node.clone()),
             timestamp: now_millis(),
             data,
+            seq: None,
         };
         self.distribute(&event).await;
         if should_broadcast_to_peers(&event, &self.source_node) {
@@ -190,6 +300,10 @@ impl KEventService {
                 return Ok(None);
             };
 
+            if let Some(event) = self.pop_durable(&reader).await {
+                return Ok(Some(event));
+            }
+
             if let Some(event) = reader.pop().await {
                 return Ok(Some(event));
             }
@@ -221,7 +335,11 @@ impl KEventService {
             KEventDaemonRequest::RegisterReader {
                 reader_id,
                 patterns,
-            } => match self.register_reader(&reader_id, patterns).await {
+                from_seq,
+            } => match self
+                .register_reader_impl(&reader_id, patterns, from_seq)
+                .await
+            {
                 Ok(_) => KEventDaemonResponse::Ok { event: None },
                 Err(err) => err_to_response(err),
             },
@@ -246,15 +364,77 @@ impl KEventService {
     }
 
     async fn distribute(&self, event: &Event) {
+        // seq is local to this daemon's log, never trust one from publishers or peers
+        let mut event = event.clone();
+        event.seq = None;
+        if let Some(log) = &self.durable_log {
+            if match_event_patterns(&self.durable_patterns, &event.eventid) {
+                let log = log.clone();
+                let persist_event = event.clone();
+                let result =
+                    tokio::task::spawn_blocking(move || lock_log(&log).append(&persist_event))
+                        .await
+                        .unwrap_or_else(|err| {
+                            Err(KEventError::Internal(format!(
+                                "durable log writer failed: {}",
+                                err
+                            )))
+                        });
+                match result {
+                    Ok(seq) => event.seq = Some(seq),
+                    Err(err) => warn!(
+                        "append event {} to durable log failed: {}",
+                        event.eventid, err
+                    ),
+                }
+            }
+        }
+
         let snapshot: Vec<Arc<ServiceReaderState>> =
             self.readers.read().await.values().cloned().collect();
         for reader in snapshot {
-            if match_event_patterns(&reader.patterns, &event.eventid) {
+            if !match_event_patterns(&reader.patterns, &event.eventid) {
+                continue;
+            }
+            if event.seq.is_some() && reader.cursor.lock().await.is_some() {
+                reader.notify.notify_one();
+            } else {
                 reader.push(event.clone()).await;
             }
         }
     }
 
+    async fn pop_durable(&self, reader: &ServiceReaderState) -> Option<Event> {
+        let log = self.durable_log.as_ref()?;
+        let mut cursor = reader.cursor.lock().await;
+        let from_seq = (*cursor)?;
+        let log = log.clone();
+        let patterns = reader.patterns.clone();
+        let (read, last_seq) = tokio::task::spawn_blocking(move || {
+            let log = lock_log(&log);
+            (log.read_from(from_seq, &patterns), log.last_seq())
+        })
+        .await
+        .ok()?;
+        match read {
+            DurableRead::Event(event) => {
+                *cursor = Some(event.seq.unwrap_or(from_seq) + 1);
+                Some(event)
+            }
+            DurableRead::Gap {
+                requested_seq,
+                first_seq,
+            } => {
+                *cursor = Some(first_seq);
+                Some(build_gap_event(&self.source_node, requested_seq, first_seq))
+            }
+            DurableRead::Empty => {
+                *cursor = Some(from_seq.max(last_seq + 1));
+                None
+            }
+        }
+    }
+
     async fn broadcast_to_peers(&self, event: &Event) -> KEventResult<()> {
         let peers = self.peers.read().await.clone();
         let mut last_error: Option<KEventError> = None;
@@ -270,6 +450,49 @@ impl KEventService {
     }
 }
 
+// In-process bridge, for a KEventClient running inside the daemon.
+// The client pulls the events of resumed readers with pull_reader_event.
+#[async_trait]
+impl KEventDaemonBridge for KEventService {
+    async fn register_reader(&self, reader_id: &str, patterns: &[String]) -> KEventResult<()> {
+        KEventService::register_reader(self, reader_id, patterns.to_vec()).await
+    }
+
+    async fn register_reader_from(
+        &self,
+        reader_id: &str,
+        patterns: &[String],
+        from_seq: u64,
+    ) -> KEventResult<()> {
+        KEventService::register_reader_from(self, reader_id, patterns.to_vec(), from_seq).await
+    }
+
+    async fn pull_reader_event(
+        &self,
+        reader_id: &str,
+        timeout_ms: Option<u64>,
+    ) -> KEventResult<Option<Event>> {
+        if !self.readers.read().await.contains_key(reader_id) {
+            return Err(KEventError::ReaderClosed(reader_id.to_string()));
+        }
+        self.pull_event(reader_id, timeout_ms).await
+    }
+
+    async fn unregister_reader(&self, reader_id: &str) -> KEventResult<()> {
+        KEventService::unregister_reader(self, reader_id).await;
+        Ok(())
+    }
+
+    async fn publish_global(&self, event: &Event) -> KEventResult<()> {
+        self.publish_external_global(event.clone()).await
+    }
+}
+
+// appends hold the lock across fsync and compaction, so it is only taken inside spawn_blocking
+fn lock_log(log: &StdMutex<DurableEventLog>) -> StdMutexGuard<'_, DurableEventLog> {
+    log.lock().unwrap_or_else(|err| err.into_inner())
+}
+
 fn now_millis() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -298,6 +521,7 @@ pub fn protocol_ok() -> Value {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use buckyos_api::{KEventClient, KEVENT_GAP_EVENTID};
 
     #[tokio::test]
     async fn test_daemon_register_publish_pull() {
@@ -322,8 +546,150 @@ mod tests {
             .handle_protocol_request(KEventDaemonRequest::RegisterReader {
                 reader_id: "r1".to_string(),
                 patterns: vec!["/system/**".to_string()],
+                from_seq: None,
             })
             .await;
         assert!(matches!(resp, KEventDaemonResponse::Ok { .. }));
+
+        let resp = service
+            .handle_protocol_request(KEventDaemonRequest::RegisterReader {
+                reader_id: "r2".to_string(),
+                patterns: vec!["/system/**".to_string()],
+                from_seq: Some(1),
+            })
+            .await;
+        assert!(matches!(resp, KEventDaemonResponse::Err { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_daemon_config_enables_durable_log() {
+        let path =
+            std::env::temp_dir().join(format!("buckyos_kevent_config_{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let config: KEventDaemonConfig = serde_json::from_value(json!({
+            "node_id": "node_a",
+            "durable": {"patterns": ["/taskmgr/**"], "path": path},
+        }))
+        .unwrap();
+        let service = KEventService::from_config(config).unwrap();
+        service
+            .publish_local_global("/taskmgr/task_0", json!({}))
+            .await
+            .unwrap();
+        assert_eq!(service.durable_seq_range().await, Some((1, 1)));
+
+        let config: KEventDaemonConfig =
+            serde_json::from_value(json!({"node_id": "node_a"})).unwrap();
+        let service = KEventService::from_config(config).unwrap();
+        assert_eq!(service.durable_seq_range().await, None);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    fn durable_service(name: &str, max_events: usize, reset: bool) -> KEventService {
+        let path = std::env::temp_dir().join(format!(
+            "buckyos_kevent_service_{}_{}.log",
+            name,
+            std::process::id()
+        ));
+        if reset {
+            let _ = std::fs::remove_file(&path);
+        }
+        let log = DurableEventLog::open(DurableLogConfig {
+            patterns: vec!["/taskmgr/**".to_string()],
+            path: Some(path),
+            max_events,
+        })
+        .unwrap();
+        KEventService::new_with_capacity("node_a", 2).with_durable_log(log)
+    }
+
+    #[tokio::test]
+    async fn test_durable_reader_resume_after_restart() {
+        let service = durable_service("resume", 16, true);
+        for index in 0..3 {
+            service
+                .publish_local_global(&format!("/taskmgr/task_{}", index), json!({}))
+                .await
+                .unwrap();
+        }
+        drop(service);
+
+        let service = durable_service("resume", 16, false);
+        assert_eq!(service.durable_seq_range().await, Some((1, 3)));
+        service
+            .register_reader_from("r1", vec!["/taskmgr/**".to_string()], 2)
+            .await
+            .unwrap();
+        // live events beyond reader capacity are not dropped for resumed readers
+        for index in 3..6 {
+            service
+                .publish_local_global(&format!("/taskmgr/task_{}", index), json!({}))
+                .await
+                .unwrap();
+        }
+
+        for expect_seq in 2..=6 {
+            let event = service.pull_event("r1", Some(100)).await.unwrap().unwrap();
+            assert_eq!(event.seq, Some(expect_seq));
+            assert_eq!(event.eventid, format!("/taskmgr/task_{}", expect_seq - 1));
+        }
+        assert!(service.pull_event("r1", Some(0)).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_durable_reader_gap_notification() {
+        let service = durable_service("gap", 2, true);
+        for index in 0..5 {
+            service
+                .publish_local_global(&format!("/taskmgr/task_{}", index), json!({}))
+                .await
+                .unwrap();
+        }
+        service
+            .register_reader_from("r1", vec!["/taskmgr/**".to_string()], 1)
+            .await
+            .unwrap();
+
+        let gap = service.pull_event("r1", Some(100)).await.unwrap().unwrap();
+        assert_eq!(gap.eventid, KEVENT_GAP_EVENTID);
+        assert_eq!(gap.data["requested_seq"], json!(1));
+        assert_eq!(gap.data["first_seq"], json!(4));
+        let event = service.pull_event("r1", Some(100)).await.unwrap().unwrap();
+        assert_eq!(event.seq, Some(4));
+        let event = service.pull_event("r1", Some(100)).await.unwrap().unwrap();
+        assert_eq!(event.seq, Some(5));
+    }
+
+    #[tokio::test]
+    async fn test_client_reader_resume_through_service_bridge() {
+        let service = durable_service("bridge", 16, true);
+        for index in 0..3 {
+            service
+                .publish_local_global(&format!("/taskmgr/task_{}", index), json!({}))
+                .await
+                .unwrap();
+        }
+
+        let bridge: Arc<dyn KEventDaemonBridge> = Arc::new(service.clone());
+        let client = KEventClient::new_full("node_a", Some(bridge));
+        let reader = client
+            .create_event_reader_from(vec!["/taskmgr/**".to_string()], 2)
+            .await
+            .unwrap();
+        service
+            .publish_local_global("/taskmgr/task_3", json!({}))
+            .await
+            .unwrap();
+
+        // replayed and live events arrive through the bridge, each exactly once
+        for expect_seq in 2..=4 {
+            let event = reader.pull_event(Some(1000)).await.unwrap().unwrap();
+            assert_eq!(event.seq, Some(expect_seq));
+        }
+        assert!(reader.pull_event(Some(100)).await.unwrap().is_none());
+
+        let reader_id = reader.reader_id().to_string();
+        reader.close().await.unwrap();
+        assert!(!service.readers.read().await.contains_key(&reader_id));
     }
 }
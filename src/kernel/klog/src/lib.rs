@@ -5,18 +5,20 @@ use openraft::{LogId, declare_raft_types};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
-#[cfg(test)]
 mod logs;
-#[cfg(test)]
 mod network;
-#[cfg(test)]
+mod node;
 mod state_machine;
-#[cfg(test)]
 mod storage;
 #[cfg(test)]
 mod test;
 
-#[cfg(test)]
+pub use logs::*;
+pub use network::*;
+pub use node::*;
+pub use state_machine::*;
+pub use storage::*;
+
 #[macro_use]
 extern crate log;
 
@@ -24,6 +26,19 @@ extern crate log;
 pub enum KLogError {
     #[error("Invalid format: {0}")]
     InvalidFormat(String),
+    #[error("Invalid param: {0}")]
+    InvalidParam(String),
+    #[error("Storage error: {0}")]
+    StorageError(String),
+    #[error("Network error: {0}")]
+    NetworkError(String),
+    #[error("Raft error: {0}")]
+    RaftError(String),
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    // Write requests must go to the leader, the current leader is attached if known
+    #[error("Not leader, current leader: {0:?}")]
+    NotLeader(Option<KNode>),
 }
 
 pub type KResult<T> = Result<T, KLogError>;
@@ -46,7 +61,8 @@ pub enum KLogResponse {
     Ok,
     Empty, // For empty and membership payloads
     AppendOk { id: u64 },
-    Err(String),
+    // The error returned by the log storage, the same on every node
+    Err(KLogError),
 }
 
 pub type KNodeId = u64;
@@ -1,3 +1,5 @@
 mod memory;
+mod sled_storage;
 
 pub use memory::*;
+pub use sled_storage::*;
@@ -0,0 +1,279 @@
+use openraft::{Entry, LogId, OptionalSend, Vote};
+use openraft::{
+    RaftLogReader, StorageError, StorageIOError,
+    storage::{LogFlushed, RaftLogStorage},
+};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::fmt::Debug;
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+
+use crate::{KNodeId, KTypeConfig, StorageResult};
+
+type LogEntry = Entry<KTypeConfig>;
+
+const KEY_VOTE: &str = "vote";
+const KEY_LAST_PURGED: &str = "last_purged";
+const KEY_COMMITTED: &str = "committed";
+
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, std::io::Error> {
+    bincode::serde::encode_to_vec(value, bincode::config::legacy())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))
+}
+
+fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, std::io::Error> {
+    bincode::serde::decode_from_slice(data, bincode::config::legacy())
+        .map(|(value, _)| value)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))
+}
+
+/// Raft log and vote storage persisted in a sled database.
+/// Log entries are keyed by big-endian index so that sled iteration order is log order.
+#[derive(Debug, Clone)]
+pub struct SledLogStorage {
+    db: sled::Db,
+    logs: sled::Tree,
+    meta: sled::Tree,
+}
+
+impl SledLogStorage {
+    pub fn open(path: &Path) -> StorageResult<Self> {
+        info!("Opening raft log storage at {:?}", path);
+        let db = sled::open(path).map_err(|e| {
+            error!("Failed to open raft log storage {:?}: {}", path, e);
+            StorageError::IO {
+                source: StorageIOError::read_logs(&e),
+            }
+        })?;
+        let logs = db.open_tree("logs").map_err(|e| StorageError::IO {
+            source: StorageIOError::read_logs(&e),
+        })?;
+        let meta = db.open_tree("meta").map_err(|e| StorageError::IO {
+            source: StorageIOError::read_logs(&e),
+        })?;
+
+        Ok(Self { db, logs, meta })
+    }
+
+    fn read_meta<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, std::io::Error> {
+        let value = self
+            .meta
+            .get(key)
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        value.map(|value| decode(&value)).transpose()
+    }
+
+    fn write_meta<T: Serialize>(&self, key: &str, value: &T) -> Result<(), std::io::Error> {
+        let data = encode(value)?;
+        self.meta
+            .insert(key, data)
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<(), std::io::Error> {
+        self.db
+            .flush_async()
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        Ok(())
+    }
+
+    fn remove_range(&self, start: u64, end: Option<u64>) -> Result<(), std::io::Error> {
+        let keys: Vec<sled::IVec> = match end {
+            Some(end) => self
+                .logs
+                .range(start.to_be_bytes()..=end.to_be_bytes())
+                .keys()
+                .collect::<Result<_, _>>(),
+            None => self
+                .logs
+                .range(start.to_be_bytes()..)
+                .keys()
+                .collect::<Result<_, _>>(),
+        }
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+
+        for key in keys {
+            self.logs
+                .remove(key)
+                .map_err(|e| std::io::Error::other(e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+impl RaftLogReader<KTypeConfig> for SledLogStorage {
+    async fn try_get_log_entries<RB: RangeBounds<u64> + Clone + Debug + OptionalSend>(
+        &mut self,
+        range: RB,
+    ) -> StorageResult<Vec<LogEntry>> {
+        debug!("try_get_log_entries: range={:?}", range);
+
+        let start = match range.start_bound() {
+            Bound::Included(start) => *start,
+            Bound::Excluded(start) => *start + 1,
+            Bound::Unbounded => 0,
+        };
+
+        let mut entries = Vec::new();
+        for item in self.logs.range(start.to_be_bytes()..) {
+            let (key, value) = item.map_err(|e| StorageError::IO {
+                source: StorageIOError::read_logs(&e),
+            })?;
+            let mut index = [0u8; 8];
+            index.copy_from_slice(&key);
+            if !range.contains(&u64::from_be_bytes(index)) {
+                break;
+            }
+
+            let entry: LogEntry = decode(&value).map_err(|e| StorageError::IO {
+                source: StorageIOError::read_logs(&e),
+            })?;
+            entries.push(entry);
+        }
+
+        Ok(entries)
+    }
+}
+
+impl RaftLogStorage<KTypeConfig> for SledLogStorage {
+    type LogReader = Self;
+
+    async fn get_log_state(&mut self) -> StorageResult<openraft::LogState<KTypeConfig>> {
+        let last_log_id = match self.logs.last().map_err(|e| StorageError::IO {
+            source: StorageIOError::read_logs(&e),
+        })? {
+            Some((_, value)) => {
+                let entry: LogEntry = decode(&value).map_err(|e| StorageError::IO {
+                    source: StorageIOError::read_logs(&e),
+                })?;
+                Some(entry.log_id)
+            }
+            None => None,
+        };
+
+        let last_purged_log_id: Option<LogId<KNodeId>> =
+            self.read_meta(KEY_LAST_PURGED)
+                .map_err(|e| StorageError::IO {
+                    source: StorageIOError::read_logs(&e),
+                })?;
+        let last_log_id = match last_log_id {
+            Some(id) => Some(id),
+            None => last_purged_log_id,
+        };
+
+        Ok(openraft::LogState {
+            last_log_id,
+            last_purged_log_id,
+        })
+    }
+
+    async fn get_log_reader(&mut self) -> Self::LogReader {
+        self.clone()
+    }
+
+    async fn save_vote(&mut self, vote: &Vote<KNodeId>) -> StorageResult<()> {
+        debug!("save_vote: {:?}", vote);
+        self.write_meta(KEY_VOTE, vote)
+            .map_err(|e| StorageError::IO {
+                source: StorageIOError::write_vote(&e),
+            })?;
+        self.flush().await.map_err(|e| StorageError::IO {
+            source: StorageIOError::write_vote(&e),
+        })?;
+
+        Ok(())
+    }
+
+    async fn read_vote(&mut self) -> StorageResult<Option<Vote<KNodeId>>> {
+        self.read_meta(KEY_VOTE).map_err(|e| StorageError::IO {
+            source: StorageIOError::read_vote(&e),
+        })
+    }
+
+    // Committed log id is persisted so that a restarted node can re-apply
+    // the logs after its last snapshot without waiting for the leader.
+    async fn save_committed(&mut self, committed: Option<LogId<KNodeId>>) -> StorageResult<()> {
+        self.write_meta(KEY_COMMITTED, &committed)
+            .map_err(|e| StorageError::IO {
+                source: StorageIOError::write_logs(&e),
+            })?;
+        Ok(())
+    }
+
+    async fn read_committed(&mut self) -> StorageResult<Option<LogId<KNodeId>>> {
+        let committed: Option<Option<LogId<KNodeId>>> =
+            self.read_meta(KEY_COMMITTED)
+                .map_err(|e| StorageError::IO {
+                    source: StorageIOError::read_logs(&e),
+                })?;
+        Ok(committed.flatten())
+    }
+
+    async fn append<I>(
+        &mut self,
+        entries: I,
+        callback: LogFlushed<KTypeConfig>,
+    ) -> StorageResult<()>
+    where
+        I: IntoIterator<Item = LogEntry> + OptionalSend,
+        I::IntoIter: OptionalSend,
+    {
+        for entry in entries {
+            debug!("Appending raft log entry: {:?}", entry.log_id);
+            let data = encode(&entry).map_err(|e| StorageError::IO {
+                source: StorageIOError::write_logs(&e),
+            })?;
+            self.logs
+                .insert(entry.log_id.index.to_be_bytes(), data)
+                .map_err(|e| StorageError::IO {
+                    source: StorageIOError::write_logs(&e),
+                })?;
+        }
+
+        // Only report the io completed after the entries reach the disk
+        let ret = self.flush().await;
+        let result = ret
+            .as_ref()
+            .map(|_| ())
+            .map_err(|e| std::io::Error::new(e.kind(), e.to_string()));
+        callback.log_io_completed(result);
+
+        ret.map_err(|e| StorageError::IO {
+            source: StorageIOError::write_logs(&e),
+        })
+    }
+
+    async fn truncate(&mut self, log_id: LogId<KNodeId>) -> StorageResult<()> {
+        info!("Truncating raft logs from index {}", log_id.index);
+
+        // Remove all entries with index >= log_id.index
+        self.remove_range(log_id.index, None)
+            .map_err(|e| StorageError::IO {
+                source: StorageIOError::write_logs(&e),
+            })?;
+        self.flush().await.map_err(|e| StorageError::IO {
+            source: StorageIOError::write_logs(&e),
+        })
+    }
+
+    /// Remove all log entries that index <= `log_id.index`
+    async fn purge(&mut self, log_id: LogId<KNodeId>) -> StorageResult<()> {
+        info!("Purging raft logs up to index {}", log_id.index);
+
+        // Record the purged id first, a crash in between only leaves some extra logs
+        self.write_meta(KEY_LAST_PURGED, &log_id)
+            .map_err(|e| StorageError::IO {
+                source: StorageIOError::write_logs(&e),
+            })?;
+        self.remove_range(0, Some(log_id.index))
+            .map_err(|e| StorageError::IO {
+                source: StorageIOError::write_logs(&e),
+            })?;
+        self.flush().await.map_err(|e| StorageError::IO {
+            source: StorageIOError::write_logs(&e),
+        })
+    }
+}
@@ -1,3 +1,30 @@
-fn main() {
-    println!("Hello, world!");
+use buckyos_kit::init_logging;
+use klog::{KLOG_SERVICE_NAME, KLogNode, KLogNodeConfig};
+use log::{error, info};
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    init_logging(KLOG_SERVICE_NAME, true);
+
+    let config = KLogNodeConfig::from_env()
+        .map_err(|err| anyhow::anyhow!("load klog config failed: {}", err))?;
+    let node = KLogNode::start(config)
+        .await
+        .map_err(|err| anyhow::anyhow!("start klog node failed: {}", err))?;
+    info!("klog node started: {:?}", node.node());
+
+    // The first node of a new cluster bootstraps itself as the single voter,
+    // other nodes join later through the add-learner/change-membership api
+    if std::env::var("KLOG_INIT_CLUSTER").is_ok_and(|v| v == "1" || v == "true") {
+        if let Err(err) = node.initialize(vec![node.node().clone()]).await {
+            error!("init klog cluster failed: {}", err);
+        }
+    }
+
+    tokio::signal::ctrl_c().await?;
+    if let Err(err) = node.shutdown().await {
+        error!("shutdown klog node failed: {}", err);
+    }
+
+    Ok(())
 }
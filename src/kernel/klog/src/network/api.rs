@@ -0,0 +1,189 @@
+use super::auth::KLogAuthRef;
+use crate::{KLogEntry, KLogError, KNode, KNodeId, KResult};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_READ_LIMIT: usize = 100;
+pub const MAX_READ_LIMIT: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClusterInitRequest {
+    // Initial voters, must contain the node which receives the request
+    pub nodes: Vec<KNode>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddLearnerRequest {
+    pub node: KNode,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangeMembershipRequest {
+    // The new voter set, all of them must have been added as learners before
+    pub members: Vec<KNodeId>,
+    // Keep the removed voters as learners
+    #[serde(default)]
+    pub retain: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClusterStatus {
+    pub node_id: KNodeId,
+    pub state: String,
+    pub current_term: u64,
+    pub current_leader: Option<KNodeId>,
+    pub last_log_index: Option<u64>,
+    pub last_applied_index: Option<u64>,
+    pub voters: Vec<KNodeId>,
+    pub learners: Vec<KNodeId>,
+    pub nodes: Vec<KNode>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppendLogRequest {
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppendLogResult {
+    pub id: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadLogsRequest {
+    #[serde(default)]
+    pub from_id: Option<u64>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+/// Http client for the klog cluster and logs api.
+/// Reads are served by the requested node, appends are redirected to the leader if needed.
+pub struct KLogClient {
+    client: reqwest::Client,
+    base_url: String,
+    auth: Option<KLogAuthRef>,
+}
+
+impl KLogClient {
+    pub fn new(addr: &str, port: u16) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            base_url: format!("http://{}:{}/klog", addr, port),
+            auth: None,
+        }
+    }
+
+    pub fn new_with_node(node: &KNode) -> Self {
+        Self::new(&node.addr, node.port)
+    }
+
+    // Sign every request with the auth of the local node
+    pub fn with_auth(mut self, auth: Option<KLogAuthRef>) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    fn authorize(&self, builder: reqwest::RequestBuilder) -> KResult<reqwest::RequestBuilder> {
+        match &self.auth {
+            Some(auth) => Ok(builder.bearer_auth(auth.token()?)),
+            None => Ok(builder),
+        }
+    }
+
+    async fn parse_response<T: DeserializeOwned>(
+        url: &str,
+        resp: Result<reqwest::Response, reqwest::Error>,
+    ) -> KResult<T> {
+        let resp = resp.map_err(|e| {
+            let msg = format!("Failed to send request to {}: {}", url, e);
+            error!("{}", msg);
+            KLogError::NetworkError(msg)
+        })?;
+
+        let status = resp.status();
+        let body = resp.bytes().await.map_err(|e| {
+            let msg = format!("Failed to read response body from {}: {}", url, e);
+            error!("{}", msg);
+            KLogError::NetworkError(msg)
+        })?;
+
+        serde_json::from_slice::<KResult<T>>(&body).map_err(|e| {
+            let msg = format!("Invalid response from {}, status {}: {}", url, status, e);
+            error!("{}", msg);
+            KLogError::InvalidFormat(msg)
+        })?
+    }
+
+    async fn post<Req: Serialize, T: DeserializeOwned>(&self, path: &str, req: &Req) -> KResult<T> {
+        let url = format!("{}/{}", self.base_url, path);
+        let resp = self
+            .authorize(self.client.post(&url))?
+            .json(req)
+            .send()
+            .await;
+        Self::parse_response(&url, resp).await
+    }
+
+    pub async fn init_cluster(&self, nodes: Vec<KNode>) -> KResult<()> {
+        self.post("cluster/init", &ClusterInitRequest { nodes })
+            .await
+    }
+
+    pub async fn add_learner(&self, node: KNode) -> KResult<()> {
+        self.post("cluster/add-learner", &AddLearnerRequest { node })
+            .await
+    }
+
+    pub async fn change_membership(&self, members: Vec<KNodeId>, retain: bool) -> KResult<()> {
+        let req = ChangeMembershipRequest { members, retain };
+        self.post("cluster/change-membership", &req).await
+    }
+
+    pub async fn status(&self) -> KResult<ClusterStatus> {
+        let url = format!("{}/cluster/status", self.base_url);
+        let resp = self.authorize(self.client.get(&url))?.send().await;
+        Self::parse_response(&url, resp).await
+    }
+
+    pub async fn append(&self, message: &str) -> KResult<u64> {
+        self.append_to("logs", message).await
+    }
+
+    // Append through the peer route, used by the nodes of an embedded state machine
+    // to forward writes to the leader, the generic logs api may be off there
+    pub async fn forward_append(&self, message: &str) -> KResult<u64> {
+        self.append_to("forward-append", message).await
+    }
+
+    async fn append_to(&self, path: &str, message: &str) -> KResult<u64> {
+        let req = AppendLogRequest {
+            message: message.to_string(),
+        };
+
+        let ret: KResult<AppendLogResult> = self.post(path, &req).await;
+        match ret {
+            Ok(ret) => Ok(ret.id),
+            // Retry once on the leader known by the requested node
+            Err(KLogError::NotLeader(Some(leader))) => {
+                info!("Redirect append request to leader {:?}", leader);
+                let ret: AppendLogResult = Self::new_with_node(&leader)
+                    .with_auth(self.auth.clone())
+                    .post(path, &req)
+                    .await?;
+                Ok(ret.id)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn read(&self, from_id: u64, limit: usize) -> KResult<Vec<KLogEntry>> {
+        let url = format!("{}/logs", self.base_url);
+        let resp = self
+            .authorize(self.client.get(&url))?
+            .query(&[("from_id", from_id), ("limit", limit as u64)])
+            .send()
+            .await;
+        Self::parse_response(&url, resp).await
+    }
+}
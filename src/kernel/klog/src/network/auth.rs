@@ -0,0 +1,134 @@
+use crate::{KLogError, KResult};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub const KLOG_TOKEN_AUDIENCE: &str = "klog";
+// Tokens are signed per request, so a short lifetime only has to cover clock skew
+pub const KLOG_TOKEN_TTL_SECS: u64 = 60;
+
+/// Authentication of the klog http api. Every request carries `Authorization: Bearer <token>`,
+/// the raft rpcs and forwarded appends use `token`, the server checks them with `verify`.
+pub trait KLogAuth: Send + Sync + std::fmt::Debug {
+    fn token(&self) -> KResult<String>;
+
+    fn verify(&self, token: &str) -> KResult<()>;
+}
+
+pub type KLogAuthRef = Arc<dyn KLogAuth>;
+
+// "auth" section of the node config, the keys are the device keys of the zone
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KLogAuthConfig {
+    // Put in the token header, the other nodes look up the public key by it
+    pub kid: String,
+    // Ed25519 private key in pem format
+    pub private_key_path: PathBuf,
+    // kid -> Ed25519 public key (the `x` of the jwk), must contain every node of the cluster
+    pub trusted_keys: HashMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct KLogClaims {
+    sub: String,
+    aud: String,
+    exp: u64,
+}
+
+/// EdDSA JWT signed by the device key of the node, accepted if the kid is a trusted node
+pub struct KLogJwtAuth {
+    kid: String,
+    signing_key: EncodingKey,
+    trusted_keys: HashMap<String, DecodingKey>,
+}
+
+impl KLogJwtAuth {
+    pub fn new(
+        kid: String,
+        signing_key: EncodingKey,
+        trusted_keys: HashMap<String, DecodingKey>,
+    ) -> Self {
+        Self {
+            kid,
+            signing_key,
+            trusted_keys,
+        }
+    }
+
+    pub fn load(config: &KLogAuthConfig) -> KResult<Self> {
+        let pem = std::fs::read(&config.private_key_path).map_err(|e| {
+            let msg = format!(
+                "Failed to read klog private key {:?}: {}",
+                config.private_key_path, e
+            );
+            error!("{}", msg);
+            KLogError::InvalidParam(msg)
+        })?;
+        let signing_key = EncodingKey::from_ed_pem(&pem).map_err(|e| {
+            let msg = format!("Invalid klog private key: {}", e);
+            error!("{}", msg);
+            KLogError::InvalidParam(msg)
+        })?;
+
+        let mut trusted_keys = HashMap::new();
+        for (kid, x) in config.trusted_keys.iter() {
+            let key = DecodingKey::from_ed_components(x).map_err(|e| {
+                let msg = format!("Invalid klog trusted key {}: {}", kid, e);
+                error!("{}", msg);
+                KLogError::InvalidParam(msg)
+            })?;
+            trusted_keys.insert(kid.clone(), key);
+        }
+
+        Ok(Self::new(config.kid.clone(), signing_key, trusted_keys))
+    }
+}
+
+impl std::fmt::Debug for KLogJwtAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KLogJwtAuth")
+            .field("kid", &self.kid)
+            .field(
+                "trusted_keys",
+                &self.trusted_keys.keys().collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl KLogAuth for KLogJwtAuth {
+    fn token(&self) -> KResult<String> {
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(self.kid.clone());
+        let claims = KLogClaims {
+            sub: self.kid.clone(),
+            aud: KLOG_TOKEN_AUDIENCE.to_string(),
+            exp: chrono::Utc::now().timestamp() as u64 + KLOG_TOKEN_TTL_SECS,
+        };
+        jsonwebtoken::encode(&header, &claims, &self.signing_key).map_err(|e| {
+            let msg = format!("Failed to sign klog token: {}", e);
+            error!("{}", msg);
+            KLogError::InvalidParam(msg)
+        })
+    }
+
+    fn verify(&self, token: &str) -> KResult<()> {
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|e| KLogError::Unauthorized(format!("Invalid token: {}", e)))?;
+        let kid = header
+            .kid
+            .ok_or_else(|| KLogError::Unauthorized("Token without kid".to_string()))?;
+        let key = self
+            .trusted_keys
+            .get(&kid)
+            .ok_or_else(|| KLogError::Unauthorized(format!("Untrusted kid: {}", kid)))?;
+
+        let mut validation = Validation::new(Algorithm::EdDSA);
+        validation.set_audience(&[KLOG_TOKEN_AUDIENCE]);
+        jsonwebtoken::decode::<KLogClaims>(token, key, &validation)
+            .map_err(|e| KLogError::Unauthorized(format!("Invalid token of {}: {}", kid, e)))?;
+        Ok(())
+    }
+}
@@ -1,7 +1,8 @@
+use super::auth::KLogAuthRef;
 use super::request::{RaftRequest, RaftResponse};
 use crate::{KNode, KNodeId, KTypeConfig};
 use openraft::error::{
-    InstallSnapshotError, NetworkError, RPCError, RaftError, Timeout, Unreachable,
+    InstallSnapshotError, NetworkError, RPCError, RaftError, RemoteError, Timeout, Unreachable,
 };
 use openraft::network::{RPCOption, RaftNetwork, RaftNetworkFactory};
 use openraft::raft::{
@@ -11,13 +12,20 @@ use openraft::raft::{
 
 pub struct KNetworkFactory {
     local: KNodeId,
+    auth: Option<KLogAuthRef>,
+}
+
+impl KNetworkFactory {
+    pub fn new(local: KNodeId, auth: Option<KLogAuthRef>) -> Self {
+        Self { local, auth }
+    }
 }
 
 impl RaftNetworkFactory<KTypeConfig> for KNetworkFactory {
     type Network = KNetworkClient;
 
     async fn new_client(&mut self, target: KNodeId, node: &KNode) -> Self::Network {
-        KNetworkClient::new(self.local.clone(), target, node.clone())
+        KNetworkClient::new(self.local.clone(), target, node.clone(), self.auth.clone())
     }
 }
 
@@ -26,14 +34,16 @@ pub struct KNetworkClient {
     local: KNodeId,
     target: KNodeId,
     node: KNode,
+    auth: Option<KLogAuthRef>,
 }
 
 impl KNetworkClient {
-    pub fn new(local: KNodeId, target: KNodeId, node: KNode) -> Self {
+    pub fn new(local: KNodeId, target: KNodeId, node: KNode, auth: Option<KLogAuthRef>) -> Self {
         Self {
             local,
             target,
             node,
+            auth,
             client: reqwest::Client::new(),
         }
     }
@@ -54,11 +64,20 @@ impl KNetworkClient {
             RPCError::Unreachable(Unreachable::new(&e))
         })?;
 
-        let resp = self
+        let mut builder = self
             .client
             .post(&url)
-            .header("Content-Type", "application/octet-stream")
+            .header("Content-Type", "application/octet-stream");
+        if let Some(auth) = &self.auth {
+            let token = auth
+                .token()
+                .map_err(|e| RPCError::Unreachable(Unreachable::new(&e)))?;
+            builder = builder.bearer_auth(token);
+        }
+
+        let resp = builder
             .body(body)
+            .timeout(option.hard_ttl())
             .send()
             .await
             .map_err(|e| {
@@ -129,6 +148,18 @@ impl KNetworkClient {
 
 // pub type RPCResult<T> = Result<T, openraft::network::RPCError<KNodeId>>;
 
+fn unexpected_response<Err>(resp: RaftResponse) -> RPCError<KNodeId, KNode, Err>
+where
+    Err: std::error::Error + 'static + Clone,
+{
+    let msg = format!("Unexpected response type: {:?}", resp);
+    error!("{}", msg);
+    RPCError::Unreachable(Unreachable::new(&std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        msg,
+    )))
+}
+
 impl RaftNetwork<KTypeConfig> for KNetworkClient {
     /// Send an AppendEntries RPC to the target.
     async fn append_entries(
@@ -140,10 +171,11 @@ impl RaftNetwork<KTypeConfig> for KNetworkClient {
             .request::<RaftError<KNodeId>>(RaftRequest::AppendEntries(rpc), option)
             .await
         {
-            Ok(RaftResponse::AppendEntries(resp)) => Ok(resp),
-            Ok(other) => {
-                unreachable!("Unexpected response type: {:?}", other);
+            Ok(RaftResponse::AppendEntries(Ok(resp))) => Ok(resp),
+            Ok(RaftResponse::AppendEntries(Err(e))) => {
+                Err(RPCError::RemoteError(RemoteError::new(self.target, e)))
             }
+            Ok(other) => Err(unexpected_response(other)),
             Err(e) => Err(e),
         }
     }
@@ -163,10 +195,11 @@ impl RaftNetwork<KTypeConfig> for KNetworkClient {
             )
             .await
         {
-            Ok(RaftResponse::InstallSnapshot(resp)) => Ok(resp),
-            Ok(other) => {
-                unreachable!("Unexpected response type: {:?}", other);
+            Ok(RaftResponse::InstallSnapshot(Ok(resp))) => Ok(resp),
+            Ok(RaftResponse::InstallSnapshot(Err(e))) => {
+                Err(RPCError::RemoteError(RemoteError::new(self.target, e)))
             }
+            Ok(other) => Err(unexpected_response(other)),
             Err(e) => Err(e),
         }
     }
@@ -181,10 +214,11 @@ impl RaftNetwork<KTypeConfig> for KNetworkClient {
             .request::<RaftError<KNodeId>>(RaftRequest::Vote(rpc), option)
             .await
         {
-            Ok(RaftResponse::Vote(resp)) => Ok(resp),
-            Ok(other) => {
-                unreachable!("Unexpected response type: {:?}", other);
+            Ok(RaftResponse::Vote(Ok(resp))) => Ok(resp),
+            Ok(RaftResponse::Vote(Err(e))) => {
+                Err(RPCError::RemoteError(RemoteError::new(self.target, e)))
             }
+            Ok(other) => Err(unexpected_response(other)),
             Err(e) => Err(e),
         }
     }
@@ -1,3 +1,11 @@
+mod api;
+mod auth;
 mod client;
-//mod server;
 mod request;
+mod server;
+
+pub use api::*;
+pub use auth::*;
+pub use client::*;
+pub use request::*;
+pub use server::*;
@@ -1,5 +1,5 @@
 use crate::{KLogError, KNodeId, KResult, KTypeConfig};
-use openraft::error::PayloadTooLarge;
+use openraft::error::{InstallSnapshotError, PayloadTooLarge, RaftError};
 use openraft::network::RPCTypes;
 use openraft::raft::{
     AppendEntriesRequest, AppendEntriesResponse, InstallSnapshotRequest, InstallSnapshotResponse,
@@ -89,11 +89,15 @@ impl RaftRequest {
     }
 }
 
+// Raft errors returned by the remote node are carried in the response body,
+// so the caller can tell them apart from transport failures.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum RaftResponse {
-    AppendEntries(AppendEntriesResponse<KNodeId>),
-    InstallSnapshot(InstallSnapshotResponse<KNodeId>),
-    Vote(VoteResponse<KNodeId>),
+    AppendEntries(Result<AppendEntriesResponse<KNodeId>, RaftError<KNodeId>>),
+    InstallSnapshot(
+        Result<InstallSnapshotResponse<KNodeId>, RaftError<KNodeId, InstallSnapshotError>>,
+    ),
+    Vote(Result<VoteResponse<KNodeId>, RaftError<KNodeId>>),
 }
 
 impl RaftResponse {
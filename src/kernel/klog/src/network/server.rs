@@ -1,71 +1,223 @@
-// NOTE: warp dependency has been removed, this code is commented out
-// use wrap::Filter;
-// use crate::{KRaft, network::request::RaftResponse};
-// use openraft::Raft;
-// use super::request::{RaftRequest, RaftRequestType};
-// use crate::{KNode, KNodeId, KTypeConfig, KRaftRef};
-
-// pub struct KNetworkServer {
-//     addr: String,
-//     raft: KRaftRef,
-// }
-
-// impl KNetworkServer {
-//     pub fn new(addr: String, raft: KRaftRef) -> Self {
-//         Self { addr, raft }
-//     }
-
-//     async fn on_append_entries(req: RaftRequest) -> RaftResponse {
-//         // Handle the AppendEntries request
-//         println!("Received AppendEntries request: {:?}", req);
-//         warp::reply::json(&"AppendEntries response")
-//     }
-
-//     pub fn run(&self) {
-//         let append_entries = warp::path(RaftRequestType::AppendEntries.as_str())
-//             .and(warp::post())
-//             .and(warp::body::bytes())
-//             .map(|body: bytes::Bytes| {
-//                 // Deserialize the request
-//                 match RaftRequest::deserialize(&body) {
-//                     Ok(req) => {
-//                         let ret = match self.
-//                     }
-//                     Err(e) => {
-//                         eprintln!("Failed to deserialize AppendEntries request: {}", e);
-//                         warp::reply::json(&"Error")
-//                     }
-//                 }
-//                 // Handle the request (this is just a placeholder)
-//                 println!("Received AppendEntries request: {:?}", req);
-//                 // Respond with a placeholder response
-//                 warp::reply::json(&"AppendEntries response")
-//             });
-
-//         let install_snapshot = warp::path("klog")
-//             .and(warp::path("install-snapshot"))
-//             .and(warp::post())
-//             .and(warp::body::bytes())
-//             .map(|body: bytes::Bytes| {
-//                 let req = RaftRequest::deserialize(&body).unwrap();
-//                 println!("Received InstallSnapshot request: {:?}", req);
-//                 warp::reply::json(&"InstallSnapshot response")
-//             });
-
-//         let vote = warp::path("klog")
-//             .and(warp::path("vote"))
-//             .and(warp::post())
-//             .and(warp::body::bytes())
-//             .map(|body: bytes::Bytes| {
-//                 let req = RaftRequest::deserialize(&body).unwrap();
-//                 println!("Received Vote request: {:?}", req);
-//                 warp::reply::json(&"Vote response")
-//             });
-
-//         let routes = append_entries.or(install_snapshot).or(vote);
-
-//         let addr = self.addr.parse().unwrap();
-//         println!("Starting server at {}", self.addr);
-//         warp::serve(routes).run(addr);
-//     }
-// }
\ No newline at end of file
+use super::api::{
+    AddLearnerRequest, AppendLogRequest, AppendLogResult, ChangeMembershipRequest,
+    ClusterInitRequest, ClusterStatus, ReadLogsRequest,
+};
+use super::auth::KLogAuthRef;
+use super::request::{RaftRequest, RaftResponse};
+use crate::{KLogEntry, KLogError, KLogNode, KResult};
+use axum::{
+    Json, Router,
+    body::Bytes,
+    extract::{Query, Request, State},
+    http::{StatusCode, header::AUTHORIZATION},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use serde::Serialize;
+
+fn error_status(e: &KLogError) -> StatusCode {
+    match e {
+        KLogError::InvalidFormat(_) | KLogError::InvalidParam(_) => StatusCode::BAD_REQUEST,
+        // The body carries the leader, the caller should retry there
+        KLogError::NotLeader(_) => StatusCode::MISDIRECTED_REQUEST,
+        KLogError::NetworkError(_) => StatusCode::BAD_GATEWAY,
+        KLogError::StorageError(_) | KLogError::RaftError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        KLogError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+    }
+}
+
+// The api responses are always json encoded `KResult<T>`, so the client can
+// decode the error (with the leader hint) regardless of the status code.
+fn to_json_response<T: Serialize>(ret: KResult<T>) -> (StatusCode, Json<KResult<T>>) {
+    let status = match &ret {
+        Ok(_) => StatusCode::OK,
+        Err(e) => error_status(e),
+    };
+
+    (status, Json(ret))
+}
+
+// Every route (raft rpcs included) needs a valid bearer token when the node has auth
+async fn check_auth(State(auth): State<Option<KLogAuthRef>>, req: Request, next: Next) -> Response {
+    if let Some(auth) = auth {
+        let token = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let ret = match token {
+            Some(token) => auth.verify(token),
+            None => Err(KLogError::Unauthorized("Missing token".to_string())),
+        };
+        if let Err(e) = ret {
+            warn!("Reject klog request {}: {}", req.uri().path(), e);
+            return to_json_response::<()>(Err(e)).into_response();
+        }
+    }
+
+    next.run(req).await
+}
+
+async fn handle_raft_request(node: KLogNode, body: Bytes) -> (StatusCode, Vec<u8>) {
+    let req = match RaftRequest::deserialize(&body) {
+        Ok(req) => req,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, e.to_string().into_bytes());
+        }
+    };
+
+    let raft = node.raft();
+    let resp = match req {
+        RaftRequest::AppendEntries(req) => {
+            RaftResponse::AppendEntries(raft.append_entries(req).await)
+        }
+        RaftRequest::InstallSnapshot(req) => {
+            RaftResponse::InstallSnapshot(raft.install_snapshot(req).await)
+        }
+        RaftRequest::Vote(req) => RaftResponse::Vote(raft.vote(req).await),
+    };
+
+    match resp.serialize() {
+        Ok(data) => (StatusCode::OK, data),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            e.to_string().into_bytes(),
+        ),
+    }
+}
+
+async fn handle_cluster_init(node: KLogNode, req: ClusterInitRequest) -> KResult<()> {
+    info!("Received cluster init request: {:?}", req.nodes);
+    node.initialize(req.nodes).await
+}
+
+async fn handle_add_learner(node: KLogNode, req: AddLearnerRequest) -> KResult<()> {
+    info!("Received add learner request: {:?}", req.node);
+    node.add_learner(req.node).await
+}
+
+async fn handle_change_membership(node: KLogNode, req: ChangeMembershipRequest) -> KResult<()> {
+    info!(
+        "Received change membership request: members={:?}, retain={}",
+        req.members, req.retain
+    );
+    node.change_membership(req.members, req.retain).await
+}
+
+async fn handle_append_log(node: KLogNode, req: AppendLogRequest) -> KResult<AppendLogResult> {
+    let id = node.append(req.message).await?;
+    Ok(AppendLogResult { id })
+}
+
+async fn handle_read_logs(node: KLogNode, req: ReadLogsRequest) -> KResult<Vec<KLogEntry>> {
+    node.read(req.from_id, req.limit).await
+}
+
+pub struct KLogHttpServer {
+    node: KLogNode,
+}
+
+impl KLogHttpServer {
+    pub fn new(node: KLogNode) -> Self {
+        Self { node }
+    }
+
+    pub fn router(&self) -> Router {
+        let raft_node = self.node.clone();
+        let raft_route = move |body: Bytes| {
+            let node = raft_node.clone();
+            async move { handle_raft_request(node, body).await }
+        };
+
+        let init_node = self.node.clone();
+        let learner_node = self.node.clone();
+        let membership_node = self.node.clone();
+        let status_node = self.node.clone();
+        let forward_node = self.node.clone();
+        let append_node = self.node.clone();
+        let read_node = self.node.clone();
+
+        let router = Router::new()
+            .route("/klog/append-entries", post(raft_route.clone()))
+            .route("/klog/install-snapshot", post(raft_route.clone()))
+            .route("/klog/vote", post(raft_route))
+            .route(
+                "/klog/cluster/init",
+                post(move |req: Json<ClusterInitRequest>| {
+                    let node = init_node.clone();
+                    async move { to_json_response(handle_cluster_init(node, req.0).await) }
+                }),
+            )
+            .route(
+                "/klog/cluster/add-learner",
+                post(move |req: Json<AddLearnerRequest>| {
+                    let node = learner_node.clone();
+                    async move { to_json_response(handle_add_learner(node, req.0).await) }
+                }),
+            )
+            .route(
+                "/klog/cluster/change-membership",
+                post(move |req: Json<ChangeMembershipRequest>| {
+                    let node = membership_node.clone();
+                    async move { to_json_response(handle_change_membership(node, req.0).await) }
+                }),
+            )
+            .route(
+                "/klog/cluster/status",
+                get(move || {
+                    let node = status_node.clone();
+                    async move { to_json_response::<ClusterStatus>(Ok(node.status())) }
+                }),
+            )
+            .route(
+                "/klog/forward-append",
+                post(move |req: Json<AppendLogRequest>| {
+                    let node = forward_node.clone();
+                    async move { to_json_response(handle_append_log(node, req.0).await) }
+                }),
+            );
+
+        let router = if self.node.logs_api() {
+            router.route(
+                "/klog/logs",
+                post(move |req: Json<AppendLogRequest>| {
+                    let node = append_node.clone();
+                    async move { to_json_response(handle_append_log(node, req.0).await) }
+                })
+                .get(move |req: Query<ReadLogsRequest>| {
+                    let node = read_node.clone();
+                    async move { to_json_response(handle_read_logs(node, req.0).await) }
+                }),
+            )
+        } else {
+            router
+        };
+
+        router.layer(middleware::from_fn_with_state(self.node.auth(), check_auth))
+    }
+
+    // Serve on an already bound listener until `shutdown` turns true
+    pub async fn run(
+        &self,
+        listener: tokio::net::TcpListener,
+        mut shutdown: tokio::sync::watch::Receiver<bool>,
+    ) -> Result<(), String> {
+        let app = self.router();
+
+        let signal = async move {
+            let _ = shutdown.wait_for(|stopped| *stopped).await;
+        };
+        match axum::serve(listener, app)
+            .with_graceful_shutdown(signal)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                let msg = format!("HTTP server error: {}", e);
+                error!("{}", msg);
+                Err(msg)
+            }
+        }
+    }
+}
@@ -0,0 +1,416 @@
+use crate::network::{
+    ClusterStatus, DEFAULT_READ_LIMIT, KLogAuthConfig, KLogAuthRef, KLogHttpServer, KLogJwtAuth,
+    KNetworkFactory, MAX_READ_LIMIT,
+};
+use crate::state_machine::{KLogMemoryStateMachine, SnapshotManager};
+use crate::storage::{
+    KLogStorage, KLogStorageManager, KLogStorageManagerRef, KLogStorageRef, SimpleLogStorage,
+};
+use crate::{
+    KLogEntry, KLogError, KLogRequest, KLogResponse, KNode, KNodeId, KRaft, KResult, SledLogStorage,
+};
+use openraft::error::{CheckIsLeaderError, ClientWriteError, RaftError};
+use openraft::{Config, SnapshotPolicy};
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+pub const KLOG_SERVICE_NAME: &str = "klog";
+pub const DEFAULT_KLOG_PORT: u16 = 4070;
+pub const DEFAULT_KLOG_BIND_ADDR: &str = "127.0.0.1";
+
+// Build a snapshot (and purge the raft logs before it) every this many applied logs
+pub const DEFAULT_SNAPSHOT_LOGS: u64 = 5000;
+
+#[derive(Debug, Clone)]
+pub struct KLogNodeConfig {
+    pub node_id: KNodeId,
+
+    pub listen_addr: String,
+
+    // 0 means pick an ephemeral port, the real one is reported by `KLogNode::node`
+    pub port: u16,
+
+    // The addr other nodes use to reach this node, the listen addr if None
+    pub advertise_addr: Option<String>,
+
+    pub data_dir: PathBuf,
+
+    pub raft_config: Config,
+
+    // Required on every request when set; without it the node only binds to loopback
+    pub auth: Option<KLogAuthRef>,
+
+    // Serve the generic `/klog/logs` append/read api, off for embedded state machines
+    // that only take commands from their owner through `KLogNode::append` (followers
+    // forward them to the leader with `KLogClient::forward_append`)
+    pub logs_api: bool,
+}
+
+fn is_loopback_addr(addr: &str) -> bool {
+    addr == "localhost"
+        || addr
+            .parse::<IpAddr>()
+            .map(|ip| ip.is_loopback())
+            .unwrap_or(false)
+}
+
+impl KLogNodeConfig {
+    pub fn new(node_id: KNodeId, listen_addr: &str, port: u16, data_dir: PathBuf) -> Self {
+        let raft_config = Config {
+            heartbeat_interval: 250,
+            election_timeout_min: 1000,
+            election_timeout_max: 2000,
+            snapshot_policy: SnapshotPolicy::LogsSinceLast(DEFAULT_SNAPSHOT_LOGS),
+            ..Default::default()
+        };
+
+        Self {
+            node_id,
+            listen_addr: listen_addr.to_string(),
+            port,
+            advertise_addr: None,
+            data_dir,
+            raft_config,
+            auth: None,
+            logs_api: true,
+        }
+    }
+
+    // KLOG_NODE_ID, KLOG_BIND_ADDR, KLOG_PORT, KLOG_ADVERTISE_ADDR, KLOG_DATA_DIR and
+    // KLOG_AUTH_CONFIG (path of a json encoded KLogAuthConfig)
+    pub fn from_env() -> KResult<Self> {
+        let node_id = match std::env::var("KLOG_NODE_ID") {
+            Ok(id) => id.parse::<KNodeId>().map_err(|e| {
+                let msg = format!("Invalid KLOG_NODE_ID {}: {}", id, e);
+                error!("{}", msg);
+                KLogError::InvalidParam(msg)
+            })?,
+            Err(_) => 1,
+        };
+
+        let listen_addr =
+            std::env::var("KLOG_BIND_ADDR").unwrap_or(DEFAULT_KLOG_BIND_ADDR.to_string());
+        let port = match std::env::var("KLOG_PORT") {
+            Ok(port) => port.parse::<u16>().map_err(|e| {
+                let msg = format!("Invalid KLOG_PORT {}: {}", port, e);
+                error!("{}", msg);
+                KLogError::InvalidParam(msg)
+            })?,
+            Err(_) => DEFAULT_KLOG_PORT,
+        };
+
+        let data_dir = match std::env::var("KLOG_DATA_DIR") {
+            Ok(dir) => PathBuf::from(dir),
+            Err(_) => buckyos_kit::get_buckyos_service_data_dir(KLOG_SERVICE_NAME)
+                .join(format!("node_{}", node_id)),
+        };
+
+        let mut config = Self::new(node_id, &listen_addr, port, data_dir);
+        config.advertise_addr = std::env::var("KLOG_ADVERTISE_ADDR").ok();
+        if let Ok(path) = std::env::var("KLOG_AUTH_CONFIG") {
+            let content = std::fs::read_to_string(&path).map_err(|e| {
+                let msg = format!("Failed to read KLOG_AUTH_CONFIG {}: {}", path, e);
+                error!("{}", msg);
+                KLogError::InvalidParam(msg)
+            })?;
+            let auth_config: KLogAuthConfig = serde_json::from_str(&content).map_err(|e| {
+                let msg = format!("Invalid KLOG_AUTH_CONFIG {}: {}", path, e);
+                error!("{}", msg);
+                KLogError::InvalidParam(msg)
+            })?;
+            config.auth = Some(Arc::new(KLogJwtAuth::load(&auth_config)?));
+        }
+        Ok(config)
+    }
+}
+
+struct KLogNodeInner {
+    node: KNode,
+    raft: KRaft,
+    log_storage: KLogStorageManagerRef,
+    auth: Option<KLogAuthRef>,
+    logs_api: bool,
+    shutdown: watch::Sender<bool>,
+}
+
+/// A running klog node: the raft instance plus its http server.
+#[derive(Clone)]
+pub struct KLogNode {
+    inner: Arc<KLogNodeInner>,
+}
+
+impl KLogNode {
+    pub async fn start(config: KLogNodeConfig) -> KResult<Self> {
+        let klog_storage = Arc::new(Box::new(SimpleLogStorage::new()) as Box<dyn KLogStorage>);
+        Self::start_with_storage(config, klog_storage).await
+    }
+
+    // Start with a custom storage as the state machine content, it must be empty:
+    // the data is rebuilt from the local snapshot and the raft logs on every start
+    pub async fn start_with_storage(
+        config: KLogNodeConfig,
+        klog_storage: KLogStorageRef,
+    ) -> KResult<Self> {
+        if config.auth.is_none() && !is_loopback_addr(&config.listen_addr) {
+            let msg = format!(
+                "Refuse to serve the klog api on {} without auth",
+                config.listen_addr
+            );
+            error!("{}", msg);
+            return Err(KLogError::InvalidParam(msg));
+        }
+
+        // Bind first, so the advertised node info has the real port
+        let listener = tokio::net::TcpListener::bind((config.listen_addr.as_str(), config.port))
+            .await
+            .map_err(|e| {
+                let msg = format!(
+                    "Failed to bind to {}:{}: {}",
+                    config.listen_addr, config.port, e
+                );
+                error!("{}", msg);
+                KLogError::NetworkError(msg)
+            })?;
+        let port = listener.local_addr().map(|addr| addr.port()).map_err(|e| {
+            let msg = format!("Failed to get local addr: {}", e);
+            error!("{}", msg);
+            KLogError::NetworkError(msg)
+        })?;
+
+        let node = KNode {
+            id: config.node_id,
+            addr: config
+                .advertise_addr
+                .clone()
+                .unwrap_or(config.listen_addr.clone()),
+            port,
+        };
+        info!(
+            "Starting klog node {:?}, data dir {:?}",
+            node, config.data_dir
+        );
+
+        std::fs::create_dir_all(&config.data_dir).map_err(|e| {
+            let msg = format!("Failed to create data dir {:?}: {}", config.data_dir, e);
+            error!("{}", msg);
+            KLogError::StorageError(msg)
+        })?;
+
+        let raft_log_storage =
+            SledLogStorage::open(&config.data_dir.join("raft")).map_err(|e| {
+                let msg = format!("Failed to open raft log storage: {}", e);
+                KLogError::StorageError(msg)
+            })?;
+
+        let log_storage = Arc::new(KLogStorageManager::new(klog_storage));
+        let snapshot_manager = Arc::new(SnapshotManager::new(config.data_dir.clone()));
+
+        // The logs after the snapshot are re-applied by raft from the committed raft logs
+        let state_machine = KLogMemoryStateMachine::new(log_storage.clone(), snapshot_manager);
+        state_machine.restore().await.map_err(|e| {
+            let msg = format!("Failed to restore state machine: {}", e);
+            error!("{}", msg);
+            KLogError::StorageError(msg)
+        })?;
+
+        let raft_config = config.raft_config.clone().validate().map_err(|e| {
+            let msg = format!("Invalid raft config: {}", e);
+            error!("{}", msg);
+            KLogError::InvalidParam(msg)
+        })?;
+
+        let raft = KRaft::new(
+            config.node_id,
+            Arc::new(raft_config),
+            KNetworkFactory::new(config.node_id, config.auth.clone()),
+            raft_log_storage,
+            state_machine,
+        )
+        .await
+        .map_err(|e| {
+            let msg = format!("Failed to create raft: {}", e);
+            error!("{}", msg);
+            KLogError::RaftError(msg)
+        })?;
+
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        let this = Self {
+            inner: Arc::new(KLogNodeInner {
+                node,
+                raft,
+                log_storage,
+                auth: config.auth.clone(),
+                logs_api: config.logs_api,
+                shutdown,
+            }),
+        };
+
+        let server = KLogHttpServer::new(this.clone());
+        tokio::spawn(async move {
+            if let Err(e) = server.run(listener, shutdown_rx).await {
+                error!("klog http server stopped: {}", e);
+            }
+        });
+
+        Ok(this)
+    }
+
+    pub fn node(&self) -> &KNode {
+        &self.inner.node
+    }
+
+    pub fn raft(&self) -> &KRaft {
+        &self.inner.raft
+    }
+
+    pub fn auth(&self) -> Option<KLogAuthRef> {
+        self.inner.auth.clone()
+    }
+
+    pub fn logs_api(&self) -> bool {
+        self.inner.logs_api
+    }
+
+    fn map_write_error(e: RaftError<KNodeId, ClientWriteError<KNodeId, KNode>>) -> KLogError {
+        if let Some(forward) = e.forward_to_leader() {
+            return KLogError::NotLeader(forward.leader_node.clone());
+        }
+
+        let msg = format!("Raft write failed: {}", e);
+        error!("{}", msg);
+        KLogError::RaftError(msg)
+    }
+
+    // Append a message to the replicated log, only works on the leader
+    pub async fn append(&self, message: String) -> KResult<u64> {
+        let item = KLogEntry {
+            id: 0, // Assigned by the state machine
+            timestamp: chrono::Utc::now().timestamp_millis() as u64,
+            node_id: self.inner.node.id,
+            message,
+        };
+
+        let resp = self
+            .inner
+            .raft
+            .client_write(KLogRequest::AppendLog { item })
+            .await
+            .map_err(Self::map_write_error)?;
+
+        match resp.data {
+            KLogResponse::AppendOk { id } => Ok(id),
+            KLogResponse::Err(e) => Err(e),
+            other => {
+                let msg = format!("Unexpected append response: {:?}", other);
+                error!("{}", msg);
+                Err(KLogError::RaftError(msg))
+            }
+        }
+    }
+
+    // Confirm this node is still the leader and wait until all the logs committed
+    // before the call are applied, so the following local reads are linearizable
+    pub async fn ensure_linearizable(&self) -> KResult<()> {
+        self.inner.raft.ensure_linearizable().await.map_err(
+            |e: RaftError<KNodeId, CheckIsLeaderError<KNodeId, KNode>>| {
+                if let Some(forward) = e.forward_to_leader() {
+                    return KLogError::NotLeader(forward.leader_node.clone());
+                }
+
+                let msg = format!("Raft read barrier failed: {}", e);
+                error!("{}", msg);
+                KLogError::RaftError(msg)
+            },
+        )?;
+        Ok(())
+    }
+
+    // Read from the local state machine, followers may lag behind the leader
+    pub async fn read(
+        &self,
+        from_id: Option<u64>,
+        limit: Option<usize>,
+    ) -> KResult<Vec<KLogEntry>> {
+        let limit = limit.unwrap_or(DEFAULT_READ_LIMIT).min(MAX_READ_LIMIT);
+        self.inner
+            .log_storage
+            .read(from_id.unwrap_or(0), limit)
+            .await
+    }
+
+    pub async fn initialize(&self, nodes: Vec<KNode>) -> KResult<()> {
+        if !nodes.iter().any(|node| node.id == self.inner.node.id) {
+            let msg = format!(
+                "Init nodes must contain the local node {}",
+                self.inner.node.id
+            );
+            warn!("{}", msg);
+            return Err(KLogError::InvalidParam(msg));
+        }
+
+        let members: BTreeMap<KNodeId, KNode> =
+            nodes.into_iter().map(|node| (node.id, node)).collect();
+        self.inner.raft.initialize(members).await.map_err(|e| {
+            let msg = format!("Failed to initialize cluster: {}", e);
+            error!("{}", msg);
+            KLogError::RaftError(msg)
+        })
+    }
+
+    // Add a node as learner and wait until it catches up with the leader
+    pub async fn add_learner(&self, node: KNode) -> KResult<()> {
+        self.inner
+            .raft
+            .add_learner(node.id, node, true)
+            .await
+            .map_err(Self::map_write_error)?;
+        Ok(())
+    }
+
+    pub async fn change_membership(&self, members: Vec<KNodeId>, retain: bool) -> KResult<()> {
+        if members.is_empty() {
+            return Err(KLogError::InvalidParam(
+                "Members must not be empty".to_string(),
+            ));
+        }
+
+        let members: BTreeSet<KNodeId> = members.into_iter().collect();
+        self.inner
+            .raft
+            .change_membership(members, retain)
+            .await
+            .map_err(Self::map_write_error)?;
+        Ok(())
+    }
+
+    pub fn status(&self) -> ClusterStatus {
+        let metrics = self.inner.raft.metrics().borrow().clone();
+        let membership = metrics.membership_config.membership();
+
+        ClusterStatus {
+            node_id: metrics.id,
+            state: format!("{:?}", metrics.state),
+            current_term: metrics.current_term,
+            current_leader: metrics.current_leader,
+            last_log_index: metrics.last_log_index,
+            last_applied_index: metrics.last_applied.map(|id| id.index),
+            voters: membership.voter_ids().collect(),
+            learners: membership.learner_ids().collect(),
+            nodes: membership.nodes().map(|(_, node)| node.clone()).collect(),
+        }
+    }
+
+    pub async fn shutdown(&self) -> KResult<()> {
+        info!("Shutting down klog node {:?}", self.inner.node);
+        let _ = self.inner.shutdown.send(true);
+
+        self.inner.raft.shutdown().await.map_err(|e| {
+            let msg = format!("Failed to shutdown raft: {}", e);
+            error!("{}", msg);
+            KLogError::RaftError(msg)
+        })
+    }
+}
@@ -1,6 +1,6 @@
 use super::snapshot::{KSnapshotMeta, SnapshotManager, SnapshotManagerRef};
 use crate::state_machine::snapshot::KSnapshotData;
-use crate::storage::KLogStorageManagerRef;
+use crate::storage::{KLogStorageManagerRef, KLogStorageSnapshot};
 use crate::{KLogId, KLogRequest, KLogResponse, KNode, KNodeId, KTypeConfig, StorageResult};
 use openraft::{
     Entry, EntryPayload, OptionalSend, RaftSnapshotBuilder, SnapshotMeta, StoredMembership,
@@ -38,12 +38,56 @@ impl KLogMemoryStateMachine {
         }
     }
 
+    // Load the latest local snapshot, should be called once before the state machine is handed to raft
+    pub async fn restore(&self) -> StorageResult<()> {
+        let Some((path, data)) = self.snapshot_manager.load_current_snapshot().await? else {
+            return Ok(());
+        };
+
+        info!(
+            "Restoring state machine from snapshot {:?}, last log id: {:?}",
+            path, data.meta.last_log_id
+        );
+        self.apply_snapshot_data(data).await
+    }
+
+    async fn apply_snapshot_data(&self, data: KSnapshotData) -> StorageResult<()> {
+        // Hold the write lock so no entry is applied between the storage and meta update
+        let mut state = self.data.write().await;
+
+        let snapshot = KLogStorageSnapshot {
+            data: data.klog_data,
+        };
+        self.log_storage
+            .install_snapshot(&snapshot)
+            .await
+            .map_err(|e| {
+                let msg = format!("Failed to install log storage snapshot: {}", e);
+                error!("{}", msg);
+                StorageError::IO {
+                    source: StorageIOError::write_snapshot(
+                        Some(data.meta.signature()),
+                        &std::io::Error::new(std::io::ErrorKind::InvalidData, msg),
+                    ),
+                }
+            })?;
+
+        state.last_applied_log_id = data.meta.last_log_id;
+        state.last_membership = data.meta.last_membership.clone();
+
+        Ok(())
+    }
+
+    pub fn log_storage(&self) -> &KLogStorageManagerRef {
+        &self.log_storage
+    }
+
     async fn process_request(&self, req: KLogRequest) -> KLogResponse {
         match req {
             KLogRequest::AppendLog { item } => {
                 match self.log_storage.process_append_request(item).await {
                     Ok(id) => KLogResponse::AppendOk { id },
-                    Err(err) => KLogResponse::Err(err.to_string()),
+                    Err(err) => KLogResponse::Err(err),
                 }
             }
         }
@@ -112,16 +156,7 @@ impl RaftStateMachine<KTypeConfig> for KLogMemoryStateMachine {
             .install_snapshot(meta, snapshot)
             .await?;
 
-        // First, update the state machine data
-        let mut state = self.data.write().await;
-        state.last_applied_log_id = data.meta.last_log_id.clone();
-        state.last_membership = data.meta.last_membership.clone();
-        drop(state); // Release the lock before potentially long operations
-
-        // Then, update the state machine's internal data structures
-        // todo!();
-
-        Ok(())
+        self.apply_snapshot_data(data).await
     }
 
     async fn get_current_snapshot(&mut self) -> StorageResult<Option<KSnapshot>> {
@@ -151,19 +186,15 @@ impl RaftStateMachine<KTypeConfig> for KLogMemoryStateMachine {
 
 impl RaftSnapshotBuilder<KTypeConfig> for KLogMemoryStateMachine {
     async fn build_snapshot(&mut self) -> StorageResult<KSnapshot> {
-        let meta = {
-            let data = self.data.read().await;
-
-            let snapshot_id =
-                SnapshotManager::generate_snapshot_id(data.last_applied_log_id.as_ref());
-
-            let meta = SnapshotMeta {
-                last_log_id: data.last_applied_log_id,
-                last_membership: data.last_membership.clone(),
-                snapshot_id,
-            };
+        // Keep the read lock until the storage snapshot is taken, so the meta
+        // matches the data exactly even if entries are being applied
+        let data = self.data.read().await;
 
-            meta
+        let snapshot_id = SnapshotManager::generate_snapshot_id(data.last_applied_log_id.as_ref());
+        let meta = SnapshotMeta {
+            last_log_id: data.last_applied_log_id,
+            last_membership: data.last_membership.clone(),
+            snapshot_id,
         };
 
         let klog_data = self.log_storage.build_snapshot().await.map_err(|e| {
@@ -173,6 +204,7 @@ impl RaftSnapshotBuilder<KTypeConfig> for KLogMemoryStateMachine {
                 source: StorageIOError::write_snapshot(None, &e),
             }
         })?;
+        drop(data);
 
         let snapshot = KSnapshotData::new(meta, klog_data.data);
 
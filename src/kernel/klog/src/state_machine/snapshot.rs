@@ -4,7 +4,7 @@ use openraft::{StorageError, StorageIOError};
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::io::AsyncWriteExt;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
 
 pub type KSnapshotMeta = openraft::SnapshotMeta<KNodeId, KNode>;
 
@@ -164,6 +164,18 @@ impl SnapshotManager {
             }
         })?;
 
+        // The received file was written by raft, rewind before copying it out
+        snapshot
+            .seek(std::io::SeekFrom::Start(0))
+            .await
+            .map_err(|err| {
+                let msg = format!("Failed to seek snapshot file: {}", err);
+                error!("{}", msg);
+                StorageError::IO {
+                    source: StorageIOError::read(&err),
+                }
+            })?;
+
         // Copy the temp file to the final destination
         tokio::io::copy(&mut snapshot, &mut dest_file)
             .await
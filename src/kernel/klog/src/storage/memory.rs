@@ -36,4 +36,33 @@ impl KLogStorage for SimpleLogStorage {
 
         Ok(KLogStorageSnapshot { data })
     }
+
+    async fn install_snapshot(&self, snapshot: &KLogStorageSnapshot) -> KResult<()> {
+        let (entries, _): (Vec<KLogEntry>, _) =
+            bincode::serde::decode_from_slice(&snapshot.data, bincode::config::legacy()).map_err(
+                |e| {
+                    let msg = format!("Failed to deserialize logs from snapshot: {}", e);
+                    error!("{}", msg);
+                    KLogError::InvalidFormat(msg)
+                },
+            )?;
+
+        let mut logs = self.logs.lock().await;
+        *logs = entries;
+
+        Ok(())
+    }
+
+    async fn read(&self, from_id: u64, limit: usize) -> KResult<Vec<KLogEntry>> {
+        let logs = self.logs.lock().await;
+
+        // Ids are assigned in apply order, so the logs are always sorted by id
+        let start = logs.partition_point(|entry| entry.id < from_id);
+        Ok(logs.iter().skip(start).take(limit).cloned().collect())
+    }
+
+    async fn last_id(&self) -> KResult<u64> {
+        let logs = self.logs.lock().await;
+        Ok(logs.last().map(|entry| entry.id).unwrap_or(0))
+    }
 }
@@ -11,6 +11,15 @@ pub trait KLogStorage: Send + Sync {
     async fn append(&self, entries: Vec<KLogEntry>) -> KResult<()>;
 
     async fn build_snapshot(&self) -> KResult<KLogStorageSnapshot>;
+
+    // Replace all the data with the content of a snapshot built by `build_snapshot`
+    async fn install_snapshot(&self, snapshot: &KLogStorageSnapshot) -> KResult<()>;
+
+    // Read at most `limit` entries with id >= `from_id`, in id order
+    async fn read(&self, from_id: u64, limit: usize) -> KResult<Vec<KLogEntry>>;
+
+    // The max id of the stored entries, 0 if empty
+    async fn last_id(&self) -> KResult<u64>;
 }
 
 pub type KLogStorageRef = Arc<Box<dyn KLogStorage>>;
@@ -54,6 +63,28 @@ impl KLogStorageManager {
     pub async fn build_snapshot(&self) -> KResult<KLogStorageSnapshot> {
         self.storage.build_snapshot().await
     }
+
+    pub async fn install_snapshot(&self, snapshot: &KLogStorageSnapshot) -> KResult<()> {
+        self.storage.install_snapshot(snapshot).await?;
+
+        // The next id must continue from the snapshot, or replayed logs will reuse ids
+        let last_id = self.storage.last_id().await?;
+        self.next_log_id.store(last_id + 1, Ordering::SeqCst);
+        info!(
+            "Installed log storage snapshot, next log id: {}",
+            last_id + 1
+        );
+
+        Ok(())
+    }
+
+    pub async fn read(&self, from_id: u64, limit: usize) -> KResult<Vec<KLogEntry>> {
+        self.storage.read(from_id, limit).await
+    }
+
+    pub fn next_log_id(&self) -> u64 {
+        self.next_log_id.load(Ordering::SeqCst)
+    }
 }
 
 pub type KLogStorageManagerRef = Arc<KLogStorageManager>;
@@ -8,7 +8,7 @@ use std::sync::Arc;
 
 use log::error;
 use server_runner::*;
-use sled_msg_queue::SledMsgQueueServer;
+use sled_msg_queue::{DEFAULT_COMPACTION_INTERVAL, SledMsgQueueServer};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -31,6 +31,7 @@ async fn main() -> anyhow::Result<()> {
         .map_err(|err| anyhow::anyhow!("register kmsg runtime failed: {}", err))?;
 
     let server = SledMsgQueueServer::new();
+    server.start_compaction(DEFAULT_COMPACTION_INTERVAL);
 
     let runner = Runner::new(KMSG_SERVICE_MAIN_PORT);
     if let Err(err) = runner.add_http_server("/kapi/kmsg".to_string(), Arc::new(server)) {
@@ -9,13 +9,21 @@ use cyfs_gateway_lib::{
 };
 use http::{Method, Version};
 use http_body_util::combinators::BoxBody;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 use sled::{Db, IVec, Tree, transaction::Transactional};
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
-use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Notify;
 use tokio::task;
 
+/// 长轮询时即使没有新消息也定期重查，让 nack 延迟到期的消息能被投递
+const NACK_RECHECK_INTERVAL_MS: u64 = 1000;
+/// 后台按 QueueConfig 清理过期消息的周期
+pub const DEFAULT_COMPACTION_INTERVAL: Duration = Duration::from_secs(60);
+
 pub struct SledMsgQueueServer {
     handler: MsgQueueServerHandler<SledMsgQueue>,
 }
@@ -27,6 +35,10 @@ impl SledMsgQueueServer {
             handler: MsgQueueServerHandler::new(queue),
         }
     }
+
+    pub fn start_compaction(&self, interval: Duration) -> task::JoinHandle<()> {
+        self.handler.0.spawn_compaction(interval)
+    }
 }
 
 #[async_trait]
@@ -91,6 +103,44 @@ struct QueueMeta {
 struct SubscriptionState {
     queue_urn: QueueUrn,
     cursor: MsgIndex,
+    /// 消费组成员的 cursor 不使用，进度记录在 GroupState 中
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    filter: Option<HeaderFilter>,
+}
+
+/// 消费组状态，cursor 之前的消息都已被组内成员确认
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct GroupState {
+    queue_urn: QueueUrn,
+    cursor: MsgIndex,
+    visibility_timeout_ms: u64,
+}
+
+/// 订阅对单条未确认消息的投递记录，cursor 越过后即删除。
+/// 消费组的租约也记录在这里，以 group_id 代替 sub_id
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct DeliveryState {
+    attempts: u32,
+    /// nack 后在此时间 (毫秒) 之前不再投递
+    not_before_ms: u64,
+    /// 已转入死信队列，不再投递
+    dead: bool,
+    /// 持有租约的组成员
+    #[serde(default)]
+    owner: Option<SubscriptionId>,
+    #[serde(default)]
+    lease_until_ms: u64,
+    /// 组成员逐条确认，等 cursor 越过后删除
+    #[serde(default)]
+    acked: bool,
+}
+
+impl DeliveryState {
+    fn is_leased(&self, now_ms: u64) -> bool {
+        self.owner.is_some() && self.lease_until_ms > now_ms
+    }
 }
 
 #[derive(Clone)]
@@ -100,7 +150,11 @@ pub struct SledMsgQueue {
     queue_meta: Tree,
     messages: Tree,
     subs: Tree,
+    deliveries: Tree,
+    groups: Tree,
     meta: Tree,
+    /// 每个队列一个 Notify，写入新消息时唤醒长轮询的 fetch
+    notifiers: Arc<Mutex<HashMap<QueueUrn, Arc<Notify>>>>,
 }
 
 impl SledMsgQueue {
@@ -118,7 +172,10 @@ impl SledMsgQueue {
             queue_meta: db.open_tree("queue_meta")?,
             messages: db.open_tree("messages")?,
             subs: db.open_tree("subs")?,
+            deliveries: db.open_tree("deliveries")?,
+            groups: db.open_tree("groups")?,
             meta: db.open_tree("meta")?,
+            notifiers: Arc::new(Mutex::new(HashMap::new())),
             db: Arc::new(db),
         })
     }
@@ -130,6 +187,13 @@ impl SledMsgQueue {
             .as_secs()
     }
 
+    fn now_millis() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64
+    }
+
     fn queue_key(queue_urn: &str) -> Vec<u8> {
         queue_urn.as_bytes().to_vec()
     }
@@ -147,6 +211,30 @@ impl SledMsgQueue {
         key
     }
 
+    /// 消费组的唯一标识，同时作为 groups 的 key 和组内租约的 delivery id
+    fn group_id(queue_urn: &str, group: &str) -> String {
+        format!("{}\u{1}{}", queue_urn, group)
+    }
+
+    fn group_prefix(queue_urn: &str) -> Vec<u8> {
+        let mut key = queue_urn.as_bytes().to_vec();
+        key.push(1u8);
+        key
+    }
+
+    fn delivery_prefix(sub_id: &str) -> Vec<u8> {
+        let mut key = Vec::with_capacity(sub_id.len() + 1);
+        key.extend_from_slice(sub_id.as_bytes());
+        key.push(0u8);
+        key
+    }
+
+    fn delivery_key(sub_id: &str, index: MsgIndex) -> Vec<u8> {
+        let mut key = Self::delivery_prefix(sub_id);
+        key.extend_from_slice(&index.to_be_bytes());
+        key
+    }
+
     fn decode_index_from_key(key: &[u8]) -> Option<MsgIndex> {
         if key.len() < 8 {
             return None;
@@ -241,237 +329,748 @@ impl SledMsgQueue {
             .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
         Ok(())
     }
-}
 
-#[async_trait]
-impl MsgQueueHandler for SledMsgQueue {
-    async fn handle_create_queue(
+    fn check_dead_letter_queue(
         &self,
-        name: Option<&str>,
-        appid: &str,
-        app_owner: &str,
-        config: QueueConfig,
-        _ctx: RPCContext,
-    ) -> std::result::Result<QueueUrn, RPCErrors> {
-        let name = match name {
-            Some(value) => value.to_string(),
-            None => format!("queue-{}", self.next_id("queue_id")?),
+        queue_urn: &str,
+        config: &QueueConfig,
+    ) -> std::result::Result<(), RPCErrors> {
+        let Some(dead_letter_queue) = config.dead_letter_queue.as_deref() else {
+            return Ok(());
         };
-        let queue_urn = calc_queue_urn(appid, app_owner, &name);
-        let key = Self::queue_key(&queue_urn);
-        let config_data =
-            serde_json::to_vec(&config).map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
-        let create_result = self
-            .queues
-            .compare_and_swap(key.clone(), None as Option<IVec>, Some(config_data))
-            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
-        if create_result.is_err() {
+        if dead_letter_queue == queue_urn {
             return Err(RPCErrors::ReasonError(format!(
-                "Queue already exists: {}",
+                "Queue {} can not be its own dead letter queue",
                 queue_urn
             )));
         }
+        self.get_queue_config(dead_letter_queue)?;
+        Ok(())
+    }
 
-        let meta = QueueMeta {
-            next_index: 1,
-            message_count: 0,
-            first_index: 0,
-            last_index: 0,
-            size_bytes: 0,
-        };
-        if let Err(err) = self.store_queue_meta(&queue_urn, &meta) {
-            let _ = self.queues.remove(key);
-            return Err(err);
-        }
-
-        if config.sync_write {
-            self.db
-                .flush()
-                .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
-        }
-
-        Ok(queue_urn)
+    fn load_subscription(&self, sub_id: &str) -> std::result::Result<SubscriptionState, RPCErrors> {
+        let sub_value = self
+            .subs
+            .get(sub_id.as_bytes())
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?
+            .ok_or_else(|| RPCErrors::ReasonError(format!("Subscription not found: {}", sub_id)))?;
+        serde_json::from_slice(&sub_value).map_err(|err| RPCErrors::ReasonError(err.to_string()))
     }
 
-    async fn handle_delete_queue(
+    fn store_subscription(
         &self,
-        queue_urn: &str,
-        _ctx: RPCContext,
+        sub_id: &str,
+        sub: &SubscriptionState,
     ) -> std::result::Result<(), RPCErrors> {
-        let key = Self::queue_key(queue_urn);
-        if self
-            .queues
-            .remove(key)
-            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?
-            .is_none()
-        {
-            return Err(RPCErrors::ReasonError(format!(
-                "Queue not found: {}",
-                queue_urn
-            )));
-        }
-
-        let prefix = Self::message_prefix(queue_urn);
-        let message_keys: Vec<Vec<u8>> = self
-            .messages
-            .scan_prefix(prefix)
-            .filter_map(|item| item.ok().map(|(key, _)| key.to_vec()))
-            .collect();
-        for key in message_keys {
-            let _ = self.messages.remove(key);
-        }
-
-        let sub_keys: Vec<Vec<u8>> = self
-            .subs
-            .iter()
-            .filter_map(|item| item.ok())
-            .filter_map(|(key, value)| {
-                let sub: SubscriptionState = serde_json::from_slice(&value).ok()?;
-                if sub.queue_urn == queue_urn {
-                    Some(key.to_vec())
-                } else {
-                    None
-                }
-            })
-            .collect();
-        for key in sub_keys {
-            let _ = self.subs.remove(key);
-        }
-
-        let _ = self.queue_meta.remove(Self::queue_key(queue_urn));
-        self.db
-            .flush()
+        let data =
+            serde_json::to_vec(sub).map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        self.subs
+            .insert(sub_id.as_bytes(), data)
             .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
         Ok(())
     }
 
-    async fn handle_get_queue_stats(
+    fn get_delivery(
         &self,
-        queue_urn: &str,
-        _ctx: RPCContext,
-    ) -> std::result::Result<QueueStats, RPCErrors> {
-        let meta = self.get_queue_meta(queue_urn)?;
-        Ok(QueueStats {
-            message_count: meta.message_count,
-            first_index: meta.first_index,
-            last_index: meta.last_index,
-            size_bytes: meta.size_bytes,
-        })
+        sub_id: &str,
+        index: MsgIndex,
+    ) -> std::result::Result<DeliveryState, RPCErrors> {
+        let value = self
+            .deliveries
+            .get(Self::delivery_key(sub_id, index))
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        match value {
+            Some(value) => serde_json::from_slice(&value)
+                .map_err(|err| RPCErrors::ReasonError(err.to_string())),
+            None => Ok(DeliveryState::default()),
+        }
     }
 
-    async fn handle_update_queue_config(
+    fn store_delivery(
         &self,
-        queue_urn: &str,
-        config: QueueConfig,
-        _ctx: RPCContext,
+        sub_id: &str,
+        index: MsgIndex,
+        delivery: &DeliveryState,
     ) -> std::result::Result<(), RPCErrors> {
-        let key = Self::queue_key(queue_urn);
-        if self
-            .queues
-            .get(key.clone())
+        let data =
+            serde_json::to_vec(delivery).map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        self.deliveries
+            .insert(Self::delivery_key(sub_id, index), data)
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        Ok(())
+    }
+
+    /// 原子地修改一条投递记录，update 返回 None 表示放弃修改。
+    /// 并发修改时返回 Ok(None)，调用方应视为被其他消费者抢先
+    fn update_delivery<F>(
+        &self,
+        delivery_id: &str,
+        index: MsgIndex,
+        update: F,
+    ) -> std::result::Result<Option<DeliveryState>, RPCErrors>
+    where
+        F: FnOnce(DeliveryState) -> Option<DeliveryState>,
+    {
+        let key = Self::delivery_key(delivery_id, index);
+        let old = self
+            .deliveries
+            .get(&key)
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        let state = match old.as_ref() {
+            Some(value) => serde_json::from_slice(value)
+                .map_err(|err| RPCErrors::ReasonError(err.to_string()))?,
+            None => DeliveryState::default(),
+        };
+        let Some(state) = update(state) else {
+            return Ok(None);
+        };
+        let data =
+            serde_json::to_vec(&state).map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        match self
+            .deliveries
+            .compare_and_swap(key, old, Some(data))
             .map_err(|err| RPCErrors::ReasonError(err.to_string()))?
-            .is_none()
         {
-            return Err(RPCErrors::ReasonError(format!(
-                "Queue not found: {}",
-                queue_urn
-            )));
-        }
-        let config_data =
-            serde_json::to_vec(&config).map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
-        self.queues
-            .insert(key, config_data)
-            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
-        if config.sync_write {
-            self.db
-                .flush()
-                .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+            Ok(()) => Ok(Some(state)),
+            Err(_) => Ok(None),
         }
-        Ok(())
     }
 
-    async fn handle_post_message(
+    fn load_group(&self, group_id: &str) -> std::result::Result<GroupState, RPCErrors> {
+        let value = self
+            .groups
+            .get(group_id.as_bytes())
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?
+            .ok_or_else(|| {
+                RPCErrors::ReasonError(format!("Consumer group not found: {}", group_id))
+            })?;
+        serde_json::from_slice(&value).map_err(|err| RPCErrors::ReasonError(err.to_string()))
+    }
+
+    fn queue_groups(
         &self,
         queue_urn: &str,
-        mut message: Message,
-        _ctx: RPCContext,
-    ) -> std::result::Result<MsgIndex, RPCErrors> {
-        let config = self.get_queue_config(queue_urn)?;
-        let now = Self::now_seconds();
-        if message.created_at == 0 {
-            message.created_at = now;
+    ) -> std::result::Result<Vec<(String, GroupState)>, RPCErrors> {
+        let mut groups = Vec::new();
+        for item in self.groups.scan_prefix(Self::group_prefix(queue_urn)) {
+            let (key, value) = item.map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+            let Ok(group) = serde_json::from_slice::<GroupState>(&value) else {
+                continue;
+            };
+            if let Ok(group_id) = String::from_utf8(key.to_vec()) {
+                groups.push((group_id, group));
+            }
         }
+        Ok(groups)
+    }
 
-        let queue_key = Self::queue_key(queue_urn);
-        let messages = &self.messages;
-        let queue_meta = &self.queue_meta;
-        let payload_len = message.payload.len() as u64;
+    /// 越过 cursor 处连续的已确认或已转入死信的消息，cursor 只会前进
+    fn advance_group_cursor(&self, group_id: &str) -> std::result::Result<MsgIndex, RPCErrors> {
+        let group = self.load_group(group_id)?;
+        let mut cursor = group.cursor;
+        let start = Self::message_key(&group.queue_urn, group.cursor);
+        let end = Self::message_key(&group.queue_urn, u64::MAX);
+        for item in self.messages.range(start..=end).keys() {
+            let key = item.map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+            let Some(index) = Self::decode_index_from_key(&key) else {
+                break;
+            };
+            let delivery = self.get_delivery(group_id, index)?;
+            if !(delivery.acked || delivery.dead) {
+                break;
+            }
+            cursor = index + 1;
+        }
+        if cursor == group.cursor {
+            return Ok(cursor);
+        }
+
+        // 扫描期间组 cursor 被 seek 回退时放弃推进，不撤销 seek
+        let mut advanced = false;
+        self.groups
+            .update_and_fetch(group_id.as_bytes(), |value| {
+                advanced = false;
+                let value = value?;
+                let Ok(mut current) = serde_json::from_slice::<GroupState>(value) else {
+                    return Some(value.to_vec());
+                };
+                if current.cursor < group.cursor {
+                    return Some(value.to_vec());
+                }
+                current.cursor = current.cursor.max(cursor);
+                advanced = true;
+                serde_json::to_vec(&current)
+                    .ok()
+                    .or_else(|| Some(value.to_vec()))
+            })
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        if advanced {
+            self.clear_deliveries(group_id, Some(cursor))?;
+        }
+        Ok(cursor)
+    }
+
+    /// 消费组 seek：在一个事务里移动组 cursor 并清除投递记录，seek 是显式的重放。
+    /// 仍在租约中的消息保留租约，避免被组内其他成员重复领取
+    fn seek_group(&self, group_id: &str, cursor: MsgIndex) -> std::result::Result<(), RPCErrors> {
+        let keys: Vec<IVec> = self
+            .deliveries
+            .scan_prefix(Self::delivery_prefix(group_id))
+            .keys()
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        let now_ms = Self::now_millis();
+        let groups = &self.groups;
+        let deliveries = &self.deliveries;
 
-        let result = (messages, queue_meta)
+        (groups, deliveries)
             .transaction(|trees| {
-                let (messages, queue_meta) = trees;
-                let meta_value = queue_meta.get(&queue_key)?.ok_or_else(|| {
-                    sled::transaction::ConflictableTransactionError::Abort(RPCErrors::ReasonError(
-                        format!("Queue not found: {}", queue_urn),
-                    ))
-                })?;
-                let mut meta: QueueMeta = serde_json::from_slice(&meta_value).map_err(|err| {
+                let (groups, deliveries) = trees;
+                let value = groups.get(group_id.as_bytes())?.ok_or_else(|| {
                     sled::transaction::ConflictableTransactionError::Abort(RPCErrors::ReasonError(
-                        format!("Failed to decode queue meta: {}", err),
+                        format!("Consumer group not found: {}", group_id),
                     ))
                 })?;
-                let index = meta.next_index;
-                meta.next_index += 1;
-                meta.message_count += 1;
-                if meta.first_index == 0 {
-                    meta.first_index = index;
-                }
-                meta.last_index = index;
-                meta.size_bytes += payload_len;
-
-                let mut stored_message = message.clone();
-                stored_message.index = index;
-                let data = serde_json::to_vec(&stored_message).map_err(|err| {
+                let mut group: GroupState = serde_json::from_slice(&value).map_err(|err| {
                     sled::transaction::ConflictableTransactionError::Abort(RPCErrors::ReasonError(
-                        format!("Failed to encode message: {}", err),
+                        format!("Failed to decode consumer group: {}", err),
                     ))
                 })?;
-                let msg_key = SledMsgQueue::message_key(queue_urn, index);
-                messages.insert(msg_key, data)?;
-                queue_meta.insert(
-                    queue_key.clone(),
-                    serde_json::to_vec(&meta).map_err(|err| {
+                group.cursor = cursor;
+                groups.insert(
+                    group_id.as_bytes(),
+                    serde_json::to_vec(&group).map_err(|err| {
                         sled::transaction::ConflictableTransactionError::Abort(
-                            RPCErrors::ReasonError(format!("Failed to encode queue meta: {}", err)),
+                            RPCErrors::ReasonError(format!(
+                                "Failed to encode consumer group: {}",
+                                err
+                            )),
                         )
                     })?,
                 )?;
-                Ok(index)
+                for key in keys.iter() {
+                    let Some(value) = deliveries.get(key)? else {
+                        continue;
+                    };
+                    let passed =
+                        Self::decode_index_from_key(key).is_some_and(|index| index < cursor);
+                    let inflight = serde_json::from_slice::<DeliveryState>(&value)
+                        .is_ok_and(|state| !state.acked && state.is_leased(now_ms));
+                    if passed || !inflight {
+                        deliveries.remove(key.clone())?;
+                    }
+                }
+                Ok(())
             })
             .map_err(|err| match err {
                 sled::transaction::TransactionError::Abort(err) => err,
                 sled::transaction::TransactionError::Storage(err) => {
                     RPCErrors::ReasonError(err.to_string())
                 }
-            })?;
+            })
+    }
 
-        if config.sync_write {
-            self.db
-                .flush()
-                .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+    /// 组成员 fetch：从组 cursor 开始领取未被租出的消息，消息之间不保证顺序
+    fn fetch_group_messages(
+        &self,
+        sub_id: &str,
+        queue_urn: &str,
+        group_name: &str,
+        length: usize,
+        auto_commit: bool,
+    ) -> std::result::Result<Vec<Message>, RPCErrors> {
+        let group_id = Self::group_id(queue_urn, group_name);
+        let group = self.load_group(&group_id)?;
+        let config = self.get_queue_config(queue_urn)?;
+        let now_ms = Self::now_millis();
+
+        let start = Self::message_key(queue_urn, group.cursor);
+        let end = Self::message_key(queue_urn, u64::MAX);
+        let mut messages = Vec::new();
+        for item in self.messages.range(start..=end) {
+            if messages.len() >= length {
+                break;
+            }
+            let (_, value) = item.map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+            let msg = Self::decode_message(&value)?;
+            // 其他订阅的重放消息对本组视为已确认，让组 cursor 越过
+            if Self::is_replay_for_other(&msg, sub_id, Some(group_name)) {
+                self.update_delivery(&group_id, msg.index, |mut state| {
+                    (!state.acked).then(|| {
+                        state.acked = true;
+                        state
+                    })
+                })?;
+                continue;
+            }
+            let delivery = self.get_delivery(&group_id, msg.index)?;
+            if delivery.acked
+                || delivery.dead
+                || delivery.is_leased(now_ms)
+                || delivery.not_before_ms > now_ms
+            {
+                continue;
+            }
+            if Self::is_delivery_exhausted(&config, &delivery) {
+                let dead = self.update_delivery(&group_id, msg.index, |mut state| {
+                    (!state.dead && !state.is_leased(now_ms)).then(|| {
+                        state.dead = true;
+                        state.owner = None;
+                        state
+                    })
+                })?;
+                if let Some(dead) = dead {
+                    self.dead_letter(
+                        &config,
+                        queue_urn,
+                        sub_id,
+                        Some(group_name),
+                        &msg,
+                        dead.attempts,
+                    )?;
+                }
+                continue;
+            }
+
+            let leased = self.update_delivery(&group_id, msg.index, |mut state| {
+                if state.acked || state.dead || state.is_leased(now_ms) {
+                    return None;
+                }
+                state.attempts += 1;
+                state.owner = Some(sub_id.to_string());
+                state.lease_until_ms = now_ms + group.visibility_timeout_ms;
+                state.acked = auto_commit;
+                Some(state)
+            })?;
+            if leased.is_some() {
+                messages.push(msg);
+            }
         }
 
-        Ok(result)
+        self.advance_group_cursor(&group_id)?;
+        Ok(messages)
     }
 
-    async fn handle_subscribe(
+    fn ack_group_message(
+        &self,
+        sub_id: &str,
+        group_id: &str,
+        index: MsgIndex,
+    ) -> std::result::Result<(), RPCErrors> {
+        let group = self.load_group(group_id)?;
+        if index < group.cursor {
+            return Ok(());
+        }
+        let now_ms = Self::now_millis();
+        let acked = self.update_delivery(group_id, index, |mut state| {
+            // 租约过期但还没被其他成员领走时，迟到的 ack 仍然有效
+            let owned = state.owner.as_deref() == Some(sub_id);
+            if !owned && state.is_leased(now_ms) {
+                return None;
+            }
+            state.acked = true;
+            Some(state)
+        })?;
+        if acked.is_none() {
+            return Err(RPCErrors::ReasonError(format!(
+                "Lease of message {} is held by another consumer of {}",
+                index, group_id
+            )));
+        }
+        self.advance_group_cursor(group_id)?;
+        Ok(())
+    }
+
+    /// 释放 sub_id 持有的所有租约，让组内其他成员可以立即领取
+    fn release_group_leases(
+        &self,
+        sub_id: &str,
+        group_id: &str,
+    ) -> std::result::Result<(), RPCErrors> {
+        let indexes: Vec<MsgIndex> = self
+            .deliveries
+            .scan_prefix(Self::delivery_prefix(group_id))
+            .filter_map(|item| item.ok())
+            .filter_map(|(key, value)| {
+                let state: DeliveryState = serde_json::from_slice(&value).ok()?;
+                if state.acked || state.owner.as_deref() != Some(sub_id) {
+                    return None;
+                }
+                Self::decode_index_from_key(&key)
+            })
+            .collect();
+        for index in indexes {
+            self.update_delivery(group_id, index, |mut state| {
+                if state.owner.as_deref() != Some(sub_id) {
+                    return None;
+                }
+                state.owner = None;
+                state.lease_until_ms = 0;
+                Some(state)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// 删除 cursor 之前 (已确认) 的投递记录，cursor 为 None 时全部删除
+    fn clear_deliveries(
+        &self,
+        sub_id: &str,
+        cursor: Option<MsgIndex>,
+    ) -> std::result::Result<(), RPCErrors> {
+        let start = Self::delivery_key(sub_id, 0);
+        let end = Self::delivery_key(sub_id, cursor.unwrap_or(u64::MAX));
+        let keys: Vec<IVec> = self
+            .deliveries
+            .range(start..=end)
+            .keys()
+            .filter_map(|key| key.ok())
+            .filter(|key| {
+                cursor.is_none() || Self::decode_index_from_key(key).unwrap_or(0) < cursor.unwrap()
+            })
+            .collect();
+        for key in keys {
+            self.deliveries
+                .remove(key)
+                .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        }
+        Ok(())
+    }
+
+    fn is_delivery_exhausted(config: &QueueConfig, delivery: &DeliveryState) -> bool {
+        match config.max_delivery_attempts {
+            Some(max_attempts) if max_attempts > 0 => delivery.attempts >= max_attempts,
+            _ => false,
+        }
+    }
+
+    /// 重放的死信消息是否属于其他订阅或消费组
+    fn is_replay_for_other(message: &Message, sub_id: &str, group: Option<&str>) -> bool {
+        if let Some(target) = message.headers.get(REPLAY_HEADER_GROUP) {
+            return group != Some(target.as_str());
+        }
+        if let Some(target) = message.headers.get(REPLAY_HEADER_SUB_ID) {
+            return group.is_some() || target != sub_id;
+        }
+        false
+    }
+
+    /// 将用尽投递次数的消息复制到死信队列，未配置死信队列时只记录日志并跳过。
+    /// 调用方先用 update_delivery 标记 dead，只有标记成功的一方写入死信队列
+    fn dead_letter(
+        &self,
+        config: &QueueConfig,
+        queue_urn: &str,
+        sub_id: &str,
+        group: Option<&str>,
+        message: &Message,
+        attempts: u32,
+    ) -> std::result::Result<(), RPCErrors> {
+        let Some(dead_letter_queue) = config.dead_letter_queue.as_deref() else {
+            warn!(
+                "message {}#{} exhausted {} delivery attempts for {}, no dead letter queue, skipped",
+                queue_urn, message.index, attempts, sub_id
+            );
+            return Ok(());
+        };
+
+        let mut dead_message = message.clone();
+        dead_message.index = 0;
+        // 死信队列的订阅者都能收到，重放时再按下面记录的订阅重新指定
+        dead_message.headers.remove(REPLAY_HEADER_SUB_ID);
+        dead_message.headers.remove(REPLAY_HEADER_GROUP);
+        dead_message
+            .headers
+            .insert(DLQ_HEADER_SOURCE_QUEUE.to_string(), queue_urn.to_string());
+        dead_message.headers.insert(
+            DLQ_HEADER_SOURCE_INDEX.to_string(),
+            message.index.to_string(),
+        );
+        dead_message
+            .headers
+            .insert(DLQ_HEADER_SUB_ID.to_string(), sub_id.to_string());
+        dead_message
+            .headers
+            .insert(DLQ_HEADER_ATTEMPTS.to_string(), attempts.to_string());
+        if let Some(group) = group {
+            dead_message
+                .headers
+                .insert(DLQ_HEADER_GROUP.to_string(), group.to_string());
+        }
+        self.append_message(dead_letter_queue, dead_message)?;
+        Ok(())
+    }
+
+    /// 删除 index 之前的所有消息，并重新计算 first_index
+    fn trim_before(&self, queue_urn: &str, index: MsgIndex) -> std::result::Result<u64, RPCErrors> {
+        let queue_key = Self::queue_key(queue_urn);
+        if self
+            .queue_meta
+            .get(&queue_key)
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?
+            .is_none()
+        {
+            return Err(RPCErrors::ReasonError(format!(
+                "Queue not found: {}",
+                queue_urn
+            )));
+        }
+
+        let start = Self::message_key(queue_urn, 0);
+        let end = Self::message_key(queue_urn, index);
+        let mut removed_count = 0u64;
+        let mut removed_bytes = 0u64;
+        let mut removed_indexes = Vec::new();
+
+        for item in self.messages.range(start..end) {
+            let (key, value) = item.map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+            if let Ok(msg) = Self::decode_message(&value) {
+                removed_bytes += msg.payload.len() as u64;
+            }
+            removed_count += 1;
+            removed_indexes.push(key.to_vec());
+        }
+
+        for key in removed_indexes {
+            let _ = self.messages.remove(key);
+        }
+
+        if removed_count == 0 {
+            return Ok(0);
+        }
+
+        let scan_start = Self::message_key(queue_urn, index);
+        let scan_end = Self::message_key(queue_urn, u64::MAX);
+        let mut new_first = None;
+        for item in self.messages.range(scan_start..=scan_end) {
+            let (key, _) = item.map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+            if let Some(idx) = Self::decode_index_from_key(&key) {
+                new_first = Some(idx);
+                break;
+            }
+        }
+
+        // 后台 compaction 可能和 post 并发，meta 的更新放在事务里重新读取
+        self.queue_meta
+            .transaction(|queue_meta| {
+                let Some(meta_value) = queue_meta.get(&queue_key)? else {
+                    return Ok(());
+                };
+                let mut meta: QueueMeta = serde_json::from_slice(&meta_value).map_err(|err| {
+                    sled::transaction::ConflictableTransactionError::Abort(RPCErrors::ReasonError(
+                        format!("Failed to decode queue meta: {}", err),
+                    ))
+                })?;
+                meta.message_count = meta.message_count.saturating_sub(removed_count);
+                meta.size_bytes = meta.size_bytes.saturating_sub(removed_bytes);
+                if meta.message_count == 0 {
+                    meta.first_index = 0;
+                    meta.last_index = 0;
+                } else {
+                    // 消息只会从队首删除，剩余的 index 是连续的
+                    meta.first_index =
+                        new_first.unwrap_or_else(|| meta.last_index + 1 - meta.message_count);
+                }
+                let data = serde_json::to_vec(&meta).map_err(|err| {
+                    sled::transaction::ConflictableTransactionError::Abort(RPCErrors::ReasonError(
+                        format!("Failed to encode queue meta: {}", err),
+                    ))
+                })?;
+                queue_meta.insert(queue_key.as_slice(), data)?;
+                Ok(())
+            })
+            .map_err(|err| match err {
+                sled::transaction::TransactionError::Abort(err) => err,
+                sled::transaction::TransactionError::Storage(err) => {
+                    RPCErrors::ReasonError(err.to_string())
+                }
+            })?;
+
+        Ok(removed_count)
+    }
+
+    /// 按 QueueConfig 的条数、时间和字节数限制删除最旧的消息。
+    /// 只删除连续的前缀，除非设置了 drop_unacked，否则不会越过任何订阅的 cursor
+    fn compact_queue(&self, queue_urn: &str) -> std::result::Result<u64, RPCErrors> {
+        let config = self.get_queue_config(queue_urn)?;
+        let max_messages = config.max_messages.filter(|value| *value > 0);
+        if max_messages.is_none()
+            && config.retention_seconds.is_none()
+            && config.max_bytes.is_none()
+        {
+            return Ok(0);
+        }
+        let meta = self.get_queue_meta(queue_urn)?;
+        let expire_before = config
+            .retention_seconds
+            .map(|retention| Self::now_seconds().saturating_sub(retention));
+
+        let queue_subs = self.queue_subscriptions(queue_urn)?;
+        // 成员都已退订的消费组不再阻止删除消息，它的 cursor 在删除后照常推进
+        let active_groups: HashSet<String> = queue_subs
+            .iter()
+            .filter_map(|(_, sub)| sub.group.as_deref())
+            .map(|group| Self::group_id(queue_urn, group))
+            .collect();
+        let subs: Vec<(String, SubscriptionState)> = queue_subs
+            .into_iter()
+            .filter(|(_, sub)| sub.group.is_none())
+            .collect();
+        let groups = self.queue_groups(queue_urn)?;
+        let min_cursor = subs
+            .iter()
+            .map(|(_, sub)| sub.cursor)
+            .chain(
+                groups
+                    .iter()
+                    .filter(|(group_id, _)| active_groups.contains(group_id))
+                    .map(|(_, group)| group.cursor),
+            )
+            .min();
+
+        let mut remaining_count = meta.message_count;
+        let mut remaining_bytes = meta.size_bytes;
+        let mut trim_to = None;
+        let start = Self::message_key(queue_urn, 0);
+        let end = Self::message_key(queue_urn, u64::MAX);
+        for item in self.messages.range(start..=end) {
+            let (_, value) = item.map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+            let msg = Self::decode_message(&value)?;
+            let over_count = max_messages.is_some_and(|limit| remaining_count > limit);
+            let over_bytes = config
+                .max_bytes
+                .is_some_and(|limit| remaining_bytes > limit);
+            let expired = expire_before.is_some_and(|before| msg.created_at < before);
+            if !(over_count || over_bytes || expired) {
+                break;
+            }
+            if !config.drop_unacked && min_cursor.is_some_and(|cursor| msg.index >= cursor) {
+                break;
+            }
+            remaining_count = remaining_count.saturating_sub(1);
+            remaining_bytes = remaining_bytes.saturating_sub(msg.payload.len() as u64);
+            trim_to = Some(msg.index + 1);
+        }
+
+        let Some(trim_to) = trim_to else {
+            return Ok(0);
+        };
+        let removed = self.trim_before(queue_urn, trim_to)?;
+        // drop_unacked 删除了未确认的消息，落后的订阅直接跳到新的队首。
+        // 读出状态之后 ack/fetch 可能已经改过，只在原值上推进 cursor，不覆盖其他字段
+        for (sub_id, sub) in subs {
+            if sub.cursor >= trim_to {
+                continue;
+            }
+            let moved = self
+                .subs
+                .update_and_fetch(sub_id.as_bytes(), |value| {
+                    let mut sub: SubscriptionState = serde_json::from_slice(value?).ok()?;
+                    sub.cursor = sub.cursor.max(trim_to);
+                    serde_json::to_vec(&sub).ok()
+                })
+                .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+            if moved.is_some() {
+                warn!(
+                    "kmsg compaction dropped unacked messages of {} before {}",
+                    sub_id, trim_to
+                );
+                self.clear_deliveries(&sub_id, Some(trim_to))?;
+            }
+        }
+        for (group_id, group) in groups {
+            if group.cursor >= trim_to {
+                continue;
+            }
+            let moved = self
+                .groups
+                .update_and_fetch(group_id.as_bytes(), |value| {
+                    let mut group: GroupState = serde_json::from_slice(value?).ok()?;
+                    group.cursor = group.cursor.max(trim_to);
+                    serde_json::to_vec(&group).ok()
+                })
+                .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+            if moved.is_some() {
+                warn!(
+                    "kmsg compaction dropped unacked messages of group {} before {}",
+                    group_id, trim_to
+                );
+                self.clear_deliveries(&group_id, Some(trim_to))?;
+            }
+        }
+        if config.sync_write {
+            self.db
+                .flush()
+                .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        }
+        Ok(removed)
+    }
+
+    /// 对所有队列执行一次 compact_queue，返回删除的消息总数
+    pub fn compact_all(&self) -> u64 {
+        let queue_urns: Vec<String> = self
+            .queues
+            .iter()
+            .keys()
+            .filter_map(|key| key.ok())
+            .filter_map(|key| String::from_utf8(key.to_vec()).ok())
+            .collect();
+        let mut removed = 0;
+        for queue_urn in queue_urns {
+            match self.compact_queue(&queue_urn) {
+                Ok(count) => removed += count,
+                Err(err) => warn!("kmsg compact queue {} failed: {}", queue_urn, err),
+            }
+        }
+        removed
+    }
+
+    /// 启动后台 compaction 任务，按 interval 周期性执行 compact_all
+    pub fn spawn_compaction(&self, interval: Duration) -> task::JoinHandle<()> {
+        let queue = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                let worker = queue.clone();
+                match task::spawn_blocking(move || worker.compact_all()).await {
+                    Ok(removed) if removed > 0 => {
+                        info!("kmsg compaction removed {} messages", removed)
+                    }
+                    Ok(_) => {}
+                    Err(err) => warn!("kmsg compaction task failed: {}", err),
+                }
+            }
+        })
+    }
+
+    fn queue_subscriptions(
+        &self,
+        queue_urn: &str,
+    ) -> std::result::Result<Vec<(String, SubscriptionState)>, RPCErrors> {
+        let mut subs = Vec::new();
+        for item in self.subs.iter() {
+            let (key, value) = item.map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+            let Ok(sub) = serde_json::from_slice::<SubscriptionState>(&value) else {
+                continue;
+            };
+            if sub.queue_urn != queue_urn {
+                continue;
+            }
+            if let Ok(sub_id) = String::from_utf8(key.to_vec()) {
+                subs.push((sub_id, sub));
+            }
+        }
+        Ok(subs)
+    }
+
+    fn create_subscription(
         &self,
         queue_urn: &str,
-        _user_id: &str,
-        _app_id: &str,
         sub_id: Option<String>,
         position: SubPosition,
-        _ctx: RPCContext,
+        filter: Option<HeaderFilter>,
     ) -> std::result::Result<SubscriptionId, RPCErrors> {
         let meta = self.get_queue_meta(queue_urn)?;
         let first_index = if meta.first_index == 0 {
@@ -505,6 +1104,8 @@ impl MsgQueueHandler for SledMsgQueue {
         let sub = SubscriptionState {
             queue_urn: queue_urn.to_string(),
             cursor,
+            group: None,
+            filter,
         };
         let data =
             serde_json::to_vec(&sub).map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
@@ -514,272 +1115,1140 @@ impl MsgQueueHandler for SledMsgQueue {
         Ok(sub_id)
     }
 
-    async fn handle_unsubscribe(
-        &self,
-        sub_id: &str,
-        _ctx: RPCContext,
-    ) -> std::result::Result<(), RPCErrors> {
-        if self
-            .subs
-            .remove(sub_id.as_bytes())
-            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?
-            .is_none()
-        {
-            return Err(RPCErrors::ReasonError(format!(
-                "Subscription not found: {}",
-                sub_id
-            )));
-        }
-        Ok(())
+    fn queue_notifier(&self, queue_urn: &str) -> Arc<Notify> {
+        let mut notifiers = self.notifiers.lock().unwrap();
+        notifiers
+            .entry(queue_urn.to_string())
+            .or_insert_with(|| Arc::new(Notify::new()))
+            .clone()
     }
 
-    async fn handle_fetch_messages(
-        &self,
-        sub_id: &str,
-        length: usize,
-        auto_commit: bool,
-        _ctx: RPCContext,
-    ) -> std::result::Result<Vec<Message>, RPCErrors> {
-        let sub_value = self
-            .subs
-            .get(sub_id.as_bytes())
-            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?
-            .ok_or_else(|| RPCErrors::ReasonError(format!("Subscription not found: {}", sub_id)))?;
-        let mut sub: SubscriptionState = serde_json::from_slice(&sub_value)
-            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
-
-        let start = Self::message_key(&sub.queue_urn, sub.cursor);
-        let end = Self::message_key(&sub.queue_urn, u64::MAX);
-        let mut messages = Vec::new();
-        for item in self.messages.range(start..=end) {
-            let (_, value) = item.map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
-            let msg = Self::decode_message(&value)?;
-            messages.push(msg);
-            if messages.len() >= length {
-                break;
-            }
-        }
-
-        if auto_commit {
-            if let Some(last) = messages.last() {
-                sub.cursor = last.index + 1;
-                let data = serde_json::to_vec(&sub)
-                    .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
-                self.subs
-                    .insert(sub_id.as_bytes(), data)
-                    .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
-            }
+    fn wake_queue(&self, queue_urn: &str) {
+        let notifiers = self.notifiers.lock().unwrap();
+        if let Some(notify) = notifiers.get(queue_urn) {
+            notify.notify_waiters();
         }
-
-        Ok(messages)
     }
 
-    async fn handle_read_message(
+    fn append_message(
         &self,
         queue_urn: &str,
-        cursor: MsgIndex,
-        length: usize,
-        _ctx: RPCContext,
-    ) -> std::result::Result<Vec<Message>, RPCErrors> {
-        let _ = self.get_queue_meta(queue_urn)?;
-        let start = Self::message_key(queue_urn, cursor);
-        let end = Self::message_key(queue_urn, u64::MAX);
-        let mut messages = Vec::new();
-        for item in self.messages.range(start..=end) {
-            let (_, value) = item.map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
-            let msg = Self::decode_message(&value)?;
-            messages.push(msg);
-            if messages.len() >= length {
-                break;
-            }
-        }
-
-        Ok(messages)
+        message: Message,
+    ) -> std::result::Result<MsgIndex, RPCErrors> {
+        let queue_key = Self::queue_key(queue_urn);
+        let messages = &self.messages;
+        let queue_meta = &self.queue_meta;
+        let payload_len = message.payload.len() as u64;
+
+        let index = (messages, queue_meta)
+            .transaction(|trees| {
+                let (messages, queue_meta) = trees;
+                let meta_value = queue_meta.get(&queue_key)?.ok_or_else(|| {
+                    sled::transaction::ConflictableTransactionError::Abort(RPCErrors::ReasonError(
+                        format!("Queue not found: {}", queue_urn),
+                    ))
+                })?;
+                let mut meta: QueueMeta = serde_json::from_slice(&meta_value).map_err(|err| {
+                    sled::transaction::ConflictableTransactionError::Abort(RPCErrors::ReasonError(
+                        format!("Failed to decode queue meta: {}", err),
+                    ))
+                })?;
+                let index = meta.next_index;
+                meta.next_index += 1;
+                meta.message_count += 1;
+                if meta.first_index == 0 {
+                    meta.first_index = index;
+                }
+                meta.last_index = index;
+                meta.size_bytes += payload_len;
+
+                let mut stored_message = message.clone();
+                stored_message.index = index;
+                let data = serde_json::to_vec(&stored_message).map_err(|err| {
+                    sled::transaction::ConflictableTransactionError::Abort(RPCErrors::ReasonError(
+                        format!("Failed to encode message: {}", err),
+                    ))
+                })?;
+                let msg_key = SledMsgQueue::message_key(queue_urn, index);
+                messages.insert(msg_key, data)?;
+                queue_meta.insert(
+                    queue_key.clone(),
+                    serde_json::to_vec(&meta).map_err(|err| {
+                        sled::transaction::ConflictableTransactionError::Abort(
+                            RPCErrors::ReasonError(format!("Failed to encode queue meta: {}", err)),
+                        )
+                    })?,
+                )?;
+                Ok(index)
+            })
+            .map_err(|err| match err {
+                sled::transaction::TransactionError::Abort(err) => err,
+                sled::transaction::TransactionError::Storage(err) => {
+                    RPCErrors::ReasonError(err.to_string())
+                }
+            })?;
+        self.wake_queue(queue_urn);
+        Ok(index)
     }
+}
 
-    async fn handle_commit_ack(
+#[async_trait]
+impl MsgQueueHandler for SledMsgQueue {
+    async fn handle_create_queue(
         &self,
-        sub_id: &str,
-        index: MsgIndex,
+        name: Option<&str>,
+        appid: &str,
+        app_owner: &str,
+        config: QueueConfig,
         _ctx: RPCContext,
-    ) -> std::result::Result<(), RPCErrors> {
-        let sub_value = self
-            .subs
-            .get(sub_id.as_bytes())
-            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?
-            .ok_or_else(|| RPCErrors::ReasonError(format!("Subscription not found: {}", sub_id)))?;
-        let mut sub: SubscriptionState = serde_json::from_slice(&sub_value)
-            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
-        sub.cursor = index + 1;
-        let data =
-            serde_json::to_vec(&sub).map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
-        self.subs
-            .insert(sub_id.as_bytes(), data)
+    ) -> std::result::Result<QueueUrn, RPCErrors> {
+        let name = match name {
+            Some(value) => value.to_string(),
+            None => format!("queue-{}", self.next_id("queue_id")?),
+        };
+        let queue_urn = calc_queue_urn(appid, app_owner, &name);
+        self.check_dead_letter_queue(&queue_urn, &config)?;
+        let key = Self::queue_key(&queue_urn);
+        let config_data =
+            serde_json::to_vec(&config).map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        let create_result = self
+            .queues
+            .compare_and_swap(key.clone(), None as Option<IVec>, Some(config_data))
             .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
-        Ok(())
+        if create_result.is_err() {
+            return Err(RPCErrors::ReasonError(format!(
+                "Queue already exists: {}",
+                queue_urn
+            )));
+        }
+
+        let meta = QueueMeta {
+            next_index: 1,
+            message_count: 0,
+            first_index: 0,
+            last_index: 0,
+            size_bytes: 0,
+        };
+        if let Err(err) = self.store_queue_meta(&queue_urn, &meta) {
+            let _ = self.queues.remove(key);
+            return Err(err);
+        }
+
+        if config.sync_write {
+            self.db
+                .flush()
+                .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        }
+
+        Ok(queue_urn)
     }
 
-    async fn handle_seek(
+    async fn handle_delete_queue(
         &self,
-        sub_id: &str,
-        index: SubPosition,
+        queue_urn: &str,
         _ctx: RPCContext,
     ) -> std::result::Result<(), RPCErrors> {
-        let sub_value = self
-            .subs
-            .get(sub_id.as_bytes())
+        let key = Self::queue_key(queue_urn);
+        if self
+            .queues
+            .remove(key)
             .map_err(|err| RPCErrors::ReasonError(err.to_string()))?
-            .ok_or_else(|| RPCErrors::ReasonError(format!("Subscription not found: {}", sub_id)))?;
-        let mut sub: SubscriptionState = serde_json::from_slice(&sub_value)
+            .is_none()
+        {
+            return Err(RPCErrors::ReasonError(format!(
+                "Queue not found: {}",
+                queue_urn
+            )));
+        }
+
+        let prefix = Self::message_prefix(queue_urn);
+        let message_keys: Vec<Vec<u8>> = self
+            .messages
+            .scan_prefix(prefix)
+            .filter_map(|item| item.ok().map(|(key, _)| key.to_vec()))
+            .collect();
+        for key in message_keys {
+            let _ = self.messages.remove(key);
+        }
+
+        let sub_keys: Vec<Vec<u8>> = self
+            .subs
+            .iter()
+            .filter_map(|item| item.ok())
+            .filter_map(|(key, value)| {
+                let sub: SubscriptionState = serde_json::from_slice(&value).ok()?;
+                if sub.queue_urn == queue_urn {
+                    Some(key.to_vec())
+                } else {
+                    None
+                }
+            })
+            .collect();
+        for key in sub_keys {
+            if let Ok(sub_id) = std::str::from_utf8(&key) {
+                let _ = self.clear_deliveries(sub_id, None);
+            }
+            let _ = self.subs.remove(key);
+        }
+        for (group_id, _) in self.queue_groups(queue_urn)? {
+            let _ = self.clear_deliveries(&group_id, None);
+            let _ = self.groups.remove(group_id.as_bytes());
+        }
+        // 唤醒还在等待的 fetch，让它们发现订阅已不存在
+        self.wake_queue(queue_urn);
+        self.notifiers.lock().unwrap().remove(queue_urn);
+
+        let _ = self.queue_meta.remove(Self::queue_key(queue_urn));
+        self.db
+            .flush()
             .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        Ok(())
+    }
 
-        let meta = self.get_queue_meta(&sub.queue_urn)?;
-        let first_index = if meta.first_index == 0 {
-            1
-        } else {
-            meta.first_index
-        };
-        let last_index = meta.last_index;
-        sub.cursor = match index {
-            SubPosition::Earliest => first_index,
-            SubPosition::Latest => last_index + 1,
-            SubPosition::At(value) => value,
-        };
+    async fn handle_get_queue_stats(
+        &self,
+        queue_urn: &str,
+        _ctx: RPCContext,
+    ) -> std::result::Result<QueueStats, RPCErrors> {
+        let meta = self.get_queue_meta(queue_urn)?;
+        Ok(QueueStats {
+            message_count: meta.message_count,
+            first_index: meta.first_index,
+            last_index: meta.last_index,
+            size_bytes: meta.size_bytes,
+        })
+    }
 
-        let data =
-            serde_json::to_vec(&sub).map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
-        self.subs
-            .insert(sub_id.as_bytes(), data)
+    async fn handle_update_queue_config(
+        &self,
+        queue_urn: &str,
+        config: QueueConfig,
+        _ctx: RPCContext,
+    ) -> std::result::Result<(), RPCErrors> {
+        let key = Self::queue_key(queue_urn);
+        if self
+            .queues
+            .get(key.clone())
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?
+            .is_none()
+        {
+            return Err(RPCErrors::ReasonError(format!(
+                "Queue not found: {}",
+                queue_urn
+            )));
+        }
+        self.check_dead_letter_queue(queue_urn, &config)?;
+        let config_data =
+            serde_json::to_vec(&config).map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        self.queues
+            .insert(key, config_data)
             .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        if config.sync_write {
+            self.db
+                .flush()
+                .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        }
         Ok(())
     }
 
-    async fn handle_delete_message_before(
+    async fn handle_post_message(
         &self,
         queue_urn: &str,
-        index: MsgIndex,
+        mut message: Message,
         _ctx: RPCContext,
-    ) -> std::result::Result<u64, RPCErrors> {
+    ) -> std::result::Result<MsgIndex, RPCErrors> {
         let config = self.get_queue_config(queue_urn)?;
-        let queue_urn = queue_urn.to_string();
-        let messages = self.messages.clone();
-        let queue_meta = self.queue_meta.clone();
-
-        let removed = task::spawn_blocking(move || {
-            let meta_value = queue_meta
-                .get(SledMsgQueue::queue_key(&queue_urn))
-                .map_err(|err| RPCErrors::ReasonError(err.to_string()))?
-                .ok_or_else(|| RPCErrors::ReasonError(format!("Queue not found: {}", queue_urn)))?;
-            let mut meta = SledMsgQueue::decode_queue_meta(&meta_value)?;
-
-            let start = SledMsgQueue::message_key(&queue_urn, 0);
-            let end = SledMsgQueue::message_key(&queue_urn, index);
-            let mut removed_count = 0u64;
-            let mut removed_bytes = 0u64;
-            let mut removed_indexes = Vec::new();
-
-            for item in messages.range(start..end) {
-                let (key, value) = item.map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
-                if let Ok(msg) = SledMsgQueue::decode_message(&value) {
-                    removed_bytes += msg.payload.len() as u64;
-                }
-                removed_count += 1;
-                removed_indexes.push(key.to_vec());
-            }
+        let now = Self::now_seconds();
+        if message.created_at == 0 {
+            message.created_at = now;
+        }
 
-            for key in removed_indexes {
-                let _ = messages.remove(key);
-            }
+        let result = self.append_message(queue_urn, message)?;
 
-            if removed_count == 0 {
-                return Ok(0u64);
-            }
+        if config.sync_write {
+            self.db
+                .flush()
+                .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        }
 
-            meta.message_count = meta.message_count.saturating_sub(removed_count);
-            meta.size_bytes = meta.size_bytes.saturating_sub(removed_bytes);
-
-            if meta.message_count == 0 {
-                meta.first_index = 0;
-                meta.last_index = 0;
-            } else {
-                let scan_start = SledMsgQueue::message_key(&queue_urn, index);
-                let scan_end = SledMsgQueue::message_key(&queue_urn, u64::MAX);
-                let mut new_first = None;
-                for item in messages.range(scan_start..=scan_end) {
-                    let (key, _) = item.map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
-                    if let Some(idx) = SledMsgQueue::decode_index_from_key(&key) {
-                        new_first = Some(idx);
-                        break;
-                    }
-                }
-                if let Some(idx) = new_first {
-                    meta.first_index = idx;
-                }
-            }
+        Ok(result)
+    }
 
-            let meta_data = SledMsgQueue::encode_queue_meta(&meta)?;
-            queue_meta
-                .insert(SledMsgQueue::queue_key(&queue_urn), meta_data)
-                .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+    async fn handle_subscribe(
+        &self,
+        queue_urn: &str,
+        _user_id: &str,
+        _app_id: &str,
+        sub_id: Option<String>,
+        position: SubPosition,
+        _ctx: RPCContext,
+    ) -> std::result::Result<SubscriptionId, RPCErrors> {
+        self.create_subscription(queue_urn, sub_id, position, None)
+    }
+
+    async fn handle_subscribe_filtered(
+        &self,
+        queue_urn: &str,
+        _user_id: &str,
+        _app_id: &str,
+        sub_id: Option<String>,
+        position: SubPosition,
+        filter: HeaderFilter,
+        _ctx: RPCContext,
+    ) -> std::result::Result<SubscriptionId, RPCErrors> {
+        let filter = (!filter.is_empty()).then_some(filter);
+        self.create_subscription(queue_urn, sub_id, position, filter)
+    }
+
+    async fn handle_subscribe_group(
+        &self,
+        queue_urn: &str,
+        _user_id: &str,
+        _app_id: &str,
+        group: &str,
+        sub_id: Option<String>,
+        position: SubPosition,
+        visibility_timeout_ms: u64,
+        _ctx: RPCContext,
+    ) -> std::result::Result<SubscriptionId, RPCErrors> {
+        if group.is_empty() {
+            return Err(RPCErrors::ReasonError(
+                "Consumer group name cannot be empty".to_string(),
+            ));
+        }
+        let meta = self.get_queue_meta(queue_urn)?;
+        let sub_id = match sub_id {
+            Some(value) => value,
+            None => format!("sub-{}", self.next_id("sub_id")?),
+        };
+        if self
+            .subs
+            .get(sub_id.as_bytes())
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?
+            .is_some()
+        {
+            return Err(RPCErrors::ReasonError(format!(
+                "Subscription already exists: {}",
+                sub_id
+            )));
+        }
+
+        // 第一个成员创建消费组，后加入的成员沿用组的进度，position 被忽略
+        let group_id = Self::group_id(queue_urn, group);
+        let first_index = if meta.first_index == 0 {
+            1
+        } else {
+            meta.first_index
+        };
+        let new_group = GroupState {
+            queue_urn: queue_urn.to_string(),
+            cursor: match position {
+                SubPosition::Earliest => first_index,
+                SubPosition::Latest => meta.last_index + 1,
+                SubPosition::At(index) => index,
+            },
+            visibility_timeout_ms: visibility_timeout_ms.max(1),
+        };
+        let data = serde_json::to_vec(&new_group)
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        let _ = self
+            .groups
+            .compare_and_swap(group_id.as_bytes(), None as Option<&[u8]>, Some(data))
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        let group_state = self.load_group(&group_id)?;
+
+        let sub = SubscriptionState {
+            queue_urn: queue_urn.to_string(),
+            cursor: group_state.cursor,
+            group: Some(group.to_string()),
+            filter: None,
+        };
+        self.store_subscription(&sub_id, &sub)?;
+        Ok(sub_id)
+    }
+
+    async fn handle_unsubscribe(
+        &self,
+        sub_id: &str,
+        _ctx: RPCContext,
+    ) -> std::result::Result<(), RPCErrors> {
+        let Some(value) = self
+            .subs
+            .remove(sub_id.as_bytes())
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?
+        else {
+            return Err(RPCErrors::ReasonError(format!(
+                "Subscription not found: {}",
+                sub_id
+            )));
+        };
+        let sub: SubscriptionState = serde_json::from_slice(&value)
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        match sub.group {
+            Some(group) => {
+                self.release_group_leases(sub_id, &Self::group_id(&sub.queue_urn, &group))?
+            }
+            None => self.clear_deliveries(sub_id, None)?,
+        }
+        Ok(())
+    }
+
+    async fn handle_fetch_messages(
+        &self,
+        sub_id: &str,
+        length: usize,
+        auto_commit: bool,
+        _ctx: RPCContext,
+    ) -> std::result::Result<Vec<Message>, RPCErrors> {
+        let mut sub = self.load_subscription(sub_id)?;
+        if let Some(group) = sub.group.as_deref() {
+            return self.fetch_group_messages(sub_id, &sub.queue_urn, group, length, auto_commit);
+        }
+        let config = self.get_queue_config(&sub.queue_urn)?;
+        let now_ms = Self::now_millis();
+        let old_cursor = sub.cursor;
+
+        let start = Self::message_key(&sub.queue_urn, sub.cursor);
+        let end = Self::message_key(&sub.queue_urn, u64::MAX);
+        let mut messages = Vec::new();
+        // cursor 处连续的死信消息可以直接越过
+        let mut at_head = true;
+        for item in self.messages.range(start..=end) {
+            if messages.len() >= length {
+                break;
+            }
+            let (_, value) = item.map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+            let msg = Self::decode_message(&value)?;
+            // 被过滤掉的消息和其他订阅的重放消息视为已处理
+            if sub
+                .filter
+                .as_ref()
+                .is_some_and(|filter| !filter.matches(&msg))
+                || Self::is_replay_for_other(&msg, sub_id, None)
+            {
+                if at_head {
+                    sub.cursor = msg.index + 1;
+                }
+                continue;
+            }
+            let mut delivery = self.get_delivery(sub_id, msg.index)?;
+            if !delivery.dead && Self::is_delivery_exhausted(&config, &delivery) {
+                // 并发的 fetch 只有一个能标记成功并写入死信队列
+                let dead = self.update_delivery(sub_id, msg.index, |mut state| {
+                    (!state.dead && Self::is_delivery_exhausted(&config, &state)).then(|| {
+                        state.dead = true;
+                        state
+                    })
+                })?;
+                if let Some(dead) = dead {
+                    self.dead_letter(&config, &sub.queue_urn, sub_id, None, &msg, dead.attempts)?;
+                }
+                delivery = self.get_delivery(sub_id, msg.index)?;
+                if !delivery.dead {
+                    // 投递记录被并发修改，下次 fetch 再处理
+                    break;
+                }
+            }
+            if delivery.dead {
+                if at_head {
+                    sub.cursor = msg.index + 1;
+                }
+                continue;
+            }
+            // 保持顺序：被 nack 延迟的消息之后的消息也不投递
+            if delivery.not_before_ms > now_ms {
+                break;
+            }
+
+            at_head = false;
+            if !auto_commit {
+                delivery.attempts += 1;
+                self.store_delivery(sub_id, msg.index, &delivery)?;
+            }
+            messages.push(msg);
+        }
+
+        if auto_commit && let Some(last) = messages.last() {
+            sub.cursor = last.index + 1;
+        }
+        if sub.cursor != old_cursor {
+            self.store_subscription(sub_id, &sub)?;
+            self.clear_deliveries(sub_id, Some(sub.cursor))?;
+        }
+
+        Ok(messages)
+    }
+
+    async fn handle_fetch_messages_wait(
+        &self,
+        sub_id: &str,
+        length: usize,
+        auto_commit: bool,
+        wait_ms: u64,
+        ctx: RPCContext,
+    ) -> std::result::Result<Vec<Message>, RPCErrors> {
+        let queue_urn = self.load_subscription(sub_id)?.queue_urn;
+        let notify = self.queue_notifier(&queue_urn);
+        let deadline =
+            tokio::time::Instant::now() + Duration::from_millis(wait_ms.min(MAX_FETCH_WAIT_MS));
+        loop {
+            // 先注册再查询，避免查询和等待之间写入的消息丢失唤醒
+            let notified = notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            let messages = self
+                .handle_fetch_messages(sub_id, length, auto_commit, ctx.clone())
+                .await?;
+            let now = tokio::time::Instant::now();
+            if !messages.is_empty() || now >= deadline {
+                return Ok(messages);
+            }
+            let wait = (deadline - now).min(Duration::from_millis(NACK_RECHECK_INTERVAL_MS));
+            let _ = tokio::time::timeout(wait, notified).await;
+        }
+    }
+
+    async fn handle_read_message(
+        &self,
+        queue_urn: &str,
+        cursor: MsgIndex,
+        length: usize,
+        _ctx: RPCContext,
+    ) -> std::result::Result<Vec<Message>, RPCErrors> {
+        let _ = self.get_queue_meta(queue_urn)?;
+        let start = Self::message_key(queue_urn, cursor);
+        let end = Self::message_key(queue_urn, u64::MAX);
+        let mut messages = Vec::new();
+        for item in self.messages.range(start..=end) {
+            let (_, value) = item.map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+            let msg = Self::decode_message(&value)?;
+            messages.push(msg);
+            if messages.len() >= length {
+                break;
+            }
+        }
+
+        Ok(messages)
+    }
+
+    async fn handle_commit_ack(
+        &self,
+        sub_id: &str,
+        index: MsgIndex,
+        _ctx: RPCContext,
+    ) -> std::result::Result<(), RPCErrors> {
+        let sub = self.load_subscription(sub_id)?;
+        // 消费组成员逐条确认，不是累积确认
+        if let Some(group) = sub.group.as_deref() {
+            return self.ack_group_message(sub_id, &Self::group_id(&sub.queue_urn, group), index);
+        }
+        // 在当前值上原子地推进 cursor，不覆盖 compaction 已经推进的 cursor
+        let updated = self
+            .subs
+            .update_and_fetch(sub_id.as_bytes(), |value| {
+                let value = value?;
+                let Ok(mut sub) = serde_json::from_slice::<SubscriptionState>(value) else {
+                    return Some(value.to_vec());
+                };
+                sub.cursor = sub.cursor.max(index + 1);
+                serde_json::to_vec(&sub)
+                    .ok()
+                    .or_else(|| Some(value.to_vec()))
+            })
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?
+            .ok_or_else(|| RPCErrors::ReasonError(format!("Subscription not found: {}", sub_id)))?;
+        let sub: SubscriptionState = serde_json::from_slice(&updated)
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        self.clear_deliveries(sub_id, Some(sub.cursor))?;
+        Ok(())
+    }
+
+    async fn handle_seek(
+        &self,
+        sub_id: &str,
+        index: SubPosition,
+        _ctx: RPCContext,
+    ) -> std::result::Result<(), RPCErrors> {
+        let sub_value = self
+            .subs
+            .get(sub_id.as_bytes())
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?
+            .ok_or_else(|| RPCErrors::ReasonError(format!("Subscription not found: {}", sub_id)))?;
+        let mut sub: SubscriptionState = serde_json::from_slice(&sub_value)
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+
+        let meta = self.get_queue_meta(&sub.queue_urn)?;
+        let first_index = if meta.first_index == 0 {
+            1
+        } else {
+            meta.first_index
+        };
+        let last_index = meta.last_index;
+        sub.cursor = match index {
+            SubPosition::Earliest => first_index,
+            SubPosition::Latest => last_index + 1,
+            SubPosition::At(value) => value,
+        };
+        // 消费组成员 seek 移动整个组的进度
+        if let Some(group) = sub.group.as_deref() {
+            return self.seek_group(&Self::group_id(&sub.queue_urn, group), sub.cursor);
+        }
+
+        let data =
+            serde_json::to_vec(&sub).map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        self.subs
+            .insert(sub_id.as_bytes(), data)
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        // seek 是显式的重放，之前的投递次数和死信标记一并清除
+        self.clear_deliveries(sub_id, None)?;
+        Ok(())
+    }
+
+    async fn handle_nack(
+        &self,
+        sub_id: &str,
+        index: MsgIndex,
+        requeue_after: u64,
+        _ctx: RPCContext,
+    ) -> std::result::Result<(), RPCErrors> {
+        let sub = self.load_subscription(sub_id)?;
+        // 消费组成员 nack 释放租约，消息可以被组内任意成员重新领取
+        let (delivery_id, cursor) = match sub.group.as_deref() {
+            Some(group) => {
+                let group_id = Self::group_id(&sub.queue_urn, group);
+                let cursor = self.load_group(&group_id)?.cursor;
+                (group_id, cursor)
+            }
+            None => (sub_id.to_string(), sub.cursor),
+        };
+        if index < cursor {
+            return Err(RPCErrors::ReasonError(format!(
+                "Message {} already acked by {}",
+                index, sub_id
+            )));
+        }
+        let value = self
+            .messages
+            .get(Self::message_key(&sub.queue_urn, index))
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?
+            .ok_or_else(|| RPCErrors::ReasonError(format!("Message not found: {}", index)))?;
+        let msg = Self::decode_message(&value)?;
+        let config = self.get_queue_config(&sub.queue_urn)?;
+
+        let now_ms = Self::now_millis();
+        let is_group = sub.group.is_some();
+        let delivery = self.update_delivery(&delivery_id, index, |mut state| {
+            if state.dead || state.acked {
+                return None;
+            }
+            if is_group && state.owner.as_deref() != Some(sub_id) && state.is_leased(now_ms) {
+                return None;
+            }
+            // 未经 fetch 直接 nack 也算一次投递
+            state.attempts = state.attempts.max(1);
+            state.not_before_ms = now_ms + requeue_after;
+            state.owner = None;
+            state.lease_until_ms = 0;
+            state.dead = Self::is_delivery_exhausted(&config, &state);
+            Some(state)
+        })?;
+        if let Some(delivery) = delivery {
+            if delivery.dead {
+                self.dead_letter(
+                    &config,
+                    &sub.queue_urn,
+                    sub_id,
+                    sub.group.as_deref(),
+                    &msg,
+                    delivery.attempts,
+                )?;
+                if is_group {
+                    self.advance_group_cursor(&delivery_id)?;
+                }
+            }
+        } else if is_group {
+            let current = self.get_delivery(&delivery_id, index)?;
+            if !(current.dead || current.acked) {
+                return Err(RPCErrors::ReasonError(format!(
+                    "Lease of message {} is held by another consumer of {}",
+                    index, delivery_id
+                )));
+            }
+        }
+
+        if config.sync_write {
+            self.db
+                .flush()
+                .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        }
+        Ok(())
+    }
+
+    async fn handle_replay_dead_letter(
+        &self,
+        queue_urn: &str,
+        index: MsgIndex,
+        _ctx: RPCContext,
+    ) -> std::result::Result<MsgIndex, RPCErrors> {
+        let value = self
+            .messages
+            .get(Self::message_key(queue_urn, index))
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?
+            .ok_or_else(|| RPCErrors::ReasonError(format!("Message not found: {}", index)))?;
+        let mut msg = Self::decode_message(&value)?;
+        let source_queue = msg.headers.remove(DLQ_HEADER_SOURCE_QUEUE).ok_or_else(|| {
+            RPCErrors::ReasonError(format!(
+                "Message {}#{} is not a dead letter",
+                queue_urn, index
+            ))
+        })?;
+        msg.headers.remove(DLQ_HEADER_SOURCE_INDEX);
+        msg.headers.remove(DLQ_HEADER_ATTEMPTS);
+        let sub_id = msg.headers.remove(DLQ_HEADER_SUB_ID);
+        // 只重新投递给把它转入死信的订阅或消费组，已经确认过的其他订阅不会再收到
+        match (msg.headers.remove(DLQ_HEADER_GROUP), sub_id) {
+            (Some(group), _) => {
+                msg.headers.insert(REPLAY_HEADER_GROUP.to_string(), group);
+            }
+            (None, Some(sub_id)) => {
+                msg.headers.insert(REPLAY_HEADER_SUB_ID.to_string(), sub_id);
+            }
+            (None, None) => {
+                return Err(RPCErrors::ReasonError(format!(
+                    "Dead letter {}#{} does not record its subscription",
+                    queue_urn, index
+                )));
+            }
+        }
+        msg.index = 0;
+        msg.created_at = Self::now_seconds();
+
+        let config = self.get_queue_config(&source_queue)?;
+        let new_index = self.append_message(&source_queue, msg)?;
+        if config.sync_write {
+            self.db
+                .flush()
+                .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        }
+        Ok(new_index)
+    }
+
+    async fn handle_delete_message_before(
+        &self,
+        queue_urn: &str,
+        index: MsgIndex,
+        _ctx: RPCContext,
+    ) -> std::result::Result<u64, RPCErrors> {
+        let config = self.get_queue_config(queue_urn)?;
+        let queue_urn = queue_urn.to_string();
+        let queue = self.clone();
+
+        let removed = task::spawn_blocking(move || queue.trim_before(&queue_urn, index))
+            .await
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))??;
+
+        if config.sync_write {
+            self.db
+                .flush()
+                .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        }
+
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use buckyos_api::msg_queue::{QueueConfig, SubPosition};
+    use tempfile::TempDir;
+
+    fn setup_queue() -> (TempDir, SledMsgQueue) {
+        let temp = TempDir::new().expect("create temp dir");
+        let queue = SledMsgQueue::new_in_dir(temp.path()).expect("create sled queue");
+        (temp, queue)
+    }
+
+    fn make_message(text: &str) -> Message {
+        Message::new(text.as_bytes().to_vec())
+    }
+
+    async fn push_messages(queue: &SledMsgQueue, queue_urn: &str, count: usize) {
+        for i in 1..=count {
+            let _ = queue
+                .handle_post_message(
+                    queue_urn,
+                    make_message(&format!("m{}", i)),
+                    RPCContext::default(),
+                )
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_msg_queue_end_to_end() -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let (_tmp, queue) = setup_queue();
+        let config = QueueConfig::default();
+        let queue_urn = queue
+            .handle_create_queue(
+                Some("inbox"),
+                "app",
+                "owner",
+                config.clone(),
+                RPCContext::default(),
+            )
+            .await?;
+        // duplicate creation should fail
+        assert!(
+            queue
+                .handle_create_queue(
+                    Some("inbox"),
+                    "app",
+                    "owner",
+                    config.clone(),
+                    RPCContext::default(),
+                )
+                .await
+                .is_err()
+        );
+
+        // update config and verify persisted
+        let mut new_cfg = config.clone();
+        new_cfg.sync_write = true;
+        queue
+            .handle_update_queue_config(&queue_urn, new_cfg.clone(), RPCContext::default())
+            .await?;
+        let stored = queue.get_queue_config(&queue_urn)?;
+        assert!(stored.sync_write);
+
+        // post messages
+        let idx1 = queue
+            .handle_post_message(&queue_urn, make_message("m1"), RPCContext::default())
+            .await?;
+        let idx2 = queue
+            .handle_post_message(&queue_urn, make_message("m2"), RPCContext::default())
+            .await?;
+        let idx3 = queue
+            .handle_post_message(&queue_urn, make_message("m3"), RPCContext::default())
+            .await?;
+        assert_eq!((idx1, idx2, idx3), (1, 2, 3));
+
+        // stats after post
+        let stats = queue
+            .handle_get_queue_stats(&queue_urn, RPCContext::default())
+            .await?;
+        assert_eq!(stats.message_count, 3);
+        assert_eq!(stats.first_index, 1);
+        assert_eq!(stats.last_index, 3);
+
+        // subscribe from earliest
+        let sub_id = queue
+            .handle_subscribe(
+                &queue_urn,
+                "user",
+                "app",
+                None,
+                SubPosition::Earliest,
+                RPCContext::default(),
+            )
+            .await?;
+        let msgs = queue
+            .handle_fetch_messages(&sub_id, 2, true, RPCContext::default())
+            .await?;
+        assert_eq!(msgs.len(), 2);
+        assert_eq!(msgs[0].index, 1);
+        assert_eq!(msgs[1].index, 2);
+
+        // fetch remaining without auto commit then ack
+        let msgs = queue
+            .handle_fetch_messages(&sub_id, 2, false, RPCContext::default())
+            .await?;
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(msgs[0].index, 3);
+        queue
+            .handle_commit_ack(&sub_id, 3, RPCContext::default())
+            .await?;
+        let msgs = queue
+            .handle_fetch_messages(&sub_id, 1, false, RPCContext::default())
+            .await?;
+        assert!(msgs.is_empty());
+        let history = queue
+            .handle_read_message(&queue_urn, 1, 2, RPCContext::default())
+            .await?;
+        assert_eq!(
+            history.iter().map(|m| m.index).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        let msgs = queue
+            .handle_fetch_messages(&sub_id, 1, false, RPCContext::default())
+            .await?;
+        assert!(msgs.is_empty());
+
+        // seek back to earliest and read again
+        queue
+            .handle_seek(&sub_id, SubPosition::Earliest, RPCContext::default())
+            .await?;
+        let msgs = queue
+            .handle_fetch_messages(&sub_id, 1, false, RPCContext::default())
+            .await?;
+        assert_eq!(msgs[0].index, 1);
+
+        // subscribe at latest should see nothing until new message
+        let sub_latest = queue
+            .handle_subscribe(
+                &queue_urn,
+                "user",
+                "app",
+                Some("latest".to_string()),
+                SubPosition::Latest,
+                RPCContext::default(),
+            )
+            .await?;
+        let msgs = queue
+            .handle_fetch_messages(&sub_latest, 1, false, RPCContext::default())
+            .await?;
+        assert!(msgs.is_empty());
+
+        // delete messages before index 3 (drops 1 and 2)
+        let removed = queue
+            .handle_delete_message_before(&queue_urn, 3, RPCContext::default())
+            .await?;
+        assert_eq!(removed, 2);
+        let stats = queue
+            .handle_get_queue_stats(&queue_urn, RPCContext::default())
+            .await?;
+        assert_eq!(stats.message_count, 1);
+        assert_eq!(stats.first_index, 3);
+        assert_eq!(stats.last_index, 3);
+
+        // unsubscribe both
+        queue
+            .handle_unsubscribe(&sub_id, RPCContext::default())
+            .await?;
+        queue
+            .handle_unsubscribe(&sub_latest, RPCContext::default())
+            .await?;
+        assert!(
+            queue
+                .handle_fetch_messages(&sub_id, 1, false, RPCContext::default())
+                .await
+                .is_err()
+        );
+
+        // delete queue
+        queue
+            .handle_delete_queue(&queue_urn, RPCContext::default())
+            .await?;
+        assert!(
+            queue
+                .handle_get_queue_stats(&queue_urn, RPCContext::default())
+                .await
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_multiple_subscribers_and_messages()
+    -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let (_tmp, queue) = setup_queue();
+        let queue_urn = queue
+            .handle_create_queue(
+                Some("multi"),
+                "app",
+                "owner",
+                QueueConfig::default(),
+                RPCContext::default(),
+            )
+            .await?;
+
+        // seed 10 messages
+        push_messages(&queue, &queue_urn, 10).await;
+
+        // subscribers at different positions
+        let sub_earliest = queue
+            .handle_subscribe(
+                &queue_urn,
+                "user",
+                "app",
+                Some("earliest".into()),
+                SubPosition::Earliest,
+                RPCContext::default(),
+            )
+            .await?;
+        let sub_latest = queue
+            .handle_subscribe(
+                &queue_urn,
+                "user",
+                "app",
+                Some("latest".into()),
+                SubPosition::Latest,
+                RPCContext::default(),
+            )
+            .await?;
+        let sub_mid = queue
+            .handle_subscribe(
+                &queue_urn,
+                "user",
+                "app",
+                Some("mid".into()),
+                SubPosition::At(5),
+                RPCContext::default(),
+            )
+            .await?;
+
+        // earliest reads first three and auto commits
+        let msgs = queue
+            .handle_fetch_messages(&sub_earliest, 3, true, RPCContext::default())
+            .await?;
+        assert_eq!(
+            msgs.iter().map(|m| m.index).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+
+        // mid reads two without commit, then commit to 6
+        let msgs = queue
+            .handle_fetch_messages(&sub_mid, 2, false, RPCContext::default())
+            .await?;
+        assert_eq!(msgs.iter().map(|m| m.index).collect::<Vec<_>>(), vec![5, 6]);
+        queue
+            .handle_commit_ack(&sub_mid, 6, RPCContext::default())
+            .await?;
+
+        // latest should see nothing until new messages arrive
+        let msgs = queue
+            .handle_fetch_messages(&sub_latest, 5, true, RPCContext::default())
+            .await?;
+        assert!(msgs.is_empty());
+
+        // add two more messages (indexes 11,12)
+        queue
+            .handle_post_message(&queue_urn, make_message("m11"), RPCContext::default())
+            .await?;
+        queue
+            .handle_post_message(&queue_urn, make_message("m12"), RPCContext::default())
+            .await?;
+
+        // latest now gets both new messages
+        let msgs = queue
+            .handle_fetch_messages(&sub_latest, 10, true, RPCContext::default())
+            .await?;
+        assert_eq!(
+            msgs.iter().map(|m| m.index).collect::<Vec<_>>(),
+            vec![11, 12]
+        );
+
+        // prune older messages (<6)
+        let removed = queue
+            .handle_delete_message_before(&queue_urn, 6, RPCContext::default())
+            .await?;
+        assert_eq!(removed, 5); // messages 1-5 removed
+        let stats = queue
+            .handle_get_queue_stats(&queue_urn, RPCContext::default())
+            .await?;
+        assert_eq!(stats.first_index, 6);
+        assert_eq!(stats.last_index, 12);
+        assert_eq!(stats.message_count, 7);
+
+        // earliest cursor was at 4 (after auto commit) and should now see from 6
+        let msgs = queue
+            .handle_fetch_messages(&sub_earliest, 3, true, RPCContext::default())
+            .await?;
+        assert_eq!(
+            msgs.iter().map(|m| m.index).collect::<Vec<_>>(),
+            vec![6, 7, 8]
+        );
 
-            Ok(removed_count)
-        })
-        .await
-        .map_err(|err| RPCErrors::ReasonError(err.to_string()))??;
+        // mid cursor at 7 after commit; fetch remaining
+        let msgs = queue
+            .handle_fetch_messages(&sub_mid, 10, true, RPCContext::default())
+            .await?;
+        assert_eq!(
+            msgs.iter().map(|m| m.index).collect::<Vec<_>>(),
+            vec![7, 8, 9, 10, 11, 12]
+        );
 
-        if config.sync_write {
-            self.db
-                .flush()
-                .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
-        }
+        // cleanup
+        queue
+            .handle_unsubscribe(&sub_earliest, RPCContext::default())
+            .await?;
+        queue
+            .handle_unsubscribe(&sub_latest, RPCContext::default())
+            .await?;
+        queue
+            .handle_unsubscribe(&sub_mid, RPCContext::default())
+            .await?;
+        queue
+            .handle_delete_queue(&queue_urn, RPCContext::default())
+            .await?;
 
-        Ok(removed)
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use buckyos_api::msg_queue::{QueueConfig, SubPosition};
-    use tempfile::TempDir;
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_path_queue_name_roundtrip() -> std::result::Result<(), Box<dyn std::error::Error>>
+    {
+        let (_tmp, queue) = setup_queue();
+        let queue_urn = "/jarvis.test.buckyos.io/sessions/tg:lzc_jarvis:5397330802/msg";
+        let sub_id = "/jarvis.test.buckyos.io/sessions/tg:lzc_jarvis:5397330802/msg_subscription";
 
-    fn setup_queue() -> (TempDir, SledMsgQueue) {
-        let temp = TempDir::new().expect("create temp dir");
-        let queue = SledMsgQueue::new_in_dir(temp.path()).expect("create sled queue");
-        (temp, queue)
-    }
+        let created = queue
+            .handle_create_queue(
+                Some(queue_urn),
+                "opendan",
+                "jarvis.test.buckyos.io",
+                QueueConfig::default(),
+                RPCContext::default(),
+            )
+            .await?;
+        assert_eq!(created, queue_urn);
 
-    fn make_message(text: &str) -> Message {
-        Message::new(text.as_bytes().to_vec())
-    }
+        queue
+            .handle_post_message(queue_urn, make_message("hello"), RPCContext::default())
+            .await?;
 
-    async fn push_messages(queue: &SledMsgQueue, queue_urn: &str, count: usize) {
-        for i in 1..=count {
-            let _ = queue
-                .handle_post_message(
-                    queue_urn,
-                    make_message(&format!("m{}", i)),
-                    RPCContext::default(),
-                )
-                .await
-                .unwrap();
-        }
+        queue
+            .handle_subscribe(
+                queue_urn,
+                "jarvis.test.buckyos.io",
+                "opendan",
+                Some(sub_id.to_string()),
+                SubPosition::Earliest,
+                RPCContext::default(),
+            )
+            .await?;
+
+        let messages = queue
+            .handle_fetch_messages(sub_id, 1, true, RPCContext::default())
+            .await?;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].payload, b"hello".to_vec());
+
+        Ok(())
     }
 
     #[tokio::test(flavor = "current_thread")]
-    async fn test_msg_queue_end_to_end() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    async fn test_nack_dead_letter_and_replay()
+    -> std::result::Result<(), Box<dyn std::error::Error>> {
         let (_tmp, queue) = setup_queue();
-        let config = QueueConfig::default();
-        let queue_urn = queue
+        let dlq_urn = queue
             .handle_create_queue(
-                Some("inbox"),
+                Some("inbox_dlq"),
                 "app",
                 "owner",
-                config.clone(),
+                QueueConfig::default(),
                 RPCContext::default(),
             )
             .await?;
-        // duplicate creation should fail
+        let mut config = QueueConfig {
+            max_delivery_attempts: Some(2),
+            dead_letter_queue: Some("missing_dlq".to_string()),
+            ..Default::default()
+        };
+        // dead letter queue must exist
         assert!(
             queue
                 .handle_create_queue(
@@ -792,320 +2261,567 @@ mod tests {
                 .await
                 .is_err()
         );
+        config.dead_letter_queue = Some(dlq_urn.clone());
+        let queue_urn = queue
+            .handle_create_queue(Some("inbox"), "app", "owner", config, RPCContext::default())
+            .await?;
+        push_messages(&queue, &queue_urn, 2).await;
+        let sub_id = queue
+            .handle_subscribe(
+                &queue_urn,
+                "owner",
+                "app",
+                None,
+                SubPosition::Earliest,
+                RPCContext::default(),
+            )
+            .await?;
 
-        // update config and verify persisted
-        let mut new_cfg = config.clone();
-        new_cfg.sync_write = true;
+        // nack with delay keeps order: nothing is delivered until the delay passes
+        let first = queue
+            .handle_fetch_messages(&sub_id, 1, false, RPCContext::default())
+            .await?;
+        assert_eq!(first[0].payload, b"m1".to_vec());
         queue
-            .handle_update_queue_config(&queue_urn, new_cfg.clone(), RPCContext::default())
+            .handle_nack(&sub_id, first[0].index, 60_000, RPCContext::default())
             .await?;
-        let stored = queue.get_queue_config(&queue_urn)?;
-        assert!(stored.sync_write);
+        assert!(
+            queue
+                .handle_fetch_messages(&sub_id, 1, false, RPCContext::default())
+                .await?
+                .is_empty()
+        );
 
-        // post messages
-        let idx1 = queue
-            .handle_post_message(&queue_urn, make_message("m1"), RPCContext::default())
+        // second delivery exhausts attempts, the nack moves m1 to the dead letter queue
+        queue
+            .handle_nack(&sub_id, first[0].index, 0, RPCContext::default())
             .await?;
-        let idx2 = queue
-            .handle_post_message(&queue_urn, make_message("m2"), RPCContext::default())
+        let second = queue
+            .handle_fetch_messages(&sub_id, 1, false, RPCContext::default())
             .await?;
-        let idx3 = queue
-            .handle_post_message(&queue_urn, make_message("m3"), RPCContext::default())
+        assert_eq!(second[0].index, first[0].index);
+        queue
+            .handle_nack(&sub_id, second[0].index, 0, RPCContext::default())
             .await?;
-        assert_eq!((idx1, idx2, idx3), (1, 2, 3));
-
-        // stats after post
-        let stats = queue
-            .handle_get_queue_stats(&queue_urn, RPCContext::default())
+        let next = queue
+            .handle_fetch_messages(&sub_id, 1, false, RPCContext::default())
             .await?;
-        assert_eq!(stats.message_count, 3);
-        assert_eq!(stats.first_index, 1);
-        assert_eq!(stats.last_index, 3);
+        assert_eq!(next[0].payload, b"m2".to_vec());
+
+        let dead = queue
+            .handle_fetch_messages(
+                &queue
+                    .handle_subscribe(
+                        &dlq_urn,
+                        "owner",
+                        "app",
+                        None,
+                        SubPosition::Earliest,
+                        RPCContext::default(),
+                    )
+                    .await?,
+                10,
+                true,
+                RPCContext::default(),
+            )
+            .await?;
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].payload, b"m1".to_vec());
+        assert_eq!(
+            dead[0].headers.get(DLQ_HEADER_SOURCE_QUEUE),
+            Some(&queue_urn)
+        );
+        assert_eq!(
+            dead[0].headers.get(DLQ_HEADER_ATTEMPTS).map(String::as_str),
+            Some("2")
+        );
 
-        // subscribe from earliest
-        let sub_id = queue
+        // another subscriber that already consumed m1 and m2
+        let other_sub = queue
             .handle_subscribe(
                 &queue_urn,
-                "user",
+                "owner",
                 "app",
                 None,
                 SubPosition::Earliest,
                 RPCContext::default(),
             )
             .await?;
-        let msgs = queue
-            .handle_fetch_messages(&sub_id, 2, true, RPCContext::default())
-            .await?;
-        assert_eq!(msgs.len(), 2);
-        assert_eq!(msgs[0].index, 1);
-        assert_eq!(msgs[1].index, 2);
+        assert_eq!(
+            queue
+                .handle_fetch_messages(&other_sub, 10, true, RPCContext::default())
+                .await?
+                .len(),
+            2
+        );
 
-        // fetch remaining without auto commit then ack
-        let msgs = queue
-            .handle_fetch_messages(&sub_id, 2, false, RPCContext::default())
+        // replay posts a fresh copy back to the source queue
+        let replayed = queue
+            .handle_replay_dead_letter(&dlq_urn, dead[0].index, RPCContext::default())
             .await?;
-        assert_eq!(msgs.len(), 1);
-        assert_eq!(msgs[0].index, 3);
+        let replayed_messages = queue
+            .handle_read_message(&queue_urn, replayed, 1, RPCContext::default())
+            .await?;
+        let msg = &replayed_messages[0];
+        assert_eq!(msg.index, replayed);
+        assert_eq!(msg.payload, b"m1".to_vec());
+        assert!(!msg.headers.contains_key(DLQ_HEADER_SOURCE_QUEUE));
+        assert_eq!(msg.headers.get(REPLAY_HEADER_SUB_ID), Some(&sub_id));
+
+        // only the subscription that dead-lettered m1 gets it again
+        assert!(
+            queue
+                .handle_fetch_messages(&other_sub, 10, true, RPCContext::default())
+                .await?
+                .is_empty()
+        );
         queue
-            .handle_commit_ack(&sub_id, 3, RPCContext::default())
+            .handle_commit_ack(&sub_id, next[0].index, RPCContext::default())
             .await?;
-        let msgs = queue
-            .handle_fetch_messages(&sub_id, 1, false, RPCContext::default())
+        let again = queue
+            .handle_fetch_messages(&sub_id, 10, true, RPCContext::default())
             .await?;
-        assert!(msgs.is_empty());
-        let history = queue
-            .handle_read_message(&queue_urn, 1, 2, RPCContext::default())
+        assert_eq!(again.len(), 1);
+        assert_eq!(again[0].index, replayed);
+        assert_eq!(again[0].payload, b"m1".to_vec());
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_fetch_messages_wait_wakes_on_post()
+    -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let (_tmp, queue) = setup_queue();
+        let queue_urn = queue
+            .handle_create_queue(
+                Some("inbox"),
+                "app",
+                "owner",
+                QueueConfig::default(),
+                RPCContext::default(),
+            )
             .await?;
-        assert_eq!(
-            history.iter().map(|m| m.index).collect::<Vec<_>>(),
-            vec![1, 2]
-        );
-        let msgs = queue
-            .handle_fetch_messages(&sub_id, 1, false, RPCContext::default())
+        let sub_id = queue
+            .handle_subscribe(
+                &queue_urn,
+                "owner",
+                "app",
+                None,
+                SubPosition::Latest,
+                RPCContext::default(),
+            )
             .await?;
-        assert!(msgs.is_empty());
 
-        // seek back to earliest and read again
-        queue
-            .handle_seek(&sub_id, SubPosition::Earliest, RPCContext::default())
+        // empty queue times out with no messages
+        let start = std::time::Instant::now();
+        let messages = queue
+            .handle_fetch_messages_wait(&sub_id, 1, true, 50, RPCContext::default())
             .await?;
-        let msgs = queue
-            .handle_fetch_messages(&sub_id, 1, false, RPCContext::default())
+        assert!(messages.is_empty());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        let poster = queue.clone();
+        let post_urn = queue_urn.clone();
+        let post_task = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            poster
+                .handle_post_message(&post_urn, make_message("wake"), RPCContext::default())
+                .await
+                .unwrap();
+        });
+        let start = std::time::Instant::now();
+        let messages = queue
+            .handle_fetch_messages_wait(&sub_id, 1, true, 10_000, RPCContext::default())
             .await?;
-        assert_eq!(msgs[0].index, 1);
+        post_task.await?;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].payload, b"wake".to_vec());
+        assert!(start.elapsed() < Duration::from_secs(5));
+        Ok(())
+    }
 
-        // subscribe at latest should see nothing until new message
-        let sub_latest = queue
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_compaction_respects_limits_and_unacked()
+    -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let (_tmp, queue) = setup_queue();
+        let config = QueueConfig {
+            max_messages: Some(3),
+            retention_seconds: Some(3600),
+            ..Default::default()
+        };
+        let queue_urn = queue
+            .handle_create_queue(
+                Some("inbox"),
+                "app",
+                "owner",
+                config.clone(),
+                RPCContext::default(),
+            )
+            .await?;
+        let mut expired = make_message("expired");
+        expired.created_at = SledMsgQueue::now_seconds() - 7200;
+        queue
+            .handle_post_message(&queue_urn, expired, RPCContext::default())
+            .await?;
+        push_messages(&queue, &queue_urn, 5).await;
+        let sub_id = queue
             .handle_subscribe(
                 &queue_urn,
-                "user",
+                "owner",
                 "app",
-                Some("latest".to_string()),
-                SubPosition::Latest,
+                None,
+                SubPosition::Earliest,
                 RPCContext::default(),
             )
             .await?;
-        let msgs = queue
-            .handle_fetch_messages(&sub_latest, 1, false, RPCContext::default())
+
+        // nothing acked yet, nothing can be dropped
+        assert_eq!(queue.compact_queue(&queue_urn)?, 0);
+
+        // the subscriber acked up to m2, only the acked prefix is trimmed
+        queue
+            .handle_commit_ack(&sub_id, 3, RPCContext::default())
+            .await?;
+        assert_eq!(queue.compact_queue(&queue_urn)?, 3);
+        let stats = queue
+            .handle_get_queue_stats(&queue_urn, RPCContext::default())
             .await?;
-        assert!(msgs.is_empty());
+        assert_eq!(stats.message_count, 3);
+        assert_eq!(stats.first_index, 4);
+        assert_eq!(stats.last_index, 6);
 
-        // delete messages before index 3 (drops 1 and 2)
-        let removed = queue
-            .handle_delete_message_before(&queue_urn, 3, RPCContext::default())
+        // within limits now, the expired message is already gone
+        assert_eq!(queue.compact_queue(&queue_urn)?, 0);
+
+        // drop_unacked trims by byte size and moves the lagging subscriber forward
+        let config = QueueConfig {
+            max_bytes: Some(2),
+            drop_unacked: true,
+            ..config
+        };
+        queue
+            .handle_update_queue_config(&queue_urn, config, RPCContext::default())
             .await?;
-        assert_eq!(removed, 2);
+        assert_eq!(queue.compact_queue(&queue_urn)?, 2);
         let stats = queue
             .handle_get_queue_stats(&queue_urn, RPCContext::default())
             .await?;
         assert_eq!(stats.message_count, 1);
-        assert_eq!(stats.first_index, 3);
-        assert_eq!(stats.last_index, 3);
+        assert_eq!(stats.first_index, 6);
+        let messages = queue
+            .handle_fetch_messages(&sub_id, 10, true, RPCContext::default())
+            .await?;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].payload, b"m5".to_vec());
+        Ok(())
+    }
 
-        // unsubscribe both
-        queue
-            .handle_unsubscribe(&sub_id, RPCContext::default())
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_compaction_skips_abandoned_group()
+    -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let (_tmp, queue) = setup_queue();
+        let config = QueueConfig {
+            max_messages: Some(2),
+            ..Default::default()
+        };
+        let queue_urn = queue
+            .handle_create_queue(Some("jobs"), "app", "owner", config, RPCContext::default())
+            .await?;
+        push_messages(&queue, &queue_urn, 5).await;
+        let sub_id = queue
+            .handle_subscribe_group(
+                &queue_urn,
+                "owner",
+                "app",
+                "workers",
+                None,
+                SubPosition::Earliest,
+                50,
+                RPCContext::default(),
+            )
             .await?;
         queue
-            .handle_unsubscribe(&sub_latest, RPCContext::default())
+            .handle_fetch_messages(&sub_id, 1, false, RPCContext::default())
             .await?;
-        assert!(
-            queue
-                .handle_fetch_messages(&sub_id, 1, false, RPCContext::default())
-                .await
-                .is_err()
-        );
 
-        // delete queue
+        // the only member holds the group back
+        assert_eq!(queue.compact_queue(&queue_urn)?, 0);
+
+        // after the last member left, the group no longer blocks retention
         queue
-            .handle_delete_queue(&queue_urn, RPCContext::default())
+            .handle_unsubscribe(&sub_id, RPCContext::default())
             .await?;
+        assert_eq!(queue.compact_queue(&queue_urn)?, 3);
+        let group_id = SledMsgQueue::group_id(&queue_urn, "workers");
+        assert_eq!(queue.load_group(&group_id)?.cursor, 4);
         assert!(
             queue
-                .handle_get_queue_stats(&queue_urn, RPCContext::default())
-                .await
-                .is_err()
+                .deliveries
+                .scan_prefix(SledMsgQueue::delivery_prefix(&group_id))
+                .next()
+                .is_none()
         );
-
         Ok(())
     }
 
     #[tokio::test(flavor = "current_thread")]
-    async fn test_multiple_subscribers_and_messages()
-    -> std::result::Result<(), Box<dyn std::error::Error>> {
+    async fn test_consumer_group_leases() -> std::result::Result<(), Box<dyn std::error::Error>> {
         let (_tmp, queue) = setup_queue();
         let queue_urn = queue
             .handle_create_queue(
-                Some("multi"),
+                Some("tasks"),
                 "app",
                 "owner",
                 QueueConfig::default(),
                 RPCContext::default(),
             )
             .await?;
+        push_messages(&queue, &queue_urn, 4).await;
+        let mut workers = Vec::new();
+        for _ in 0..2 {
+            let sub_id = queue
+                .handle_subscribe_group(
+                    &queue_urn,
+                    "owner",
+                    "app",
+                    "workers",
+                    None,
+                    SubPosition::Earliest,
+                    50,
+                    RPCContext::default(),
+                )
+                .await?;
+            workers.push(sub_id);
+        }
 
-        // seed 10 messages
-        push_messages(&queue, &queue_urn, 10).await;
-
-        // subscribers at different positions
-        let sub_earliest = queue
-            .handle_subscribe(
-                &queue_urn,
-                "user",
-                "app",
-                Some("earliest".into()),
-                SubPosition::Earliest,
-                RPCContext::default(),
-            )
-            .await?;
-        let sub_latest = queue
-            .handle_subscribe(
-                &queue_urn,
-                "user",
-                "app",
-                Some("latest".into()),
-                SubPosition::Latest,
-                RPCContext::default(),
-            )
+        // the two workers split the stream without overlap
+        let first = queue
+            .handle_fetch_messages(&workers[0], 2, false, RPCContext::default())
             .await?;
-        let sub_mid = queue
-            .handle_subscribe(
-                &queue_urn,
-                "user",
-                "app",
-                Some("mid".into()),
-                SubPosition::At(5),
-                RPCContext::default(),
-            )
+        let second = queue
+            .handle_fetch_messages(&workers[1], 10, false, RPCContext::default())
             .await?;
+        assert_eq!(first.len(), 2);
+        assert_eq!(second.len(), 2);
+        assert_eq!(first[0].payload, b"m1".to_vec());
+        assert_eq!(second[0].payload, b"m3".to_vec());
 
-        // earliest reads first three and auto commits
-        let msgs = queue
-            .handle_fetch_messages(&sub_earliest, 3, true, RPCContext::default())
-            .await?;
-        assert_eq!(
-            msgs.iter().map(|m| m.index).collect::<Vec<_>>(),
-            vec![1, 2, 3]
+        // a message leased by another worker can not be acked
+        assert!(
+            queue
+                .handle_commit_ack(&workers[1], first[0].index, RPCContext::default())
+                .await
+                .is_err()
         );
-
-        // mid reads two without commit, then commit to 6
-        let msgs = queue
-            .handle_fetch_messages(&sub_mid, 2, false, RPCContext::default())
-            .await?;
-        assert_eq!(msgs.iter().map(|m| m.index).collect::<Vec<_>>(), vec![5, 6]);
+        for msg in &second {
+            queue
+                .handle_commit_ack(&workers[1], msg.index, RPCContext::default())
+                .await?;
+        }
         queue
-            .handle_commit_ack(&sub_mid, 6, RPCContext::default())
+            .handle_commit_ack(&workers[0], first[0].index, RPCContext::default())
             .await?;
 
-        // latest should see nothing until new messages arrive
-        let msgs = queue
-            .handle_fetch_messages(&sub_latest, 5, true, RPCContext::default())
+        // m2 is never acked, after the visibility timeout another worker gets it
+        assert!(
+            queue
+                .handle_fetch_messages(&workers[1], 10, false, RPCContext::default())
+                .await?
+                .is_empty()
+        );
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        let retry = queue
+            .handle_fetch_messages(&workers[1], 10, false, RPCContext::default())
             .await?;
-        assert!(msgs.is_empty());
+        assert_eq!(retry.len(), 1);
+        assert_eq!(retry[0].index, first[1].index);
 
-        // add two more messages (indexes 11,12)
+        // unsubscribing releases the leases right away
         queue
-            .handle_post_message(&queue_urn, make_message("m11"), RPCContext::default())
+            .handle_unsubscribe(&workers[1], RPCContext::default())
             .await?;
-        queue
-            .handle_post_message(&queue_urn, make_message("m12"), RPCContext::default())
+        let released = queue
+            .handle_fetch_messages(&workers[0], 10, true, RPCContext::default())
             .await?;
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].index, first[1].index);
+        let group = queue.load_group(&SledMsgQueue::group_id(&queue_urn, "workers"))?;
+        assert_eq!(group.cursor, 5);
+        Ok(())
+    }
 
-        // latest now gets both new messages
-        let msgs = queue
-            .handle_fetch_messages(&sub_latest, 10, true, RPCContext::default())
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_group_seek_keeps_inflight_leases()
+    -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let (_tmp, queue) = setup_queue();
+        let queue_urn = queue
+            .handle_create_queue(
+                Some("tasks"),
+                "app",
+                "owner",
+                QueueConfig::default(),
+                RPCContext::default(),
+            )
             .await?;
-        assert_eq!(
-            msgs.iter().map(|m| m.index).collect::<Vec<_>>(),
-            vec![11, 12]
-        );
+        push_messages(&queue, &queue_urn, 3).await;
+        let mut workers = Vec::new();
+        for _ in 0..2 {
+            let sub_id = queue
+                .handle_subscribe_group(
+                    &queue_urn,
+                    "owner",
+                    "app",
+                    "workers",
+                    None,
+                    SubPosition::Earliest,
+                    60_000,
+                    RPCContext::default(),
+                )
+                .await?;
+            workers.push(sub_id);
+        }
 
-        // prune older messages (<6)
-        let removed = queue
-            .handle_delete_message_before(&queue_urn, 6, RPCContext::default())
-            .await?;
-        assert_eq!(removed, 5); // messages 1-5 removed
-        let stats = queue
-            .handle_get_queue_stats(&queue_urn, RPCContext::default())
+        let inflight = queue
+            .handle_fetch_messages(&workers[0], 1, false, RPCContext::default())
             .await?;
-        assert_eq!(stats.first_index, 6);
-        assert_eq!(stats.last_index, 12);
-        assert_eq!(stats.message_count, 7);
-
-        // earliest cursor was at 4 (after auto commit) and should now see from 6
-        let msgs = queue
-            .handle_fetch_messages(&sub_earliest, 3, true, RPCContext::default())
+        let done = queue
+            .handle_fetch_messages(&workers[1], 1, false, RPCContext::default())
             .await?;
-        assert_eq!(
-            msgs.iter().map(|m| m.index).collect::<Vec<_>>(),
-            vec![6, 7, 8]
-        );
-
-        // mid cursor at 7 after commit; fetch remaining
-        let msgs = queue
-            .handle_fetch_messages(&sub_mid, 10, true, RPCContext::default())
+        queue
+            .handle_commit_ack(&workers[1], done[0].index, RPCContext::default())
             .await?;
-        assert_eq!(
-            msgs.iter().map(|m| m.index).collect::<Vec<_>>(),
-            vec![7, 8, 9, 10, 11, 12]
-        );
 
-        // cleanup
+        // the acked message is replayed, the one still in flight stays with its owner
         queue
-            .handle_unsubscribe(&sub_earliest, RPCContext::default())
+            .handle_seek(&workers[1], SubPosition::Earliest, RPCContext::default())
             .await?;
+        let replayed = queue
+            .handle_fetch_messages(&workers[1], 10, false, RPCContext::default())
+            .await?;
+        let indexes: Vec<MsgIndex> = replayed.iter().map(|msg| msg.index).collect();
+        assert_eq!(indexes, vec![done[0].index, done[0].index + 1]);
         queue
-            .handle_unsubscribe(&sub_latest, RPCContext::default())
+            .handle_commit_ack(&workers[0], inflight[0].index, RPCContext::default())
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_commit_ack_never_moves_cursor_back()
+    -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let (_tmp, queue) = setup_queue();
+        let queue_urn = queue
+            .handle_create_queue(
+                Some("events"),
+                "app",
+                "owner",
+                QueueConfig::default(),
+                RPCContext::default(),
+            )
+            .await?;
+        push_messages(&queue, &queue_urn, 3).await;
+        let sub_id = queue
+            .handle_subscribe(
+                &queue_urn,
+                "owner",
+                "app",
+                None,
+                SubPosition::Earliest,
+                RPCContext::default(),
+            )
             .await?;
         queue
-            .handle_unsubscribe(&sub_mid, RPCContext::default())
+            .handle_commit_ack(&sub_id, 3, RPCContext::default())
             .await?;
         queue
-            .handle_delete_queue(&queue_urn, RPCContext::default())
+            .handle_commit_ack(&sub_id, 1, RPCContext::default())
             .await?;
-
+        assert_eq!(queue.load_subscription(&sub_id)?.cursor, 4);
         Ok(())
     }
 
     #[tokio::test(flavor = "current_thread")]
-    async fn test_path_queue_name_roundtrip() -> std::result::Result<(), Box<dyn std::error::Error>>
+    async fn test_subscription_header_filter() -> std::result::Result<(), Box<dyn std::error::Error>>
     {
         let (_tmp, queue) = setup_queue();
-        let queue_urn = "/jarvis.test.buckyos.io/sessions/tg:lzc_jarvis:5397330802/msg";
-        let sub_id = "/jarvis.test.buckyos.io/sessions/tg:lzc_jarvis:5397330802/msg_subscription";
-
-        let created = queue
+        let queue_urn = queue
             .handle_create_queue(
-                Some(queue_urn),
-                "opendan",
-                "jarvis.test.buckyos.io",
+                Some("inbox"),
+                "app",
+                "owner",
                 QueueConfig::default(),
                 RPCContext::default(),
             )
             .await?;
-        assert_eq!(created, queue_urn);
-
+        for (text, kind, session) in [
+            ("a", "chat", "tg:1"),
+            ("b", "event", "tg:1"),
+            ("c", "chat", "web:2"),
+            ("d", "notify", "tg:3"),
+        ] {
+            let mut msg = make_message(text);
+            msg.headers.insert("kind".to_string(), kind.to_string());
+            msg.headers
+                .insert("session".to_string(), session.to_string());
+            queue
+                .handle_post_message(&queue_urn, msg, RPCContext::default())
+                .await?;
+        }
         queue
-            .handle_post_message(queue_urn, make_message("hello"), RPCContext::default())
+            .handle_post_message(
+                &queue_urn,
+                make_message("no-headers"),
+                RPCContext::default(),
+            )
             .await?;
 
-        queue
-            .handle_subscribe(
-                queue_urn,
-                "jarvis.test.buckyos.io",
-                "opendan",
-                Some(sub_id.to_string()),
+        let filter = HeaderFilter::new()
+            .one_of("kind", &["chat", "notify"])
+            .prefix("session", "tg:");
+        let sub_id = queue
+            .handle_subscribe_filtered(
+                &queue_urn,
+                "owner",
+                "app",
+                None,
                 SubPosition::Earliest,
+                filter,
                 RPCContext::default(),
             )
             .await?;
+        let messages = queue
+            .handle_fetch_messages(&sub_id, 10, false, RPCContext::default())
+            .await?;
+        let payloads: Vec<&[u8]> = messages.iter().map(|msg| msg.payload.as_slice()).collect();
+        assert_eq!(payloads, vec![b"a".as_slice(), b"d".as_slice()]);
+
+        queue
+            .handle_commit_ack(&sub_id, messages[1].index, RPCContext::default())
+            .await?;
+        assert!(
+            queue
+                .handle_fetch_messages(&sub_id, 10, false, RPCContext::default())
+                .await?
+                .is_empty()
+        );
+        // the trailing unmatched message does not hold the cursor back
+        assert_eq!(queue.load_subscription(&sub_id)?.cursor, 6);
 
+        let exact = queue
+            .handle_subscribe_filtered(
+                &queue_urn,
+                "owner",
+                "app",
+                None,
+                SubPosition::Earliest,
+                HeaderFilter::new().eq("kind", "event"),
+                RPCContext::default(),
+            )
+            .await?;
         let messages = queue
-            .handle_fetch_messages(sub_id, 1, true, RPCContext::default())
+            .handle_fetch_messages(&exact, 10, true, RPCContext::default())
             .await?;
         assert_eq!(messages.len(), 1);
-        assert_eq!(messages[0].payload, b"hello".to_vec());
-
+        assert_eq!(messages[0].payload, b"b".to_vec());
         Ok(())
     }
 }
@@ -0,0 +1,327 @@
+// dry run: 在上一次调度快照的副本上应用假设的变更(mutation),再完整执行一次 schedule,
+// 得到调度器将会产生的 SchedulerAction 列表及其原因。整个过程是纯函数,不会写入 system_config。
+use anyhow::Result;
+use buckyos_api::{SchedulerDryRunMutation, SchedulerDryRunResult, SchedulerPlannedAction};
+use std::collections::HashMap;
+
+use crate::scheduler::*;
+
+const MB: u64 = 1024 * 1024;
+
+fn apply_mutation(
+    planned: &mut NodeScheduler,
+    mutation: &SchedulerDryRunMutation,
+) -> Result<String> {
+    match mutation {
+        SchedulerDryRunMutation::AddNode {
+            node_id,
+            node_type,
+            state,
+            labels,
+            network_zone,
+            support_container,
+            cpu_mhz,
+            memory,
+            gpu_memory,
+            gpu_tflops,
+        } => {
+            if planned.nodes.contains_key(node_id) {
+                return Err(anyhow::anyhow!("node {} already exists", node_id));
+            }
+            let node = NodeItem {
+                id: node_id.clone(),
+                node_type: NodeType::from(node_type.clone().unwrap_or_else(|| "ood".to_string())),
+                labels: labels.clone(),
+                network_zone: network_zone.clone().unwrap_or_default(),
+                support_container: *support_container,
+                state: NodeState::from(state.clone().unwrap_or_else(|| "New".to_string())),
+                available_cpu_mhz: *cpu_mhz,
+                total_cpu_mhz: *cpu_mhz,
+                available_memory: *memory,
+                total_memory: *memory,
+                available_gpu_memory: *gpu_memory,
+                total_gpu_memory: *gpu_memory,
+                gpu_tflops: *gpu_tflops,
+                resources: HashMap::new(),
+                op_tasks: vec![],
+            };
+            let note = format!(
+                "add node {} ({:?}, {}) with {} MHz cpu, {} MB memory",
+                node.id,
+                node.node_type,
+                node.state,
+                node.total_cpu_mhz,
+                node.total_memory / MB
+            );
+            planned.add_node(node);
+            Ok(note)
+        }
+        SchedulerDryRunMutation::RemoveNode { node_id } => {
+            let node = planned
+                .nodes
+                .get_mut(node_id)
+                .ok_or_else(|| anyhow::anyhow!("node {} not found", node_id))?;
+            let note = format!(
+                "node {}: {} -> {}",
+                node_id,
+                node.state,
+                NodeState::Removing
+            );
+            node.state = NodeState::Removing;
+            Ok(note)
+        }
+        SchedulerDryRunMutation::SetSpecResources {
+            spec_id,
+            required_cpu_mhz,
+            required_memory,
+            required_gpu_tflops,
+            required_gpu_mem,
+        } => {
+            let spec = get_spec_mut(planned, spec_id)?;
+            if let Some(cpu_mhz) = required_cpu_mhz {
+                spec.required_cpu_mhz = *cpu_mhz;
+            }
+            if let Some(memory) = required_memory {
+                spec.required_memory = *memory;
+            }
+            if let Some(gpu_tflops) = required_gpu_tflops {
+                spec.required_gpu_tflops = *gpu_tflops;
+            }
+            if let Some(gpu_mem) = required_gpu_mem {
+                spec.required_gpu_mem = *gpu_mem;
+            }
+            Ok(format!(
+                "spec {} requires {} MHz cpu, {} MB memory, {} tflops gpu, {} MB gpu memory",
+                spec_id,
+                spec.required_cpu_mhz,
+                spec.required_memory / MB,
+                spec.required_gpu_tflops,
+                spec.required_gpu_mem / MB
+            ))
+        }
+        SchedulerDryRunMutation::SetSpecAffinity {
+            spec_id,
+            node_affinity,
+            network_affinity,
+            replica_spread,
+        } => {
+            let spec = get_spec_mut(planned, spec_id)?;
+            spec.node_affinity = node_affinity.clone();
+            spec.network_affinity = network_affinity.clone();
+            spec.replica_spread = replica_spread.clone();
+            Ok(format!(
+                "spec {} node_affinity={:?} network_affinity={:?} replica_spread={:?}",
+                spec_id, spec.node_affinity, spec.network_affinity, spec.replica_spread
+            ))
+        }
+        SchedulerDryRunMutation::SetBestInstanceCount { spec_id, count } => {
+            let spec = get_spec_mut(planned, spec_id)?;
+            let note = format!(
+                "spec {} best_instance_count: {} -> {}",
+                spec_id, spec.best_instance_count, count
+            );
+            spec.best_instance_count = *count;
+            Ok(note)
+        }
+    }
+}
+
+fn get_spec_mut<'a>(planned: &'a mut NodeScheduler, spec_id: &str) -> Result<&'a mut ServiceSpec> {
+    planned
+        .specs
+        .get_mut(spec_id)
+        .ok_or_else(|| anyhow::anyhow!("spec {} not found", spec_id))
+}
+
+// mutated 是应用了 mutation、但还没有执行 schedule 的状态,原因描述都基于它
+fn explain_action(action: &SchedulerAction, mutated: &NodeScheduler) -> SchedulerPlannedAction {
+    let (action_name, target, reason) = match action {
+        SchedulerAction::ChangeNodeStatus(node_id, state) => {
+            let current = mutated
+                .nodes
+                .get(node_id)
+                .map(|node| node.state.to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+            (
+                "ChangeNodeStatus",
+                node_id.clone(),
+                format!("node {} is {}, move it to {}", node_id, current, state),
+            )
+        }
+        SchedulerAction::UpdateOPTask(node_id, op_task) => (
+            "UpdateOPTask",
+            node_id.clone(),
+            format!(
+                "op task {} on node {} is {}: {:?}",
+                op_task.id, node_id, op_task.status, op_task.body
+            ),
+        ),
+        SchedulerAction::RemoveOPTask(node_id, op_task_id) => (
+            "RemoveOPTask",
+            node_id.clone(),
+            format!(
+                "op task {} on node {} is finished and expired",
+                op_task_id, node_id
+            ),
+        ),
+        SchedulerAction::CreateOPTask(node_id, op_task) => (
+            "CreateOPTask",
+            node_id.clone(),
+            format!(
+                "create {:?} op task {} on node {}",
+                op_task.body, op_task.id, node_id
+            ),
+        ),
+        SchedulerAction::ChangeServiceStatus(spec_id, state) => {
+            let current = mutated
+                .specs
+                .get(spec_id)
+                .map(|spec| spec.state.to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+            (
+                "ChangeServiceStatus",
+                spec_id.clone(),
+                format!("spec {} is {}, mark it {}", spec_id, current, state),
+            )
+        }
+        SchedulerAction::InstanceReplica(instance) => {
+            let existing_count = mutated
+                .replica_instances
+                .values()
+                .filter(|item| {
+                    item.spec_id == instance.spec_id && item.state != InstanceState::Deleted
+                })
+                .count();
+            let desired_count = mutated
+                .specs
+                .get(&instance.spec_id)
+                .map(|spec| spec.best_instance_count)
+                .unwrap_or(0);
+            let node_desc = mutated
+                .nodes
+                .get(&instance.node_id)
+                .map(|node| {
+                    format!(
+                        "{} MHz cpu, {} MB memory available",
+                        node.available_cpu_mhz,
+                        node.available_memory / MB
+                    )
+                })
+                .unwrap_or_else(|| "unknown node".to_string());
+            let draining_node = draining_node_of_spec(mutated, &instance.spec_id);
+            let reason = match draining_node {
+                Some(draining_node) => format!(
+                    "spec {} has an instance on removing node {}, place replacement on node {} ({})",
+                    instance.spec_id, draining_node, instance.node_id, node_desc
+                ),
+                None if existing_count >= desired_count as usize => format!(
+                    "spec {} rebalances to a better scored node, place on node {} ({})",
+                    instance.spec_id, instance.node_id, node_desc
+                ),
+                None => format!(
+                    "spec {} wants {} instance(s) but has {}, place on node {} ({})",
+                    instance.spec_id, desired_count, existing_count, instance.node_id, node_desc
+                ),
+            };
+            ("InstanceReplica", instance.instance_id.clone(), reason)
+        }
+        SchedulerAction::UpdateInstance(instance_id, instance) => (
+            "UpdateInstance",
+            instance_id.clone(),
+            format!(
+                "instance {} on node {} converges to {}",
+                instance_id,
+                instance.node_id,
+                instance.state.to_string()
+            ),
+        ),
+        SchedulerAction::RemoveInstance(spec_id, instance_id, node_id) => {
+            let spec_state = mutated
+                .specs
+                .get(spec_id)
+                .map(|spec| spec.state.to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+            let is_draining = mutated
+                .nodes
+                .get(node_id)
+                .is_some_and(|node| node.state == NodeState::Removing);
+            let rebalance_move = mutated.rebalance_moves.get(spec_id);
+            let reason = if is_draining {
+                format!(
+                    "node {} is removing, its replacement of {} is running, remove instance {}",
+                    node_id, spec_id, instance_id
+                )
+            } else if let Some(rebalance_move) =
+                rebalance_move.filter(|m| m.from_instance_id == *instance_id)
+            {
+                format!(
+                    "spec {} rebalances, replacement {} is running, move instance {} off node {}",
+                    spec_id, rebalance_move.to_instance_id, instance_id, node_id
+                )
+            } else if rebalance_move.is_some_and(|m| m.to_instance_id == *instance_id) {
+                format!(
+                    "spec {} rebalance replacement {} on node {} is not ready in time, roll it back",
+                    spec_id, instance_id, node_id
+                )
+            } else {
+                format!(
+                    "spec {} is {}, remove instance {} from node {}",
+                    spec_id, spec_state, instance_id, node_id
+                )
+            };
+            ("RemoveInstance", instance_id.clone(), reason)
+        }
+        SchedulerAction::UpdateServiceInfo(spec_id, service_info) => {
+            let instance_count = match service_info {
+                ServiceInfo::SingleInstance(_) => 1,
+                ServiceInfo::RandomCluster(cluster) => cluster.len(),
+            };
+            (
+                "UpdateServiceInfo",
+                spec_id.clone(),
+                format!(
+                    "service info of {} changes, publish {} alive instance(s)",
+                    spec_id, instance_count
+                ),
+            )
+        }
+    };
+    SchedulerPlannedAction {
+        action: action_name.to_string(),
+        target,
+        reason,
+    }
+}
+
+fn draining_node_of_spec<'a>(mutated: &'a NodeScheduler, spec_id: &str) -> Option<&'a str> {
+    mutated
+        .replica_instances
+        .values()
+        .filter(|instance| instance.spec_id == spec_id && instance.state != InstanceState::Deleted)
+        .find(|instance| {
+            mutated
+                .nodes
+                .get(&instance.node_id)
+                .is_some_and(|node| node.state == NodeState::Removing)
+        })
+        .map(|instance| instance.node_id.as_str())
+}
+
+pub fn dry_run_schedule(
+    snapshot: &NodeScheduler,
+    mutations: &[SchedulerDryRunMutation],
+) -> Result<SchedulerDryRunResult> {
+    let mut mutated = snapshot.clone();
+    let mut notes = Vec::new();
+    for mutation in mutations {
+        notes.push(apply_mutation(&mut mutated, mutation)?);
+    }
+
+    let mut planned = mutated.clone();
+    let actions = planned.schedule(Some(snapshot))?;
+    let actions = actions
+        .iter()
+        .map(|action| explain_action(action, &mutated))
+        .collect();
+    Ok(SchedulerDryRunResult { actions, notes })
+}
@@ -1,5 +1,6 @@
 #![allow(unused_mut, unused, dead_code)]
 mod app;
+mod dry_run;
 mod scheduler;
 mod scheduler_server;
 mod service;
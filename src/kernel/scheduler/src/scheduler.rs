@@ -18,14 +18,22 @@ NodeScheduler — 纯函数式、平台无关的服务调度器
 - `ServiceSpec`  — 被调度对象，描述一个服务的目标状态和资源需求
     - `ServiceSpecType`: Kernel（内核服务, owner=root）| Service（系统服务）| App（用户应用, 有 owner_user_id）
     - `ServiceSpecState`: New → Deployed | DeployFailed | Abnormal | Disable | Deleted
+    - `node_affinity`: 标签选择表达式（in / not_in / exists / does_not_exist），
+      required 必须全部满足，preferred 按 weight 加分
+    - `replica_spread`: 副本按 topology_key 标签划分的拓扑域分散，required 时同域最多一个副本，
+      否则同域已有副本时降低打分
+    - node_affinity / replica_spread 来自 spec 的 install_config
 - `NodeItem`      — 资源载体，描述一个物理/逻辑节点的能力和状态
     - `NodeType`: OOD | Server | Desktop | Mobile | Sensor | IoTController
     - `NodeState`: New → Prepare → Ready → Abnormal | Unavailable | Removing → Deleted
-    - 包含 CPU/Memory/GPU 资源、labels（用于亲和性匹配）、network_zone
+    - 包含 CPU/Memory/GPU 资源、labels（"key=value" 或 "key"，用于亲和性匹配）、network_zone
+    - labels 合并自 devices/{node_id}/info 上报的 labels 和运维写入的 system/scheduler/nodes/{node_id}/labels
+    - 亲和性匹配时额外提供 node_id / node_type / network_zone 隐含标签（NodeItem::label_map）
 - `UserItem`      — 用户信息（userid, user_type: Admin/User/Limited, 可选 res_pool_id）
 - `ReplicaInstance` — ServiceSpec 在某个 Node 上的运行实例
     - `InstanceState`: Prepare | Running | Suspended | Deleted
-    - 包含 last_update_time 用于存活检测（INSTANCE_ALIVE_TIME = 90s）
+    - 存活检测优先使用上报 key 的 sys_config lease（lease_expire_time），
+      无 lease 的旧式上报才退回 last_update_time + INSTANCE_ALIVE_TIME（90s）
 - `ServiceInfo`   — 调度器基于存活的 Instance 计算出的服务访问信息
     - SingleInstance（单实例）| RandomCluster（多实例集群，带权重）
 - `OPTask`        — 运维任务定义（将在未来版本中被 Function Instance 取代，
@@ -34,7 +42,7 @@ NodeScheduler — 纯函数式、平台无关的服务调度器
 
 ## Schedule Loop（已实现，见 system_config_agent.rs::schedule_loop）
 
-每 5 秒执行一轮调度：
+每 5 秒执行一轮调度（也可以通过 scheduler 的 trigger_schedule RPC 立即触发一轮）：
 1. 从 system_config 拉取全量系统状态（dump_configs_for_scheduler）
 2. 通过 create_scheduler_by_system_config() 构造 NodeScheduler 实例
 3. 从 system_config 加载上一次调度快照（system/scheduler/snapshot）
@@ -51,22 +59,41 @@ NodeScheduler — 纯函数式、平台无关的服务调度器
 ## schedule() 四阶段流程（已实现）
 
 Step1. resort_nodes() — 节点状态审查
+    - 检查 op task 超时（check_node_op_tasks），超时的任务标记为 Failed；已结束的任务过了保留期后删除
     - New 节点 → Prepare（等待外部初始化完成后标记为 Ready）
-    - Removing 节点 → Deleted
-    - 小系统优化（节点数 ≤ 7）：如果 Step1 产生了动作，跳过 Step2，降低复杂度
+    - 小系统优化（节点数 ≤ 7）：如果 resort_nodes 产生了动作，跳过后续步骤，降低复杂度
+    - drain_removing_nodes() — 排空 Removing 节点（DrainNode op task 记录进度）：
+        1. Removing 节点不再参与调度（节点过滤只接受 Ready）
+        2. 为节点上的每个实例，在其他 Ready 节点上放置替换实例（同样经过过滤+打分，遵守资源和亲和性）
+        3. 替换实例上报 Running 且存活后，才移除旧实例
+        4. 节点上没有实例后，任务 Done，节点 → Deleted
+        5. 超过 DRAIN_NODE_TIMEOUT_SEC 仍未排空：任务 Failed，节点 → Abnormal，剩余实例保留
+        6. 运维通过 cancel_op_task 取消排空任务：节点回到 Ready，已创建的替换实例保留
+        7. 最近一次排空已结束但节点仍是 Removing：按排空结果恢复节点状态，不重复创建排空任务
 
 Step2. schedule_spec_change() — ServiceSpec 实例化/反实例化（仅在 spec 发生变化时触发）
     - New → 过滤+打分选择最优节点，创建 ReplicaInstance，标记为 Deployed
     - Disable → 保留 node config item，仅把实例 target_state 收敛到 Stopped
     - Deleted → 对 App 保留 node config item，并把实例收敛到 `Stopped/Deleted`
     - 节点过滤（filter_node_for_instance）：检查节点状态(Ready)、类型(OOD/Server)、
-      容器支持、CPU/Memory/GPU 资源、node_affinity 标签匹配
-    - 节点打分（score_node）：资源充足度 + 负载均衡 + 网络亲和性
-
-Step3. （TODO）优化 instance 的资源使用
+      容器支持、CPU/Memory/GPU 资源、node_affinity 的 required 表达式
+    - 节点打分（score_node）：资源充足度 + 负载均衡 + 网络亲和性 + node_affinity 的 preferred 规则
+    - 选择节点时逐个考虑 replica_spread：required 时跳过已有副本的拓扑域，否则扣分
+
+Step3. rebalance_instances() — 优化 instance 的资源使用（默认关闭，见 RebalanceConfig）
+    - 配置来自 system/scheduler/rebalance，只在本轮前面的步骤没有产生实例变动时执行
+    - 用 score_node 比较实例当前节点（按释放该实例后计算）与其他候选节点，
+      得分提升超过 min_score_gain 才迁移，例如新加入的空闲节点、严重过载的节点
+    - 限制抖动：每轮最多 max_moves_per_round 次迁移，同一 spec 迁移后进入 spec_cooldown_secs 冷却，
+      冷却时间记录在调度快照的 spec_rebalance_times 中
+    - 迁移是 make-before-break 的：本轮只放置新实例，进行中的迁移记录在快照的 rebalance_moves 中，
+      新实例就绪（ReplicaInstance::is_ready，app 实例没有存活上报，Running 即就绪）后才移除旧实例；超过 REBALANCE_MOVE_TIMEOUT_SEC 仍未就绪则移除新实例，旧实例保留
 
 Step4. calc_service_infos() — 基于存活的 Instance 计算 ServiceInfo
-    - 只有 Running 状态且 last_update_time 在 INSTANCE_ALIVE_TIME 内的 Instance 才计入
+    - 只有 Running 状态且存活的 Instance 才计入（ReplicaInstance::is_alive）：
+      带 lease 的上报只要 key 还在就算存活：lease 到期由 sys_config 按自己的时钟判断并删除上报 key，
+      调度器不用本地时钟比较 lease_expire_time；
+      无 lease 的上报仍按 last_update_time 在 INSTANCE_ALIVE_TIME 内判断
     - 启动期调度器会注入 bootstrap 假上报（创建 Instance 时设置当前时间戳），
       避免内核服务之间的启动依赖环
     - fake service_info 也算一次成功刷新；同一 spec 两次刷新至少间隔 30 秒
@@ -75,12 +102,15 @@ Step4. calc_service_infos() — 基于存活的 Instance 计算 ServiceInfo
 ## 执行器层（system_config_agent.rs + service.rs，非本文件）
 
 SchedulerAction 的执行由外部模块负责：
-- ChangeNodeStatus     → 更新 nodes/{node_id}/config 中的 state
+- ChangeNodeStatus     → 写入 system/scheduler/nodes/{node_id}/state，加载时覆盖 devices/{node_id}/info 中的 state
+    （nodes/{node_id}/config 中的 state 是 node_daemon 的运行状态，调度器不写入）
 - ChangeServiceStatus  → 更新 services/{spec_id}/spec 或 users/.../apps/.../spec 中的 state
 - InstanceReplica      → 写入 nodes/{node_id}/config 中的 kernel 或 app 配置
 - RemoveInstance       → 对 App 保留 node config item，并把实例状态收敛到 `Stopped/Deleted`
 - UpdateServiceInfo    → 更新 services/{spec_id}/info，供其他服务发现使用
-- CreateOPTask         → 未来版本将重构为 Function Instance 调度
+- CreateOPTask / UpdateOPTask → 写入 system/scheduler/op_tasks/{node_id}/{task_id}，
+    下一轮调度时加载到 NodeItem.op_tasks（未来版本将重构为 Function Instance 调度）
+- RemoveOPTask         → 删除已结束且超过 FINISHED_OP_TASK_RETENTION_SEC 的 op task key
     （详见 doc/arch/使用function_instance实现分布式调度器.md）
 - UpdateInstance       → （TODO: unimplemented for service type）
 
@@ -92,6 +122,12 @@ SchedulerAction 的执行由外部模块负责：
     - 单实例本机 → 直接 forward 到 127.0.0.1:{port}
     - 其他情况 → 通过 buckyos-select 路由
 
+## Dry Run（dry_run.rs）
+
+scheduler 的 dry_run RPC（buckycli scheduler --dry_run）在上一次调度快照的副本上应用假设的变更
+（增加/移除 node、修改 spec 的资源需求、亲和性或 best_instance_count），以原快照作为 last_snapshot
+再执行一次 schedule()，返回将会产生的 SchedulerAction 及其原因，不写入 system_config。
+
 ## 工作原则
 
 - 最小改动原则：尽量不改动已有的 Instance（可以增加，少删除或修改）
@@ -105,7 +141,7 @@ SchedulerAction 的执行由外部模块负责：
 以下功能在早期设计文档中提及，但尚未在代码中实现：
 - 系统级管理（重启/暂停/启动/关闭/备份/恢复）
 - 完整的用户管理（删除/停用/启用用户及其服务）
-- Node 日常体检、维护（排空/暂停）、替换、区域维护
+- Node 日常体检、维护（暂停）、替换、区域维护
 - ServiceSpec Disable 状态的处理逻辑
 - Instance 动态资源调整、优先级管理、自动错误隔离
 - 运维 event 管理（异步决策）
@@ -127,7 +163,10 @@ fun_instance 将调度原语从命令式的"在某节点执行某脚本"降级
 */
 #[warn(unused, unused_mut, dead_code)]
 use anyhow::Result;
-use buckyos_api::{ServiceInstanceState, ServiceState, BASE_APP_PORT};
+use buckyos_api::{
+    match_label_selectors, NodeAffinity, ReplicaSpread, ServiceInstanceState, ServiceState,
+    BASE_APP_PORT,
+};
 use buckyos_kit::buckyos_get_unix_timestamp;
 use log::*;
 use serde::{Deserialize, Serialize};
@@ -137,10 +176,19 @@ use std::hash::Hash;
 
 const SMALL_SYSTEM_NODE_COUNT: usize = 7;
 // INSTANCE_ALIVE_TIME 不是“请求级”可用性 SLA，而是调度器层面的存活证明窗口。
+// 只用于没有 sys_config lease 的上报（旧式上报和 bootstrap 假上报）。
 // 调度器只关心“实例第一次被判定掉线”的时刻，因此允许真实访问失败与被宣告下线之间存在误差。
 // 启动期调度器也会主动构造一次“假上报”来打破内核服务之间的启动依赖环。
 const INSTANCE_ALIVE_TIME: u64 = 90;
 const SERVICE_INFO_REFRESH_INTERVAL: u64 = 30;
+// 软性副本分散约束下，同一拓扑域每多一个副本扣除的分数
+const REPLICA_SPREAD_PENALTY: f64 = 30.0;
+// 排空一个 Removing 节点的最长时间，超时后排空任务失败，节点转为 Abnormal 等待运维处理
+const DRAIN_NODE_TIMEOUT_SEC: u64 = 30 * 60;
+// 已结束（Done/Failed/Canceled）的 op task 保留的时间，过期后从 system_config 中清理
+const FINISHED_OP_TASK_RETENTION_SEC: u64 = 24 * 3600;
+// 再平衡的新实例在这个时间内没有 Running 且存活，放弃迁移并移除新实例，旧实例保留
+const REBALANCE_MOVE_TIMEOUT_SEC: u64 = 10 * 60;
 
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub enum ServiceSpecType {
@@ -317,14 +365,22 @@ pub struct ServiceSpec {
     pub required_gpu_tflops: f32,
     pub required_gpu_mem: u64,
     // 亲和性规则
-    pub node_affinity: Option<String>,
+    pub node_affinity: Option<NodeAffinity>,
     pub network_affinity: Option<String>,
+    // 副本反亲和/分散约束
+    #[serde(default)]
+    pub replica_spread: Option<ReplicaSpread>,
 
     pub service_ports_config: HashMap<String, u16>,
 }
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub enum OPTaskBody {
     NodeInitBaseService,
+    // 排空 Removing 节点，记录迁移进度
+    DrainNode {
+        total_instances: u32,
+        drained_instances: u32,
+    },
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -333,6 +389,20 @@ pub enum OPTaskState {
     Running,
     Done,
     Failed,
+    Canceled, //运维通过scheduler rpc取消
+}
+
+impl fmt::Display for OPTaskState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match self {
+            OPTaskState::New => "New",
+            OPTaskState::Running => "Running",
+            OPTaskState::Done => "Done",
+            OPTaskState::Failed => "Failed",
+            OPTaskState::Canceled => "Canceled",
+        };
+        write!(f, "{value}")
+    }
 }
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct OPTask {
@@ -346,6 +416,12 @@ pub struct OPTask {
     pub start_time: u64,
 }
 
+impl OPTask {
+    pub fn is_pending(&self) -> bool {
+        matches!(self.status, OPTaskState::New | OPTaskState::Running)
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct NodeResource {
     pub total_capacity: u64,
@@ -363,6 +439,21 @@ pub enum NodeType {
     UnknownClient(String),
 }
 
+impl NodeType {
+    // From<String> 的逆映射，用作 node_type 隐含标签
+    pub fn to_label(&self) -> String {
+        match self {
+            NodeType::OOD => "ood".to_string(),
+            NodeType::Server => "server".to_string(),
+            NodeType::Desktop => "desktop".to_string(),
+            NodeType::Mobile => "mobile".to_string(),
+            NodeType::Sensor => "sensor".to_string(),
+            NodeType::IoTController => "controller".to_string(),
+            NodeType::UnknownClient(node_type) => node_type.clone(),
+        }
+    }
+}
+
 impl From<String> for NodeType {
     fn from(s: String) -> Self {
         match s.as_str() {
@@ -402,6 +493,28 @@ pub struct NodeItem {
     pub op_tasks: Vec<OPTask>,
 }
 
+impl NodeItem {
+    // 亲和性匹配使用的标签："key=value" 拆分为键值，只有 "key" 时值为空字符串，
+    // 另外附带 node_id / node_type / network_zone 三个隐含标签
+    pub fn label_map(&self) -> HashMap<String, String> {
+        let mut labels = HashMap::new();
+        for label in self.labels.iter() {
+            match label.split_once('=') {
+                Some((key, value)) => {
+                    labels.insert(key.trim().to_string(), value.trim().to_string());
+                }
+                None => {
+                    labels.insert(label.trim().to_string(), "".to_string());
+                }
+            }
+        }
+        labels.insert("node_id".to_string(), self.id.clone());
+        labels.insert("node_type".to_string(), self.node_type.to_label());
+        labels.insert("network_zone".to_string(), self.network_zone.clone());
+        labels
+    }
+}
+
 #[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub enum NodeState {
     New,
@@ -455,12 +568,33 @@ pub struct ReplicaInstance {
     pub last_update_time: u64,
     pub state: InstanceState,
     pub service_ports: HashMap<String, u16>,
+    // 上报 key 所绑定的 sys_config lease 的到期时间。lease 由上报方续约，
+    // 过期后 sys_config 会自动删除上报 key，调度器不再需要自己推算存活窗口。
+    #[serde(default)]
+    pub lease_expire_time: Option<u64>,
 }
 
 impl ReplicaInstance {
     pub fn is_app_instance(&self) -> bool {
         self.spec_id.contains("@")
     }
+
+    pub fn is_alive(&self, now: u64) -> bool {
+        match self.lease_expire_time {
+            // lease 到期由 sys_config 按它自己的时钟判断并删除上报 key，上报还在就说明 lease 有效。
+            // 不拿本地时钟和 expire_time 比较，避免节点间时钟偏差误判
+            Some(_) => true,
+            // 没有 lease 的旧式上报 / bootstrap 假上报
+            None => now.saturating_sub(self.last_update_time) < INSTANCE_ALIVE_TIME,
+        }
+    }
+
+    // 排空/再平衡判断替换实例是否就绪。app 实例只有节点配置里的 target_state，没有 runtime 上报，
+    // 也就没有存活证明，按 is_alive 判断会一直等到超时；对 app 实例以 Running 为准，
+    // 替换实例写入节点配置的下一轮即视为就绪
+    pub fn is_ready(&self, now: u64) -> bool {
+        self.state == InstanceState::Running && (self.is_app_instance() || self.is_alive(now))
+    }
 }
 
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
@@ -472,7 +606,9 @@ pub enum ServiceInfo {
 #[derive(Clone, Debug)]
 pub enum SchedulerAction {
     ChangeNodeStatus(String, NodeState),
-    CreateOPTask(OPTask),
+    CreateOPTask(String, OPTask), //value is node_id,op_task
+    UpdateOPTask(String, OPTask), //value is node_id,op_task
+    RemoveOPTask(String, String), //value is node_id,op_task_id
     ChangeServiceStatus(String, ServiceSpecState),
     InstanceReplica(ReplicaInstance),
     UpdateInstance(String, ReplicaInstance),
@@ -523,6 +659,40 @@ pub fn create_replica_instance_id(spec: &ServiceSpec, node_id: &str) -> String {
     format!("{}@{}", spec.id, node_id)
 }
 
+// Step3 的再平衡配置（system/scheduler/rebalance），默认关闭
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct RebalanceConfig {
+    pub enabled: bool,
+    // 每轮最多迁移的实例数
+    pub max_moves_per_round: u32,
+    // 同一个 spec 两次迁移的最小间隔
+    pub spec_cooldown_secs: u64,
+    // 目标节点得分至少比当前节点高出这么多才迁移，避免在相近节点间来回抖动
+    pub min_score_gain: f64,
+}
+
+// 一次进行中的再平衡迁移：新实例已放置，等它就绪后再移除旧实例（make-before-break）
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct RebalanceMove {
+    pub from_instance_id: String,
+    pub from_node_id: String,
+    pub to_instance_id: String,
+    pub to_node_id: String,
+    pub start_time: u64,
+}
+
+impl Default for RebalanceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_moves_per_round: 1,
+            spec_cooldown_secs: 600,
+            min_score_gain: 20.0,
+        }
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct NodeScheduler {
     schedule_step_id: u64,
@@ -535,6 +705,14 @@ pub struct NodeScheduler {
     pub service_infos: HashMap<String, ServiceInfo>,
     #[serde(default)]
     pub service_info_refresh_times: HashMap<String, u64>,
+    #[serde(default)]
+    pub rebalance_config: RebalanceConfig,
+    // spec_id → 最近一次再平衡迁移的时间，用于 cooldown
+    #[serde(default)]
+    pub spec_rebalance_times: HashMap<String, u64>,
+    // spec_id → 进行中的再平衡迁移，每个 spec 同时最多一个
+    #[serde(default)]
+    pub rebalance_moves: HashMap<String, RebalanceMove>,
     pub schedule_time: u64,
 }
 
@@ -549,6 +727,9 @@ impl NodeScheduler {
             replica_instances: HashMap::new(),
             service_infos: HashMap::new(),
             service_info_refresh_times: HashMap::new(),
+            rebalance_config: RebalanceConfig::default(),
+            spec_rebalance_times: HashMap::new(),
+            rebalance_moves: HashMap::new(),
             schedule_time: buckyos_get_unix_timestamp(),
         }
     }
@@ -571,6 +752,9 @@ impl NodeScheduler {
             replica_instances,
             service_infos,
             service_info_refresh_times: HashMap::new(),
+            rebalance_config: RebalanceConfig::default(),
+            spec_rebalance_times: HashMap::new(),
+            rebalance_moves: HashMap::new(),
             schedule_time: now,
         }
     }
@@ -615,6 +799,9 @@ impl NodeScheduler {
             || self.replica_instances != last_snapshot.replica_instances
             || self.service_infos != last_snapshot.service_infos
             || self.service_info_refresh_times != last_snapshot.service_info_refresh_times
+            || self.rebalance_config != last_snapshot.rebalance_config
+            || self.spec_rebalance_times != last_snapshot.spec_rebalance_times
+            || self.rebalance_moves != last_snapshot.rebalance_moves
     }
 
     #[cfg(test)]
@@ -646,6 +833,23 @@ impl NodeScheduler {
         if self.nodes.is_empty() {
             return Err(anyhow::anyhow!("No nodes found"));
         }
+        // 再平衡的 cooldown 记录和进行中的迁移只保存在调度快照中，每轮都需要继承
+        if let Some(last_snapshot) = last_snapshot {
+            for (spec_id, move_time) in last_snapshot.spec_rebalance_times.iter() {
+                if self.specs.contains_key(spec_id) {
+                    self.spec_rebalance_times
+                        .entry(spec_id.clone())
+                        .or_insert(*move_time);
+                }
+            }
+            for (spec_id, rebalance_move) in last_snapshot.rebalance_moves.iter() {
+                if self.specs.contains_key(spec_id) {
+                    self.rebalance_moves
+                        .entry(spec_id.clone())
+                        .or_insert_with(|| rebalance_move.clone());
+                }
+            }
+        }
         // Step1. review node (资源池)
         let is_small_system = self.nodes.len() <= SMALL_SYSTEM_NODE_COUNT;
         let node_actions = self.resort_nodes()?;
@@ -656,6 +860,9 @@ impl NodeScheduler {
             info!("Small system, skip review replica instance when have node actions");
             return Ok(actions);
         }
+        // 排空 Removing 节点：放在小系统优化之后，保证同一轮的 Step4 能及时刷新 service_info
+        let drain_actions = self.drain_removing_nodes()?;
+        actions.extend(drain_actions);
 
         // Step2. 处理service_spec的实例化与反实例化
         if self.is_spec_changed(last_snapshot) {
@@ -663,7 +870,20 @@ impl NodeScheduler {
             let spec_actions = self.schedule_spec_change()?;
             actions.extend(spec_actions);
         }
-        // Step3. 优化instance的资源使用
+        // Step3. 优化instance的资源使用：先推进进行中的迁移（即使再平衡已被关闭），
+        // 新的迁移只在本轮没有其它实例变动时进行，降低复杂度
+        let move_actions = self.finish_rebalance_moves();
+        actions.extend(move_actions);
+        let has_instance_actions = actions.iter().any(|action| {
+            matches!(
+                action,
+                SchedulerAction::InstanceReplica(_) | SchedulerAction::RemoveInstance(..)
+            )
+        });
+        if self.rebalance_config.enabled && !has_instance_actions {
+            let rebalance_actions = self.rebalance_instances()?;
+            actions.extend(rebalance_actions);
+        }
 
         // Step4. 计算service_info
         let service_spec_actions = self.calc_service_infos(last_snapshot)?;
@@ -703,7 +923,7 @@ impl NodeScheduler {
                         // 这里允许存在检测误差：实例真实失联与调度器宣告下线之间不要求严格同步。（也无法做到)
                         // 对应用来说，调度器返回服务可用时，如果访问失败可以尝试重试
                         // 如果调度器返回服务不可用，应用使用服务的接口应直接返回失败
-                        if now - instance.last_update_time < INSTANCE_ALIVE_TIME {
+                        if instance.is_alive(now) {
                             info_map
                                 .insert(instance.instance_id.clone(), (100, (*instance).clone()));
                         } else {
@@ -778,31 +998,18 @@ impl NodeScheduler {
         // TOD: 根据Node的status进行排序
         let node_ids: Vec<String> = self.nodes.keys().cloned().collect();
         for node_id in node_ids {
-            if let Some(node) = self.nodes.get(&node_id) {
-                // 1. 检查已有op tasks的完成情况，该部分可能会对可用Node进行一些标记
-                self.check_node_op_tasks(node, &mut node_actions)?;
-            }
+            // 1. 检查已有op tasks的完成情况，该部分可能会对可用Node进行一些标记
+            self.check_node_op_tasks(&node_id, &mut node_actions)?;
 
             if let Some(node_mut) = self.nodes.get_mut(&node_id) {
-                // 2. 处理新node的初始化
-                match node_mut.state {
-                    NodeState::New => {
-                        // 由调度器控制node进入初始化准备状态
-                        node_mut.state = NodeState::Prepare;
-                        node_actions.push(SchedulerAction::ChangeNodeStatus(
-                            node_mut.id.clone(),
-                            NodeState::Prepare,
-                        ));
-                    }
-                    NodeState::Removing => {
-                        // TODO:由调度器控制node 处理移除任务
-                        node_mut.state = NodeState::Deleted;
-                        node_actions.push(SchedulerAction::ChangeNodeStatus(
-                            node_mut.id.clone(),
-                            NodeState::Deleted,
-                        ));
-                    }
-                    _ => {}
+                // 2. 处理新node的初始化（Removing 节点由 drain_removing_nodes 排空）
+                if node_mut.state == NodeState::New {
+                    // 由调度器控制node进入初始化准备状态
+                    node_mut.state = NodeState::Prepare;
+                    node_actions.push(SchedulerAction::ChangeNodeStatus(
+                        node_mut.id.clone(),
+                        NodeState::Prepare,
+                    ));
                 }
             }
         }
@@ -936,6 +1143,7 @@ impl NodeScheduler {
                         || spec.required_memory != last_spec.required_memory
                         || spec.node_affinity != last_spec.node_affinity
                         || spec.network_affinity != last_spec.network_affinity
+                        || spec.replica_spread != last_spec.replica_spread
                     {
                         return true;
                     }
@@ -982,19 +1190,10 @@ impl NodeScheduler {
         let mut desired_spec = spec_snapshot.clone();
         desired_spec.best_instance_count = missing_count;
 
-        let new_instances = self.create_replica_instance(&desired_spec, &available_shadow_nodes)?;
+        let new_instances =
+            self.create_replica_instance(&desired_spec, &available_shadow_nodes, None)?;
         for instance in &new_instances {
-            if let Some(node) = shadow_nodes.iter_mut().find(|n| n.id == instance.node_id) {
-                node.available_cpu_mhz = node
-                    .available_cpu_mhz
-                    .saturating_sub(spec_snapshot.required_cpu_mhz);
-                node.available_memory = node
-                    .available_memory
-                    .saturating_sub(spec_snapshot.required_memory);
-                node.available_gpu_memory = node
-                    .available_gpu_memory
-                    .saturating_sub(spec_snapshot.required_gpu_mem);
-            }
+            deduct_shadow_resources(shadow_nodes, &instance.node_id, spec_snapshot);
         }
 
         info!(
@@ -1008,13 +1207,536 @@ impl NodeScheduler {
         Ok(new_instances)
     }
 
+    // 排空所有 Removing 节点。所有节点共用一个影子资源账本，防止替换实例在同一轮被超卖
+    fn drain_removing_nodes(&mut self) -> Result<Vec<SchedulerAction>> {
+        let mut actions = Vec::new();
+        let mut removing_node_ids: Vec<String> = self
+            .nodes
+            .values()
+            .filter(|node| node.state == NodeState::Removing)
+            .map(|node| node.id.clone())
+            .collect();
+        if removing_node_ids.is_empty() {
+            return Ok(actions);
+        }
+        removing_node_ids.sort();
+
+        let mut shadow_nodes: Vec<NodeItem> = self
+            .nodes
+            .values()
+            .filter(|node| node.state == NodeState::Ready)
+            .cloned()
+            .collect();
+        for node_id in removing_node_ids {
+            self.drain_node(&node_id, &mut shadow_nodes, &mut actions)?;
+        }
+        Ok(actions)
+    }
+
+    // 排空流程：
+    // 1. Removing 节点本身不会再被调度（filter_node_for_instance 只接受 Ready 节点）
+    // 2. 为节点上每个仍需保留的实例，在其他 Ready 节点上放置替换实例（同样经过过滤+打分）
+    // 3. 替换实例就绪（ReplicaInstance::is_ready）后，才移除旧实例（本轮刚创建的替换实例不算数）
+    // 4. 节点上没有实例后，排空任务 Done，节点标记为 Deleted
+    // 进度记录在 DrainNode op task 中，超时由 check_node_op_tasks 处理
+    fn drain_node(
+        &mut self,
+        node_id: &str,
+        shadow_nodes: &mut [NodeItem],
+        actions: &mut Vec<SchedulerAction>,
+    ) -> Result<()> {
+        let now = buckyos_get_unix_timestamp();
+        let Some(node) = self.nodes.get(node_id) else {
+            return Ok(());
+        };
+        let task_index = node.op_tasks.iter().position(|task| {
+            task.is_pending() && matches!(task.body, OPTaskBody::DrainNode { .. })
+        });
+        if task_index.is_none() {
+            // 最近一次排空已经结束（Done/Failed/Canceled）但节点仍是 Removing：
+            // 说明结束时的节点状态没有生效，按排空结果补齐节点状态，不再重复创建排空任务。
+            // 需要重新排空时，先清理掉已结束的排空任务
+            let last_drain_task = node
+                .op_tasks
+                .iter()
+                .filter(|task| matches!(task.body, OPTaskBody::DrainNode { .. }))
+                .max_by_key(|task| task.create_time);
+            if let Some(last_drain_task) = last_drain_task {
+                let target_state = match last_drain_task.status {
+                    OPTaskState::Done => Some(NodeState::Deleted),
+                    OPTaskState::Failed => Some(NodeState::Abnormal),
+                    OPTaskState::Canceled => Some(NodeState::Ready),
+                    OPTaskState::New | OPTaskState::Running => None,
+                };
+                if let Some(target_state) = target_state {
+                    warn!(
+                        "node {} is removing but drain task {} is {}, restore node state to {}",
+                        node_id, last_drain_task.id, last_drain_task.status, target_state
+                    );
+                    if let Some(node) = self.nodes.get_mut(node_id) {
+                        node.state = target_state.clone();
+                    }
+                    actions.push(SchedulerAction::ChangeNodeStatus(
+                        node_id.to_string(),
+                        target_state,
+                    ));
+                    return Ok(());
+                }
+            }
+        }
+
+        let mut old_instances: Vec<ReplicaInstance> = self
+            .replica_instances
+            .values()
+            .filter(|instance| {
+                instance.node_id == node_id && instance.state != InstanceState::Deleted
+            })
+            .cloned()
+            .collect();
+        old_instances.sort_by(|a, b| a.instance_id.cmp(&b.instance_id));
+
+        let mut task = match task_index {
+            Some(index) => node.op_tasks[index].clone(),
+            None => {
+                info!(
+                    "node {} is removing, start drain {} instances",
+                    node_id,
+                    old_instances.len()
+                );
+                OPTask {
+                    id: format!("drain-{}-{}", node_id, now),
+                    creator_id: None,
+                    body: OPTaskBody::DrainNode {
+                        total_instances: old_instances.len() as u32,
+                        drained_instances: 0,
+                    },
+                    create_time: now,
+                    create_step_id: self.schedule_step_id,
+                    max_timeout_sec: DRAIN_NODE_TIMEOUT_SEC,
+                    status: OPTaskState::Running,
+                    start_time: now,
+                }
+            }
+        };
+        let last_task = task.clone();
+
+        let mut created_instance_ids = HashSet::new();
+        let mut drained_count: u32 = 0;
+        for old_instance in old_instances.iter() {
+            let spec = self
+                .specs
+                .get(&old_instance.spec_id)
+                .filter(|spec| {
+                    matches!(
+                        spec.state,
+                        ServiceSpecState::New | ServiceSpecState::Deployed
+                    ) && spec.best_instance_count > 0
+                })
+                .cloned();
+
+            let can_remove = match spec {
+                // spec 已经不需要实例了，不必等待替换
+                None => true,
+                Some(spec) => {
+                    let mut other_instances: Vec<&ReplicaInstance> = self
+                        .replica_instances
+                        .values()
+                        .filter(|instance| {
+                            instance.spec_id == spec.id
+                                && instance.node_id != node_id
+                                && instance.state != InstanceState::Deleted
+                        })
+                        .collect();
+                    let mut new_instance = None;
+                    if (other_instances.len() as u32) < spec.best_instance_count {
+                        let used_node_ids: HashSet<&str> = other_instances
+                            .iter()
+                            .map(|instance| instance.node_id.as_str())
+                            .collect();
+                        let candidate_nodes: Vec<NodeItem> = shadow_nodes
+                            .iter()
+                            .filter(|node| !used_node_ids.contains(node.id.as_str()))
+                            .cloned()
+                            .collect();
+                        let mut replace_spec = spec.clone();
+                        replace_spec.best_instance_count = 1;
+                        match self.create_replica_instance(
+                            &replace_spec,
+                            &candidate_nodes,
+                            Some(old_instance.instance_id.as_str()),
+                        ) {
+                            Ok(instances) => new_instance = instances.into_iter().next(),
+                            Err(err) => {
+                                warn!(
+                                    "drain node {}: no placement for instance {}, wait for retry: {}",
+                                    node_id, old_instance.instance_id, err
+                                );
+                            }
+                        }
+                    }
+                    if let Some(instance) = new_instance.as_ref() {
+                        info!(
+                            "drain node {}: replace instance {} with {} @ {}",
+                            node_id,
+                            old_instance.instance_id,
+                            instance.instance_id,
+                            instance.node_id
+                        );
+                        deduct_shadow_resources(shadow_nodes, &instance.node_id, &spec);
+                        created_instance_ids.insert(instance.instance_id.clone());
+                        other_instances.push(instance);
+                    }
+
+                    // 能放置多少替换实例就等待多少个就绪，没有任何替换实例时只能一直等待（直到超时）
+                    let required_count =
+                        std::cmp::min(spec.best_instance_count as usize, other_instances.len());
+                    let ready_count = other_instances
+                        .iter()
+                        .filter(|instance| {
+                            instance.is_ready(now)
+                                && !created_instance_ids.contains(&instance.instance_id)
+                        })
+                        .count();
+                    let can_remove = required_count > 0 && ready_count >= required_count;
+                    if let Some(instance) = new_instance {
+                        self.replica_instances
+                            .insert(instance.instance_id.clone(), instance.clone());
+                        actions.push(SchedulerAction::InstanceReplica(instance));
+                    }
+                    can_remove
+                }
+            };
+
+            if can_remove {
+                info!(
+                    "drain node {}: remove instance {}, spec_id:{}",
+                    node_id, old_instance.instance_id, old_instance.spec_id
+                );
+                self.replica_instances.remove(&old_instance.instance_id);
+                actions.push(SchedulerAction::RemoveInstance(
+                    old_instance.spec_id.clone(),
+                    old_instance.instance_id.clone(),
+                    node_id.to_string(),
+                ));
+                drained_count += 1;
+            }
+        }
+
+        let remaining_count = old_instances.len() as u32 - drained_count;
+        if let OPTaskBody::DrainNode {
+            total_instances,
+            drained_instances,
+        } = &mut task.body
+        {
+            *drained_instances += drained_count;
+            *total_instances =
+                std::cmp::max(*total_instances, *drained_instances + remaining_count);
+        }
+        let node = self
+            .nodes
+            .get_mut(node_id)
+            .ok_or_else(|| anyhow::anyhow!("node {} not found", node_id))?;
+        if remaining_count == 0 {
+            info!("drain node {} done, mark it deleted", node_id);
+            task.status = OPTaskState::Done;
+            node.state = NodeState::Deleted;
+            actions.push(SchedulerAction::ChangeNodeStatus(
+                node_id.to_string(),
+                NodeState::Deleted,
+            ));
+        }
+
+        match task_index {
+            None => {
+                node.op_tasks.push(task.clone());
+                actions.push(SchedulerAction::CreateOPTask(node_id.to_string(), task));
+            }
+            Some(index) => {
+                if task != last_task {
+                    node.op_tasks[index] = task.clone();
+                    actions.push(SchedulerAction::UpdateOPTask(node_id.to_string(), task));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 再平衡：把实例从得分明显偏低的节点（例如负载很高）迁移到得分更高的节点（例如新加入的空闲节点）。
+    // - 只处理 Deployed 且所有实例都就绪（ReplicaInstance::is_ready）的 spec（上一次迁移还没稳定时不动）
+    // - 每个 spec 每轮最多迁移一个实例，迁移后进入 cooldown
+    // - 每轮最多迁移 max_moves_per_round 个实例
+    // - 迁移先只下发 InstanceReplica，旧实例由 finish_rebalance_moves 在新实例就绪后移除
+    fn rebalance_instances(&mut self) -> Result<Vec<SchedulerAction>> {
+        let now = buckyos_get_unix_timestamp();
+        let config = self.rebalance_config.clone();
+        let mut actions = Vec::new();
+        let mut shadow_nodes: Vec<NodeItem> = self
+            .nodes
+            .values()
+            .filter(|node| node.state == NodeState::Ready)
+            .cloned()
+            .collect();
+        shadow_nodes.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let mut spec_ids: Vec<String> = self.specs.keys().cloned().collect();
+        spec_ids.sort();
+        let mut move_count: u32 = 0;
+        for spec_id in spec_ids {
+            if move_count >= config.max_moves_per_round {
+                break;
+            }
+            let spec = self.specs[&spec_id].clone();
+            if spec.state != ServiceSpecState::Deployed
+                || self.rebalance_moves.contains_key(&spec_id)
+            {
+                continue;
+            }
+            if let Some(last_move_time) = self.spec_rebalance_times.get(&spec_id) {
+                if now.saturating_sub(*last_move_time) < config.spec_cooldown_secs {
+                    debug!("spec_id:{} rebalance in cooldown", spec_id);
+                    continue;
+                }
+            }
+
+            let mut instances: Vec<ReplicaInstance> = self
+                .replica_instances
+                .values()
+                .filter(|instance| {
+                    instance.spec_id == spec_id && instance.state != InstanceState::Deleted
+                })
+                .cloned()
+                .collect();
+            if instances.is_empty() || instances.iter().any(|instance| !instance.is_ready(now)) {
+                continue;
+            }
+            instances.sort_by(|a, b| a.instance_id.cmp(&b.instance_id));
+            let used_node_ids: HashSet<String> = instances
+                .iter()
+                .map(|instance| instance.node_id.clone())
+                .collect();
+
+            for instance in instances.iter() {
+                // 当前节点的得分按“释放该实例后”计算，和候选节点放在同一起点比较
+                let Some(mut source_node) = shadow_nodes
+                    .iter()
+                    .find(|node| node.id == instance.node_id)
+                    .cloned()
+                else {
+                    continue;
+                };
+                source_node.available_cpu_mhz = source_node
+                    .available_cpu_mhz
+                    .saturating_add(spec.required_cpu_mhz)
+                    .min(source_node.total_cpu_mhz);
+                source_node.available_memory = source_node
+                    .available_memory
+                    .saturating_add(spec.required_memory)
+                    .min(source_node.total_memory);
+                source_node.available_gpu_memory = source_node
+                    .available_gpu_memory
+                    .saturating_add(spec.required_gpu_mem)
+                    .min(source_node.total_gpu_memory);
+                let domain_counts =
+                    self.replica_domain_counts(&spec, Some(instance.instance_id.as_str()));
+                let source_score = self.score_node(&source_node, &spec)
+                    - replica_spread_penalty(&spec, &source_node, &domain_counts).unwrap_or(0.0);
+
+                let mut best_target: Option<(f64, &NodeItem)> = None;
+                for node in shadow_nodes.iter() {
+                    if used_node_ids.contains(&node.id)
+                        || !self.filter_node_for_instance(node, &spec)
+                    {
+                        continue;
+                    }
+                    let Some(penalty) = replica_spread_penalty(&spec, node, &domain_counts) else {
+                        continue;
+                    };
+                    let score = self.score_node(node, &spec) - penalty;
+                    // 同分时按 node id 取第一个，保证结果确定
+                    if best_target.is_none_or(|(best_score, _)| score > best_score) {
+                        best_target = Some((score, node));
+                    }
+                }
+                let Some((target_score, target_node)) = best_target else {
+                    continue;
+                };
+                if target_score - source_score < config.min_score_gain {
+                    continue;
+                }
+
+                // 单个迁移放置失败只跳过该候选，不影响本轮其它调度动作
+                let mut move_spec = spec.clone();
+                move_spec.best_instance_count = 1;
+                let new_instance = match self.create_replica_instance(
+                    &move_spec,
+                    &vec![target_node.clone()],
+                    Some(instance.instance_id.as_str()),
+                ) {
+                    Ok(new_instances) => new_instances.into_iter().next(),
+                    Err(err) => {
+                        warn!(
+                            "spec_id:{} rebalance instance {} -> {} placement failed: {}",
+                            spec_id, instance.instance_id, target_node.id, err
+                        );
+                        continue;
+                    }
+                };
+                let Some(new_instance) = new_instance else {
+                    warn!(
+                        "spec_id:{} rebalance instance {} -> {} placement returned no instance",
+                        spec_id, instance.instance_id, target_node.id
+                    );
+                    continue;
+                };
+                info!(
+                    "spec_id:{} rebalance instance {} @ {} (score {:.1}) -> {} (score {:.1})",
+                    spec_id,
+                    instance.instance_id,
+                    instance.node_id,
+                    source_score,
+                    new_instance.node_id,
+                    target_score
+                );
+
+                // 旧实例在新实例就绪前继续占用资源，源节点不释放
+                deduct_shadow_resources(&mut shadow_nodes, &new_instance.node_id, &spec);
+                self.rebalance_moves.insert(
+                    spec_id.clone(),
+                    RebalanceMove {
+                        from_instance_id: instance.instance_id.clone(),
+                        from_node_id: instance.node_id.clone(),
+                        to_instance_id: new_instance.instance_id.clone(),
+                        to_node_id: new_instance.node_id.clone(),
+                        start_time: now,
+                    },
+                );
+                self.replica_instances
+                    .insert(new_instance.instance_id.clone(), new_instance.clone());
+                actions.push(SchedulerAction::InstanceReplica(new_instance));
+                self.spec_rebalance_times.insert(spec_id.clone(), now);
+                move_count += 1;
+                break;
+            }
+        }
+        Ok(actions)
+    }
+
+    // 推进进行中的再平衡迁移：
+    // - 新实例 Running 且存活：移除旧实例，迁移完成
+    // - 超过 REBALANCE_MOVE_TIMEOUT_SEC 新实例仍未就绪：移除新实例，旧实例保留
+    // - spec 不再是 Deployed 或旧实例已经不在：交给其它步骤处理，直接结束迁移
+    fn finish_rebalance_moves(&mut self) -> Vec<SchedulerAction> {
+        let now = buckyos_get_unix_timestamp();
+        let mut actions = Vec::new();
+        let mut spec_ids: Vec<String> = self.rebalance_moves.keys().cloned().collect();
+        spec_ids.sort();
+        for spec_id in spec_ids {
+            let rebalance_move = self.rebalance_moves[&spec_id].clone();
+            let is_deployed = self
+                .specs
+                .get(&spec_id)
+                .is_some_and(|spec| spec.state == ServiceSpecState::Deployed);
+            if !is_deployed
+                || !self
+                    .replica_instances
+                    .contains_key(&rebalance_move.from_instance_id)
+            {
+                self.rebalance_moves.remove(&spec_id);
+                continue;
+            }
+
+            let is_ready = self
+                .replica_instances
+                .get(&rebalance_move.to_instance_id)
+                .is_some_and(|instance| instance.is_ready(now));
+            if is_ready {
+                info!(
+                    "spec_id:{} rebalance instance {} is ready, remove {} @ {}",
+                    spec_id,
+                    rebalance_move.to_instance_id,
+                    rebalance_move.from_instance_id,
+                    rebalance_move.from_node_id
+                );
+                self.replica_instances
+                    .remove(&rebalance_move.from_instance_id);
+                actions.push(SchedulerAction::RemoveInstance(
+                    spec_id.clone(),
+                    rebalance_move.from_instance_id.clone(),
+                    rebalance_move.from_node_id.clone(),
+                ));
+                self.rebalance_moves.remove(&spec_id);
+            } else if now.saturating_sub(rebalance_move.start_time) >= REBALANCE_MOVE_TIMEOUT_SEC {
+                warn!(
+                    "spec_id:{} rebalance instance {} @ {} not ready after {}s, roll back",
+                    spec_id,
+                    rebalance_move.to_instance_id,
+                    rebalance_move.to_node_id,
+                    REBALANCE_MOVE_TIMEOUT_SEC
+                );
+                self.replica_instances
+                    .remove(&rebalance_move.to_instance_id);
+                actions.push(SchedulerAction::RemoveInstance(
+                    spec_id.clone(),
+                    rebalance_move.to_instance_id.clone(),
+                    rebalance_move.to_node_id.clone(),
+                ));
+                self.rebalance_moves.remove(&spec_id);
+            }
+        }
+        actions
+    }
+
     // 辅助函数
     fn check_node_op_tasks(
-        &self,
-        node: &NodeItem,
+        &mut self,
+        node_id: &str,
         actions: &mut Vec<SchedulerAction>,
     ) -> Result<()> {
-        // TODO: 实现检查node的op tasks完成情况的逻辑
+        let now = buckyos_get_unix_timestamp();
+        let Some(node) = self.nodes.get_mut(node_id) else {
+            return Ok(());
+        };
+        for task in node.op_tasks.iter_mut() {
+            if !task.is_pending() || task.max_timeout_sec == 0 {
+                continue;
+            }
+            if now.saturating_sub(task.start_time) < task.max_timeout_sec {
+                continue;
+            }
+            warn!(
+                "op task {} on node {} timeout after {}s, mark it failed",
+                task.id, node_id, task.max_timeout_sec
+            );
+            task.status = OPTaskState::Failed;
+            actions.push(SchedulerAction::UpdateOPTask(
+                node_id.to_string(),
+                task.clone(),
+            ));
+            // 排空超时：停止迁移，节点转为 Abnormal，剩余实例保留在节点上等待运维处理
+            if matches!(task.body, OPTaskBody::DrainNode { .. })
+                && node.state == NodeState::Removing
+            {
+                node.state = NodeState::Abnormal;
+                actions.push(SchedulerAction::ChangeNodeStatus(
+                    node_id.to_string(),
+                    NodeState::Abnormal,
+                ));
+            }
+        }
+        // 清理过了保留期的已结束任务，避免 op_tasks 无限增长
+        node.op_tasks.retain(|task| {
+            if task.is_pending()
+                || now.saturating_sub(task.create_time) < FINISHED_OP_TASK_RETENTION_SEC
+            {
+                return true;
+            }
+            debug!("op task {} on node {} expired, remove it", task.id, node_id);
+            actions.push(SchedulerAction::RemoveOPTask(
+                node_id.to_string(),
+                task.id.clone(),
+            ));
+            false
+        });
         Ok(())
     }
 
@@ -1067,10 +1789,39 @@ impl NodeScheduler {
         return 0;
     }
 
+    // spec 现有副本在各拓扑域中的数量，exclude_instance_id 是即将被替换/迁移走的实例
+    fn replica_domain_counts(
+        &self,
+        spec: &ServiceSpec,
+        exclude_instance_id: Option<&str>,
+    ) -> HashMap<String, u32> {
+        let mut domain_counts = HashMap::new();
+        if spec.replica_spread.is_none() {
+            return domain_counts;
+        }
+        for instance in self.replica_instances.values() {
+            if instance.spec_id != spec.id
+                || instance.state == InstanceState::Deleted
+                || Some(instance.instance_id.as_str()) == exclude_instance_id
+            {
+                continue;
+            }
+            if let Some(domain) = self
+                .nodes
+                .get(&instance.node_id)
+                .and_then(|node| replica_spread_domain(spec, node))
+            {
+                *domain_counts.entry(domain).or_insert(0) += 1;
+            }
+        }
+        domain_counts
+    }
+
     fn create_replica_instance(
         &self,
         service_spec: &ServiceSpec,
         node_list: &Vec<NodeItem>,
+        replaced_instance_id: Option<&str>,
     ) -> Result<Vec<ReplicaInstance>> {
         // 1. 过滤阶段
         let candidate_nodes: Vec<&NodeItem> = node_list
@@ -1100,12 +1851,42 @@ impl NodeScheduler {
         // 按分数降序排序
         scored_nodes.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
 
-        // 选择得分最高的instance_count个节点
-        let selected_nodes: Vec<(f64, &NodeItem)> = scored_nodes
-            .iter()
-            .take(instance_count as usize)
-            .map(|&(score, node)| (score, node))
-            .collect();
+        // 选择得分最高的instance_count个节点。逐个选择以满足副本分散约束：
+        // 已有副本的拓扑域不可选（required）或者降低得分（preferred）
+        let mut domain_counts = self.replica_domain_counts(service_spec, replaced_instance_id);
+        let mut selected_nodes: Vec<&NodeItem> = Vec::new();
+        while selected_nodes.len() < instance_count as usize {
+            let mut best_node: Option<(f64, &NodeItem)> = None;
+            for (score, node) in scored_nodes.iter() {
+                if selected_nodes.iter().any(|selected| selected.id == node.id) {
+                    continue;
+                }
+                let Some(penalty) = replica_spread_penalty(service_spec, node, &domain_counts)
+                else {
+                    continue;
+                };
+                let score = score - penalty;
+                if best_node.is_none_or(|(best_score, _)| score > best_score) {
+                    best_node = Some((score, *node));
+                }
+            }
+            let Some((_, node)) = best_node else {
+                break;
+            };
+            if let Some(domain) = replica_spread_domain(service_spec, node) {
+                *domain_counts.entry(domain).or_insert(0) += 1;
+            }
+            selected_nodes.push(node);
+        }
+        if selected_nodes.is_empty() {
+            warn!(
+                "spec_id:{} all {} candidate nodes violate replica spread {:?}",
+                service_spec.id, candidate_node_count, service_spec.replica_spread
+            );
+            return Err(anyhow::anyhow!(
+                "No node satisfies replica spread of service_spec"
+            ));
+        }
 
         let mut instances = Vec::new();
         let instance_id_uuid = uuid::Uuid::new_v4();
@@ -1121,7 +1902,7 @@ impl NodeScheduler {
             service_ports.insert(service_name.clone(), service_port);
         }
         //TODO: 将port alloc的逻辑，放到调度器内部?
-        for (_, node) in selected_nodes.iter() {
+        for node in selected_nodes.iter() {
             instances.push(ReplicaInstance {
                 node_id: node.id.clone(),
                 spec_id: service_spec.id.clone(),
@@ -1133,6 +1914,7 @@ impl NodeScheduler {
                 last_update_time: buckyos_get_unix_timestamp(),
                 state: InstanceState::Running,
                 service_ports: service_ports.clone(),
+                lease_expire_time: None,
             });
         }
         Ok(instances)
@@ -1166,9 +1948,9 @@ impl NodeScheduler {
             return false;
         }
 
-        // 3. 检查亲和性
+        // 3. 检查亲和性（required 表达式必须全部满足）
         if let Some(affinity) = &spec.node_affinity {
-            if !node.labels.contains(affinity) {
+            if !match_label_selectors(&affinity.required, &node.label_map()) {
                 return false;
             }
         }
@@ -1201,6 +1983,56 @@ impl NodeScheduler {
             }
         }
 
+        // 4. 节点亲和性偏好评分 (每个满足的 preferred 规则加上它的 weight)
+        if let Some(affinity) = &spec.node_affinity {
+            let labels = node.label_map();
+            for preferred in affinity.preferred.iter() {
+                if match_label_selectors(&preferred.match_expressions, &labels) {
+                    score += preferred.weight as f64;
+                }
+            }
+        }
+
         score
     }
 }
+
+// 影子资源账本：在同一轮调度中扣减已分配给新实例的资源
+fn deduct_shadow_resources(shadow_nodes: &mut [NodeItem], node_id: &str, spec: &ServiceSpec) {
+    if let Some(node) = shadow_nodes.iter_mut().find(|n| n.id == node_id) {
+        node.available_cpu_mhz = node.available_cpu_mhz.saturating_sub(spec.required_cpu_mhz);
+        node.available_memory = node.available_memory.saturating_sub(spec.required_memory);
+        node.available_gpu_memory = node
+            .available_gpu_memory
+            .saturating_sub(spec.required_gpu_mem);
+    }
+}
+
+// 副本分散约束下 node 所属的拓扑域（node 没有 topology_key 标签时不受约束）
+fn replica_spread_domain(spec: &ServiceSpec, node: &NodeItem) -> Option<String> {
+    let spread = spec.replica_spread.as_ref()?;
+    node.label_map().remove(&spread.topology_key)
+}
+
+// None 表示放到该 node 会违反 required 分散约束，否则返回打分惩罚
+fn replica_spread_penalty(
+    spec: &ServiceSpec,
+    node: &NodeItem,
+    domain_counts: &HashMap<String, u32>,
+) -> Option<f64> {
+    let Some(domain) = replica_spread_domain(spec, node) else {
+        return Some(0.0);
+    };
+    let count = domain_counts.get(&domain).copied().unwrap_or(0);
+    if count == 0 {
+        return Some(0.0);
+    }
+    if spec
+        .replica_spread
+        .as_ref()
+        .is_some_and(|spread| spread.required)
+    {
+        return None;
+    }
+    Some(count as f64 * REPLICA_SPREAD_PENALTY)
+}
@@ -9,19 +9,420 @@ use cyfs_gateway_lib::{
 };
 use http::{Method, Version};
 use http_body_util::combinators::BoxBody;
+use log::*;
+use serde_json::Value;
 use server_runner::*;
 use std::net::IpAddr;
 use std::result::Result;
 use std::sync::Arc;
 
+use crate::dry_run::dry_run_schedule;
+use crate::scheduler::*;
+use crate::system_config_agent::{
+    cancel_op_task_tx_actions, get_node_state_key, get_op_task_key, trigger_schedule,
+    SCHEDULER_SNAPSHOT_KEY,
+};
+
 pub const SCHEDULER_SERVICE_MAIN_PORT: u16 = 3400;
 
+async fn get_optional_config(
+    client: &SystemConfigClient,
+    key: &str,
+) -> Result<Option<String>, RPCErrors> {
+    match client.get(key).await {
+        Ok(value) => Ok(Some(value.value)),
+        Err(SystemConfigError::KeyNotFound(_)) => Ok(None),
+        Err(err) => Err(RPCErrors::ReasonError(err.to_string())),
+    }
+}
+
+fn check_admin_session(
+    user_id: &str,
+    app_id: &str,
+    user_settings: Option<&str>,
+    rbac_policy: Option<&str>,
+) -> Result<(), RPCErrors> {
+    if !is_privileged_user_by_config(user_settings, rbac_policy, user_id)? {
+        warn!(
+            "scheduler rpc denied: user_id={} app_id={}",
+            user_id, app_id
+        );
+        return Err(RPCErrors::NoPermission(format!(
+            "{} of {} is not allowed to access the scheduler",
+            user_id, app_id
+        )));
+    }
+    Ok(())
+}
+
+fn node_info_from_item(node: &NodeItem, snapshot: &NodeScheduler) -> SchedulerNodeInfo {
+    SchedulerNodeInfo {
+        id: node.id.clone(),
+        node_type: format!("{:?}", node.node_type),
+        state: node.state.to_string(),
+        labels: node.labels.clone(),
+        network_zone: node.network_zone.clone(),
+        support_container: node.support_container,
+        available_cpu_mhz: node.available_cpu_mhz,
+        total_cpu_mhz: node.total_cpu_mhz,
+        available_memory: node.available_memory,
+        total_memory: node.total_memory,
+        available_gpu_memory: node.available_gpu_memory,
+        total_gpu_memory: node.total_gpu_memory,
+        instance_count: snapshot
+            .replica_instances
+            .values()
+            .filter(|instance| instance.node_id == node.id)
+            .count(),
+        pending_op_task_count: node
+            .op_tasks
+            .iter()
+            .filter(|task| task.is_pending())
+            .count(),
+    }
+}
+
+fn spec_info_from_spec(spec: &ServiceSpec, snapshot: &NodeScheduler) -> SchedulerSpecInfo {
+    SchedulerSpecInfo {
+        id: spec.id.clone(),
+        app_id: spec.app_id.clone(),
+        owner_id: spec.owner_id.clone(),
+        spec_type: format!("{:?}", spec.spec_type),
+        state: spec.state.to_string(),
+        best_instance_count: spec.best_instance_count,
+        instance_count: snapshot
+            .replica_instances
+            .values()
+            .filter(|instance| instance.spec_id == spec.id)
+            .count(),
+    }
+}
+
+fn instance_info_from_instance(instance: &ReplicaInstance) -> SchedulerInstanceInfo {
+    SchedulerInstanceInfo {
+        instance_id: instance.instance_id.clone(),
+        spec_id: instance.spec_id.clone(),
+        node_id: instance.node_id.clone(),
+        state: instance.state.to_string(),
+        last_update_time: instance.last_update_time,
+        lease_expire_time: instance.lease_expire_time,
+        service_ports: instance.service_ports.clone(),
+    }
+}
+
+fn op_task_info_from_task(node_id: &str, task: &OPTask) -> SchedulerOpTaskInfo {
+    SchedulerOpTaskInfo {
+        id: task.id.clone(),
+        node_id: node_id.to_string(),
+        creator_id: task.creator_id.clone(),
+        body: format!("{:?}", task.body),
+        status: task.status.to_string(),
+        create_time: task.create_time,
+        start_time: task.start_time,
+        max_timeout_sec: task.max_timeout_sec,
+    }
+}
+
+fn collect_op_tasks(snapshot: &NodeScheduler) -> Vec<(&str, &OPTask)> {
+    let mut tasks = Vec::new();
+    for node in snapshot.nodes.values() {
+        for task in node.op_tasks.iter() {
+            tasks.push((node.id.as_str(), task));
+        }
+    }
+    tasks.sort_by(|a, b| {
+        a.1.create_time
+            .cmp(&b.1.create_time)
+            .then_with(|| a.1.id.cmp(&b.1.id))
+    });
+    tasks
+}
+
+//调度器rpc的数据来源是上一次持久化的调度快照(system/scheduler/snapshot)
+pub struct SchedulerApiHandler {}
+
+impl SchedulerApiHandler {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    async fn load_snapshot(&self) -> Result<Option<NodeScheduler>, RPCErrors> {
+        let runtime = get_buckyos_api_runtime()?;
+        let client = runtime.get_system_config_client().await?;
+        let snapshot_str = match client.get(SCHEDULER_SNAPSHOT_KEY).await {
+            Ok(value) => value.value,
+            Err(SystemConfigError::KeyNotFound(_)) => return Ok(None),
+            Err(err) => {
+                let msg = format!("load scheduler snapshot failed: {}", err);
+                error!("{}", msg);
+                return Err(RPCErrors::ReasonError(msg));
+            }
+        };
+        let snapshot =
+            serde_json::from_str::<NodeScheduler>(snapshot_str.as_str()).map_err(|e| {
+                let msg = format!("parse scheduler snapshot failed: {}", e);
+                error!("{}", msg);
+                RPCErrors::ReasonError(msg)
+            })?;
+        Ok(Some(snapshot))
+    }
+
+    // 除get_status外的接口只对管理员(用户类型或rbac策略中为admin/root)会话开放,与system_config的特权用户判断一致;
+    // 只按会话的user判断,appid由调用方在登录时自选,不能作为授权依据. control panel会转发调用者自己的token
+    async fn require_admin_session(ctx: &RPCContext) -> Result<(), RPCErrors> {
+        let token = ctx
+            .token
+            .as_deref()
+            .ok_or_else(|| RPCErrors::NoPermission("missing session token".to_string()))?;
+        let runtime = get_buckyos_api_runtime()?;
+        let session_token = runtime
+            .verify_trusted_session_token(token)
+            .await
+            .map_err(|err| RPCErrors::NoPermission(format!("invalid session token: {}", err)))?;
+        let (user_id, app_id) = session_token
+            .get_subs()
+            .map_err(|err| RPCErrors::NoPermission(format!("invalid session token: {}", err)))?;
+        let client = runtime.get_system_config_client().await?;
+        let user_settings =
+            get_optional_config(&client, &format!("users/{}/settings", user_id)).await?;
+        let rbac_policy = get_optional_config(&client, "system/rbac/policy").await?;
+        check_admin_session(
+            &user_id,
+            &app_id,
+            user_settings.as_deref(),
+            rbac_policy.as_deref(),
+        )
+    }
+
+    async fn require_snapshot(&self) -> Result<NodeScheduler, RPCErrors> {
+        self.load_snapshot().await?.ok_or_else(|| {
+            RPCErrors::ReasonError("scheduler snapshot not found, schedule not run yet".to_string())
+        })
+    }
+}
+
+#[async_trait]
+impl SchedulerHandler for SchedulerApiHandler {
+    async fn handle_get_status(&self, _ctx: RPCContext) -> Result<SchedulerStatus, RPCErrors> {
+        let Some(snapshot) = self.load_snapshot().await? else {
+            return Ok(SchedulerStatus {
+                has_snapshot: false,
+                schedule_time: 0,
+                node_count: 0,
+                spec_count: 0,
+                instance_count: 0,
+                pending_op_task_count: 0,
+            });
+        };
+        let pending_op_task_count = collect_op_tasks(&snapshot)
+            .iter()
+            .filter(|(_, task)| task.is_pending())
+            .count();
+        Ok(SchedulerStatus {
+            has_snapshot: true,
+            schedule_time: snapshot.schedule_time,
+            node_count: snapshot.nodes.len(),
+            spec_count: snapshot.specs.len(),
+            instance_count: snapshot.replica_instances.len(),
+            pending_op_task_count,
+        })
+    }
+
+    async fn handle_get_snapshot(&self, ctx: RPCContext) -> Result<Option<Value>, RPCErrors> {
+        Self::require_admin_session(&ctx).await?;
+        let Some(snapshot) = self.load_snapshot().await? else {
+            return Ok(None);
+        };
+        let snapshot = serde_json::to_value(&snapshot)
+            .map_err(|e| RPCErrors::ReasonError(format!("serialize snapshot failed: {}", e)))?;
+        Ok(Some(snapshot))
+    }
+
+    async fn handle_list_nodes(
+        &self,
+        ctx: RPCContext,
+    ) -> Result<Vec<SchedulerNodeInfo>, RPCErrors> {
+        Self::require_admin_session(&ctx).await?;
+        let Some(snapshot) = self.load_snapshot().await? else {
+            return Ok(vec![]);
+        };
+        let mut nodes: Vec<SchedulerNodeInfo> = snapshot
+            .nodes
+            .values()
+            .map(|node| node_info_from_item(node, &snapshot))
+            .collect();
+        nodes.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(nodes)
+    }
+
+    async fn handle_list_specs(
+        &self,
+        ctx: RPCContext,
+    ) -> Result<Vec<SchedulerSpecInfo>, RPCErrors> {
+        Self::require_admin_session(&ctx).await?;
+        let Some(snapshot) = self.load_snapshot().await? else {
+            return Ok(vec![]);
+        };
+        let mut specs: Vec<SchedulerSpecInfo> = snapshot
+            .specs
+            .values()
+            .map(|spec| spec_info_from_spec(spec, &snapshot))
+            .collect();
+        specs.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(specs)
+    }
+
+    async fn handle_list_instances(
+        &self,
+        req: SchedulerListInstancesReq,
+        ctx: RPCContext,
+    ) -> Result<Vec<SchedulerInstanceInfo>, RPCErrors> {
+        Self::require_admin_session(&ctx).await?;
+        let Some(snapshot) = self.load_snapshot().await? else {
+            return Ok(vec![]);
+        };
+        let mut instances: Vec<SchedulerInstanceInfo> = snapshot
+            .replica_instances
+            .values()
+            .filter(|instance| {
+                req.spec_id
+                    .as_ref()
+                    .is_none_or(|spec_id| &instance.spec_id == spec_id)
+                    && req
+                        .node_id
+                        .as_ref()
+                        .is_none_or(|node_id| &instance.node_id == node_id)
+            })
+            .map(instance_info_from_instance)
+            .collect();
+        instances.sort_by(|a, b| a.instance_id.cmp(&b.instance_id));
+        Ok(instances)
+    }
+
+    async fn handle_list_op_tasks(
+        &self,
+        req: SchedulerListOpTasksReq,
+        ctx: RPCContext,
+    ) -> Result<Vec<SchedulerOpTaskInfo>, RPCErrors> {
+        Self::require_admin_session(&ctx).await?;
+        let Some(snapshot) = self.load_snapshot().await? else {
+            return Ok(vec![]);
+        };
+        let tasks = collect_op_tasks(&snapshot)
+            .into_iter()
+            .filter(|(node_id, task)| {
+                req.node_id
+                    .as_ref()
+                    .is_none_or(|expected| expected == node_id)
+                    && (req.include_finished || task.is_pending())
+            })
+            .map(|(node_id, task)| op_task_info_from_task(node_id, task))
+            .collect();
+        Ok(tasks)
+    }
+
+    async fn handle_trigger_schedule(&self, ctx: RPCContext) -> Result<bool, RPCErrors> {
+        Self::require_admin_session(&ctx).await?;
+        info!("scheduler rpc: trigger schedule");
+        trigger_schedule();
+        Ok(true)
+    }
+
+    async fn handle_cancel_op_task(
+        &self,
+        task_id: &str,
+        ctx: RPCContext,
+    ) -> Result<SchedulerOpTaskInfo, RPCErrors> {
+        Self::require_admin_session(&ctx).await?;
+        let snapshot = self.require_snapshot().await?;
+        // 快照只用来定位task所在的node,task本身以system_config中的最新值为准
+        let node_id = collect_op_tasks(&snapshot)
+            .into_iter()
+            .find(|(_, task)| task.id == task_id)
+            .map(|(node_id, _)| node_id)
+            .ok_or_else(|| RPCErrors::ReasonError(format!("op task {} not found", task_id)))?;
+        let runtime = get_buckyos_api_runtime()?;
+        let client = runtime.get_system_config_client().await?;
+        let op_task_key = get_op_task_key(node_id, task_id);
+        let task_value = client.get(op_task_key.as_str()).await.map_err(|err| {
+            let msg = format!("load op task {} failed: {}", op_task_key, err);
+            warn!("{}", msg);
+            RPCErrors::ReasonError(msg)
+        })?;
+        let task: OPTask = serde_json::from_str(task_value.value.as_str())
+            .map_err(|e| RPCErrors::ReasonError(format!("parse op task failed: {}", e)))?;
+        if !task.is_pending() {
+            let msg = format!(
+                "op task {} is already {}, cannot cancel",
+                task_id, task.status
+            );
+            warn!("{}", msg);
+            return Err(RPCErrors::ReasonError(msg));
+        }
+
+        let node_state = match client.get(get_node_state_key(node_id).as_str()).await {
+            Ok(value) => crate::scheduler::NodeState::from(value.value),
+            Err(SystemConfigError::KeyNotFound(_)) => snapshot
+                .nodes
+                .get(node_id)
+                .map(|node| node.state.clone())
+                .unwrap_or(crate::scheduler::NodeState::Ready),
+            Err(err) => {
+                let msg = format!("load node {} state failed: {}", node_id, err);
+                warn!("{}", msg);
+                return Err(RPCErrors::ReasonError(msg));
+            }
+        };
+        let (canceled_task, tx_actions) = cancel_op_task_tx_actions(node_id, &task, &node_state)
+            .map_err(|e| RPCErrors::ReasonError(format!("build cancel op task failed: {}", e)))?;
+        // 以op task的revision作为main_key,调度循环在此期间更新了task时取消失败,由调用方重试
+        client
+            .exec_tx(tx_actions, Some((op_task_key, task_value.version)))
+            .await
+            .map_err(|err| {
+                let msg = format!("cancel op task {} failed: {}", task_id, err);
+                error!("{}", msg);
+                RPCErrors::ReasonError(msg)
+            })?;
+        info!(
+            "scheduler rpc: op task {} on node {} canceled",
+            task_id, node_id
+        );
+        trigger_schedule();
+        Ok(op_task_info_from_task(node_id, &canceled_task))
+    }
+
+    async fn handle_dry_run(
+        &self,
+        req: SchedulerDryRunReq,
+        ctx: RPCContext,
+    ) -> Result<SchedulerDryRunResult, RPCErrors> {
+        Self::require_admin_session(&ctx).await?;
+        let snapshot = self.require_snapshot().await?;
+        let result = dry_run_schedule(&snapshot, &req.mutations).map_err(|err| {
+            let msg = format!("scheduler dry run failed: {}", err);
+            warn!("{}", msg);
+            RPCErrors::ReasonError(msg)
+        })?;
+        info!(
+            "scheduler rpc: dry run with {} mutations produced {} actions",
+            req.mutations.len(),
+            result.actions.len()
+        );
+        Ok(result)
+    }
+}
+
 #[derive(Clone)]
-pub struct SchedulerServer {}
+pub struct SchedulerServer {
+    rpc_handler: Arc<SchedulerServerHandler<SchedulerApiHandler>>,
+}
 
 impl SchedulerServer {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            rpc_handler: Arc::new(SchedulerServerHandler::new(SchedulerApiHandler::new())),
+        }
     }
 }
 
@@ -32,7 +433,7 @@ impl RPCHandler for SchedulerServer {
         req: RPCRequest,
         ip_from: IpAddr,
     ) -> Result<RPCResponse, RPCErrors> {
-        unimplemented!()
+        self.rpc_handler.handle_rpc_call(req, ip_from).await
     }
 }
 
@@ -64,3 +465,33 @@ impl HttpServer for SchedulerServer {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_admin_session_rejects_non_admin_control_panel_session() {
+        let settings = r#"{"type":"user"}"#;
+        let policy = "g, alice, admin\n";
+        let result = check_admin_session(
+            "bob",
+            CONTROL_PANEL_SERVICE_NAME,
+            Some(settings),
+            Some(policy),
+        );
+        assert!(matches!(result, Err(RPCErrors::NoPermission(_))));
+        let result = check_admin_session("bob", "kernel", None, None);
+        assert!(matches!(result, Err(RPCErrors::NoPermission(_))));
+    }
+
+    #[test]
+    fn test_check_admin_session_accepts_admin_users() {
+        let settings = r#"{"type":"admin"}"#;
+        assert!(check_admin_session("alice", "buckycli", Some(settings), None).is_ok());
+        let settings = r#"{"user_type":"root"}"#;
+        assert!(check_admin_session("root", "buckycli", Some(settings), None).is_ok());
+        let policy = "g, alice, admin\n";
+        assert!(check_admin_session("alice", "buckycli", None, Some(policy)).is_ok());
+    }
+}
@@ -8,10 +8,29 @@
 // 3. Distributed-system liveness cases:
 //    service discovery should only publish fresh running replicas and update
 //    cluster membership after heartbeat state changes.
+// 4. Dry run / plan preview:
+//    hypothetical mutations are applied to a cloned snapshot, the resulting
+//    actions carry readable reasons and the snapshot itself is left untouched.
+// 5. Node drain:
+//    a Removing node keeps its replicas until replacements on other Ready
+//    nodes report Running, is only then marked Deleted, and a drain that
+//    cannot finish in time fails and leaves the node Abnormal.
+// 6. Rebalancing (Step3):
+//    opt-in only; moves replicas off an overloaded node onto idle nodes as
+//    paired replica/remove actions, capped per round and cooled down per spec,
+//    and never touches specs whose replicas are not all running.
+// 7. Affinity expressions:
+//    required label selectors filter nodes, preferred ones add weight to the
+//    score, and replica spread keeps replicas out of a shared topology domain.
 use std::collections::{HashMap, HashSet};
 
+use buckyos_api::{
+    LabelSelectorOperator, LabelSelectorRequirement, NodeAffinity, PreferredLabelSelector,
+    ReplicaSpread, SchedulerDryRunMutation,
+};
 use buckyos_kit::buckyos_get_unix_timestamp;
 
+use crate::dry_run::dry_run_schedule;
 use crate::scheduler::*;
 
 fn create_test_node(
@@ -57,6 +76,7 @@ fn create_test_service_spec(id: &str) -> ServiceSpec {
         required_gpu_mem: 0,
         node_affinity: None,
         network_affinity: None,
+        replica_spread: None,
         app_index: 0,
         service_ports_config: HashMap::new(),
     }
@@ -76,6 +96,7 @@ fn create_test_replica_instance(
         last_update_time,
         state,
         service_ports: HashMap::new(),
+        lease_expire_time: None,
     }
 }
 
@@ -107,7 +128,7 @@ fn service_info_nodes(service_info: &ServiceInfo) -> HashSet<String> {
 
 // test node state change
 // New -> Prepare
-// Removing -> Deleted
+// Removing is left to drain_removing_nodes
 #[test]
 fn test_node_state_change() {
     let mut scheduler = NodeScheduler::new_empty(1);
@@ -145,16 +166,15 @@ fn test_node_state_change() {
     scheduler.add_node(node3);
 
     let actions = scheduler.resort_nodes().unwrap();
-    assert_eq!(actions.len(), 2);
-    if let SchedulerAction::ChangeNodeStatus(node_id, new_state) = &actions[0] {
-        if node_id == "node1" {
+    assert_eq!(actions.len(), 1);
+    match &actions[0] {
+        SchedulerAction::ChangeNodeStatus(node_id, new_state) => {
+            assert_eq!(node_id, "node1");
             assert_eq!(new_state, &NodeState::Prepare);
-        } else if node_id == "node2" {
-            assert_eq!(new_state, &NodeState::Deleted);
-        } else {
-            panic!("Unexpected node id: {}", node_id);
         }
+        other => panic!("unexpected action: {:?}", other),
     }
+    assert_eq!(scheduler.nodes["node2"].state, NodeState::Removing);
 }
 
 // test create service_spec instance
@@ -176,6 +196,7 @@ fn test_create_pod_instance() {
         required_gpu_mem: 0,
         node_affinity: None,
         network_affinity: None,
+        replica_spread: None,
         app_index: 0,
         service_ports_config: HashMap::new(),
     };
@@ -241,6 +262,7 @@ fn test_pod_state_change() {
         required_gpu_mem: 0,
         node_affinity: None,
         network_affinity: None,
+        replica_spread: None,
         app_index: 10,
         service_ports_config: HashMap::new(),
     };
@@ -258,6 +280,7 @@ fn test_pod_state_change() {
         required_gpu_mem: 0,
         node_affinity: None,
         network_affinity: None,
+        replica_spread: None,
         app_index: 12,
         service_ports_config: HashMap::new(),
     };
@@ -340,6 +363,7 @@ fn test_app_disable_updates_instance_instead_of_removing_it() {
         required_gpu_mem: 0,
         node_affinity: None,
         network_affinity: None,
+        replica_spread: None,
         app_index: 11,
         service_ports_config: HashMap::new(),
     });
@@ -352,6 +376,7 @@ fn test_app_disable_updates_instance_instead_of_removing_it() {
         last_update_time: buckyos_get_unix_timestamp(),
         state: InstanceState::Running,
         service_ports: HashMap::new(),
+        lease_expire_time: None,
     });
 
     let actions = scheduler.schedule_spec_change().unwrap();
@@ -385,6 +410,7 @@ fn test_create_pod_instance_no_suitable_node() {
         required_gpu_mem: 0,
         node_affinity: None,
         network_affinity: None,
+        replica_spread: None,
         app_index: 10,
         service_ports_config: HashMap::new(),
     };
@@ -424,8 +450,9 @@ fn test_node_and_network_affinity() {
         required_memory: 1024 * 1024 * 256,
         required_gpu_tflops: 0.0,
         required_gpu_mem: 0,
-        node_affinity: Some("gpu".to_string()),
+        node_affinity: Some(NodeAffinity::required_label("gpu")),
         network_affinity: Some("zone3".to_string()),
+        replica_spread: None,
         app_index: 12,
         service_ports_config: HashMap::new(),
     };
@@ -567,19 +594,19 @@ fn test_large_cluster_multi_service_schedule() {
 
     let mut gpu_service = create_test_service_spec("gpu-service");
     gpu_service.best_instance_count = 2;
-    gpu_service.node_affinity = Some("gpu".to_string());
+    gpu_service.node_affinity = Some(NodeAffinity::required_label("gpu"));
 
     let mut db_service = create_test_service_spec("db-service");
-    db_service.node_affinity = Some("db".to_string());
+    db_service.node_affinity = Some(NodeAffinity::required_label("db"));
     db_service.network_affinity = Some("zone-c".to_string());
 
     let mut edge_service = create_test_service_spec("edge-service");
-    edge_service.node_affinity = Some("edge".to_string());
+    edge_service.node_affinity = Some(NodeAffinity::required_label("edge"));
     edge_service.need_container = true;
 
     let mut frontend_service = create_test_service_spec("frontend-service");
     frontend_service.best_instance_count = 2;
-    frontend_service.node_affinity = Some("frontend".to_string());
+    frontend_service.node_affinity = Some(NodeAffinity::required_label("frontend"));
 
     for spec in [gpu_service, db_service, edge_service, frontend_service] {
         scheduler.add_service_spec(spec);
@@ -821,6 +848,60 @@ fn test_service_info_only_publishes_alive_running_replicas() {
     );
 }
 
+#[test]
+fn test_service_info_trusts_sys_config_lease_for_leased_reports() {
+    let mut scheduler = NodeScheduler::new_empty(1);
+    for node_id in ["node1", "node2", "node3"] {
+        scheduler.add_node(create_test_node(
+            node_id,
+            4000,
+            1024 * 1024 * 2048,
+            vec!["search".to_string()],
+            0.0,
+            NodeState::Ready,
+            "zone-search",
+        ));
+    }
+
+    let mut search_service = create_test_service_spec("search-service");
+    search_service.state = ServiceSpecState::Deployed;
+    search_service.best_instance_count = 3;
+    scheduler.add_service_spec(search_service);
+
+    let now = buckyos_get_unix_timestamp();
+    // last_update_time is far outside INSTANCE_ALIVE_TIME, but the lease is still valid
+    let mut leased =
+        create_test_replica_instance("search-service", "node1", InstanceState::Running, 0);
+    leased.lease_expire_time = Some(now + 60);
+    scheduler.add_replica_instance(leased);
+    // the lease expiry is behind the local clock (clock skew with sys_config),
+    // but sys_config still keeps the report key, so the lease is alive
+    let mut skewed =
+        create_test_replica_instance("search-service", "node2", InstanceState::Running, 0);
+    skewed.lease_expire_time = Some(now - 1);
+    scheduler.add_replica_instance(skewed);
+    // legacy report without lease falls back to last_update_time
+    scheduler.add_replica_instance(create_test_replica_instance(
+        "search-service",
+        "node3",
+        InstanceState::Running,
+        0,
+    ));
+
+    let actions = scheduler.schedule(None).unwrap();
+    assert_eq!(actions.len(), 1);
+    match &actions[0] {
+        SchedulerAction::UpdateServiceInfo(spec_id, service_info) => {
+            assert_eq!(spec_id, "search-service");
+            assert_eq!(
+                service_info_nodes(service_info),
+                node_set(&["node1", "node2"])
+            );
+        }
+        other => panic!("unexpected action: {:?}", other),
+    }
+}
+
 #[test]
 fn test_service_info_refresh_is_throttled_within_30_seconds() {
     let mut last_snapshot = NodeScheduler::new_empty(1);
@@ -1042,3 +1123,818 @@ fn test_best_instance_count_change_triggers_scale_out_for_deployed_service() {
         node_set(&["node1", "node2"])
     );
 }
+
+fn create_deployed_snapshot() -> NodeScheduler {
+    let mut scheduler = NodeScheduler::new_empty(1);
+    for node_id in ["node1", "node2"] {
+        scheduler.add_node(create_test_node(
+            node_id,
+            4000,
+            1024 * 1024 * 2048,
+            vec!["core".to_string()],
+            0.0,
+            NodeState::Ready,
+            "zone-1",
+        ));
+    }
+
+    let mut api_service = create_test_service_spec("api-service");
+    api_service.state = ServiceSpecState::Deployed;
+    scheduler.add_service_spec(api_service);
+    scheduler.add_replica_instance(create_test_replica_instance(
+        "api-service",
+        "node1",
+        InstanceState::Running,
+        buckyos_get_unix_timestamp(),
+    ));
+    scheduler.schedule(None).unwrap();
+    scheduler
+}
+
+#[test]
+fn test_dry_run_scale_out_previews_placement_without_touching_snapshot() {
+    let snapshot = create_deployed_snapshot();
+
+    let result = dry_run_schedule(
+        &snapshot,
+        &[SchedulerDryRunMutation::SetBestInstanceCount {
+            spec_id: "api-service".to_string(),
+            count: 2,
+        }],
+    )
+    .unwrap();
+
+    assert_eq!(
+        result.notes,
+        vec!["spec api-service best_instance_count: 1 -> 2".to_string()]
+    );
+    let replica_actions: Vec<_> = result
+        .actions
+        .iter()
+        .filter(|action| action.action == "InstanceReplica")
+        .collect();
+    assert_eq!(replica_actions.len(), 1);
+    assert_eq!(replica_actions[0].target, "api-service@node2");
+    assert!(replica_actions[0]
+        .reason
+        .contains("spec api-service wants 2 instance(s) but has 1, place on node node2"));
+
+    assert_eq!(snapshot.specs["api-service"].best_instance_count, 1);
+    assert_eq!(snapshot.replica_instances.len(), 1);
+}
+
+#[test]
+fn test_dry_run_remove_node_and_unknown_target() {
+    let snapshot = create_deployed_snapshot();
+
+    let result = dry_run_schedule(
+        &snapshot,
+        &[SchedulerDryRunMutation::RemoveNode {
+            node_id: "node2".to_string(),
+        }],
+    )
+    .unwrap();
+    assert_eq!(
+        result.notes,
+        vec!["node node2: Ready -> Removing".to_string()]
+    );
+    let node_action = result
+        .actions
+        .iter()
+        .find(|action| action.action == "ChangeNodeStatus")
+        .expect("removing node should produce a node action");
+    assert_eq!(node_action.target, "node2");
+    assert_eq!(
+        node_action.reason,
+        "node node2 is Removing, move it to Deleted"
+    );
+    assert!(result
+        .actions
+        .iter()
+        .any(|action| action.action == "CreateOPTask" && action.target == "node2"));
+    assert_eq!(snapshot.nodes["node2"].state, NodeState::Ready);
+
+    // node1 still hosts api-service, so removing it only previews the replacement
+    let result = dry_run_schedule(
+        &snapshot,
+        &[SchedulerDryRunMutation::RemoveNode {
+            node_id: "node1".to_string(),
+        }],
+    )
+    .unwrap();
+    let replica_action = result
+        .actions
+        .iter()
+        .find(|action| action.action == "InstanceReplica")
+        .expect("draining node1 should place a replacement");
+    assert!(replica_action.reason.starts_with(
+        "spec api-service has an instance on removing node node1, place replacement on node node2"
+    ));
+    assert!(!result
+        .actions
+        .iter()
+        .any(|action| action.action == "RemoveInstance" || action.action == "ChangeNodeStatus"));
+
+    let err = dry_run_schedule(
+        &snapshot,
+        &[SchedulerDryRunMutation::SetSpecAffinity {
+            spec_id: "missing-service".to_string(),
+            node_affinity: Some(NodeAffinity::required_label("gpu")),
+            network_affinity: None,
+            replica_spread: None,
+        }],
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("spec missing-service not found"));
+}
+
+fn drain_task(node: &NodeItem) -> &OPTask {
+    node.op_tasks
+        .iter()
+        .find(|task| matches!(task.body, OPTaskBody::DrainNode { .. }))
+        .expect("removing node should have a drain task")
+}
+
+#[test]
+fn test_drain_node_waits_for_running_replacement_before_delete() {
+    let mut scheduler = NodeScheduler::new_empty(1);
+    for (node_id, state) in [
+        ("node1", NodeState::Ready),
+        ("node2", NodeState::Ready),
+        ("node3", NodeState::Removing),
+    ] {
+        scheduler.add_node(create_test_node(
+            node_id,
+            4000,
+            1024 * 1024 * 2048,
+            vec![],
+            0.0,
+            state,
+            "zone-1",
+        ));
+    }
+    let mut api_service = create_test_service_spec("api-service");
+    api_service.state = ServiceSpecState::Deployed;
+    scheduler.add_service_spec(api_service);
+    scheduler.add_replica_instance(create_test_replica_instance(
+        "api-service",
+        "node3",
+        InstanceState::Running,
+        buckyos_get_unix_timestamp(),
+    ));
+
+    // round 1: start the drain and place a replacement, the old replica stays
+    let actions = scheduler.schedule(None).unwrap();
+    assert!(actions.iter().any(|action| matches!(
+        action,
+        SchedulerAction::CreateOPTask(node_id, task)
+            if node_id == "node3"
+                && task.status == OPTaskState::Running
+                && task.body == OPTaskBody::DrainNode { total_instances: 1, drained_instances: 0 }
+    )));
+    let replacement_nodes = action_instance_nodes(&actions, "api-service");
+    assert_eq!(replacement_nodes.len(), 1);
+    assert!(!replacement_nodes.contains("node3"));
+    assert!(!actions
+        .iter()
+        .any(|action| matches!(action, SchedulerAction::RemoveInstance(..))));
+    assert_eq!(scheduler.nodes["node3"].state, NodeState::Removing);
+    assert!(scheduler
+        .replica_instances
+        .contains_key("api-service@node3"));
+    let replacement_node = replacement_nodes.into_iter().next().unwrap();
+    let replacement_id = format!("api-service@{}", replacement_node);
+
+    // round 2: the replacement has not reported Running yet, keep waiting
+    let mut pending_round = scheduler.clone();
+    pending_round
+        .replica_instances
+        .get_mut(&replacement_id)
+        .unwrap()
+        .state = InstanceState::Prepare;
+    let actions = pending_round.schedule(Some(&scheduler)).unwrap();
+    assert!(!actions.iter().any(|action| matches!(
+        action,
+        SchedulerAction::RemoveInstance(..)
+            | SchedulerAction::InstanceReplica(..)
+            | SchedulerAction::UpdateOPTask(..)
+    )));
+    assert_eq!(pending_round.nodes["node3"].state, NodeState::Removing);
+
+    // round 3: the replacement is Running, remove the old replica and delete the node
+    let mut running_round = scheduler.clone();
+    let actions = running_round.schedule(Some(&scheduler)).unwrap();
+    assert!(actions.iter().any(|action| matches!(
+        action,
+        SchedulerAction::RemoveInstance(spec_id, instance_id, node_id)
+            if spec_id == "api-service" && instance_id == "api-service@node3" && node_id == "node3"
+    )));
+    assert!(actions.iter().any(|action| matches!(
+        action,
+        SchedulerAction::ChangeNodeStatus(node_id, NodeState::Deleted) if node_id == "node3"
+    )));
+    let task = drain_task(&running_round.nodes["node3"]);
+    assert_eq!(task.status, OPTaskState::Done);
+    assert_eq!(
+        task.body,
+        OPTaskBody::DrainNode {
+            total_instances: 1,
+            drained_instances: 1
+        }
+    );
+    assert!(actions.iter().any(|action| matches!(
+        action,
+        SchedulerAction::UpdateOPTask(node_id, updated) if node_id == "node3" && updated == task
+    )));
+}
+
+#[test]
+fn test_drain_node_moves_app_instance_without_liveness_report() {
+    let mut scheduler = NodeScheduler::new_empty(1);
+    for (node_id, state) in [("node1", NodeState::Ready), ("node3", NodeState::Removing)] {
+        scheduler.add_node(create_test_node(
+            node_id,
+            4000,
+            1024 * 1024 * 2048,
+            vec![],
+            0.0,
+            state,
+            "zone-1",
+        ));
+    }
+    let mut app_spec = create_test_service_spec("jarvis@alice");
+    app_spec.app_id = "jarvis".to_string();
+    app_spec.owner_id = "alice".to_string();
+    app_spec.spec_type = ServiceSpecType::App;
+    app_spec.state = ServiceSpecState::Deployed;
+    scheduler.add_service_spec(app_spec);
+    // app instances are loaded from the node config, they never carry a liveness report
+    scheduler.add_replica_instance(create_test_replica_instance(
+        "jarvis@alice",
+        "node3",
+        InstanceState::Running,
+        0,
+    ));
+
+    // round 1: place the replacement on node1, the old instance stays
+    let actions = scheduler.schedule(None).unwrap();
+    assert_eq!(
+        action_instance_nodes(&actions, "jarvis@alice"),
+        node_set(&["node1"])
+    );
+    assert!(!actions
+        .iter()
+        .any(|action| matches!(action, SchedulerAction::RemoveInstance(..))));
+
+    // round 2: the replacement is reloaded from the node config without a liveness report
+    let mut next_round = scheduler.clone();
+    next_round
+        .replica_instances
+        .get_mut("jarvis@alice@node1")
+        .unwrap()
+        .last_update_time = 0;
+    let actions = next_round.schedule(Some(&scheduler)).unwrap();
+    assert!(actions.iter().any(|action| matches!(
+        action,
+        SchedulerAction::RemoveInstance(spec_id, instance_id, node_id)
+            if spec_id == "jarvis@alice" && instance_id == "jarvis@alice@node3" && node_id == "node3"
+    )));
+    assert!(actions.iter().any(|action| matches!(
+        action,
+        SchedulerAction::ChangeNodeStatus(node_id, NodeState::Deleted) if node_id == "node3"
+    )));
+    assert_eq!(
+        drain_task(&next_round.nodes["node3"]).status,
+        OPTaskState::Done
+    );
+}
+
+#[test]
+fn test_drain_node_without_placement_times_out_to_abnormal() {
+    let mut scheduler = NodeScheduler::new_empty(1);
+    scheduler.add_node(create_test_node(
+        "node1",
+        50,
+        1024 * 1024 * 2048,
+        vec![],
+        0.0,
+        NodeState::Ready,
+        "zone-1",
+    ));
+    scheduler.add_node(create_test_node(
+        "node2",
+        4000,
+        1024 * 1024 * 2048,
+        vec![],
+        0.0,
+        NodeState::Removing,
+        "zone-1",
+    ));
+    let mut api_service = create_test_service_spec("api-service");
+    api_service.state = ServiceSpecState::Deployed;
+    scheduler.add_service_spec(api_service);
+    scheduler.add_replica_instance(create_test_replica_instance(
+        "api-service",
+        "node2",
+        InstanceState::Running,
+        buckyos_get_unix_timestamp(),
+    ));
+
+    // node1 cannot host the replica, so the drain keeps the old one running
+    let actions = scheduler.schedule(None).unwrap();
+    assert!(actions.iter().any(
+        |action| matches!(action, SchedulerAction::CreateOPTask(node_id, _) if node_id == "node2")
+    ));
+    assert!(!actions.iter().any(|action| matches!(
+        action,
+        SchedulerAction::InstanceReplica(..) | SchedulerAction::RemoveInstance(..)
+    )));
+
+    let mut timeout_round = scheduler.clone();
+    let task = timeout_round
+        .nodes
+        .get_mut("node2")
+        .unwrap()
+        .op_tasks
+        .first_mut()
+        .unwrap();
+    task.start_time = task.start_time.saturating_sub(task.max_timeout_sec + 1);
+    let actions = timeout_round.schedule(Some(&scheduler)).unwrap();
+    assert!(actions.iter().any(|action| matches!(
+        action,
+        SchedulerAction::UpdateOPTask(node_id, task)
+            if node_id == "node2" && task.status == OPTaskState::Failed
+    )));
+    assert!(actions.iter().any(|action| matches!(
+        action,
+        SchedulerAction::ChangeNodeStatus(node_id, NodeState::Abnormal) if node_id == "node2"
+    )));
+    assert!(!actions
+        .iter()
+        .any(|action| matches!(action, SchedulerAction::RemoveInstance(..))));
+    assert!(timeout_round
+        .replica_instances
+        .contains_key("api-service@node2"));
+}
+
+#[test]
+fn test_drain_node_is_not_restarted_after_task_finished() {
+    let now = buckyos_get_unix_timestamp();
+    for (status, expected_state) in [
+        (OPTaskState::Done, NodeState::Deleted),
+        (OPTaskState::Failed, NodeState::Abnormal),
+        (OPTaskState::Canceled, NodeState::Ready),
+    ] {
+        let mut scheduler = NodeScheduler::new_empty(1);
+        scheduler.add_node(create_test_node(
+            "node1",
+            4000,
+            1024 * 1024 * 2048,
+            vec![],
+            0.0,
+            NodeState::Ready,
+            "zone-1",
+        ));
+        let mut removing_node = create_test_node(
+            "node2",
+            4000,
+            1024 * 1024 * 2048,
+            vec![],
+            0.0,
+            NodeState::Removing,
+            "zone-1",
+        );
+        removing_node.op_tasks.push(OPTask {
+            id: format!("drain-node2-{}", now),
+            creator_id: None,
+            body: OPTaskBody::DrainNode {
+                total_instances: 1,
+                drained_instances: 0,
+            },
+            create_time: now,
+            create_step_id: 1,
+            max_timeout_sec: 600,
+            status: status.clone(),
+            start_time: now,
+        });
+        scheduler.add_node(removing_node);
+
+        // Removing with a finished drain task: restore the drain result instead of a new task
+        let actions = scheduler.schedule(None).unwrap();
+        assert!(!actions.iter().any(|action| matches!(
+            action,
+            SchedulerAction::CreateOPTask(..) | SchedulerAction::UpdateOPTask(..)
+        )));
+        assert!(actions.iter().any(|action| matches!(
+            action,
+            SchedulerAction::ChangeNodeStatus(node_id, state)
+                if node_id == "node2" && *state == expected_state
+        )));
+        let node = scheduler.nodes.get("node2").unwrap();
+        assert_eq!(node.state, expected_state);
+        assert_eq!(node.op_tasks.len(), 1);
+        assert_eq!(node.op_tasks[0].status, status);
+    }
+}
+
+#[test]
+fn test_finished_op_tasks_are_removed_after_retention() {
+    let now = buckyos_get_unix_timestamp();
+    let mut scheduler = NodeScheduler::new_empty(1);
+    let mut node = create_test_node(
+        "node1",
+        4000,
+        1024 * 1024 * 2048,
+        vec![],
+        0.0,
+        NodeState::Ready,
+        "zone-1",
+    );
+    for (task_id, status, create_time) in [
+        ("expired-done", OPTaskState::Done, 100),
+        ("expired-pending", OPTaskState::Running, 100),
+        ("recent-done", OPTaskState::Done, now),
+    ] {
+        node.op_tasks.push(OPTask {
+            id: task_id.to_string(),
+            creator_id: None,
+            body: OPTaskBody::NodeInitBaseService,
+            create_time,
+            create_step_id: 1,
+            max_timeout_sec: 0,
+            status,
+            start_time: create_time,
+        });
+    }
+    scheduler.add_node(node);
+
+    let actions = scheduler.schedule(None).unwrap();
+    let removed: Vec<&str> = actions
+        .iter()
+        .filter_map(|action| match action {
+            SchedulerAction::RemoveOPTask(node_id, task_id) if node_id == "node1" => {
+                Some(task_id.as_str())
+            }
+            _ => None,
+        })
+        .collect();
+    assert_eq!(removed, vec!["expired-done"]);
+    let task_ids: Vec<&str> = scheduler.nodes["node1"]
+        .op_tasks
+        .iter()
+        .map(|task| task.id.as_str())
+        .collect();
+    assert_eq!(task_ids, vec!["expired-pending", "recent-done"]);
+}
+
+fn create_overloaded_scheduler(spec_ids: &[&str]) -> NodeScheduler {
+    let mut scheduler = NodeScheduler::new_empty(1);
+    let mut busy_node = create_test_node(
+        "node1",
+        4000,
+        1024 * 1024 * 2048,
+        vec![],
+        0.9,
+        NodeState::Ready,
+        "zone-1",
+    );
+    busy_node.available_cpu_mhz = 400;
+    busy_node.available_memory = 1024 * 1024 * 256;
+    scheduler.add_node(busy_node);
+    for node_id in ["node2", "node3"] {
+        scheduler.add_node(create_test_node(
+            node_id,
+            4000,
+            1024 * 1024 * 2048,
+            vec![],
+            0.0,
+            NodeState::Ready,
+            "zone-1",
+        ));
+    }
+    for spec_id in spec_ids {
+        let mut spec = create_test_service_spec(spec_id);
+        spec.state = ServiceSpecState::Deployed;
+        scheduler.add_service_spec(spec);
+        scheduler.add_replica_instance(create_test_replica_instance(
+            spec_id,
+            "node1",
+            InstanceState::Running,
+            buckyos_get_unix_timestamp(),
+        ));
+    }
+    scheduler
+}
+
+// make-before-break: a move only places the replacement, nothing is removed in the same round
+fn moved_specs(actions: &[SchedulerAction]) -> HashSet<String> {
+    let mut replicas = HashSet::new();
+    for action in actions {
+        match action {
+            SchedulerAction::InstanceReplica(instance) => {
+                assert_ne!(instance.node_id, "node1");
+                replicas.insert(instance.spec_id.clone());
+            }
+            SchedulerAction::RemoveInstance(spec_id, ..) => {
+                panic!(
+                    "spec {} removes an instance before its replacement is ready",
+                    spec_id
+                )
+            }
+            _ => {}
+        }
+    }
+    replicas
+}
+
+fn removed_instances(actions: &[SchedulerAction]) -> HashSet<String> {
+    actions
+        .iter()
+        .filter_map(|action| match action {
+            SchedulerAction::RemoveInstance(_, instance_id, _) => Some(instance_id.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+// the replacements report Running from their new nodes
+fn report_rebalance_replacements(scheduler: &mut NodeScheduler, last: &NodeScheduler) {
+    for (spec_id, rebalance_move) in last.rebalance_moves.iter() {
+        scheduler.add_replica_instance(create_test_replica_instance(
+            spec_id,
+            rebalance_move.to_node_id.as_str(),
+            InstanceState::Running,
+            buckyos_get_unix_timestamp(),
+        ));
+    }
+}
+
+#[test]
+fn test_rebalance_is_opt_in_and_limits_churn() {
+    let specs = ["svc-a", "svc-b", "svc-c"];
+    let mut disabled = create_overloaded_scheduler(&specs);
+    let actions = disabled.schedule(None).unwrap();
+    assert!(moved_specs(&actions).is_empty());
+
+    let mut round1 = create_overloaded_scheduler(&specs);
+    round1.rebalance_config = RebalanceConfig {
+        enabled: true,
+        max_moves_per_round: 2,
+        spec_cooldown_secs: 600,
+        min_score_gain: 20.0,
+    };
+    let actions = round1.schedule(None).unwrap();
+    assert_eq!(
+        moved_specs(&actions),
+        ["svc-a", "svc-b"].iter().map(|id| id.to_string()).collect()
+    );
+    // the shadow ledger spreads the two moves over both idle nodes
+    assert_eq!(
+        action_instance_nodes(&actions, "svc-a")
+            .union(&action_instance_nodes(&actions, "svc-b"))
+            .cloned()
+            .collect::<HashSet<_>>(),
+        node_set(&["node2", "node3"])
+    );
+    assert!(round1.replica_instances.contains_key("svc-a@node1"));
+    assert_eq!(round1.rebalance_moves.len(), 2);
+    assert!(round1.spec_rebalance_times.contains_key("svc-b"));
+
+    // replacements are not reported yet: keep the old instances, only the remaining spec moves
+    let mut round2 = create_overloaded_scheduler(&specs);
+    round2.rebalance_config = round1.rebalance_config.clone();
+    let actions = round2.schedule(Some(&round1)).unwrap();
+    assert_eq!(
+        moved_specs(&actions),
+        ["svc-c"].iter().map(|id| id.to_string()).collect()
+    );
+    assert_eq!(round2.spec_rebalance_times.len(), 3);
+    assert_eq!(round2.rebalance_moves.len(), 3);
+
+    // replacements are running: now the old instances are removed
+    let mut round3 = create_overloaded_scheduler(&specs);
+    round3.rebalance_config = round2.rebalance_config.clone();
+    report_rebalance_replacements(&mut round3, &round2);
+    let actions = round3.schedule(Some(&round2)).unwrap();
+    assert!(!actions
+        .iter()
+        .any(|action| matches!(action, SchedulerAction::InstanceReplica(..))));
+    assert_eq!(
+        removed_instances(&actions),
+        ["svc-a@node1", "svc-b@node1", "svc-c@node1"]
+            .iter()
+            .map(|id| id.to_string())
+            .collect()
+    );
+    assert!(round3.rebalance_moves.is_empty());
+
+    // after the cooldown, replicas on similar idle nodes stay where they are
+    let mut last = round3.clone();
+    for move_time in last.spec_rebalance_times.values_mut() {
+        *move_time = move_time.saturating_sub(601);
+    }
+    let mut round4 = create_overloaded_scheduler(&specs);
+    round4.rebalance_config = round3.rebalance_config.clone();
+    round4.replica_instances = round3.replica_instances.clone();
+    let actions = round4.schedule(Some(&last)).unwrap();
+    assert!(moved_specs(&actions).is_empty());
+}
+
+#[test]
+fn test_rebalance_rolls_back_replacement_not_ready_in_time() {
+    let mut round1 = create_overloaded_scheduler(&["svc-a"]);
+    round1.rebalance_config.enabled = true;
+    let actions = round1.schedule(None).unwrap();
+    assert_eq!(moved_specs(&actions).len(), 1);
+    let rebalance_move = round1.rebalance_moves["svc-a"].clone();
+
+    // the replacement reports but never becomes Running before the timeout
+    let mut last = round1.clone();
+    last.rebalance_moves.get_mut("svc-a").unwrap().start_time -= 601;
+    let mut round2 = create_overloaded_scheduler(&["svc-a"]);
+    round2.rebalance_config = round1.rebalance_config.clone();
+    round2.add_replica_instance(create_test_replica_instance(
+        "svc-a",
+        rebalance_move.to_node_id.as_str(),
+        InstanceState::Prepare,
+        buckyos_get_unix_timestamp(),
+    ));
+    let actions = round2.schedule(Some(&last)).unwrap();
+    assert_eq!(
+        removed_instances(&actions),
+        [rebalance_move.to_instance_id.clone()]
+            .into_iter()
+            .collect()
+    );
+    assert!(round2.replica_instances.contains_key("svc-a@node1"));
+    assert!(round2.rebalance_moves.is_empty());
+}
+
+#[test]
+fn test_rebalance_skips_spec_with_unstable_replicas() {
+    let mut scheduler = create_overloaded_scheduler(&["svc-a"]);
+    scheduler.rebalance_config.enabled = true;
+    scheduler
+        .specs
+        .get_mut("svc-a")
+        .unwrap()
+        .best_instance_count = 2;
+    scheduler.add_replica_instance(create_test_replica_instance(
+        "svc-a",
+        "node2",
+        InstanceState::Prepare,
+        buckyos_get_unix_timestamp(),
+    ));
+    let last = scheduler.clone();
+
+    let actions = scheduler.schedule(Some(&last)).unwrap();
+    assert!(moved_specs(&actions).is_empty());
+    assert!(scheduler.replica_instances.contains_key("svc-a@node1"));
+    assert!(scheduler.spec_rebalance_times.is_empty());
+}
+
+#[test]
+fn test_label_selector_affinity_filters_and_prefers_nodes() {
+    let mut scheduler = NodeScheduler::new_empty(1);
+    let node_labels = [
+        ("node1", vec!["ssd", "rack=r1"]),
+        ("node2", vec!["ssd", "rack=r2"]),
+        ("node3", vec!["rack=r2"]),
+        ("node4", vec!["ssd", "rack=r2"]),
+    ];
+    for (node_id, labels) in node_labels {
+        let mut node = create_test_node(
+            node_id,
+            4000,
+            1024 * 1024 * 2048,
+            labels.iter().map(|label| label.to_string()).collect(),
+            0.0,
+            NodeState::Ready,
+            "zone-1",
+        );
+        if node_id == "node4" {
+            node.node_type = NodeType::Server;
+        }
+        scheduler.add_node(node);
+    }
+
+    // run on an ssd node that is not a server, prefer rack r2
+    let mut spec = create_test_service_spec("storage-service");
+    spec.node_affinity = Some(NodeAffinity {
+        required: vec![
+            LabelSelectorRequirement::new("ssd", LabelSelectorOperator::Exists, vec![]),
+            LabelSelectorRequirement::new(
+                "node_type",
+                LabelSelectorOperator::NotIn,
+                vec!["server".to_string()],
+            ),
+        ],
+        preferred: vec![PreferredLabelSelector {
+            weight: 100,
+            match_expressions: vec![LabelSelectorRequirement::new(
+                "rack",
+                LabelSelectorOperator::In,
+                vec!["r2".to_string()],
+            )],
+        }],
+    });
+    scheduler.add_service_spec(spec.clone());
+    let actions = scheduler.schedule_spec_change().unwrap();
+    assert_eq!(
+        action_instance_nodes(&actions, "storage-service"),
+        node_set(&["node2"])
+    );
+
+    // without the preference both ssd ood nodes qualify, the server never does
+    spec.id = "cache-service".to_string();
+    spec.best_instance_count = 3;
+    spec.node_affinity.as_mut().unwrap().preferred.clear();
+    scheduler.add_service_spec(spec);
+    let actions = scheduler.schedule_spec_change().unwrap();
+    assert_eq!(
+        action_instance_nodes(&actions, "cache-service"),
+        node_set(&["node1", "node2"])
+    );
+}
+
+fn create_spread_scheduler(replica_spread: Option<ReplicaSpread>, count: u32) -> NodeScheduler {
+    let mut scheduler = NodeScheduler::new_empty(1);
+    for (node_id, cpu, zone) in [
+        ("node1", 8000, "zone-a"),
+        ("node2", 8000, "zone-a"),
+        ("node3", 4000, "zone-b"),
+    ] {
+        scheduler.add_node(create_test_node(
+            node_id,
+            cpu,
+            1024 * 1024 * 2048,
+            vec![],
+            0.0,
+            NodeState::Ready,
+            zone,
+        ));
+    }
+    let mut spec = create_test_service_spec("api-service");
+    spec.best_instance_count = count;
+    spec.replica_spread = replica_spread;
+    scheduler.add_service_spec(spec);
+    scheduler
+}
+
+#[test]
+fn test_replica_spread_across_network_zones() {
+    // without spread the two larger nodes in zone-a win
+    let mut scheduler = create_spread_scheduler(None, 2);
+    let actions = scheduler.schedule_spec_change().unwrap();
+    assert_eq!(
+        action_instance_nodes(&actions, "api-service"),
+        node_set(&["node1", "node2"])
+    );
+
+    // preferred spread trades a little score for a second zone
+    let preferred = ReplicaSpread {
+        topology_key: "network_zone".to_string(),
+        required: false,
+    };
+    let mut scheduler = create_spread_scheduler(Some(preferred.clone()), 2);
+    let actions = scheduler.schedule_spec_change().unwrap();
+    let nodes = action_instance_nodes(&actions, "api-service");
+    assert_eq!(nodes.len(), 2);
+    assert!(nodes.contains("node3"));
+
+    // preferred spread still fills every node when replicas outnumber zones
+    let mut scheduler = create_spread_scheduler(Some(preferred), 3);
+    let actions = scheduler.schedule_spec_change().unwrap();
+    assert_eq!(action_instance_nodes(&actions, "api-service").len(), 3);
+
+    // required spread allows at most one replica per zone
+    let required = ReplicaSpread {
+        topology_key: "network_zone".to_string(),
+        required: true,
+    };
+    let mut scheduler = create_spread_scheduler(Some(required), 3);
+    let actions = scheduler.schedule_spec_change().unwrap();
+    let nodes = action_instance_nodes(&actions, "api-service");
+    assert_eq!(nodes.len(), 2);
+    assert!(nodes.contains("node3"));
+    let zone_a_nodes = nodes.intersection(&node_set(&["node1", "node2"])).count();
+    assert_eq!(zone_a_nodes, 1);
+
+    // a replacement for zone-b can't land in zone-a next to the existing replica
+    let mut scheduler = create_spread_scheduler(
+        Some(ReplicaSpread {
+            topology_key: "network_zone".to_string(),
+            required: true,
+        }),
+        2,
+    );
+    scheduler.specs.get_mut("api-service").unwrap().state = ServiceSpecState::Deployed;
+    scheduler.add_replica_instance(create_test_replica_instance(
+        "api-service",
+        "node1",
+        InstanceState::Running,
+        buckyos_get_unix_timestamp(),
+    ));
+    scheduler.nodes.get_mut("node3").unwrap().state = NodeState::Unavailable;
+    assert!(scheduler.schedule_spec_change().is_err());
+}
@@ -24,6 +24,62 @@ use name_lib::{get_x_from_jwk, DeviceInfo, ZoneConfig};
 const SYSTEM_CONFIG_SERVICE_PORT: u16 = 3200;
 const FIXED_SERVICE_WEIGHT: u32 = 100;
 const DEFAULT_REQUIRED_MEMORY: u64 = 32 * 1024 * 1024;
+pub const SCHEDULER_SNAPSHOT_KEY: &str = "system/scheduler/snapshot";
+pub const SCHEDULER_OP_TASKS_KEY_PREFIX: &str = "system/scheduler/op_tasks/";
+pub const SCHEDULER_REBALANCE_CONFIG_KEY: &str = "system/scheduler/rebalance";
+pub const SCHEDULER_NODES_KEY_PREFIX: &str = "system/scheduler/nodes/";
+const SCHEDULE_LOOP_INTERVAL_SECS: u64 = 5;
+
+lazy_static::lazy_static! {
+    //trigger_schedule rpc通过这个Notify唤醒schedule_loop,立即执行一轮调度
+    static ref SCHEDULE_TRIGGER: tokio::sync::Notify = tokio::sync::Notify::new();
+}
+
+pub fn trigger_schedule() {
+    SCHEDULE_TRIGGER.notify_one();
+}
+
+// system/scheduler/op_tasks/$node_id/$task_id
+pub fn get_op_task_key(node_id: &str, task_id: &str) -> String {
+    format!("{}{}/{}", SCHEDULER_OP_TASKS_KEY_PREFIX, node_id, task_id)
+}
+
+// system/scheduler/nodes/$node_id/state
+// 调度器自己维护的节点状态(Removing/Deleted等),覆盖devices/$node_id/info中上报的状态
+// 运维写入"Removing"即开始排空该节点
+pub fn get_node_state_key(node_id: &str) -> String {
+    format!("{}{}/state", SCHEDULER_NODES_KEY_PREFIX, node_id)
+}
+
+// system/scheduler/nodes/$node_id/labels
+// 运维给节点打的标签(json字符串数组,"key=value"或"key"),和devices/$node_id/info中上报的labels合并
+pub fn get_node_labels_key(node_id: &str) -> String {
+    format!("{}{}/labels", SCHEDULER_NODES_KEY_PREFIX, node_id)
+}
+
+// 取消op task需要写入的kv:任务置为Canceled,取消排空时节点回到Ready,已创建的替换实例保留
+pub(crate) fn cancel_op_task_tx_actions(
+    node_id: &str,
+    task: &OPTask,
+    node_state: &NodeState,
+) -> Result<(OPTask, HashMap<String, KVAction>)> {
+    let mut canceled_task = task.clone();
+    canceled_task.status = OPTaskState::Canceled;
+    let mut tx_actions = HashMap::new();
+    tx_actions.insert(
+        get_op_task_key(node_id, task.id.as_str()),
+        KVAction::Update(serde_json::to_string(&canceled_task)?),
+    );
+    if matches!(canceled_task.body, OPTaskBody::DrainNode { .. })
+        && *node_state == NodeState::Removing
+    {
+        tx_actions.insert(
+            get_node_state_key(node_id),
+            KVAction::Update(NodeState::Ready.to_string()),
+        );
+    }
+    Ok((canceled_task, tx_actions))
+}
 
 fn map_api_user_type(user_type: &ApiUserType) -> UserType {
     match user_type {
@@ -33,14 +89,27 @@ fn map_api_user_type(user_type: &ApiUserType) -> UserType {
     }
 }
 
-fn craete_node_item_by_device_info(device_name: &str, device_info: &DeviceInfo) -> NodeItem {
+// DeviceInfo没有labels字段,从上报的原始json中读取(可选)
+fn get_device_labels(device_info_str: &str) -> Vec<String> {
+    serde_json::from_str::<Value>(device_info_str)
+        .ok()
+        .and_then(|value| value.get("labels").cloned())
+        .and_then(|labels| serde_json::from_value::<Vec<String>>(labels).ok())
+        .unwrap_or_default()
+}
+
+fn craete_node_item_by_device_info(
+    device_name: &str,
+    device_info: &DeviceInfo,
+    labels: Vec<String>,
+) -> NodeItem {
     let node_state =
         crate::scheduler::NodeState::from(device_info.state.clone().unwrap_or("Ready".to_string()));
     let net_id = device_info.net_id.clone().unwrap_or("".to_string());
     NodeItem {
         id: device_name.to_string(),
         node_type: NodeType::from(device_info.device_doc.device_type.clone()),
-        labels: vec![],
+        labels,
         network_zone: net_id,
         state: node_state,
         support_container: device_info.support_container,
@@ -96,8 +165,9 @@ fn create_service_spec_by_app_config(
         required_memory: DEFAULT_REQUIRED_MEMORY,
         required_gpu_tflops: 0.0,
         required_gpu_mem: 0,
-        node_affinity: None,
+        node_affinity: app_config.install_config.node_affinity.clone(),
         network_affinity: None,
+        replica_spread: app_config.install_config.replica_spread.clone(),
         service_ports_config: service_ports_config,
     }
 }
@@ -121,8 +191,9 @@ fn create_service_spec_by_service_config(
         required_memory: DEFAULT_REQUIRED_MEMORY,
         required_gpu_tflops: 0.0,
         required_gpu_mem: 0,
-        node_affinity: None,
+        node_affinity: service_config.install_config.node_affinity.clone(),
         network_affinity: None,
+        replica_spread: service_config.install_config.replica_spread.clone(),
         service_ports_config: service_ports_config,
     }
 }
@@ -132,6 +203,9 @@ pub fn create_scheduler_by_system_config(
 ) -> Result<(NodeScheduler, HashMap<String, DeviceInfo>)> {
     let mut scheduler_ctx = NodeScheduler::new_empty(1);
     let mut device_list: HashMap<String, DeviceInfo> = HashMap::new();
+    let mut op_tasks: Vec<(String, OPTask)> = Vec::new();
+    let mut node_states: Vec<(String, NodeState)> = Vec::new();
+    let mut node_labels: Vec<(String, Vec<String>)> = Vec::new();
     for (key, value) in input_config.iter() {
         //add node
         if key.starts_with("devices/") && key.ends_with("/info") {
@@ -140,8 +214,11 @@ pub fn create_scheduler_by_system_config(
                 error!("serde_json::from_str failed: {:?}", e);
                 e
             })?;
-            let node_item = craete_node_item_by_device_info(device_name, &device_info);
-            let node_item = craete_node_item_by_device_info(device_name, &device_info);
+            let node_item = craete_node_item_by_device_info(
+                device_name,
+                &device_info,
+                get_device_labels(value.as_str()),
+            );
             device_list.insert(device_name.to_string(), device_info);
             scheduler_ctx.add_node(node_item);
         }
@@ -237,6 +314,7 @@ pub fn create_scheduler_by_system_config(
                     last_update_time: 0,
                     state: InstanceState::from(app_config.target_state.clone()),
                     service_ports: app_config.service_ports_config.clone(),
+                    lease_expire_time: None,
                 };
                 scheduler_ctx.add_replica_instance(instance);
             }
@@ -262,9 +340,77 @@ pub fn create_scheduler_by_system_config(
                 last_update_time: instance_info.last_update_time,
                 state: InstanceState::from(instance_info.state.clone()),
                 service_ports: instance_info.service_ports.clone(),
+                lease_expire_time: instance_info.lease_expire_time,
             };
             scheduler_ctx.add_replica_instance(instance);
         }
+
+        //add op task
+        if let Some(task_path) = key.strip_prefix(SCHEDULER_OP_TASKS_KEY_PREFIX) {
+            let Some((node_id, _task_id)) = task_path.split_once('/') else {
+                warn!("invalid op task key:{}", key);
+                continue;
+            };
+            let op_task: OPTask = serde_json::from_str(value.as_str()).map_err(|e| {
+                error!("OPTask serde_json::from_str failed: {:?}", e);
+                e
+            })?;
+            op_tasks.push((node_id.to_string(), op_task));
+        }
+
+        //scheduler node state / labels
+        if let Some(node_path) = key.strip_prefix(SCHEDULER_NODES_KEY_PREFIX) {
+            if let Some(node_id) = node_path.strip_suffix("/state") {
+                node_states.push((node_id.to_string(), NodeState::from(value.clone())));
+            } else if let Some(node_id) = node_path.strip_suffix("/labels") {
+                let labels: Vec<String> = serde_json::from_str(value.as_str()).map_err(|e| {
+                    error!("node labels serde_json::from_str failed: {:?}", e);
+                    e
+                })?;
+                node_labels.push((node_id.to_string(), labels));
+            }
+        }
+
+        //rebalance config,可选的,解析失败时保持默认(关闭)不影响调度
+        if key == SCHEDULER_REBALANCE_CONFIG_KEY {
+            match serde_json::from_str::<RebalanceConfig>(value.as_str()) {
+                Ok(rebalance_config) => scheduler_ctx.rebalance_config = rebalance_config,
+                Err(e) => warn!("invalid rebalance config, keep rebalance disabled: {:?}", e),
+            }
+        }
+    }
+
+    //node可能在op task/node state/labels之后才被加载,所以最后再把它们挂到node上
+    for (node_id, node_state) in node_states {
+        if let Some(node) = scheduler_ctx.nodes.get_mut(&node_id) {
+            node.state = node_state;
+        } else {
+            warn!(
+                "node state {} belongs to unknown node {}",
+                node_state, node_id
+            );
+        }
+    }
+    for (node_id, labels) in node_labels {
+        if let Some(node) = scheduler_ctx.nodes.get_mut(&node_id) {
+            for label in labels {
+                if !node.labels.contains(&label) {
+                    node.labels.push(label);
+                }
+            }
+        } else {
+            warn!("node labels belong to unknown node {}", node_id);
+        }
+    }
+    for (node_id, op_task) in op_tasks {
+        if let Some(node) = scheduler_ctx.nodes.get_mut(&node_id) {
+            node.op_tasks.push(op_task);
+        } else {
+            warn!("op task {} belongs to unknown node {}", op_task.id, node_id);
+        }
+    }
+    for node in scheduler_ctx.nodes.values_mut() {
+        node.op_tasks.sort_by_key(|task| task.create_time);
     }
 
     info!(
@@ -297,12 +443,10 @@ pub(crate) fn schedule_action_to_tx_actions(
     let zone_gateway = zone_config.get_default_zone_gateway();
     match action {
         SchedulerAction::ChangeNodeStatus(node_id, node_status) => {
-            let key = format!("nodes/{}/config", node_id);
-            let mut set_paths = HashMap::new();
-            set_paths.insert("state".to_string(), Some(json!(node_status.to_string())));
-            //TODO:需要将insert替换成合并
+            //nodes/$node_id/config中的state是node_daemon的运行状态,这里写入调度器自己的节点状态key
+            let key = get_node_state_key(node_id);
             info!("will change node status: {} -> {}", node_id, node_status);
-            result.insert(key, KVAction::SetByJsonPath(set_paths));
+            result.insert(key, KVAction::Update(node_status.to_string()));
         }
         SchedulerAction::ChangeServiceStatus(spec_id, spec_status) => {
             let service_spec = scheduler_ctx.get_service_spec(spec_id.as_str());
@@ -327,9 +471,22 @@ pub(crate) fn schedule_action_to_tx_actions(
                 }
             }
         }
-        SchedulerAction::CreateOPTask(_new_op_task) => {
-            //TODO:
-            unimplemented!();
+        SchedulerAction::CreateOPTask(node_id, op_task)
+        | SchedulerAction::UpdateOPTask(node_id, op_task) => {
+            let op_task_key = get_op_task_key(node_id, op_task.id.as_str());
+            info!(
+                "will write op task: {} -> {} ({})",
+                op_task_key, op_task.id, op_task.status
+            );
+            result.insert(
+                op_task_key,
+                KVAction::Update(serde_json::to_string(op_task)?),
+            );
+        }
+        SchedulerAction::RemoveOPTask(node_id, op_task_id) => {
+            let op_task_key = get_op_task_key(node_id, op_task_id);
+            info!("will remove op task: {}", op_task_key);
+            result.insert(op_task_key, KVAction::Remove);
         }
         SchedulerAction::InstanceReplica(new_instance) => {
             //最复杂的流程,需要根据pod的类型,来执行实例化操作
@@ -392,6 +549,7 @@ pub(crate) fn schedule_action_to_tx_actions(
                         last_update_time: 0,
                         state: InstanceState::Deleted,
                         service_ports: HashMap::new(),
+                        lease_expire_time: None,
                     }
                 });
             match service_spec.spec_type {
@@ -1299,7 +1457,7 @@ pub(crate) async fn build_schedule_plan(
 ) -> Result<SchedulePlan> {
     let (mut scheduler_ctx, device_list) = create_scheduler_by_system_config(input_system_config)?;
     let last_schedule_snapshot =
-        if let Some(snapshot_str) = input_system_config.get("system/scheduler/snapshot") {
+        if let Some(snapshot_str) = input_system_config.get(SCHEDULER_SNAPSHOT_KEY) {
             Some(serde_json::from_str::<NodeScheduler>(
                 snapshot_str.as_str(),
             )?)
@@ -1403,7 +1561,13 @@ pub async fn schedule_loop(is_boot: bool) -> Result<()> {
             break;
         }
 
-        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+        let loop_interval = tokio::time::Duration::from_secs(SCHEDULE_LOOP_INTERVAL_SECS);
+        tokio::select! {
+            _ = tokio::time::sleep(loop_interval) => {}
+            _ = SCHEDULE_TRIGGER.notified() => {
+                info!("schedule loop triggered by rpc");
+            }
+        }
         loop_step += 1;
         info!("schedule loop step:{}.", loop_step);
         let buckyos_api_runtime = get_buckyos_api_runtime().unwrap();
@@ -1477,7 +1641,7 @@ pub async fn schedule_loop(is_boot: bool) -> Result<()> {
         if schedule_plan.need_persist_snapshot {
             let schedule_snapshot_str = serde_json::to_string(&schedule_plan.schedule_snapshot)?;
             system_config_client
-                .set("system/scheduler/snapshot", &schedule_snapshot_str)
+                .set(SCHEDULER_SNAPSHOT_KEY, &schedule_snapshot_str)
                 .await
                 .map_err(|err| {
                     error!(
@@ -1510,8 +1674,8 @@ pub async fn schedule_loop(is_boot: bool) -> Result<()> {
 mod tests {
     use super::*;
     use buckyos_api::{
-        AppDocBuilder, AppServiceSpec, AppType, ServiceExposeConfig, ServiceInstallConfig,
-        ServiceInstanceState, ServiceState, SubPkgDesc,
+        AppDocBuilder, AppServiceSpec, AppType, NodeAffinity, ReplicaSpread, ServiceExposeConfig,
+        ServiceInstallConfig, ServiceInstanceState, ServiceState, SubPkgDesc,
     };
     use jsonwebtoken::jwk::Jwk;
     use name_lib::generate_ed25519_key_pair;
@@ -1534,6 +1698,7 @@ mod tests {
                 .iter()
                 .map(|(name, port)| ((*name).to_string(), *port))
                 .collect(),
+            lease_expire_time: None,
         }
     }
 
@@ -1805,6 +1970,303 @@ mod tests {
         assert_eq!(spec.state, ServiceSpecState::Deleted);
     }
 
+    #[test]
+    fn test_create_scheduler_by_system_config_loads_labels_and_affinity() {
+        let zone_config = create_test_zone_config();
+        let mut kernel_spec = KernelServiceSpec {
+            service_doc: create_test_app_spec().app_doc,
+            enable: true,
+            app_index: 0,
+            expected_instance_count: 2,
+            state: ServiceState::Running,
+            install_config: ServiceInstallConfig::default(),
+        };
+        kernel_spec.install_config.node_affinity = Some(NodeAffinity::required_label("ssd"));
+        kernel_spec.install_config.replica_spread = Some(ReplicaSpread {
+            topology_key: "rack".to_string(),
+            required: true,
+        });
+
+        let mut input_system_config = HashMap::new();
+        input_system_config.insert(
+            "boot/config".to_string(),
+            serde_json::to_string(&zone_config).unwrap(),
+        );
+        for (node_id, labels) in [("ood1", json!(["ssd", "rack=r1"])), ("ood2", json!([]))] {
+            let mut device_info =
+                serde_json::to_value(create_test_device_info(node_id, None)).unwrap();
+            device_info["labels"] = labels;
+            input_system_config.insert(
+                format!("devices/{}/info", node_id),
+                serde_json::to_string(&device_info).unwrap(),
+            );
+        }
+        input_system_config.insert(
+            get_node_labels_key("ood2"),
+            serde_json::to_string(&json!(["ssd", "rack=r2"])).unwrap(),
+        );
+        input_system_config.insert(
+            "services/search/spec".to_string(),
+            serde_json::to_string(&kernel_spec).unwrap(),
+        );
+
+        let (mut scheduler_ctx, _) =
+            create_scheduler_by_system_config(&input_system_config).unwrap();
+        for node in scheduler_ctx.nodes.values_mut() {
+            node.node_type = NodeType::OOD;
+        }
+        assert_eq!(
+            scheduler_ctx.nodes.get("ood1").unwrap().labels,
+            vec!["ssd".to_string(), "rack=r1".to_string()]
+        );
+        assert_eq!(
+            scheduler_ctx.nodes.get("ood2").unwrap().labels,
+            vec!["ssd".to_string(), "rack=r2".to_string()]
+        );
+        let spec = scheduler_ctx.get_service_spec("search").unwrap();
+        assert_eq!(
+            spec.node_affinity,
+            Some(NodeAffinity::required_label("ssd"))
+        );
+        assert!(spec.replica_spread.as_ref().unwrap().required);
+
+        // both ssd nodes are in different racks, one replica on each
+        let actions = scheduler_ctx.schedule(None).unwrap();
+        let placed: HashSet<String> = actions
+            .iter()
+            .filter_map(|action| match action {
+                SchedulerAction::InstanceReplica(instance) if instance.spec_id == "search" => {
+                    Some(instance.node_id.clone())
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            placed,
+            ["ood1", "ood2"].iter().map(|id| id.to_string()).collect()
+        );
+    }
+
+    #[test]
+    fn test_create_op_task_round_trips_through_system_config() {
+        let zone_config = create_test_zone_config();
+        let device_ood1 = create_test_device_info("ood1", None);
+
+        let mut input_system_config = HashMap::new();
+        input_system_config.insert(
+            "boot/config".to_string(),
+            serde_json::to_string(&zone_config).unwrap(),
+        );
+        input_system_config.insert(
+            "devices/ood1/info".to_string(),
+            serde_json::to_string(&device_ood1).unwrap(),
+        );
+
+        let op_task = OPTask {
+            id: "init-ood1".to_string(),
+            creator_id: None,
+            body: OPTaskBody::NodeInitBaseService,
+            create_time: 100,
+            create_step_id: 1,
+            max_timeout_sec: 600,
+            status: OPTaskState::New,
+            start_time: 0,
+        };
+        let (scheduler_ctx, device_list) =
+            create_scheduler_by_system_config(&input_system_config).unwrap();
+        let mut need_update_gateway_node_list = HashSet::new();
+        let mut need_update_rbac = false;
+        let tx_actions = schedule_action_to_tx_actions(
+            &SchedulerAction::CreateOPTask("ood1".to_string(), op_task.clone()),
+            &scheduler_ctx,
+            &device_list,
+            &input_system_config,
+            &mut need_update_gateway_node_list,
+            &mut need_update_rbac,
+        )
+        .unwrap();
+        let op_task_key = get_op_task_key("ood1", "init-ood1");
+        assert_eq!(op_task_key, "system/scheduler/op_tasks/ood1/init-ood1");
+        let op_task_value = match tx_actions.get(&op_task_key).unwrap() {
+            KVAction::Update(value) => value.clone(),
+            other => panic!("unexpected kv action: {:?}", other),
+        };
+
+        input_system_config.insert(op_task_key, op_task_value);
+        input_system_config.insert(
+            get_op_task_key("ood-missing", "orphan"),
+            serde_json::to_string(&op_task).unwrap(),
+        );
+        let (scheduler_ctx, _) = create_scheduler_by_system_config(&input_system_config).unwrap();
+        let node = scheduler_ctx.nodes.get("ood1").unwrap();
+        assert_eq!(node.op_tasks, vec![op_task]);
+        assert!(node.op_tasks[0].is_pending());
+    }
+
+    fn apply_tx_actions(
+        input_system_config: &mut HashMap<String, String>,
+        tx_actions: &HashMap<String, KVAction>,
+    ) {
+        for (key, action) in tx_actions.iter() {
+            match action {
+                KVAction::Create(value) | KVAction::Update(value) => {
+                    input_system_config.insert(key.clone(), value.clone());
+                }
+                KVAction::Remove => {
+                    input_system_config.remove(key);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn create_drain_test_system_config(
+        ood2_state: NodeState,
+        drain_task: Option<OPTask>,
+    ) -> HashMap<String, String> {
+        let zone_config = create_test_zone_config();
+        let mut input_system_config = HashMap::new();
+        input_system_config.insert(
+            "boot/config".to_string(),
+            serde_json::to_string(&zone_config).unwrap(),
+        );
+        for node_id in ["ood1", "ood2"] {
+            input_system_config.insert(
+                format!("devices/{}/info", node_id),
+                serde_json::to_string(&create_test_device_info(node_id, None)).unwrap(),
+            );
+        }
+        input_system_config.insert(get_node_state_key("ood2"), ood2_state.to_string());
+        if let Some(drain_task) = drain_task {
+            input_system_config.insert(
+                get_op_task_key("ood2", drain_task.id.as_str()),
+                serde_json::to_string(&drain_task).unwrap(),
+            );
+        }
+        input_system_config
+    }
+
+    fn create_test_drain_task(status: OPTaskState, start_time: u64) -> OPTask {
+        OPTask {
+            id: "drain-ood2-100".to_string(),
+            creator_id: None,
+            body: OPTaskBody::DrainNode {
+                total_instances: 1,
+                drained_instances: 0,
+            },
+            create_time: start_time,
+            create_step_id: 1,
+            max_timeout_sec: 600,
+            status,
+            start_time,
+        }
+    }
+
+    fn op_task_keys(tx_actions: &HashMap<String, KVAction>) -> Vec<String> {
+        tx_actions
+            .keys()
+            .filter(|key| key.starts_with(SCHEDULER_OP_TASKS_KEY_PREFIX))
+            .cloned()
+            .collect()
+    }
+
+    fn load_node_state(input_system_config: &HashMap<String, String>, node_id: &str) -> NodeState {
+        let (scheduler_ctx, _) = create_scheduler_by_system_config(input_system_config).unwrap();
+        scheduler_ctx.nodes.get(node_id).unwrap().state.clone()
+    }
+
+    #[tokio::test]
+    async fn test_drain_node_round_trips_to_deleted_without_new_task() {
+        let mut input_system_config = create_drain_test_system_config(NodeState::Removing, None);
+
+        let first_plan = build_schedule_plan(&input_system_config, false)
+            .await
+            .expect("drain plan should build");
+        assert_eq!(op_task_keys(&first_plan.tx_actions).len(), 1);
+        assert!(matches!(
+            first_plan.tx_actions.get(&get_node_state_key("ood2")),
+            Some(KVAction::Update(state)) if state == "Deleted"
+        ));
+        // 调度器的节点状态不能写进node_daemon的NodeConfig
+        assert!(!first_plan.tx_actions.contains_key("nodes/ood2/config"));
+        apply_tx_actions(&mut input_system_config, &first_plan.tx_actions);
+        assert_eq!(
+            load_node_state(&input_system_config, "ood2"),
+            NodeState::Deleted
+        );
+
+        let second_plan = build_schedule_plan(&input_system_config, false)
+            .await
+            .expect("second plan should build");
+        assert!(op_task_keys(&second_plan.tx_actions).is_empty());
+        assert!(!second_plan
+            .tx_actions
+            .contains_key(&get_node_state_key("ood2")));
+    }
+
+    #[tokio::test]
+    async fn test_drain_node_timeout_round_trips_to_abnormal() {
+        let start_time = buckyos_get_unix_timestamp() - 601;
+        let running_task = create_test_drain_task(OPTaskState::Running, start_time);
+        let mut input_system_config =
+            create_drain_test_system_config(NodeState::Removing, Some(running_task.clone()));
+
+        let first_plan = build_schedule_plan(&input_system_config, false)
+            .await
+            .expect("timeout plan should build");
+        let op_task_key = get_op_task_key("ood2", running_task.id.as_str());
+        assert_eq!(
+            op_task_keys(&first_plan.tx_actions),
+            vec![op_task_key.clone()]
+        );
+        apply_tx_actions(&mut input_system_config, &first_plan.tx_actions);
+        let failed_task: OPTask =
+            serde_json::from_str(input_system_config.get(&op_task_key).unwrap()).unwrap();
+        assert_eq!(failed_task.status, OPTaskState::Failed);
+        assert_eq!(
+            load_node_state(&input_system_config, "ood2"),
+            NodeState::Abnormal
+        );
+
+        let second_plan = build_schedule_plan(&input_system_config, false)
+            .await
+            .expect("second plan should build");
+        assert!(op_task_keys(&second_plan.tx_actions).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_drain_round_trips_to_ready() {
+        let running_task =
+            create_test_drain_task(OPTaskState::Running, buckyos_get_unix_timestamp());
+        let mut input_system_config =
+            create_drain_test_system_config(NodeState::Removing, Some(running_task.clone()));
+
+        let (canceled_task, cancel_actions) =
+            cancel_op_task_tx_actions("ood2", &running_task, &NodeState::Removing).unwrap();
+        assert_eq!(canceled_task.status, OPTaskState::Canceled);
+        apply_tx_actions(&mut input_system_config, &cancel_actions);
+        assert_eq!(
+            load_node_state(&input_system_config, "ood2"),
+            NodeState::Ready
+        );
+
+        let plan = build_schedule_plan(&input_system_config, false)
+            .await
+            .expect("plan after cancel should build");
+        assert!(op_task_keys(&plan.tx_actions).is_empty());
+
+        // 节点状态没能和取消一起生效时,按最近一次排空的结果恢复,不会重新排空
+        input_system_config.insert(get_node_state_key("ood2"), NodeState::Removing.to_string());
+        let plan = build_schedule_plan(&input_system_config, false)
+            .await
+            .expect("plan with stale removing state should build");
+        assert!(op_task_keys(&plan.tx_actions).is_empty());
+        assert!(matches!(
+            plan.tx_actions.get(&get_node_state_key("ood2")),
+            Some(KVAction::Update(state)) if state == "Ready"
+        ));
+    }
+
     #[test]
     fn test_schedule_action_to_tx_actions_instances_agent_and_marks_gateway_update() {
         let zone_config = create_test_zone_config();
@@ -1841,6 +2303,7 @@ mod tests {
             required_gpu_mem: 0,
             node_affinity: None,
             network_affinity: None,
+            replica_spread: None,
             service_ports_config: HashMap::new(),
         });
 
@@ -1911,6 +2374,7 @@ mod tests {
             required_gpu_mem: 0,
             node_affinity: None,
             network_affinity: None,
+            replica_spread: None,
             service_ports_config: HashMap::new(),
         });
 
@@ -1985,6 +2449,7 @@ mod tests {
             required_gpu_mem: 0,
             node_affinity: None,
             network_affinity: None,
+            replica_spread: None,
             service_ports_config: HashMap::new(),
         });
 
@@ -2050,6 +2515,7 @@ mod tests {
             required_gpu_mem: 0,
             node_affinity: None,
             network_affinity: None,
+            replica_spread: None,
             service_ports_config: HashMap::new(),
         });
 
@@ -2125,6 +2591,7 @@ mod tests {
             required_gpu_mem: 0,
             node_affinity: None,
             network_affinity: None,
+            replica_spread: None,
             service_ports_config: HashMap::new(),
         });
 
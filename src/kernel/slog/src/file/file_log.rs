@@ -716,6 +716,7 @@ mod tests {
             file: None,
             line: None,
             content: content.to_string(),
+            fields: Default::default(),
         }
     }
 
@@ -1,26 +1,37 @@
 use crate::system_log::{LogLevel, LogTimeHelper, SystemLogRecord};
+use std::collections::BTreeMap;
 use std::str::FromStr;
 
 pub struct SystemLogRecordLineFormatter;
 
 impl SystemLogRecordLineFormatter {
+    // The fields are written as a json object right after the target, like
+    // `[target]{"request_id":"r1"} <file:line> content`. Lines without fields keep the old format.
     pub fn format_record(record: &SystemLogRecord) -> String {
+        let fields = if record.fields.is_empty() {
+            String::new()
+        } else {
+            serde_json::to_string(&record.fields).unwrap_or_default()
+        };
+
         if record.has_file_pos() {
             format!(
-                "{} [{}] [{}] <{}:{}> {}\n",
+                "{} [{}] [{}]{} <{}:{}> {}\n",
                 record.time_string(),
                 record.level(),
                 record.target(),
+                fields,
                 record.file(),
                 record.line(),
                 record.content(),
             )
         } else {
             format!(
-                "{} [{}] [{}] {}\n",
+                "{} [{}] [{}]{} {}\n",
                 record.time_string(),
                 record.level(),
                 record.target(),
+                fields,
                 record.content(),
             )
         }
@@ -28,8 +39,8 @@ impl SystemLogRecordLineFormatter {
 
     pub fn parse_record(line: &str) -> Result<SystemLogRecord, String> {
         // println!("Parsing log line: {}", line);
-        let parts: Vec<&str> = line.splitn(4, ' ').collect();
-        if parts.len() < 4 {
+        let parts: Vec<&str> = line.splitn(3, ' ').collect();
+        if parts.len() < 3 {
             let msg = format!("invalid log line format: {}", line);
             return Err(msg);
         }
@@ -40,10 +51,10 @@ impl SystemLogRecordLineFormatter {
         let level_str = parts[1].trim_matches(&['[', ']'][..]);
         let level = LogLevel::from_str(level_str)?;
 
-        let target_str = parts[2].trim_matches(&['[', ']'][..]);
+        let (target_str, fields, content_part) = Self::parse_target_and_fields(line, parts[2])?;
 
         // Check if there is file and line info
-        let content_part = parts[3].trim();
+        let content_part = content_part.trim();
         let (file, line, content) = if content_part.starts_with('<') {
             // Has file and line info
             let end_pos = content_part.find('>').ok_or_else(|| {
@@ -71,11 +82,58 @@ impl SystemLogRecordLineFormatter {
             file,
             line,
             content,
+            fields,
         };
 
         Ok(record)
     }
 
+    // Split `[target]{fields} rest` or the old `[target] rest` into its parts
+    fn parse_target_and_fields<'a>(
+        line: &str,
+        part: &'a str,
+    ) -> Result<(&'a str, BTreeMap<String, String>, &'a str), String> {
+        let token_end = part.find(' ').ok_or_else(|| {
+            let msg = format!("invalid log line format: {}", line);
+            msg
+        })?;
+
+        // The old format always has a space right after the target
+        let fields_start = match part[..token_end].find("]{") {
+            Some(pos) => pos + 1,
+            None => {
+                let target = part[..token_end].trim_matches(&['[', ']'][..]);
+                return Ok((target, BTreeMap::new(), &part[token_end + 1..]));
+            }
+        };
+
+        let target = part[..fields_start].trim_matches(&['[', ']'][..]);
+        let mut stream = serde_json::Deserializer::from_str(&part[fields_start..])
+            .into_iter::<BTreeMap<String, String>>();
+        let fields = match stream.next() {
+            Some(Ok(fields)) => fields,
+            Some(Err(e)) => {
+                let msg = format!("invalid log fields: {}, {}", line, e);
+                return Err(msg);
+            }
+            None => {
+                let msg = format!("invalid log fields, missing json: {}", line);
+                return Err(msg);
+            }
+        };
+
+        let rest = &part[fields_start + stream.byte_offset()..];
+        let rest = rest.strip_prefix(' ').ok_or_else(|| {
+            let msg = format!(
+                "invalid log line format, missing content after fields: {}",
+                line
+            );
+            msg
+        })?;
+
+        Ok((target, fields, rest))
+    }
+
     fn parse_file_line(file_line_str: &str) -> Result<(String, u32), String> {
         // Split by the last `:` so Windows drive letters like `C:\...` are preserved.
         let mut parts = file_line_str.rsplitn(2, ':');
@@ -111,6 +169,7 @@ mod tests {
             file: Some("test_file.rs".to_string()),
             line: Some(42),
             content: "This is a test log message.".to_string(),
+            fields: BTreeMap::new(),
         };
 
         let formatted = SystemLogRecordLineFormatter::format_record(&record);
@@ -134,6 +193,7 @@ mod tests {
             file: Some(r"C:\work\buckyos\src\main.rs".to_string()),
             line: Some(128),
             content: "windows path test".to_string(),
+            fields: BTreeMap::new(),
         };
 
         let formatted = SystemLogRecordLineFormatter::format_record(&record);
@@ -154,6 +214,7 @@ mod tests {
             file: None,
             line: None,
             content: "content without file pos".to_string(),
+            fields: BTreeMap::new(),
         };
 
         let formatted = SystemLogRecordLineFormatter::format_record(&record);
@@ -174,6 +235,7 @@ mod tests {
             file: Some("/var/log/archive:v1/app.rs".to_string()),
             line: Some(9),
             content: "colon in file path".to_string(),
+            fields: BTreeMap::new(),
         };
 
         let formatted = SystemLogRecordLineFormatter::format_record(&record);
@@ -182,6 +244,55 @@ mod tests {
         assert_eq!(parsed.line, Some(9));
     }
 
+    #[test]
+    fn test_format_and_parse_record_with_fields() {
+        let record = SystemLogRecord {
+            level: LogLevel::Info,
+            target: "fields_target".to_string(),
+            time: 1721000400000,
+            file: Some("fields.rs".to_string()),
+            line: Some(7),
+            content: "{\"not\": \"fields\"} order created".to_string(),
+            fields: BTreeMap::new(),
+        }
+        .with_field("request_id", "req 1")
+        .with_field("user_id", "u\"2\"}");
+
+        let formatted = SystemLogRecordLineFormatter::format_record(&record);
+        let parsed = SystemLogRecordLineFormatter::parse_record(&formatted).unwrap();
+        assert_eq!(parsed.target, "fields_target");
+        assert_eq!(parsed.fields, record.fields);
+        assert_eq!(parsed.field("request_id"), Some("req 1"));
+        assert_eq!(parsed.file.as_deref(), Some("fields.rs"));
+        assert_eq!(parsed.line, Some(7));
+        assert_eq!(parsed.content, record.content);
+
+        let no_pos = SystemLogRecord {
+            file: None,
+            line: None,
+            ..record.clone()
+        };
+        let formatted = SystemLogRecordLineFormatter::format_record(&no_pos);
+        let parsed = SystemLogRecordLineFormatter::parse_record(&formatted).unwrap();
+        assert_eq!(parsed.fields, record.fields);
+        assert_eq!(parsed.file, None);
+        assert_eq!(parsed.content, record.content);
+    }
+
+    #[test]
+    fn test_parse_record_old_format_content_like_fields() {
+        // Lines written before the fields existed must keep their content untouched
+        let line = "2024-01-01_00:00:00.000_+00:00 [info] [test] {\"a\":\"b\"} json body";
+        let parsed = SystemLogRecordLineFormatter::parse_record(line).unwrap();
+        assert!(parsed.fields.is_empty());
+        assert_eq!(parsed.target, "test");
+        assert_eq!(parsed.content, "{\"a\":\"b\"} json body");
+
+        let line = "2024-01-01_00:00:00.000_+00:00 [info] [test]{\"a\":1} bad";
+        let ret = SystemLogRecordLineFormatter::parse_record(line);
+        assert!(ret.unwrap_err().contains("invalid log fields"));
+    }
+
     #[test]
     fn test_parse_record_rejects_invalid_file_line_missing_colon() {
         let line = "2024-01-01_00:00:00.000_+00:00 [info] [test] <only_file> bad";
@@ -569,6 +569,7 @@ mod tests {
             file: Some("reader.rs".to_string()),
             line: Some(42),
             content: content.to_string(),
+            fields: Default::default(),
         }
     }
 
@@ -2,7 +2,9 @@ use super::constants::*;
 use chrono::DateTime;
 use chrono::offset::{Local, Utc};
 use log::Record;
+use log::kv::{Key, Value, VisitSource};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 pub struct LogTimeHelper;
 
@@ -41,6 +43,19 @@ pub struct SystemLogRecord {
     pub file: Option<String>,
     pub line: Option<u32>,
     pub content: String,
+    // Structured key-value fields, like request_id/app_id/user_id
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+}
+
+// Collect the key-values of a log record, e.g. `info!(request_id = id; "...")`
+struct FieldsCollector<'a>(&'a mut BTreeMap<String, String>);
+
+impl<'kvs> VisitSource<'kvs> for FieldsCollector<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        self.0.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
 }
 
 impl SystemLogRecord {
@@ -51,6 +66,11 @@ impl SystemLogRecord {
 
         let content = format!("{}", record.args());
 
+        let mut fields = BTreeMap::new();
+        if let Err(e) = record.key_values().visit(&mut FieldsCollector(&mut fields)) {
+            println!("collect log record fields failed! {}", e);
+        }
+
         Self {
             level,
             target,
@@ -58,6 +78,7 @@ impl SystemLogRecord {
             file: record.file().map(|v| v.to_owned()),
             line: record.line(),
             content,
+            fields,
         }
     }
 
@@ -69,9 +90,15 @@ impl SystemLogRecord {
             file: None,
             line: None,
             content,
+            fields: BTreeMap::new(),
         }
     }
 
+    pub fn with_field(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.fields.insert(key.into(), value.into());
+        self
+    }
+
     pub fn content(&self) -> &str {
         self.content.as_str()
     }
@@ -109,6 +136,14 @@ impl SystemLogRecord {
             _ => 0,
         }
     }
+
+    pub fn fields(&self) -> &BTreeMap<String, String> {
+        &self.fields
+    }
+
+    pub fn field(&self, key: &str) -> Option<&str> {
+        self.fields.get(key).map(|v| v.as_str())
+    }
 }
 
 impl std::fmt::Display for SystemLogRecord {
@@ -145,3 +180,26 @@ impl SystemLogTarget for ConsoleCyfsLogTarget {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_record_collects_key_values() {
+        let kvs = [("request_id", "req-1"), ("app_id", "app-2")];
+        let record = SystemLogRecord::new(
+            &Record::builder()
+                .level(log::Level::Warn)
+                .target("kv_target")
+                .args(format_args!("order created"))
+                .key_values(&kvs)
+                .build(),
+        );
+
+        assert_eq!(record.content(), "order created");
+        assert_eq!(record.field("request_id"), Some("req-1"));
+        assert_eq!(record.field("app_id"), Some("app-2"));
+        assert_eq!(record.fields().len(), 2);
+    }
+}
@@ -66,6 +66,7 @@ impl LogDirReader {
             record.file.hash(&mut hasher);
             record.line.hash(&mut hasher);
             record.content.hash(&mut hasher);
+            record.fields.hash(&mut hasher);
         }
         format!("{}:fallback:{:x}", id, hasher.finish())
     }
@@ -371,6 +372,7 @@ mod tests {
                 file: Some(format!("{}.rs", name)),
                 line: Some(i as u32 + 1),
                 content: format!("{}-{}", name, i),
+                fields: Default::default(),
             };
             content.push_str(&SystemLogRecordLineFormatter::format_record(&record));
         }
@@ -18,5 +18,6 @@ mod test_pipeline_server_recovery;
 mod test_pipeline_server_request_interruption;
 mod test_pipeline_service_churn;
 mod test_pipeline_sqlite_locked_retry;
+mod test_pipeline_structured_fields;
 mod test_pipeline_transient_append_failure;
 mod test_pipeline_upload_timeout_recovery;
@@ -135,6 +135,7 @@ async fn query_uploaded_count(
             start_time: None,
             end_time: None,
             limit: Some(1000),
+            ..Default::default()
         })
         .await?;
     Ok(result.iter().map(|records| records.logs.len()).sum())
@@ -46,6 +46,7 @@ fn make_record(service: &str, time: u64, content: &str) -> SystemLogRecord {
         file: Some("pipeline_backpressure_test.rs".to_string()),
         line: Some(1),
         content: content.to_string(),
+        fields: Default::default(),
     }
 }
 
@@ -155,6 +156,7 @@ async fn query_service_contents(
             start_time: None,
             end_time: None,
             limit: Some(20_000),
+            ..Default::default()
         })
         .await?;
 
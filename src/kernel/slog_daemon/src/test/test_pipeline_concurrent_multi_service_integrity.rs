@@ -44,6 +44,7 @@ fn make_record(service: &str, time: u64, content: &str) -> SystemLogRecord {
         file: Some("pipeline_concurrent_integrity.rs".to_string()),
         line: Some(1),
         content: content.to_string(),
+        fields: Default::default(),
     }
 }
 
@@ -93,6 +94,7 @@ async fn query_service_contents(
             start_time: None,
             end_time: None,
             limit: Some(20_000),
+            ..Default::default()
         })
         .await?;
 
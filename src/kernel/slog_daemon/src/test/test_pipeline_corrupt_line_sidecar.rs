@@ -40,6 +40,7 @@ fn make_record(service: &str, time: u64, content: &str) -> SystemLogRecord {
         file: Some("pipeline_corrupt_sidecar_test.rs".to_string()),
         line: Some(1),
         content: content.to_string(),
+        fields: Default::default(),
     }
 }
 
@@ -107,6 +108,7 @@ async fn wait_for_uploaded_logs(
                 start_time: None,
                 end_time: None,
                 limit: Some(1000),
+                ..Default::default()
             })
             .await?;
         let count: usize = result.iter().map(|records| records.logs.len()).sum();
@@ -45,6 +45,7 @@ fn make_large_record(service: &str, time: u64, idx: usize, size: usize) -> Syste
         file: Some("pipeline_large_records_test.rs".to_string()),
         line: Some(1),
         content,
+        fields: Default::default(),
     }
 }
 
@@ -92,6 +93,7 @@ async fn query_uploaded_contents(
             start_time: None,
             end_time: None,
             limit: Some(20_000),
+            ..Default::default()
         })
         .await?;
     let mut contents = Vec::new();
@@ -41,6 +41,7 @@ fn make_record(service: &str, time: u64, content: &str) -> SystemLogRecord {
         file: Some("pipeline_multi_node_test.rs".to_string()),
         line: Some(1),
         content: content.to_string(),
+        fields: Default::default(),
     }
 }
 
@@ -88,6 +89,7 @@ async fn query_uploaded_contents(
             start_time: None,
             end_time: None,
             limit: Some(10_000),
+            ..Default::default()
         })
         .await?;
 
@@ -44,6 +44,7 @@ fn make_record(service: &str, time: u64, content: &str) -> SystemLogRecord {
         file: Some("pipeline_restart_resume_many_test.rs".to_string()),
         line: Some(1),
         content: content.to_string(),
+        fields: Default::default(),
     }
 }
 
@@ -91,6 +92,7 @@ async fn query_service_contents(
             start_time: None,
             end_time: None,
             limit: Some(20_000),
+            ..Default::default()
         })
         .await?;
 
@@ -185,6 +185,7 @@ pub fn make_record(service: &str, time: u64, content: &str) -> SystemLogRecord {
         file: Some("process_e2e.rs".to_string()),
         line: Some(1),
         content: content.to_string(),
+        fields: Default::default(),
     }
 }
 
@@ -539,6 +540,7 @@ pub async fn wait_for_uploaded_count(
                 start_time: None,
                 end_time: None,
                 limit: Some(1000),
+                ..Default::default()
             })
             .await?;
         let count: usize = result.iter().map(|r| r.logs.len()).sum();
@@ -568,6 +570,7 @@ pub async fn query_uploaded_count(
             start_time: None,
             end_time: None,
             limit: Some(5000),
+            ..Default::default()
         })
         .await?;
     Ok(result.iter().map(|r| r.logs.len()).sum())
@@ -586,6 +589,7 @@ pub async fn query_uploaded_contents(
             start_time: None,
             end_time: None,
             limit: Some(5000),
+            ..Default::default()
         })
         .await?;
 
@@ -610,6 +614,7 @@ pub async fn query_uploaded_counts_by_service(
             start_time: None,
             end_time: None,
             limit: None,
+            ..Default::default()
         })
         .await?;
 
@@ -172,6 +172,7 @@ async fn test_process_multi_node_same_service_name() {
             start_time: None,
             end_time: None,
             limit: Some(5000),
+            ..Default::default()
         })
         .await
         .unwrap();
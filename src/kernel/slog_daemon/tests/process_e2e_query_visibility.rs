@@ -59,6 +59,7 @@ async fn query_records(
             start_time,
             end_time,
             limit,
+            ..Default::default()
         })
         .await?;
 
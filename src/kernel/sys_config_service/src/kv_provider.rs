@@ -1,11 +1,13 @@
 #![allow(dead_code)]
 use async_trait::async_trait;
 use buckyos_kit::*;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
 use thiserror::Error;
 
-#[derive(Error, Debug)]
+// Serializable so the errors of replicated writes can be sent back from the node that applied them
+#[derive(Error, Debug, Clone, Serialize, Deserialize)]
 pub enum KVStoreErrors {
     #[error("key not found : {0}")]
     KeyNotFound(String),
@@ -17,12 +19,50 @@ pub enum KVStoreErrors {
         expected: u64,
         actual: u64,
     },
+    #[error("revision {requested} has been compacted, oldest available revision is {oldest}")]
+    Compacted { requested: u64, oldest: u64 },
+    #[error("lease not found or expired : {0}")]
+    LeaseNotFound(String),
+    #[error("lease {lease_id} is not owned by {owner}")]
+    LeaseNotOwned { lease_id: String, owner: String },
+    #[error("lock {key} is held by {owner}")]
+    LockHeld { key: String, owner: String },
     #[error("internal error : {0}")]
     InternalError(String),
 }
 
 pub type Result<T> = std::result::Result<T, KVStoreErrors>;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KVChangeAction {
+    Create,
+    Set,
+    Delete,
+}
+
+// One entry of the store change log (and of a key's history). `revision` is the store-wide
+// revision (monotonic across all keys), `key_revision` is the per-key revision after the change.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KVChangeEvent {
+    pub revision: u64,
+    pub key: String,
+    pub action: KVChangeAction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    pub key_revision: u64,
+    #[serde(default)]
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadConsistency {
+    // served by the leader after a read barrier (or by a single node store)
+    Linearizable,
+    // served by a follower from its local state, may miss the latest writes
+    PossiblyStale,
+}
+
 #[async_trait]
 pub trait KVStoreProvider: Send + Sync {
     async fn get(&self, key: String) -> Result<Option<String>>;
@@ -39,4 +79,33 @@ pub trait KVStoreProvider: Send + Sync {
     async fn list_data(&self, key_perfix: &str) -> Result<HashMap<String, String>>;
     async fn list_keys(&self, key_perfix: &str) -> Result<Vec<String>>;
     async fn list_direct_children(&self, prefix: String) -> Result<Vec<String>>;
+
+    // latest store-wide revision, 0 if nothing was ever written
+    async fn get_store_revision(&self) -> Result<u64>;
+    // return changes under key_prefix with revision >= from_revision (at most limit entries),
+    // and the revision the caller should resume from.
+    // Err(Compacted) if from_revision is older than the oldest retained change.
+    async fn list_changes(
+        &self,
+        key_prefix: &str,
+        from_revision: u64,
+        limit: usize,
+    ) -> Result<(Vec<KVChangeEvent>, u64)>;
+
+    // retained history of one key, newest first, at most limit entries
+    async fn get_history(&self, key: &str, limit: usize) -> Result<Vec<KVChangeEvent>>;
+    // the change that produced key_revision of key, None if it is not retained
+    async fn get_at_revision(&self, key: &str, key_revision: u64) -> Result<Option<KVChangeEvent>>;
+
+    // what the reads issued right after this call see, replicated stores may serve them
+    // from a follower that lags behind the leader
+    async fn read_consistency(&self) -> Result<ReadConsistency> {
+        Ok(ReadConsistency::Linearizable)
+    }
+
+    // background maintenance (lease expiry) runs on one node only: the leader of a
+    // replicated store, always true for single node stores
+    fn is_primary(&self) -> bool {
+        true
+    }
 }
@@ -0,0 +1,538 @@
+// Conformance cases every KVStoreProvider backend must pass:
+// 1. Basic CRUD: create/set/delete/set_by_path and the errors for missing or existing keys.
+// 2. Revisions: per-key revision bumps on every write and survives delete.
+// 3. exec_tx: all-or-nothing apply, main_key CAS check, action semantics.
+// 4. Listing: list_data/list_keys/list_direct_children see only user keys.
+// 5. Change log and history: ordered events, prefix filter, compaction and bounded history.
+//
+// To hold a new backend to the same contract, add a factory below and one
+// `kv_provider_conformance!(<name>, <factory>);` line.
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use buckyos_kit::KVAction;
+use serde_json::{json, Value};
+
+use crate::klog_provider::{tests::test_config as klog_test_config, KlogStore};
+use crate::kv_provider::*;
+use crate::sled_provider::SledStore;
+use crate::sqlite_provider::SqliteStore;
+
+const TEST_CHANGE_LOG_CAPACITY: u64 = 4;
+const TEST_KEY_HISTORY_CAPACITY: u64 = 3;
+
+async fn create_sled_store() -> Box<dyn KVStoreProvider> {
+    let db = sled::Config::new()
+        .temporary(true)
+        .open()
+        .expect("open temporary sled db");
+    let mut store = SledStore::from_db(db);
+    store.set_retention(TEST_CHANGE_LOG_CAPACITY, TEST_KEY_HISTORY_CAPACITY);
+    Box::new(store)
+}
+
+async fn create_sqlite_store() -> Box<dyn KVStoreProvider> {
+    let mut store = SqliteStore::open_in_memory().expect("open in-memory sqlite db");
+    store.set_retention(TEST_CHANGE_LOG_CAPACITY, TEST_KEY_HISTORY_CAPACITY);
+    Box::new(store)
+}
+
+// a single node cluster, every write goes through the raft log and the kv state machine
+async fn create_klog_store() -> Box<dyn KVStoreProvider> {
+    let config = klog_test_config(1, true);
+    let mut state_store = KlogStore::open_state_store(&config).expect("open klog state store");
+    state_store.set_retention(TEST_CHANGE_LOG_CAPACITY, TEST_KEY_HISTORY_CAPACITY);
+    let store = KlogStore::start(config, state_store, Arc::new(|| {}))
+        .await
+        .expect("start klog store");
+    store
+        .node()
+        .raft()
+        .wait(Some(Duration::from_secs(10)))
+        .current_leader(1, "wait leader")
+        .await
+        .expect("klog leader elected");
+    Box::new(store)
+}
+
+async fn revision_of(store: &dyn KVStoreProvider, key: &str) -> Option<u64> {
+    store
+        .get_with_revision(key.to_string())
+        .await
+        .expect("get with revision")
+        .map(|(_, revision)| revision)
+}
+
+async fn case_crud_roundtrip(store: &dyn KVStoreProvider) {
+    assert_eq!(store.get("boot/config".to_string()).await.unwrap(), None);
+
+    store.create("boot/config", "v1").await.expect("create key");
+    assert!(matches!(
+        store.create("boot/config", "v2").await,
+        Err(KVStoreErrors::KeyExist(_))
+    ));
+    assert_eq!(
+        store.get("boot/config".to_string()).await.unwrap(),
+        Some("v1".to_string())
+    );
+
+    store
+        .set("boot/config".to_string(), "v2".to_string())
+        .await
+        .expect("set key");
+    assert_eq!(
+        store.get("boot/config".to_string()).await.unwrap(),
+        Some("v2".to_string())
+    );
+
+    store.delete("boot/config").await.expect("delete key");
+    assert_eq!(store.get("boot/config".to_string()).await.unwrap(), None);
+    assert!(matches!(
+        store.delete("boot/config").await,
+        Err(KVStoreErrors::KeyNotFound(_))
+    ));
+}
+
+async fn case_set_by_path(store: &dyn KVStoreProvider) {
+    assert!(matches!(
+        store
+            .set_by_path(
+                "users/alice/settings".to_string(),
+                "/theme".to_string(),
+                &json!("dark"),
+            )
+            .await,
+        Err(KVStoreErrors::KeyNotFound(_))
+    ));
+
+    store
+        .create("users/alice/settings", r#"{"theme":"light","lang":"en"}"#)
+        .await
+        .expect("create settings");
+    store
+        .set_by_path(
+            "users/alice/settings".to_string(),
+            "/theme".to_string(),
+            &json!("dark"),
+        )
+        .await
+        .expect("set by path");
+
+    let value = store
+        .get("users/alice/settings".to_string())
+        .await
+        .unwrap()
+        .expect("settings exist");
+    let value: Value = serde_json::from_str(&value).unwrap();
+    assert_eq!(value, json!({"theme": "dark", "lang": "en"}));
+}
+
+async fn case_revision_survives_delete(store: &dyn KVStoreProvider) {
+    assert_eq!(revision_of(store, "nodes/ood1/config").await, None);
+
+    store.create("nodes/ood1/config", "{}").await.unwrap();
+    assert_eq!(revision_of(store, "nodes/ood1/config").await, Some(1));
+
+    store
+        .set("nodes/ood1/config".to_string(), r#"{"a":1}"#.to_string())
+        .await
+        .unwrap();
+    store
+        .set_by_path("nodes/ood1/config".to_string(), "/a".to_string(), &json!(2))
+        .await
+        .unwrap();
+    assert_eq!(revision_of(store, "nodes/ood1/config").await, Some(3));
+
+    store.delete("nodes/ood1/config").await.unwrap();
+    assert_eq!(revision_of(store, "nodes/ood1/config").await, None);
+
+    store.create("nodes/ood1/config", "{}").await.unwrap();
+    assert_eq!(revision_of(store, "nodes/ood1/config").await, Some(5));
+}
+
+async fn case_exec_tx_applies_all_actions(store: &dyn KVStoreProvider) {
+    store.create("services/a/config", "a").await.unwrap();
+    store
+        .create("services/b/settings", r#"{"port":80}"#)
+        .await
+        .unwrap();
+    store.create("services/c/config", "c").await.unwrap();
+
+    let mut tx = HashMap::new();
+    tx.insert(
+        "services/new/config".to_string(),
+        KVAction::Create("new".to_string()),
+    );
+    tx.insert(
+        "services/a/config".to_string(),
+        KVAction::Append("-tail".to_string()),
+    );
+    tx.insert(
+        "services/b/settings".to_string(),
+        KVAction::SetByJsonPath(HashMap::from([
+            ("/port".to_string(), Some(json!(8080))),
+            ("/host".to_string(), Some(json!("ood1"))),
+        ])),
+    );
+    tx.insert("services/c/config".to_string(), KVAction::Remove);
+    tx.insert(
+        "services/d/config".to_string(),
+        KVAction::Update("d".to_string()),
+    );
+    store.exec_tx(tx, None).await.expect("exec tx");
+
+    assert_eq!(
+        store.get("services/new/config".to_string()).await.unwrap(),
+        Some("new".to_string())
+    );
+    assert_eq!(
+        store.get("services/a/config".to_string()).await.unwrap(),
+        Some("a-tail".to_string())
+    );
+    let settings: Value = serde_json::from_str(
+        &store
+            .get("services/b/settings".to_string())
+            .await
+            .unwrap()
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(settings, json!({"port": 8080, "host": "ood1"}));
+    assert_eq!(
+        store.get("services/c/config".to_string()).await.unwrap(),
+        None
+    );
+    assert_eq!(
+        store.get("services/d/config".to_string()).await.unwrap(),
+        Some("d".to_string())
+    );
+    assert_eq!(revision_of(store, "services/a/config").await, Some(2));
+    assert_eq!(revision_of(store, "services/d/config").await, Some(1));
+}
+
+async fn case_exec_tx_is_atomic(store: &dyn KVStoreProvider) {
+    store.create("services/a/config", "a").await.unwrap();
+    store.create("services/b/config", "b").await.unwrap();
+
+    let mut tx = HashMap::new();
+    tx.insert(
+        "services/a/config".to_string(),
+        KVAction::Update("a2".to_string()),
+    );
+    tx.insert(
+        "services/b/config".to_string(),
+        KVAction::Create("b2".to_string()),
+    );
+    assert!(matches!(
+        store.exec_tx(tx, None).await,
+        Err(KVStoreErrors::KeyExist(_))
+    ));
+
+    let mut tx = HashMap::new();
+    tx.insert(
+        "services/a/config".to_string(),
+        KVAction::Update("a3".to_string()),
+    );
+    tx.insert(
+        "services/missing/config".to_string(),
+        KVAction::Append("x".to_string()),
+    );
+    assert!(matches!(
+        store.exec_tx(tx, None).await,
+        Err(KVStoreErrors::KeyNotFound(_))
+    ));
+
+    assert_eq!(
+        store.get("services/a/config".to_string()).await.unwrap(),
+        Some("a".to_string())
+    );
+    assert_eq!(revision_of(store, "services/a/config").await, Some(1));
+    assert_eq!(store.get_store_revision().await.unwrap(), 2);
+}
+
+async fn case_exec_tx_checks_main_key(store: &dyn KVStoreProvider) {
+    store.create("system/lock", "owner-a").await.unwrap();
+
+    let mut tx = HashMap::new();
+    tx.insert(
+        "system/data".to_string(),
+        KVAction::Update("payload-1".to_string()),
+    );
+    store
+        .exec_tx(tx, Some(("system/lock".to_string(), 1)))
+        .await
+        .expect("cas with current revision");
+    // main_key is bumped even when the tx does not write it
+    assert_eq!(revision_of(store, "system/lock").await, Some(2));
+
+    let mut tx = HashMap::new();
+    tx.insert(
+        "system/data".to_string(),
+        KVAction::Update("payload-2".to_string()),
+    );
+    let result = store
+        .exec_tx(tx, Some(("system/lock".to_string(), 1)))
+        .await;
+    match result {
+        Err(KVStoreErrors::RevisionMismatch {
+            key,
+            expected,
+            actual,
+        }) => {
+            assert_eq!(key, "system/lock");
+            assert_eq!(expected, 1);
+            assert_eq!(actual, 2);
+        }
+        other => panic!("expected revision mismatch, got {:?}", other),
+    }
+    assert_eq!(
+        store.get("system/data".to_string()).await.unwrap(),
+        Some("payload-1".to_string())
+    );
+
+    // a missing main_key has revision 0
+    let mut tx = HashMap::new();
+    tx.insert(
+        "system/other".to_string(),
+        KVAction::Create("v".to_string()),
+    );
+    store
+        .exec_tx(tx, Some(("system/new_lock".to_string(), 0)))
+        .await
+        .expect("cas on missing key");
+    assert_eq!(
+        store.get("system/new_lock".to_string()).await.unwrap(),
+        None
+    );
+}
+
+async fn case_list_operations(store: &dyn KVStoreProvider) {
+    store.create("users/alice/settings", "1").await.unwrap();
+    store.create("users/alice/apps/home", "2").await.unwrap();
+    store.create("users/bob/settings", "3").await.unwrap();
+    store.create("users_extra", "4").await.unwrap();
+
+    let data = store.list_data("users/").await.unwrap();
+    assert_eq!(data.len(), 3);
+    assert_eq!(data.get("users/bob/settings"), Some(&"3".to_string()));
+
+    let mut keys = store.list_keys("users/alice/").await.unwrap();
+    keys.sort();
+    assert_eq!(
+        keys,
+        vec![
+            "users/alice/apps/home".to_string(),
+            "users/alice/settings".to_string()
+        ]
+    );
+
+    let mut children = store
+        .list_direct_children("users".to_string())
+        .await
+        .unwrap();
+    children.sort();
+    assert_eq!(children, vec!["alice".to_string(), "bob".to_string()]);
+
+    let mut children = store
+        .list_direct_children("users/alice/".to_string())
+        .await
+        .unwrap();
+    children.sort();
+    assert_eq!(children, vec!["apps".to_string(), "settings".to_string()]);
+
+    assert!(store
+        .list_keys("")
+        .await
+        .unwrap()
+        .iter()
+        .all(|key| !key.starts_with("__meta/")));
+}
+
+async fn case_change_log(store: &dyn KVStoreProvider) {
+    assert_eq!(store.get_store_revision().await.unwrap(), 0);
+
+    store.create("services/a/config", "1").await.unwrap();
+    store
+        .set("services/a/config".to_string(), "2".to_string())
+        .await
+        .unwrap();
+    store.create("users/alice/settings", "x").await.unwrap();
+    store.delete("services/a/config").await.unwrap();
+    assert_eq!(store.get_store_revision().await.unwrap(), 4);
+
+    let (events, next_revision) = store.list_changes("", 1, 16).await.unwrap();
+    assert_eq!(next_revision, 5);
+    assert_eq!(
+        events
+            .iter()
+            .map(|event| event.revision)
+            .collect::<Vec<_>>(),
+        vec![1, 2, 3, 4]
+    );
+    assert_eq!(events[0].action, KVChangeAction::Create);
+    assert_eq!(events[1].action, KVChangeAction::Set);
+    assert_eq!(events[1].value.as_deref(), Some("2"));
+    assert_eq!(events[3].action, KVChangeAction::Delete);
+    assert_eq!(events[3].value, None);
+    assert_eq!(events[3].key_revision, 3);
+
+    let (events, next_revision) = store.list_changes("services/", 1, 1).await.unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(next_revision, 2);
+    let (events, next_revision) = store
+        .list_changes("services/", next_revision, 16)
+        .await
+        .unwrap();
+    assert_eq!(
+        events
+            .iter()
+            .map(|event| event.revision)
+            .collect::<Vec<_>>(),
+        vec![2, 4]
+    );
+    assert_eq!(next_revision, 5);
+
+    let (events, next_revision) = store.list_changes("", 5, 16).await.unwrap();
+    assert!(events.is_empty());
+    assert_eq!(next_revision, 5);
+}
+
+async fn case_change_log_compaction(store: &dyn KVStoreProvider) {
+    for index in 0..6 {
+        store
+            .set("services/a/config".to_string(), index.to_string())
+            .await
+            .unwrap();
+    }
+    assert_eq!(store.get_store_revision().await.unwrap(), 6);
+
+    match store.list_changes("", 1, 16).await {
+        Err(KVStoreErrors::Compacted { requested, oldest }) => {
+            assert_eq!(requested, 1);
+            assert_eq!(oldest, 6 - TEST_CHANGE_LOG_CAPACITY + 1);
+        }
+        other => panic!("expected compacted error, got {:?}", other),
+    }
+
+    let (events, _) = store.list_changes("", 3, 16).await.unwrap();
+    assert_eq!(events.len() as u64, TEST_CHANGE_LOG_CAPACITY);
+}
+
+async fn case_key_history(store: &dyn KVStoreProvider) {
+    for index in 1..=5 {
+        store
+            .set("services/a/config".to_string(), format!("v{}", index))
+            .await
+            .unwrap();
+    }
+
+    let history = store.get_history("services/a/config", 16).await.unwrap();
+    assert_eq!(history.len() as u64, TEST_KEY_HISTORY_CAPACITY);
+    assert_eq!(
+        history
+            .iter()
+            .map(|event| event.key_revision)
+            .collect::<Vec<_>>(),
+        vec![5, 4, 3]
+    );
+    assert_eq!(
+        store
+            .get_history("services/a/config", 1)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+
+    let event = store
+        .get_at_revision("services/a/config", 4)
+        .await
+        .unwrap()
+        .expect("revision 4 kept");
+    assert_eq!(event.value.as_deref(), Some("v4"));
+    assert!(store
+        .get_at_revision("services/a/config", 1)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(store
+        .get_history("services/missing", 16)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // keys sharing a prefix (DIDs contain ':') keep separate histories
+    for key in [
+        "users/did:bns:alice",
+        "users/did:bns:alice:2",
+        "users/did:bns:alice/x",
+    ] {
+        store.set(key.to_string(), "v".to_string()).await.unwrap();
+    }
+    let history = store.get_history("users/did:bns:alice", 16).await.unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].key, "users/did:bns:alice");
+}
+
+macro_rules! kv_provider_conformance {
+    ($provider:ident, $factory:expr) => {
+        mod $provider {
+            use super::*;
+
+            #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+            async fn crud_roundtrip() {
+                case_crud_roundtrip($factory().await.as_ref()).await;
+            }
+
+            #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+            async fn set_by_path() {
+                case_set_by_path($factory().await.as_ref()).await;
+            }
+
+            #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+            async fn revision_survives_delete() {
+                case_revision_survives_delete($factory().await.as_ref()).await;
+            }
+
+            #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+            async fn exec_tx_applies_all_actions() {
+                case_exec_tx_applies_all_actions($factory().await.as_ref()).await;
+            }
+
+            #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+            async fn exec_tx_is_atomic() {
+                case_exec_tx_is_atomic($factory().await.as_ref()).await;
+            }
+
+            #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+            async fn exec_tx_checks_main_key() {
+                case_exec_tx_checks_main_key($factory().await.as_ref()).await;
+            }
+
+            #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+            async fn list_operations() {
+                case_list_operations($factory().await.as_ref()).await;
+            }
+
+            #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+            async fn change_log() {
+                case_change_log($factory().await.as_ref()).await;
+            }
+
+            #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+            async fn change_log_compaction() {
+                case_change_log_compaction($factory().await.as_ref()).await;
+            }
+
+            #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+            async fn key_history() {
+                case_key_history($factory().await.as_ref()).await;
+            }
+        }
+    };
+}
+
+kv_provider_conformance!(sled_store, create_sled_store);
+kv_provider_conformance!(sqlite_store, create_sqlite_store);
+kv_provider_conformance!(klog_store, create_klog_store);
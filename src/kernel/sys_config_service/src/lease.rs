@@ -0,0 +1,737 @@
+use std::collections::{HashMap, HashSet};
+
+use buckyos_kit::KVAction;
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::kv_provider::*;
+
+// lease records are stored in the kv store itself, so they survive restarts and
+// work with every backend; clients can not read or write this prefix directly.
+pub(crate) const LEASE_KEY_PREFIX: &str = "__lease/";
+// key -> lease_id index, a key is attached to at most one lease. It is the only record of
+// the attachment, so a write detaches the key by removing one entry in its own tx.
+pub(crate) const LEASE_INDEX_PREFIX: &str = "__lease/keys/";
+pub(crate) const LEASE_MIN_TTL: u64 = 1;
+pub(crate) const LEASE_MAX_TTL: u64 = 24 * 3600;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct LeaseRecord {
+    pub lease_id: String,
+    pub owner: String,
+    pub ttl: u64,
+    pub expire_at: u64,
+}
+
+impl LeaseRecord {
+    pub fn is_expired(&self, now: u64) -> bool {
+        now >= self.expire_at
+    }
+}
+
+// value stored in a lock key by sys_config_lock
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct LockRecord {
+    pub owner: String,
+    pub lease_id: String,
+    pub acquired_at: u64,
+}
+
+pub(crate) fn lease_key(lease_id: &str) -> String {
+    format!("{}{}", LEASE_KEY_PREFIX, lease_id)
+}
+
+pub(crate) fn lease_index_key(key: &str) -> String {
+    format!("{}{}", LEASE_INDEX_PREFIX, key)
+}
+
+// lease records are rewritten by every keep-alive, the stores keep them out of
+// the change log and key history so they do not flood watchers
+pub(crate) fn is_lease_key(key: &str) -> bool {
+    key.starts_with(LEASE_KEY_PREFIX)
+}
+
+// the id is shown in lock values, it must not be guessable from the time or the revision
+pub(crate) fn create_lease_id() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+pub(crate) fn check_lease_ttl(ttl: u64) -> Result<u64> {
+    if !(LEASE_MIN_TTL..=LEASE_MAX_TTL).contains(&ttl) {
+        return Err(KVStoreErrors::InternalError(format!(
+            "lease ttl must be in [{}, {}] seconds",
+            LEASE_MIN_TTL, LEASE_MAX_TTL
+        )));
+    }
+    Ok(ttl)
+}
+
+pub(crate) async fn load_lease(
+    store: &dyn KVStoreProvider,
+    lease_id: &str,
+) -> Result<Option<(LeaseRecord, u64)>> {
+    let Some((value, revision)) = store.get_with_revision(lease_key(lease_id)).await? else {
+        return Ok(None);
+    };
+    let record: LeaseRecord = serde_json::from_str(&value)
+        .map_err(|err| KVStoreErrors::InternalError(err.to_string()))?;
+    Ok(Some((record, revision)))
+}
+
+pub(crate) fn lease_tx_value(record: &LeaseRecord) -> Result<String> {
+    serde_json::to_string(record).map_err(|err| KVStoreErrors::InternalError(err.to_string()))
+}
+
+pub(crate) async fn grant_lease(
+    store: &dyn KVStoreProvider,
+    owner: &str,
+    ttl: u64,
+    now: u64,
+) -> Result<LeaseRecord> {
+    let ttl = check_lease_ttl(ttl)?;
+    let record = LeaseRecord {
+        lease_id: create_lease_id(),
+        owner: owner.to_string(),
+        ttl,
+        expire_at: now + ttl,
+    };
+    store
+        .create(&lease_key(&record.lease_id), &lease_tx_value(&record)?)
+        .await?;
+    Ok(record)
+}
+
+// load a live lease and check it belongs to owner ("root" may act on any lease)
+pub(crate) async fn load_live_lease(
+    store: &dyn KVStoreProvider,
+    lease_id: &str,
+    owner: &str,
+    now: u64,
+) -> Result<(LeaseRecord, u64)> {
+    let Some((record, revision)) = load_lease(store, lease_id).await? else {
+        return Err(KVStoreErrors::LeaseNotFound(lease_id.to_string()));
+    };
+    if record.is_expired(now) {
+        return Err(KVStoreErrors::LeaseNotFound(lease_id.to_string()));
+    }
+    if record.owner != owner && owner != "root" {
+        return Err(KVStoreErrors::LeaseNotOwned {
+            lease_id: lease_id.to_string(),
+            owner: owner.to_string(),
+        });
+    }
+    Ok((record, revision))
+}
+
+pub(crate) async fn keep_alive_lease(
+    store: &dyn KVStoreProvider,
+    lease_id: &str,
+    owner: &str,
+    ttl: Option<u64>,
+    now: u64,
+) -> Result<LeaseRecord> {
+    let (mut record, revision) = load_live_lease(store, lease_id, owner, now).await?;
+    if let Some(ttl) = ttl {
+        record.ttl = check_lease_ttl(ttl)?;
+    }
+    record.expire_at = now + record.ttl;
+
+    let lease_key = lease_key(lease_id);
+    let mut tx = HashMap::new();
+    tx.insert(
+        lease_key.clone(),
+        KVAction::Update(lease_tx_value(&record)?),
+    );
+    store.exec_tx(tx, Some((lease_key, revision))).await?;
+    Ok(record)
+}
+
+// write key and attach it to the lease in one tx, so an expiring lease never misses the key.
+// The index entry is overwritten, which detaches the key from the lease it had before.
+pub(crate) async fn put_with_lease(
+    store: &dyn KVStoreProvider,
+    key: &str,
+    action: KVAction,
+    lease_id: &str,
+    owner: &str,
+    now: u64,
+) -> Result<LeaseRecord> {
+    let (record, revision) = load_live_lease(store, lease_id, owner, now).await?;
+
+    // the lease record is rewritten unchanged, the revision check fails if it expired meanwhile
+    let lease_key = lease_key(lease_id);
+    let mut tx = HashMap::new();
+    tx.insert(key.to_string(), action);
+    tx.insert(lease_index_key(key), KVAction::Update(lease_id.to_string()));
+    tx.insert(
+        lease_key.clone(),
+        KVAction::Update(lease_tx_value(&record)?),
+    );
+    store.exec_tx(tx, Some((lease_key, revision))).await?;
+    Ok(record)
+}
+
+// every write that does not carry a lease goes through here (set, create, delete, append,
+// set_by_json_path, exec_tx, import): the index entries of the written keys are removed in
+// the same tx, so an expiring lease does not remove or overwrite a value it no longer owns
+pub(crate) async fn exec_tx_without_lease(
+    store: &dyn KVStoreProvider,
+    mut tx: HashMap<String, KVAction>,
+    main_key: Option<(String, u64)>,
+) -> Result<()> {
+    let keys: Vec<String> = tx.keys().cloned().collect();
+    for key in keys {
+        let index_key = lease_index_key(&key);
+        if store.get(index_key.clone()).await?.is_some() {
+            tx.insert(index_key, KVAction::Remove);
+        }
+    }
+    store.exec_tx(tx, main_key).await
+}
+
+// single key variant of exec_tx_without_lease, keeps the error semantics of create/set/delete
+pub(crate) async fn write_without_lease(
+    store: &dyn KVStoreProvider,
+    key: &str,
+    action: KVAction,
+) -> Result<()> {
+    let index_key = lease_index_key(key);
+    if store.get(index_key.clone()).await?.is_none() {
+        return match action {
+            KVAction::Create(value) => store.create(key, &value).await,
+            KVAction::Update(value) => store.set(key.to_string(), value).await,
+            KVAction::Remove => store.delete(key).await,
+            action => {
+                store
+                    .exec_tx(HashMap::from([(key.to_string(), action)]), None)
+                    .await
+            }
+        };
+    }
+    if matches!(action, KVAction::Remove) && store.get(key.to_string()).await?.is_none() {
+        return Err(KVStoreErrors::KeyNotFound(key.to_string()));
+    }
+    let mut tx = HashMap::new();
+    tx.insert(key.to_string(), action);
+    tx.insert(index_key, KVAction::Remove);
+    store.exec_tx(tx, None).await
+}
+
+// keys currently attached to lease_id
+pub(crate) async fn list_lease_keys(
+    store: &dyn KVStoreProvider,
+    lease_id: &str,
+) -> Result<Vec<String>> {
+    let mut keys = Vec::new();
+    for (index_key, value) in store.list_data(LEASE_INDEX_PREFIX).await? {
+        if value == lease_id {
+            keys.push(index_key[LEASE_INDEX_PREFIX.len()..].to_string());
+        }
+    }
+    keys.sort();
+    Ok(keys)
+}
+
+// remove key if it is still attached to lease_id; the index revision check skips keys that
+// were rewritten or re-attached after the index was read. Returns true if the key was removed.
+async fn remove_leased_key(store: &dyn KVStoreProvider, key: &str, lease_id: &str) -> Result<bool> {
+    let index_key = lease_index_key(key);
+    let Some((value, revision)) = store.get_with_revision(index_key.clone()).await? else {
+        return Ok(false);
+    };
+    if value != lease_id {
+        return Ok(false);
+    }
+
+    let mut tx = HashMap::new();
+    tx.insert(index_key.clone(), KVAction::Remove);
+    let exists = store.get(key.to_string()).await?.is_some();
+    if exists {
+        tx.insert(key.to_string(), KVAction::Remove);
+    }
+    match store.exec_tx(tx, Some((index_key, revision))).await {
+        Ok(_) => Ok(exists),
+        Err(KVStoreErrors::RevisionMismatch { .. }) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+// remove the keys attached to leases that no longer exist (expired, revoked, or left
+// behind by a sweep that stopped half way)
+async fn remove_orphan_keys(
+    store: &dyn KVStoreProvider,
+    live_leases: &HashSet<String>,
+) -> Result<Vec<String>> {
+    let mut removed_keys = Vec::new();
+    for (index_key, lease_id) in store.list_data(LEASE_INDEX_PREFIX).await? {
+        if live_leases.contains(&lease_id) {
+            continue;
+        }
+        let key = &index_key[LEASE_INDEX_PREFIX.len()..];
+        if remove_leased_key(store, key, &lease_id).await? {
+            removed_keys.push(key.to_string());
+        }
+    }
+    removed_keys.sort();
+    Ok(removed_keys)
+}
+
+// drop the lease first (under its revision check, a keep-alive wins), then its keys
+pub(crate) async fn revoke_lease(
+    store: &dyn KVStoreProvider,
+    record: &LeaseRecord,
+    revision: u64,
+) -> Result<Vec<String>> {
+    let lease_key = lease_key(&record.lease_id);
+    let mut tx = HashMap::new();
+    tx.insert(lease_key.clone(), KVAction::Remove);
+    store.exec_tx(tx, Some((lease_key, revision))).await?;
+
+    let mut removed_keys = Vec::new();
+    for key in list_lease_keys(store, &record.lease_id).await? {
+        if remove_leased_key(store, &key, &record.lease_id).await? {
+            removed_keys.push(key);
+        }
+    }
+    Ok(removed_keys)
+}
+
+pub(crate) async fn list_leases(store: &dyn KVStoreProvider) -> Result<Vec<LeaseRecord>> {
+    let mut result = Vec::new();
+    for (key, value) in store.list_data(LEASE_KEY_PREFIX).await? {
+        if key.starts_with(LEASE_INDEX_PREFIX) {
+            continue;
+        }
+        match serde_json::from_str::<LeaseRecord>(&value) {
+            Ok(record) => result.push(record),
+            Err(err) => warn!("skip broken lease record {}: {}", key, err),
+        }
+    }
+    Ok(result)
+}
+
+// remove expired leases with their keys, return the removed keys
+pub(crate) async fn expire_leases(store: &dyn KVStoreProvider, now: u64) -> Result<Vec<String>> {
+    let leases = list_leases(store).await?;
+    let mut live_leases: HashSet<String> = leases
+        .iter()
+        .map(|record| record.lease_id.clone())
+        .collect();
+
+    let mut expired = false;
+    for record in leases.iter().filter(|record| record.is_expired(now)) {
+        // re-read under the same revision check, a keep-alive may have landed after the list
+        let Some((current, revision)) = load_lease(store, &record.lease_id).await? else {
+            live_leases.remove(&record.lease_id);
+            expired = true;
+            continue;
+        };
+        if !current.is_expired(now) {
+            continue;
+        }
+        let lease_key = lease_key(&current.lease_id);
+        let mut tx = HashMap::new();
+        tx.insert(lease_key.clone(), KVAction::Remove);
+        match store.exec_tx(tx, Some((lease_key, revision))).await {
+            Ok(_) => {
+                info!("lease {} of {} expired", current.lease_id, current.owner);
+                live_leases.remove(&current.lease_id);
+                expired = true;
+            }
+            Err(KVStoreErrors::RevisionMismatch { .. }) => {
+                debug!(
+                    "lease {} changed during expiry, retry later",
+                    current.lease_id
+                );
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    // the index is only scanned when a lease went away
+    if !expired {
+        return Ok(Vec::new());
+    }
+    let removed_keys = remove_orphan_keys(store, &live_leases).await?;
+    if !removed_keys.is_empty() {
+        info!("expired leases removed keys: {:?}", removed_keys);
+    }
+    Ok(removed_keys)
+}
+
+// take key as a lock held by a new lease; an expired holder is cleaned up first
+pub(crate) async fn acquire_lock(
+    store: &dyn KVStoreProvider,
+    key: &str,
+    owner: &str,
+    ttl: u64,
+    now: u64,
+) -> Result<(LockRecord, LeaseRecord)> {
+    let ttl = check_lease_ttl(ttl)?;
+    let mut existing = store.get_with_revision(key.to_string()).await?;
+    if let Some((value, _)) = existing.as_ref() {
+        let holder: LockRecord = serde_json::from_str(value)
+            .map_err(|_| KVStoreErrors::InternalError(format!("key {} is not a lock", key)))?;
+        if let Some((lease, _)) = load_lease(store, &holder.lease_id).await? {
+            if !lease.is_expired(now) {
+                return Err(KVStoreErrors::LockHeld {
+                    key: key.to_string(),
+                    owner: holder.owner,
+                });
+            }
+            expire_leases(store, now).await?;
+            existing = store.get_with_revision(key.to_string()).await?;
+        }
+    }
+
+    let lease = LeaseRecord {
+        lease_id: create_lease_id(),
+        owner: owner.to_string(),
+        ttl,
+        expire_at: now + ttl,
+    };
+    let lock = LockRecord {
+        owner: owner.to_string(),
+        lease_id: lease.lease_id.clone(),
+        acquired_at: now,
+    };
+    let lock_value = serde_json::to_string(&lock)
+        .map_err(|err| KVStoreErrors::InternalError(err.to_string()))?;
+
+    let mut tx = HashMap::new();
+    tx.insert(
+        lease_key(&lease.lease_id),
+        KVAction::Create(lease_tx_value(&lease)?),
+    );
+    tx.insert(
+        lease_index_key(key),
+        KVAction::Update(lease.lease_id.clone()),
+    );
+    let main_key = match existing {
+        // holder lease is gone but the lock key is still there: take it over
+        Some((_, revision)) => {
+            tx.insert(key.to_string(), KVAction::Update(lock_value));
+            Some((key.to_string(), revision))
+        }
+        None => {
+            tx.insert(key.to_string(), KVAction::Create(lock_value));
+            None
+        }
+    };
+    match store.exec_tx(tx, main_key).await {
+        Ok(_) => Ok((lock, lease)),
+        Err(KVStoreErrors::KeyExist(_)) | Err(KVStoreErrors::RevisionMismatch { .. }) => {
+            Err(KVStoreErrors::LockHeld {
+                key: key.to_string(),
+                owner: "another owner".to_string(),
+            })
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// the lease_id can be read from the lock value, so the caller must also own the lock
+// ("root" may release any lock)
+pub(crate) async fn release_lock(
+    store: &dyn KVStoreProvider,
+    key: &str,
+    lease_id: &str,
+    owner: &str,
+) -> Result<()> {
+    let Some((value, revision)) = store.get_with_revision(key.to_string()).await? else {
+        return Err(KVStoreErrors::KeyNotFound(key.to_string()));
+    };
+    let holder: LockRecord = serde_json::from_str(&value)
+        .map_err(|_| KVStoreErrors::InternalError(format!("key {} is not a lock", key)))?;
+    if holder.lease_id != lease_id {
+        return Err(KVStoreErrors::LockHeld {
+            key: key.to_string(),
+            owner: holder.owner,
+        });
+    }
+    if holder.owner != owner && owner != "root" {
+        return Err(KVStoreErrors::LeaseNotOwned {
+            lease_id: lease_id.to_string(),
+            owner: owner.to_string(),
+        });
+    }
+
+    let mut tx = HashMap::new();
+    tx.insert(key.to_string(), KVAction::Remove);
+    if store.get(lease_index_key(key)).await?.is_some() {
+        tx.insert(lease_index_key(key), KVAction::Remove);
+    }
+    if store.get(lease_key(lease_id)).await?.is_some() {
+        tx.insert(lease_key(lease_id), KVAction::Remove);
+    }
+    store.exec_tx(tx, Some((key.to_string(), revision))).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sqlite_provider::SqliteStore;
+
+    #[tokio::test]
+    async fn expired_lease_removes_attached_keys() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let lease = grant_lease(&store, "alice", 10, 1000).await.unwrap();
+        put_with_lease(
+            &store,
+            "services/a/instances/ood1",
+            KVAction::Update("{}".to_string()),
+            &lease.lease_id,
+            "alice",
+            1001,
+        )
+        .await
+        .unwrap();
+
+        assert!(expire_leases(&store, 1005).await.unwrap().is_empty());
+        keep_alive_lease(&store, &lease.lease_id, "alice", None, 1008)
+            .await
+            .unwrap();
+        assert!(expire_leases(&store, 1012).await.unwrap().is_empty());
+
+        let removed = expire_leases(&store, 1018).await.unwrap();
+        assert_eq!(removed, vec!["services/a/instances/ood1".to_string()]);
+        assert_eq!(
+            store
+                .get("services/a/instances/ood1".to_string())
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(load_lease(&store, &lease.lease_id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn key_reattached_to_live_lease_survives_old_lease() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let old_lease = grant_lease(&store, "alice", 10, 1000).await.unwrap();
+        let new_lease = grant_lease(&store, "alice", 60, 1000).await.unwrap();
+        assert_ne!(old_lease.lease_id, new_lease.lease_id);
+        for lease in [&old_lease, &new_lease] {
+            put_with_lease(
+                &store,
+                "services/a/instances/ood1",
+                KVAction::Update("{}".to_string()),
+                &lease.lease_id,
+                "alice",
+                1001,
+            )
+            .await
+            .unwrap();
+        }
+
+        assert!(expire_leases(&store, 1020).await.unwrap().is_empty());
+        assert!(store
+            .get("services/a/instances/ood1".to_string())
+            .await
+            .unwrap()
+            .is_some());
+        assert!(load_lease(&store, &old_lease.lease_id)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn tx_write_detaches_keys_from_every_lease() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let first = grant_lease(&store, "alice", 10, 1000).await.unwrap();
+        let second = grant_lease(&store, "bob", 10, 1000).await.unwrap();
+        for (key, lease) in [
+            ("services/a/instances/ood1", &first),
+            ("services/b/instances/ood1", &second),
+            ("services/c/instances/ood1", &second),
+        ] {
+            put_with_lease(
+                &store,
+                key,
+                KVAction::Update("{}".to_string()),
+                &lease.lease_id,
+                &lease.owner,
+                1001,
+            )
+            .await
+            .unwrap();
+        }
+        assert_eq!(
+            list_lease_keys(&store, &second.lease_id).await.unwrap(),
+            vec![
+                "services/b/instances/ood1".to_string(),
+                "services/c/instances/ood1".to_string(),
+            ]
+        );
+
+        let mut tx = HashMap::new();
+        tx.insert(
+            "services/a/instances/ood1".to_string(),
+            KVAction::Append("!".to_string()),
+        );
+        tx.insert(
+            "services/b/instances/ood1".to_string(),
+            KVAction::Update("static".to_string()),
+        );
+        exec_tx_without_lease(&store, tx, None).await.unwrap();
+        assert!(list_lease_keys(&store, &first.lease_id)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let removed = expire_leases(&store, 1020).await.unwrap();
+        assert_eq!(removed, vec!["services/c/instances/ood1".to_string()]);
+        assert_eq!(
+            store
+                .get("services/a/instances/ood1".to_string())
+                .await
+                .unwrap(),
+            Some("{}!".to_string())
+        );
+        assert_eq!(
+            store
+                .get("services/b/instances/ood1".to_string())
+                .await
+                .unwrap(),
+            Some("static".to_string())
+        );
+        assert!(store
+            .list_data(LEASE_INDEX_PREFIX)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn plain_write_detaches_key_from_lease() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let lease = grant_lease(&store, "alice", 10, 1000).await.unwrap();
+        for key in ["services/a/instances/ood1", "services/a/instances/ood2"] {
+            put_with_lease(
+                &store,
+                key,
+                KVAction::Update("{}".to_string()),
+                &lease.lease_id,
+                "alice",
+                1001,
+            )
+            .await
+            .unwrap();
+        }
+
+        write_without_lease(
+            &store,
+            "services/a/instances/ood1",
+            KVAction::Update("static".to_string()),
+        )
+        .await
+        .unwrap();
+        write_without_lease(&store, "services/a/instances/ood2", KVAction::Remove)
+            .await
+            .unwrap();
+        assert!(list_lease_keys(&store, &lease.lease_id)
+            .await
+            .unwrap()
+            .is_empty());
+
+        assert!(expire_leases(&store, 1020).await.unwrap().is_empty());
+        assert_eq!(
+            store
+                .get("services/a/instances/ood1".to_string())
+                .await
+                .unwrap(),
+            Some("static".to_string())
+        );
+        assert!(matches!(
+            write_without_lease(&store, "services/a/instances/ood2", KVAction::Remove).await,
+            Err(KVStoreErrors::KeyNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn lock_is_exclusive_until_released_or_expired() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let (lock, lease) = acquire_lock(&store, "system/locks/scheduler", "ood1", 10, 1000)
+            .await
+            .unwrap();
+        assert_eq!(lock.lease_id, lease.lease_id);
+
+        match acquire_lock(&store, "system/locks/scheduler", "ood2", 10, 1005).await {
+            Err(KVStoreErrors::LockHeld { owner, .. }) => assert_eq!(owner, "ood1"),
+            other => panic!("expected lock held, got {:?}", other),
+        }
+        assert!(
+            release_lock(&store, "system/locks/scheduler", "bad-lease", "ood1")
+                .await
+                .is_err()
+        );
+        // the lease_id is readable from the lock value, it does not let others release the lock
+        assert!(matches!(
+            release_lock(&store, "system/locks/scheduler", &lock.lease_id, "ood2").await,
+            Err(KVStoreErrors::LeaseNotOwned { .. })
+        ));
+
+        // holder stops keeping the lease alive: the next owner takes over
+        let (lock, _) = acquire_lock(&store, "system/locks/scheduler", "ood2", 10, 1010)
+            .await
+            .unwrap();
+        assert_eq!(lock.owner, "ood2");
+        assert!(load_lease(&store, &lease.lease_id).await.unwrap().is_none());
+
+        release_lock(&store, "system/locks/scheduler", &lock.lease_id, "ood2")
+            .await
+            .unwrap();
+        assert!(store
+            .get("system/locks/scheduler".to_string())
+            .await
+            .unwrap()
+            .is_none());
+        assert!(
+            acquire_lock(&store, "system/locks/scheduler", "ood3", 10, 1011)
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn keep_alive_stays_out_of_change_log() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let first = grant_lease(&store, "alice", 10, 1000).await.unwrap();
+        let second = grant_lease(&store, "alice", 10, 1000).await.unwrap();
+        assert_ne!(first.lease_id, second.lease_id);
+        for now in 1001..1005 {
+            keep_alive_lease(&store, &first.lease_id, "alice", None, now)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(store.get_store_revision().await.unwrap(), 0);
+        let (changes, _) = store.list_changes("", 0, 16).await.unwrap();
+        assert!(changes.is_empty());
+        assert!(store
+            .get_history(&lease_key(&first.lease_id), 16)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn lease_is_checked_for_owner_and_expiry() {
+        let store = SqliteStore::open_in_memory().unwrap();
+        let lease = grant_lease(&store, "alice", 10, 1000).await.unwrap();
+        assert!(keep_alive_lease(&store, &lease.lease_id, "bob", None, 1001)
+            .await
+            .is_err());
+        assert!(
+            keep_alive_lease(&store, &lease.lease_id, "root", None, 1001)
+                .await
+                .is_ok()
+        );
+        assert!(matches!(
+            keep_alive_lease(&store, &lease.lease_id, "alice", None, 2000).await,
+            Err(KVStoreErrors::LeaseNotFound(_))
+        ));
+        assert!(grant_lease(&store, "alice", 0, 1000).await.is_err());
+    }
+}
@@ -1,12 +1,20 @@
+mod archive;
+mod klog_provider;
 mod kv_provider;
-//mod etcd_provider;
+mod lease;
+mod schema_registry;
 //mod rocksdb_provider;
 mod sled_provider;
+mod sqlite_provider;
 mod zone_did_resolver;
 
+#[cfg(test)]
+mod kv_provider_test;
+
 use std::collections::HashMap;
 use std::net::IpAddr;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 
 use jsonwebtoken::{Algorithm, DecodingKey};
 use log::*;
@@ -14,9 +22,11 @@ use log::*;
 use async_trait::async_trait;
 use lazy_static::lazy_static;
 use serde_json::Value;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, MutexGuard, Notify};
 
 use ::kRPC::*;
+use archive::is_reserved_key;
+use buckyos_api::{is_privileged_user_type, user_has_privileged_role_in_policy};
 use buckyos_kit::*;
 use bytes::Bytes;
 use cyfs_gateway_lib::{
@@ -25,11 +35,14 @@ use cyfs_gateway_lib::{
 };
 use http::{Method, Version};
 use http_body_util::combinators::BoxBody;
-use kv_provider::KVStoreProvider;
+use klog_provider::{KlogStore, KlogStoreConfig};
+use kv_provider::{KVStoreErrors, KVStoreProvider, ReadConsistency};
 use name_lib::*;
 use rbac::*;
+use schema_registry::SchemaRegistry;
 use server_runner::*;
 use sled_provider::SledStore;
+use sqlite_provider::SqliteStore;
 
 use crate::zone_did_resolver::ZoneDidResolver;
 
@@ -40,19 +53,55 @@ lazy_static! {
     };
 }
 
+// opened asynchronously by service_main before any request is served
+pub(crate) struct SysStore(OnceLock<Arc<Mutex<dyn KVStoreProvider>>>);
+
+impl SysStore {
+    pub(crate) async fn lock(&self) -> MutexGuard<'_, dyn KVStoreProvider> {
+        self.0
+            .get()
+            .expect("system config store is not opened")
+            .lock()
+            .await
+    }
+
+    fn init(&self, store: Arc<Mutex<dyn KVStoreProvider>>) {
+        if self.0.set(store).is_err() {
+            warn!("system config store is already opened");
+        }
+    }
+}
+
+pub(crate) static SYS_STORE: SysStore = SysStore(OnceLock::new());
+
+lazy_static! {
+    static ref SCHEMA_REGISTRY: SchemaRegistry = SchemaRegistry::with_builtin_schemas();
+}
+
 lazy_static! {
-    pub(crate) static ref SYS_STORE: Arc<Mutex<dyn KVStoreProvider>> =
-        Arc::new(Mutex::new(SledStore::new().unwrap()));
+    // wake up pending sys_config_watch calls after every successful write
+    static ref CONFIG_CHANGED: Notify = Notify::new();
 }
 
-const INTERNAL_META_PREFIX: &str = "__meta/";
+const SYS_STORE_CONFIG_FILE: &str = "system_config.json";
+const DEFAULT_SYS_STORE_BACKEND: &str = "sled";
+const LEASE_SWEEP_INTERVAL_SECS: u64 = 1;
+const WATCH_DEFAULT_TIMEOUT_MS: u64 = 30 * 1000;
+const WATCH_MAX_TIMEOUT_MS: u64 = 60 * 1000;
+const WATCH_BATCH_LIMIT: usize = 256;
+const HISTORY_DEFAULT_LIMIT: u64 = 20;
+const HISTORY_MAX_LIMIT: u64 = 128;
+
+fn notify_config_changed() {
+    CONFIG_CHANGED.notify_waiters();
+}
 
 fn is_internal_meta_key(key_path: &str) -> bool {
     let key = key_path
         .trim_start_matches("/config/")
         .trim_start_matches('/')
         .trim_start_matches('\\');
-    key.starts_with(INTERNAL_META_PREFIX)
+    is_reserved_key(key)
 }
 
 fn get_full_res_path(key_path: &str) -> Result<(String, String)> {
@@ -72,6 +121,71 @@ fn get_full_res_path(key_path: &str) -> Result<(String, String)> {
     ));
 }
 
+// reject values that do not match the schema registered for key; Ok(pattern) on match
+fn check_config_schema(key: &str, value: &str) -> Result<Option<String>> {
+    SCHEMA_REGISTRY.validate(key, value).map_err(|errors| {
+        warn!("schema check failed: {}", errors.join("; "));
+        RPCErrors::ReasonError(format!("schema validation failed: {}", errors.join("; ")))
+    })
+}
+
+// value that `action` would leave in key, None for remove / missing key
+async fn preview_action_value(
+    store: &dyn KVStoreProvider,
+    key: &str,
+    action: &KVAction,
+) -> Result<Option<String>> {
+    let result = match action {
+        KVAction::Create(value) | KVAction::Update(value) => Some(value.clone()),
+        KVAction::Append(value) => store
+            .get(key.to_string())
+            .await
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?
+            .map(|old_value| format!("{}{}", old_value, value)),
+        KVAction::SetByJsonPath(all_set) => {
+            let old_value = store
+                .get(key.to_string())
+                .await
+                .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+            match old_value {
+                Some(old_value) => {
+                    let mut old_value: Value = serde_json::from_str(&old_value)
+                        .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+                    for (path, sub_value) in all_set.iter() {
+                        set_json_by_path(&mut old_value, path, sub_value.as_ref());
+                    }
+                    Some(old_value.to_string())
+                }
+                None => None,
+            }
+        }
+        KVAction::Remove => None,
+    };
+    Ok(result)
+}
+
+async fn check_action_schema(
+    store: &dyn KVStoreProvider,
+    key: &str,
+    action: &KVAction,
+) -> Result<()> {
+    if SCHEMA_REGISTRY.find_schema(key).is_none() {
+        return Ok(());
+    }
+    if let Some(value) = preview_action_value(store, key, action).await? {
+        check_config_schema(key, &value)?;
+    }
+    Ok(())
+}
+
+// keep the caller's trailing '/' on a prefix, so "users/alice/" does not match "users/alice2"
+fn keep_trailing_slash(key: &str, mut real_key_path: String) -> String {
+    if key.ends_with('/') && !real_key_path.is_empty() && !real_key_path.ends_with('/') {
+        real_key_path.push('/');
+    }
+    real_key_path
+}
+
 async fn handle_get(params: Value, session_token: &RPCSessionToken) -> Result<Value> {
     let key = params.get("key");
     if key.is_none() {
@@ -110,6 +224,10 @@ async fn handle_get(params: Value, session_token: &RPCSessionToken) -> Result<Va
     };
 
     let store = SYS_STORE.lock().await;
+    let consistency = store
+        .read_consistency()
+        .await
+        .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
     let result = store
         .get_with_revision(real_key_path)
         .await
@@ -117,9 +235,11 @@ async fn handle_get(params: Value, session_token: &RPCSessionToken) -> Result<Va
     let Some((value, version)) = result else {
         return Ok(Value::Null);
     };
+    // a klog follower may answer before it has applied the latest writes
     Ok(serde_json::json!({
         "value": value,
         "version": version,
+        "stale": consistency == ReadConsistency::PossiblyStale,
     }))
 }
 
@@ -170,14 +290,44 @@ async fn handle_set(params: Value, session_token: &RPCSessionToken) -> Result<Va
         )));
     }
 
+    let schema_pattern = check_config_schema(&real_key_path, new_value)?;
+    // dry run: permission and schema checked, nothing written
+    if params
+        .get("dry_run")
+        .and_then(|value| value.as_bool())
+        .unwrap_or(false)
+    {
+        return Ok(serde_json::json!({
+            "valid": true,
+            "schema": schema_pattern,
+        }));
+    }
+
     //do business logic
     let store = SYS_STORE.lock().await;
     info!("Set key:[{}], value_len={}", key, new_value.len());
-    store
-        .set(real_key_path, String::from(new_value))
+    if let Some(lease_id) = params.get("lease_id").and_then(|value| value.as_str()) {
+        lease::put_with_lease(
+            &*store,
+            &real_key_path,
+            KVAction::Update(new_value.to_string()),
+            lease_id,
+            userid,
+            buckyos_get_unix_timestamp(),
+        )
+        .await
+        .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+    } else {
+        lease::write_without_lease(
+            &*store,
+            &real_key_path,
+            KVAction::Update(new_value.to_string()),
+        )
         .await
         .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+    }
     drop(store);
+    notify_config_changed();
     if should_reload_security_state(&full_res_path) {
         info!(
             "security config changed via set, reloading trust keys and rbac: {}",
@@ -236,14 +386,33 @@ async fn handle_create(params: Value, session_token: &RPCSessionToken) -> Result
         )));
     }
 
+    check_config_schema(&real_key_path, new_value)?;
+
     //do business logic
     let store = SYS_STORE.lock().await;
     info!("Create key:[{}], value_len={}", key, new_value.len());
-    store
-        .create(&real_key_path, new_value)
+    if let Some(lease_id) = params.get("lease_id").and_then(|value| value.as_str()) {
+        lease::put_with_lease(
+            &*store,
+            &real_key_path,
+            KVAction::Create(new_value.to_string()),
+            lease_id,
+            userid,
+            buckyos_get_unix_timestamp(),
+        )
+        .await
+        .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+    } else {
+        lease::write_without_lease(
+            &*store,
+            &real_key_path,
+            KVAction::Create(new_value.to_string()),
+        )
         .await
         .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+    }
     drop(store);
+    notify_config_changed();
     if should_reload_security_state(&full_res_path) {
         info!(
             "security config changed via create, reloading trust keys and rbac: {}",
@@ -300,11 +469,11 @@ async fn handle_delete(params: Value, session_token: &RPCSessionToken) -> Result
     //do business logic
     let store = SYS_STORE.lock().await;
     info!("Delete key:[{}]", key);
-    store
-        .delete(&real_key_path)
+    lease::write_without_lease(&*store, &real_key_path, KVAction::Remove)
         .await
         .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
     drop(store);
+    notify_config_changed();
     if should_reload_security_state(&full_res_path) {
         info!(
             "security config changed via delete, reloading trust keys and rbac: {}",
@@ -374,10 +543,12 @@ async fn handle_append(params: Value, session_token: &RPCSessionToken) -> Result
     } else {
         let old_value = result.unwrap();
         let new_value = format!("{}{}", old_value, append_value);
-        store
-            .set(real_key_path, new_value)
+        check_config_schema(&real_key_path, &new_value)?;
+        lease::write_without_lease(&*store, &real_key_path, KVAction::Update(new_value))
             .await
             .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+        drop(store);
+        notify_config_changed();
         return Ok(Value::Null);
     }
 }
@@ -440,12 +611,14 @@ async fn handle_set_by_json_path(params: Value, session_token: &RPCSessionToken)
 
     //do business logic
     let store = SYS_STORE.lock().await;
-    store
-        .set_by_path(real_key_path, String::from(json_path), &new_value)
+    let action = KVAction::SetByJsonPath(HashMap::from([(json_path.to_string(), Some(new_value))]));
+    check_action_schema(&*store, &real_key_path, &action).await?;
+    lease::write_without_lease(&*store, &real_key_path, action)
         .await
         .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
     //let result = store.get(String::from(key)).await.map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
     drop(store);
+    notify_config_changed();
     if should_reload_security_state(&full_res_path) {
         info!(
             "security config changed via set_by_json_path, reloading trust keys and rbac: {}",
@@ -586,11 +759,14 @@ async fn handle_exec_tx(params: Value, session_token: &RPCSessionToken) -> Resul
     }
 
     let store = SYS_STORE.lock().await;
-    store
-        .exec_tx(tx_actions, real_main_key)
+    for (key, action) in tx_actions.iter() {
+        check_action_schema(&*store, key, action).await?;
+    }
+    lease::exec_tx_without_lease(&*store, tx_actions, real_main_key)
         .await
         .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
     drop(store);
+    notify_config_changed();
 
     if need_reload_security_state {
         info!("exec_tx touched security config, reloading trust keys and rbac");
@@ -639,13 +815,536 @@ async fn handle_list(params: Value, session_token: &RPCSessionToken) -> Result<V
 
     //do business logic
     let store = SYS_STORE.lock().await;
+    let consistency = store
+        .read_consistency()
+        .await
+        .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
     let result = store
-        .list_direct_children(real_key_path)
+        .list_direct_children(real_key_path.clone())
+        .await
+        .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+    // "__lease" sits next to the user keys at the root
+    let children = Value::Array(
+        result
+            .iter()
+            .filter(|child| !real_key_path.is_empty() || !is_reserved_key(&format!("{}/", child)))
+            .map(|v| Value::String(v.clone()))
+            .collect(),
+    );
+    // the plain array is kept for old callers, "detail" also reports a stale follower read
+    if params
+        .get("detail")
+        .and_then(|value| value.as_bool())
+        .unwrap_or(false)
+    {
+        return Ok(serde_json::json!({
+            "children": children,
+            "stale": consistency == ReadConsistency::PossiblyStale,
+        }));
+    }
+    Ok(children)
+}
+
+// check params and read permission shared by history / point-in-time reads
+async fn check_read_key(params: &Value, session_token: &RPCSessionToken) -> Result<String> {
+    let key = params
+        .get("key")
+        .and_then(|value| value.as_str())
+        .ok_or(RPCErrors::ReasonError("Missing key".to_string()))?;
+    if is_internal_meta_key(key) {
+        return Err(RPCErrors::ReasonError(
+            "internal metadata key is reserved".to_string(),
+        ));
+    }
+
+    if session_token.sub.is_none() {
+        return Err(RPCErrors::NoPermission("No sub(userid)".to_string()));
+    }
+    let userid = session_token.sub.as_ref().unwrap();
+    let (full_res_path, real_key_path) = get_full_res_path(key)?;
+    if !enforce(
+        userid,
+        session_token.appid.as_deref(),
+        full_res_path.as_str(),
+        "read",
+    )
+    .await
+    {
+        warn!(
+            "read history denied: appid={} userid={} key={}",
+            session_token.appid.as_deref().unwrap_or("kernel"),
+            userid,
+            full_res_path
+        );
+        return Err(RPCErrors::NoPermission("No read permission".to_string()));
+    }
+    Ok(real_key_path)
+}
+
+async fn handle_get_history(params: Value, session_token: &RPCSessionToken) -> Result<Value> {
+    let real_key_path = check_read_key(&params, session_token).await?;
+    let limit = params
+        .get("limit")
+        .and_then(|value| value.as_u64())
+        .unwrap_or(HISTORY_DEFAULT_LIMIT)
+        .clamp(1, HISTORY_MAX_LIMIT);
+
+    let store = SYS_STORE.lock().await;
+    let history = store
+        .get_history(&real_key_path, limit as usize)
+        .await
+        .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+    Ok(serde_json::json!({
+        "key": real_key_path,
+        "history": history,
+    }))
+}
+
+async fn handle_get_at(params: Value, session_token: &RPCSessionToken) -> Result<Value> {
+    let real_key_path = check_read_key(&params, session_token).await?;
+    let revision = params
+        .get("revision")
+        .and_then(|value| value.as_u64())
+        .ok_or(RPCErrors::ReasonError("Missing revision".to_string()))?;
+
+    let store = SYS_STORE.lock().await;
+    let entry = store
+        .get_at_revision(&real_key_path, revision)
+        .await
+        .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+    let Some(entry) = entry else {
+        return Ok(Value::Null);
+    };
+    serde_json::to_value(&entry).map_err(|err| RPCErrors::ReasonError(err.to_string()))
+}
+
+fn lease_to_json(record: &lease::LeaseRecord) -> Value {
+    serde_json::json!({
+        "lease_id": record.lease_id,
+        "ttl": record.ttl,
+        "expire_at": record.expire_at,
+    })
+}
+
+fn get_session_userid(session_token: &RPCSessionToken) -> Result<&str> {
+    session_token
+        .sub
+        .as_deref()
+        .ok_or_else(|| RPCErrors::NoPermission("No sub(userid)".to_string()))
+}
+
+fn get_lease_ttl(params: &Value) -> Result<u64> {
+    params
+        .get("ttl")
+        .and_then(|value| value.as_u64())
+        .ok_or_else(|| RPCErrors::ReasonError("Missing ttl".to_string()))
+}
+
+fn get_lease_id(params: &Value) -> Result<&str> {
+    params
+        .get("lease_id")
+        .and_then(|value| value.as_str())
+        .ok_or_else(|| RPCErrors::ReasonError("Missing lease_id".to_string()))
+}
+
+// a lease is owned by the caller's userid; keys are attached to it by set/create with lease_id
+async fn handle_grant_lease(params: Value, session_token: &RPCSessionToken) -> Result<Value> {
+    let userid = get_session_userid(session_token)?;
+    let ttl = get_lease_ttl(&params)?;
+    let store = SYS_STORE.lock().await;
+    let record = lease::grant_lease(&*store, userid, ttl, buckyos_get_unix_timestamp())
+        .await
+        .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+    info!(
+        "grant lease:[{}] ttl:{} to {}",
+        record.lease_id, record.ttl, userid
+    );
+    Ok(lease_to_json(&record))
+}
+
+async fn handle_keep_alive(params: Value, session_token: &RPCSessionToken) -> Result<Value> {
+    let userid = get_session_userid(session_token)?;
+    let lease_id = get_lease_id(&params)?;
+    let ttl = params.get("ttl").and_then(|value| value.as_u64());
+    let store = SYS_STORE.lock().await;
+    let record =
+        lease::keep_alive_lease(&*store, lease_id, userid, ttl, buckyos_get_unix_timestamp())
+            .await
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+    Ok(lease_to_json(&record))
+}
+
+async fn handle_revoke_lease(params: Value, session_token: &RPCSessionToken) -> Result<Value> {
+    let userid = get_session_userid(session_token)?;
+    let lease_id = get_lease_id(&params)?;
+    let now = buckyos_get_unix_timestamp();
+    let store = SYS_STORE.lock().await;
+    let (record, revision) = lease::load_live_lease(&*store, lease_id, userid, now)
+        .await
+        .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+    let removed_keys = lease::revoke_lease(&*store, &record, revision)
+        .await
+        .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+    drop(store);
+    notify_config_changed();
+    info!(
+        "revoke lease:[{}], removed keys:{:?}",
+        lease_id, removed_keys
+    );
+    Ok(serde_json::json!({ "removed": removed_keys }))
+}
+
+// lock key holds {owner, lease_id, acquired_at}; it is released by unlock or when the lease expires
+async fn handle_lock(params: Value, session_token: &RPCSessionToken) -> Result<Value> {
+    let key = params
+        .get("key")
+        .and_then(|value| value.as_str())
+        .ok_or_else(|| RPCErrors::ReasonError("Missing key".to_string()))?;
+    if is_internal_meta_key(key) {
+        return Err(RPCErrors::ReasonError(
+            "internal metadata key is reserved".to_string(),
+        ));
+    }
+    let ttl = get_lease_ttl(&params)?;
+    let userid = get_session_userid(session_token)?;
+    let (full_res_path, real_key_path) = get_full_res_path(key)?;
+    if !enforce(
+        userid,
+        session_token.appid.as_deref(),
+        full_res_path.as_str(),
+        "write",
+    )
+    .await
+    {
+        return Err(RPCErrors::NoPermission(format!(
+            "No write permission for key: {}",
+            &real_key_path
+        )));
+    }
+    let store = SYS_STORE.lock().await;
+    let (lock, lease) = lease::acquire_lock(
+        &*store,
+        &real_key_path,
+        userid,
+        ttl,
+        buckyos_get_unix_timestamp(),
+    )
+    .await
+    .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+    drop(store);
+    notify_config_changed();
+    info!(
+        "lock:[{}] acquired by {}, lease:[{}]",
+        real_key_path, lock.owner, lock.lease_id
+    );
+    Ok(lease_to_json(&lease))
+}
+
+async fn handle_unlock(params: Value, session_token: &RPCSessionToken) -> Result<Value> {
+    let key = params
+        .get("key")
+        .and_then(|value| value.as_str())
+        .ok_or_else(|| RPCErrors::ReasonError("Missing key".to_string()))?;
+    if is_internal_meta_key(key) {
+        return Err(RPCErrors::ReasonError(
+            "internal metadata key is reserved".to_string(),
+        ));
+    }
+    let lease_id = get_lease_id(&params)?;
+    let userid = get_session_userid(session_token)?;
+    let (full_res_path, real_key_path) = get_full_res_path(key)?;
+    if !enforce(
+        userid,
+        session_token.appid.as_deref(),
+        full_res_path.as_str(),
+        "write",
+    )
+    .await
+    {
+        return Err(RPCErrors::NoPermission(format!(
+            "No write permission for key: {}",
+            &real_key_path
+        )));
+    }
+
+    let store = SYS_STORE.lock().await;
+    lease::release_lock(&*store, &real_key_path, lease_id, userid)
+        .await
+        .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+    drop(store);
+    notify_config_changed();
+    info!("lock:[{}] released by {}", real_key_path, userid);
+    Ok(Value::Null)
+}
+
+async fn run_lease_sweeper() {
+    let mut interval = tokio::time::interval(Duration::from_secs(LEASE_SWEEP_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        let store = SYS_STORE.lock().await;
+        // with a replicated store only the leader expires leases, the removals reach every OOD
+        if !store.is_primary() {
+            continue;
+        }
+        let result = lease::expire_leases(&*store, buckyos_get_unix_timestamp()).await;
+        drop(store);
+        match result {
+            Ok(removed_keys) => {
+                if !removed_keys.is_empty() {
+                    notify_config_changed();
+                }
+            }
+            Err(err) => warn!("expire leases failed: {}", err),
+        }
+    }
+}
+
+// long-poll the change log: return as soon as there are changes under `key` with
+// revision >= from_revision, or an empty batch when timeout_ms expires.
+// from_revision == 0 means "only changes after now".
+async fn handle_watch(params: Value, session_token: &RPCSessionToken) -> Result<Value> {
+    let key = params.get("key");
+    if key.is_none() {
+        return Err(RPCErrors::ReasonError("Missing key".to_string()));
+    }
+    let key = key.unwrap();
+    let key = key
+        .as_str()
+        .ok_or(RPCErrors::ReasonError("key must be string".to_string()))?;
+    if is_internal_meta_key(key) {
+        return Err(RPCErrors::ReasonError(
+            "internal metadata key is reserved".to_string(),
+        ));
+    }
+    let from_revision = params
+        .get("from_revision")
+        .and_then(|value| value.as_u64())
+        .unwrap_or(0);
+    let timeout_ms = params
+        .get("timeout_ms")
+        .and_then(|value| value.as_u64())
+        .unwrap_or(WATCH_DEFAULT_TIMEOUT_MS)
+        .min(WATCH_MAX_TIMEOUT_MS);
+
+    //check access control
+    if session_token.sub.is_none() {
+        return Err(RPCErrors::NoPermission("No sub(userid)".to_string()));
+    }
+    let userid = session_token.sub.as_ref().unwrap();
+    let (full_res_path, real_key_path) = get_full_res_path(key)?;
+    if !enforce(
+        userid,
+        session_token.appid.as_deref(),
+        full_res_path.as_str(),
+        "read",
+    )
+    .await
+    {
+        warn!(
+            "watch denied: appid={} userid={} key={}",
+            session_token.appid.as_deref().unwrap_or("kernel"),
+            userid,
+            full_res_path
+        );
+        return Err(RPCErrors::NoPermission("No read permission".to_string()));
+    }
+
+    let key_prefix = keep_trailing_slash(key, real_key_path);
+
+    let mut from_revision = from_revision;
+    if from_revision == 0 {
+        let store = SYS_STORE.lock().await;
+        from_revision = store
+            .get_store_revision()
+            .await
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?
+            + 1;
+    }
+
+    let deadline = tokio::time::Instant::now() + Duration::from_millis(timeout_ms);
+    loop {
+        // register before reading the log, so a write between the read and the wait is not lost
+        let notified = CONFIG_CHANGED.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+
+        let store = SYS_STORE.lock().await;
+        let result = store
+            .list_changes(&key_prefix, from_revision, WATCH_BATCH_LIMIT)
+            .await;
+        drop(store);
+
+        let (events, next_revision) = match result {
+            Ok(result) => result,
+            Err(KVStoreErrors::Compacted { requested, oldest }) => {
+                info!(
+                    "watch {} from revision {} is compacted, oldest is {}",
+                    key_prefix, requested, oldest
+                );
+                return Ok(serde_json::json!({
+                    "compacted": true,
+                    "oldest_revision": oldest,
+                    "events": [],
+                    "revision": from_revision,
+                }));
+            }
+            Err(err) => return Err(RPCErrors::ReasonError(err.to_string())),
+        };
+        from_revision = next_revision;
+
+        if !events.is_empty() {
+            return Ok(serde_json::json!({
+                "events": events,
+                "revision": next_revision,
+            }));
+        }
+
+        if tokio::time::timeout_at(deadline, notified).await.is_err() {
+            return Ok(serde_json::json!({
+                "events": [],
+                "revision": from_revision,
+            }));
+        }
+    }
+}
+
+async fn handle_export(params: Value, session_token: &RPCSessionToken) -> Result<Value> {
+    let prefix = params
+        .get("prefix")
+        .and_then(|value| value.as_str())
+        .unwrap_or("");
+    if is_internal_meta_key(prefix) {
+        return Err(RPCErrors::ReasonError(
+            "internal metadata key is reserved".to_string(),
+        ));
+    }
+
+    //check access control
+    if session_token.sub.is_none() {
+        return Err(RPCErrors::NoPermission("No sub(userid)".to_string()));
+    }
+    let userid = session_token.sub.as_ref().unwrap();
+    let (full_res_path, real_key_path) = if prefix.is_empty() {
+        ("/config/".to_string(), String::new())
+    } else {
+        let (full_res_path, real_key_path) = get_full_res_path(prefix)?;
+        (full_res_path, keep_trailing_slash(prefix, real_key_path))
+    };
+    if !enforce(
+        userid,
+        session_token.appid.as_deref(),
+        full_res_path.as_str(),
+        "read",
+    )
+    .await
+    {
+        warn!(
+            "export denied: appid={} userid={} prefix={}",
+            session_token.appid.as_deref().unwrap_or("kernel"),
+            userid,
+            full_res_path
+        );
+        return Err(RPCErrors::NoPermission("No read permission".to_string()));
+    }
+
+    let store = SYS_STORE.lock().await;
+    let archive =
+        archive::export_config_archive(&*store, &real_key_path, buckyos_get_unix_timestamp())
+            .await
+            .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+    drop(store);
+
+    info!(
+        "export sys_config: prefix={} keys={} store_revision={}",
+        archive.prefix,
+        archive.items.len(),
+        archive.store_revision
+    );
+    serde_json::to_value(&archive).map_err(|err| RPCErrors::ReasonError(err.to_string()))
+}
+
+async fn handle_import(params: Value, session_token: &RPCSessionToken) -> Result<Value> {
+    let signed_archive = params
+        .get("archive")
+        .and_then(|value| value.as_str())
+        .ok_or(RPCErrors::ReasonError("Missing archive".to_string()))?;
+    let replace = params
+        .get("replace")
+        .and_then(|value| value.as_bool())
+        .unwrap_or(false);
+
+    if session_token.sub.is_none() {
+        return Err(RPCErrors::NoPermission("No sub(userid)".to_string()));
+    }
+    let userid = session_token.sub.as_ref().unwrap();
+
+    // the archive must be signed by the zone owner key ("root" in TRUST_KEYS)
+    let owner_key = TRUST_KEYS
+        .lock()
+        .await
+        .get("root")
+        .cloned()
+        .ok_or(RPCErrors::KeyNotExist("root".to_string()))?;
+    let archive = archive::verify_config_archive(signed_archive, &owner_key)?;
+
+    let mut need_reload_security_state = false;
+    for (key, value) in archive.items.iter() {
+        check_config_schema(key, value)?;
+    }
+
+    // list and write under one lock, so replace mode never misses a key created in between
+    let store = SYS_STORE.lock().await;
+    let (tx_actions, removed) = archive::import_tx_actions(&*store, &archive, replace)
+        .await
+        .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
+
+    // the signature proves where the archive comes from, the caller still needs write access
+    for key in tx_actions.keys() {
+        let (full_res_path, real_key_path) = get_full_res_path(key)?;
+        if !enforce(
+            userid,
+            session_token.appid.as_deref(),
+            full_res_path.as_str(),
+            "write",
+        )
+        .await
+        {
+            warn!(
+                "import denied: appid={} userid={} key={}",
+                session_token.appid.as_deref().unwrap_or("kernel"),
+                userid,
+                full_res_path
+            );
+            return Err(RPCErrors::NoPermission(format!(
+                "No write permission for key: {}",
+                &real_key_path
+            )));
+        }
+        if should_reload_security_state(&full_res_path) {
+            need_reload_security_state = true;
+        }
+    }
+
+    let updated = archive.items.len();
+    info!(
+        "import sys_config: prefix={} updated={} removed={} archive_revision={}",
+        archive.prefix, updated, removed, archive.store_revision
+    );
+    lease::exec_tx_without_lease(&*store, tx_actions, None)
         .await
         .map_err(|err| RPCErrors::ReasonError(err.to_string()))?;
-    Ok(Value::Array(
-        result.iter().map(|v| Value::String(v.clone())).collect(),
-    ))
+    drop(store);
+    notify_config_changed();
+
+    if need_reload_security_state {
+        info!("import touched security config, reloading trust keys and rbac");
+        handle_refresh_trust_keys().await?;
+    }
+
+    Ok(serde_json::json!({
+        "updated": updated,
+        "removed": removed,
+    }))
 }
 
 async fn handle_refresh_trust_keys() -> Result<Value> {
@@ -861,6 +1560,36 @@ impl SystemConfigServer {
                 "sys_config_list" => {
                     return handle_list(param, &rpc_session_token).await;
                 }
+                "sys_config_watch" => {
+                    return handle_watch(param, &rpc_session_token).await;
+                }
+                "sys_config_get_history" => {
+                    return handle_get_history(param, &rpc_session_token).await;
+                }
+                "sys_config_get_at" => {
+                    return handle_get_at(param, &rpc_session_token).await;
+                }
+                "sys_config_export" => {
+                    return handle_export(param, &rpc_session_token).await;
+                }
+                "sys_config_import" => {
+                    return handle_import(param, &rpc_session_token).await;
+                }
+                "sys_config_grant_lease" => {
+                    return handle_grant_lease(param, &rpc_session_token).await;
+                }
+                "sys_config_keep_alive" => {
+                    return handle_keep_alive(param, &rpc_session_token).await;
+                }
+                "sys_config_revoke_lease" => {
+                    return handle_revoke_lease(param, &rpc_session_token).await;
+                }
+                "sys_config_lock" => {
+                    return handle_lock(param, &rpc_session_token).await;
+                }
+                "sys_config_unlock" => {
+                    return handle_unlock(param, &rpc_session_token).await;
+                }
                 "dump_configs_for_scheduler" => {
                     return dump_configs_for_scheduler(param, &rpc_session_token).await;
                 }
@@ -946,29 +1675,6 @@ async fn load_device_doc(device_name: &str) -> Result<DeviceConfig> {
     return Ok(device_doc);
 }
 
-fn is_privileged_user_type(user_type: &str) -> bool {
-    matches!(
-        user_type.trim().to_ascii_lowercase().as_str(),
-        "admin" | "root"
-    )
-}
-
-fn user_has_privileged_role_in_policy(policy: &str, user_name: &str) -> bool {
-    policy.lines().any(|line| {
-        let line = line.trim();
-        if line.is_empty() || line.starts_with('#') {
-            return false;
-        }
-
-        let parts: Vec<&str> = line.split(',').map(|part| part.trim()).collect();
-        if parts.len() < 3 || parts[0] != "g" || parts[1] != user_name {
-            return false;
-        }
-
-        is_privileged_user_type(parts[2])
-    })
-}
-
 async fn load_privileged_user_doc(user_name: &str) -> Result<OwnerConfig> {
     let store = SYS_STORE.lock().await;
     let mut is_privileged = false;
@@ -1144,6 +1850,64 @@ async fn verify_trusted_jwt(jwt: &str) -> Result<RPCSessionToken> {
 
 // }
 
+// the store backend is picked at boot from etc/system_config.json:
+// {"kv_store": "sled" | "sqlite" | "klog", "klog": {...KlogStoreConfig}}
+async fn open_sys_store() -> std::result::Result<Arc<Mutex<dyn KVStoreProvider>>, String> {
+    let config_path = get_buckyos_system_etc_dir().join(SYS_STORE_CONFIG_FILE);
+    let config = std::fs::read_to_string(&config_path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<Value>(&content).ok());
+    let backend = config
+        .as_ref()
+        .and_then(|config| {
+            config
+                .get("kv_store")
+                .and_then(|value| value.as_str())
+                .map(|value| value.to_string())
+        })
+        .unwrap_or_else(|| DEFAULT_SYS_STORE_BACKEND.to_string());
+
+    info!("system config store backend: {}", backend);
+    match backend.as_str() {
+        "sqlite" => {
+            let store = SqliteStore::new().map_err(|err| format!("open sqlite store: {}", err))?;
+            Ok(Arc::new(Mutex::new(store)))
+        }
+        "sled" => {
+            let store = SledStore::new().map_err(|err| format!("open sled store: {}", err))?;
+            Ok(Arc::new(Mutex::new(store)))
+        }
+        "klog" => {
+            let klog_config = match config.as_ref().and_then(|config| config.get("klog")) {
+                Some(value) => serde_json::from_value::<KlogStoreConfig>(value.clone())
+                    .map_err(|err| format!("invalid klog config in {:?}: {}", config_path, err))?,
+                None => KlogStoreConfig::default(),
+            };
+            let store = create_klog_store(klog_config)
+                .await
+                .map_err(|err| format!("start klog store: {}", err))?;
+            Ok(Arc::new(Mutex::new(store)))
+        }
+        _ => {
+            warn!(
+                "unknown system config store backend: {}, fallback to {}",
+                backend, DEFAULT_SYS_STORE_BACKEND
+            );
+            let store = SledStore::new().map_err(|err| format!("open sled store: {}", err))?;
+            Ok(Arc::new(Mutex::new(store)))
+        }
+    }
+}
+
+async fn create_klog_store(
+    config: KlogStoreConfig,
+) -> std::result::Result<KlogStore, KVStoreErrors> {
+    let state_store = KlogStore::open_state_store(&config)?;
+    // writes replicated from other OODs must wake up the local watchers too
+    let on_applied = Arc::new(|| CONFIG_CHANGED.notify_waiters());
+    KlogStore::start(config, state_store, on_applied).await
+}
+
 async fn init_by_boot_config() -> Result<()> {
     let r = handle_refresh_trust_keys().await;
     if r.is_err() {
@@ -1187,11 +1951,19 @@ async fn init_by_boot_config() -> Result<()> {
     Ok(())
 }
 
-async fn service_main() {
+async fn service_main() -> std::result::Result<(), String> {
     //std::env::set_var("BUCKY_LOG","debug");
     init_logging("system_config_service", true);
     info!("Starting system config service............................");
-    init_by_boot_config().await.unwrap();
+    // the trust keys are loaded from the store, open it first
+    let store = open_sys_store()
+        .await
+        .map_err(|err| format!("open system config store failed: {}", err))?;
+    SYS_STORE.init(store);
+    init_by_boot_config()
+        .await
+        .map_err(|err| format!("init by boot config failed: {}", err))?;
+    tokio::spawn(run_lease_sweeper());
 
     let server = SystemConfigServer::new();
     const SYSTEM_CONFIG_SERVICE_MAIN_PORT: u16 = 3200;
@@ -1205,11 +1977,16 @@ async fn service_main() {
     let resolver = ZoneDidResolver {};
     let _ = runner.add_http_server("/".to_string(), Arc::new(resolver));
     let _ = runner.run().await;
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() {
-    service_main().await;
+    if let Err(err) = service_main().await {
+        // nothing can be served without the store, exit so node daemon restarts the service
+        error!("{}", err);
+        std::process::exit(1);
+    }
 }
 
 #[cfg(test)]
@@ -1241,7 +2018,7 @@ mod test {
         }
 
         let server = task::spawn(async {
-            service_main().await;
+            service_main().await.unwrap();
         });
 
         let now = SystemTime::now()
@@ -1380,7 +2157,7 @@ mod test {
         }
 
         let server = task::spawn(async {
-            service_main().await;
+            service_main().await.unwrap();
         });
 
         // Create JWT token for authentication
@@ -0,0 +1,374 @@
+use serde_json::{json, Value};
+
+// Schemas for well-known key families, matched by path pattern ("*" matches one segment).
+// A value is checked against the first matching pattern; keys without a pattern are free-form.
+//
+// The schema language is a small JSON Schema subset:
+// type, required, properties, additionalProperties, items, enum, minimum.
+pub(crate) struct SchemaRegistry {
+    rules: Vec<SchemaRule>,
+}
+
+struct SchemaRule {
+    pattern: String,
+    segments: Vec<String>,
+    schema: Value,
+}
+
+fn install_config_schema() -> Value {
+    json!({
+        "type": "object",
+        "required": ["data_mount_point", "cache_mount_point", "local_cache_mount_point", "res_pool_id"],
+        "properties": {
+            "data_mount_point": {"type": "object", "additionalProperties": {"type": "string"}},
+            "cache_mount_point": {"type": "array", "items": {"type": "string"}},
+            "local_cache_mount_point": {"type": "array", "items": {"type": "string"}},
+            "bind_address": {"type": "string"},
+            "expose_config": {"type": "object"},
+            "container_param": {"type": "string"},
+            "start_param": {"type": "string"},
+            "res_pool_id": {"type": "string"},
+            "node_affinity": {"type": "object"},
+            "replica_spread": {"type": "object", "required": ["topology_key"]}
+        }
+    })
+}
+
+fn service_spec_schema() -> Value {
+    json!({
+        "type": "object",
+        "required": ["service_doc", "enable", "app_index", "expected_instance_count", "state", "install_config"],
+        "properties": {
+            "service_doc": {"type": "object"},
+            "enable": {"type": "boolean"},
+            "app_index": {"type": "integer", "minimum": 0},
+            "expected_instance_count": {"type": "integer", "minimum": 0},
+            "state": {"type": "string"},
+            "install_config": install_config_schema()
+        }
+    })
+}
+
+fn app_spec_schema() -> Value {
+    json!({
+        "type": "object",
+        "required": ["app_doc", "app_index", "user_id", "enable", "expected_instance_count", "state", "install_config"],
+        "properties": {
+            "app_doc": {"type": "object"},
+            "app_index": {"type": "integer", "minimum": 0},
+            "user_id": {"type": "string"},
+            "enable": {"type": "boolean"},
+            "expected_instance_count": {"type": "integer", "minimum": 0},
+            "state": {"type": "string"},
+            "install_config": install_config_schema()
+        }
+    })
+}
+
+fn user_settings_schema() -> Value {
+    json!({
+        "type": "object",
+        "required": ["user_id", "type", "show_name", "password", "state", "res_pool_id"],
+        "properties": {
+            "user_id": {"type": "string"},
+            "type": {"enum": ["admin", "user", "root", "limited", "guest"]},
+            "show_name": {"type": "string"},
+            "password": {"type": "string"},
+            "state": {"type": "string"},
+            "res_pool_id": {"type": "string"},
+            "contact": {"type": "object"}
+        }
+    })
+}
+
+fn node_config_schema() -> Value {
+    json!({
+        "type": "object",
+        "required": ["node_id", "node_did", "kernel", "apps", "frame_services", "state"],
+        "properties": {
+            "node_id": {"type": "string"},
+            "node_did": {"type": "string"},
+            "kernel": {"type": "object", "additionalProperties": {"type": "object"}},
+            "apps": {"type": "object", "additionalProperties": {"type": "object"}},
+            "frame_services": {"type": "object", "additionalProperties": {"type": "object"}},
+            "state": {"enum": [
+                "running", "stopped", "starting", "stopping", "restarting",
+                "initializing", "removed", "maintenance", "repalcing"
+            ]}
+        }
+    })
+}
+
+fn instance_report_schema() -> Value {
+    json!({
+        "type": "object",
+        "required": ["instance_id", "node_id", "state", "service_ports", "last_update_time"],
+        "properties": {
+            "instance_id": {"type": "string"},
+            "node_id": {"type": "string"},
+            "state": {"type": "string"},
+            "service_ports": {"type": "object", "additionalProperties": {"type": "integer", "minimum": 0}},
+            "last_update_time": {"type": "integer", "minimum": 0},
+            "start_time": {"type": "integer", "minimum": 0},
+            "pid": {"type": "integer", "minimum": 0},
+            "lease_expire_time": {"type": "integer", "minimum": 0}
+        }
+    })
+}
+
+impl SchemaRegistry {
+    pub fn new() -> Self {
+        SchemaRegistry { rules: Vec::new() }
+    }
+
+    pub fn with_builtin_schemas() -> Self {
+        let mut registry = Self::new();
+        registry.register("boot/config", json!({"type": "object", "required": ["id"]}));
+        registry.register("services/*/spec", service_spec_schema());
+        registry.register("services/*/settings", json!({"type": "object"}));
+        registry.register("services/*/instances/*", instance_report_schema());
+        registry.register("users/*/settings", user_settings_schema());
+        registry.register("users/*/apps/*/spec", app_spec_schema());
+        registry.register("users/*/agents/*/spec", app_spec_schema());
+        registry.register("nodes/*/config", node_config_schema());
+        registry
+    }
+
+    pub fn register(&mut self, pattern: &str, schema: Value) {
+        let pattern = pattern.trim_matches('/');
+        self.rules.push(SchemaRule {
+            pattern: pattern.to_string(),
+            segments: pattern.split('/').map(|item| item.to_string()).collect(),
+            schema,
+        });
+    }
+
+    pub fn find_schema(&self, key: &str) -> Option<(&str, &Value)> {
+        let key_segments: Vec<&str> = key.trim_matches('/').split('/').collect();
+        self.rules
+            .iter()
+            .find(|rule| {
+                rule.segments.len() == key_segments.len()
+                    && rule
+                        .segments
+                        .iter()
+                        .zip(key_segments.iter())
+                        .all(|(pattern, segment)| pattern == "*" || pattern == segment)
+            })
+            .map(|rule| (rule.pattern.as_str(), &rule.schema))
+    }
+
+    // Ok(None) if no schema is registered for key, Ok(Some(pattern)) if value matches
+    pub fn validate(&self, key: &str, value: &str) -> Result<Option<String>, Vec<String>> {
+        let Some((pattern, schema)) = self.find_schema(key) else {
+            return Ok(None);
+        };
+        let value: Value = serde_json::from_str(value)
+            .map_err(|err| vec![format!("{} must be json: {}", key, err)])?;
+        let mut errors = Vec::new();
+        validate_value(schema, &value, "", &mut errors);
+        if errors.is_empty() {
+            Ok(Some(pattern.to_string()))
+        } else {
+            Err(errors
+                .into_iter()
+                .map(|error| format!("{} ({}): {}", key, pattern, error))
+                .collect())
+        }
+    }
+}
+
+fn json_type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(number) if number.is_i64() || number.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn type_matches(expected: &str, value: &Value) -> bool {
+    let actual = json_type_name(value);
+    actual == expected || (expected == "number" && actual == "integer")
+}
+
+fn validate_value(schema: &Value, value: &Value, path: &str, errors: &mut Vec<String>) {
+    let display_path = if path.is_empty() { "/" } else { path };
+
+    if let Some(expected) = schema.get("type") {
+        let matched = match expected {
+            Value::String(expected) => type_matches(expected, value),
+            Value::Array(expected) => expected
+                .iter()
+                .filter_map(|item| item.as_str())
+                .any(|expected| type_matches(expected, value)),
+            _ => true,
+        };
+        if !matched {
+            errors.push(format!(
+                "{}: expected {}, got {}",
+                display_path,
+                expected,
+                json_type_name(value)
+            ));
+            return;
+        }
+    }
+
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(value) {
+            errors.push(format!(
+                "{}: {} is not one of {}",
+                display_path,
+                value,
+                Value::Array(allowed.clone())
+            ));
+        }
+    }
+
+    if let (Some(minimum), Some(number)) = (
+        schema.get("minimum").and_then(|item| item.as_f64()),
+        value.as_f64(),
+    ) {
+        if number < minimum {
+            errors.push(format!(
+                "{}: {} is less than {}",
+                display_path, number, minimum
+            ));
+        }
+    }
+
+    if let Value::Object(object) = value {
+        if let Some(Value::Array(required)) = schema.get("required") {
+            for field in required.iter().filter_map(|item| item.as_str()) {
+                if !object.contains_key(field) {
+                    errors.push(format!(
+                        "{}: missing required field {}",
+                        display_path, field
+                    ));
+                }
+            }
+        }
+
+        let properties = schema.get("properties").and_then(|item| item.as_object());
+        for (field, field_value) in object.iter() {
+            let field_path = format!("{}/{}", path, field);
+            if let Some(field_schema) = properties.and_then(|properties| properties.get(field)) {
+                validate_value(field_schema, field_value, &field_path, errors);
+                continue;
+            }
+            match schema.get("additionalProperties") {
+                Some(Value::Bool(false)) => {
+                    errors.push(format!("{}: unknown field", field_path));
+                }
+                Some(additional) if additional.is_object() => {
+                    validate_value(additional, field_value, &field_path, errors);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if let (Value::Array(items), Some(item_schema)) = (value, schema.get("items")) {
+        for (index, item) in items.iter().enumerate() {
+            validate_value(item_schema, item, &format!("{}/{}", path, index), errors);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_pattern_by_segment() {
+        let registry = SchemaRegistry::with_builtin_schemas();
+        assert_eq!(
+            registry
+                .find_schema("services/repo/spec")
+                .map(|item| item.0),
+            Some("services/*/spec")
+        );
+        assert_eq!(
+            registry
+                .find_schema("users/alice/apps/files/spec")
+                .map(|item| item.0),
+            Some("users/*/apps/*/spec")
+        );
+        assert!(registry.find_schema("services/repo/spec/extra").is_none());
+        assert!(registry.find_schema("system/rbac/policy").is_none());
+        assert_eq!(
+            registry.validate("system/rbac/policy", "not json"),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_node_config() {
+        let registry = SchemaRegistry::with_builtin_schemas();
+        let valid = json!({
+            "node_id": "ood1",
+            "node_did": "did:dev:ood1",
+            "kernel": {},
+            "apps": {},
+            "frame_services": {},
+            "state": "running"
+        });
+        assert_eq!(
+            registry.validate("nodes/ood1/config", &valid.to_string()),
+            Ok(Some("nodes/*/config".to_string()))
+        );
+
+        let invalid = json!({
+            "node_id": 1,
+            "kernel": [],
+            "apps": {"files": "bad"},
+            "frame_services": {},
+            "state": "flying"
+        });
+        let errors = registry
+            .validate("nodes/ood1/config", &invalid.to_string())
+            .unwrap_err();
+        assert!(errors.iter().any(|error| error.contains("/node_id")));
+        assert!(errors
+            .iter()
+            .any(|error| error.contains("missing required field node_did")));
+        assert!(errors.iter().any(|error| error.contains("/kernel")));
+        assert!(errors.iter().any(|error| error.contains("/apps/files")));
+        assert!(errors.iter().any(|error| error.contains("/state")));
+
+        assert!(registry.validate("nodes/ood1/config", "{bad json").is_err());
+    }
+
+    #[test]
+    fn checks_nested_install_config() {
+        let registry = SchemaRegistry::with_builtin_schemas();
+        let mut spec = json!({
+            "service_doc": {"name": "repo-service"},
+            "enable": true,
+            "app_index": 1,
+            "expected_instance_count": 1,
+            "state": "running",
+            "install_config": {
+                "data_mount_point": {},
+                "cache_mount_point": [],
+                "local_cache_mount_point": [],
+                "res_pool_id": "default"
+            }
+        });
+        assert!(registry
+            .validate("services/repo-service/spec", &spec.to_string())
+            .is_ok());
+
+        spec["expected_instance_count"] = json!(-1);
+        spec["install_config"]["cache_mount_point"] = json!(["/cache", 1]);
+        let errors = registry
+            .validate("services/repo-service/spec", &spec.to_string())
+            .unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("services/*/spec"));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::kv_provider::*;
+use crate::lease::is_lease_key;
 use async_trait::async_trait;
 use buckyos_kit::*;
 use log::*;
@@ -11,16 +12,31 @@ use std::{collections::HashMap, sync::Arc};
 
 type TxResult<T> = std::result::Result<T, ConflictableTransactionError<KVStoreErrors>>;
 
+// how many change log entries are kept for watchers before the oldest ones are compacted
+pub const DEFAULT_CHANGE_LOG_CAPACITY: u64 = 4096;
+// how many revisions of each key are kept for get_history / get_at_revision
+pub const DEFAULT_KEY_HISTORY_CAPACITY: u64 = 32;
+
 pub struct SledStore {
     db: Arc<Db>,
+    change_log_capacity: u64,
+    key_history_capacity: u64,
 }
 
 impl SledStore {
     const INTERNAL_META_PREFIX: &'static str = "__meta/";
     const REVISION_PREFIX: &'static str = "__meta/revision/";
-
-    fn from_db(db: Db) -> Self {
-        SledStore { db: Arc::new(db) }
+    const CHANGE_LOG_PREFIX: &'static str = "__meta/changelog/";
+    const CHANGE_LOG_HEAD_KEY: &'static str = "__meta/changelog_head";
+    const CHANGE_LOG_FIRST_KEY: &'static str = "__meta/changelog_first";
+    const HISTORY_PREFIX: &'static str = "__meta/history/";
+
+    pub(crate) fn from_db(db: Db) -> Self {
+        SledStore {
+            db: Arc::new(db),
+            change_log_capacity: DEFAULT_CHANGE_LOG_CAPACITY,
+            key_history_capacity: DEFAULT_KEY_HISTORY_CAPACITY,
+        }
     }
 
     pub fn new() -> std::result::Result<Self, Box<dyn std::error::Error>> {
@@ -30,6 +46,11 @@ impl SledStore {
         Ok(Self::from_db(db))
     }
 
+    pub fn set_retention(&mut self, change_log_capacity: u64, key_history_capacity: u64) {
+        self.change_log_capacity = change_log_capacity.max(1);
+        self.key_history_capacity = key_history_capacity.max(1);
+    }
+
     fn revision_key(key: &str) -> String {
         format!("{}{}", Self::REVISION_PREFIX, key)
     }
@@ -38,20 +59,40 @@ impl SledStore {
         key.starts_with(Self::INTERNAL_META_PREFIX)
     }
 
-    fn current_revision(db: &TransactionalTree, key: &str) -> TxResult<u64> {
-        let revision_key = Self::revision_key(key);
-        let raw_revision = db.get(revision_key.as_bytes())?;
-        let Some(raw_revision) = raw_revision else {
-            return Ok(0);
-        };
+    fn change_log_key(revision: u64) -> String {
+        format!("{}{:020}", Self::CHANGE_LOG_PREFIX, revision)
+    }
 
-        let revision = String::from_utf8(raw_revision.to_vec()).map_err(|err| {
-            ConflictableTransactionError::Abort(KVStoreErrors::InternalError(err.to_string()))
-        })?;
+    // keys may contain ':' (DIDs) and '/', so the key is terminated with '\0',
+    // otherwise the history of "a" would also match keys like "a:b" or "a/b"
+    fn history_prefix(key: &str) -> String {
+        format!("{}{}\u{0}", Self::HISTORY_PREFIX, key)
+    }
 
-        revision.parse::<u64>().map_err(|err| {
-            ConflictableTransactionError::Abort(KVStoreErrors::InternalError(err.to_string()))
-        })
+    fn history_key(key: &str, key_revision: u64) -> String {
+        format!("{}{:020}", Self::history_prefix(key), key_revision)
+    }
+
+    fn parse_u64(raw: &[u8]) -> Result<u64> {
+        let value = std::str::from_utf8(raw)
+            .map_err(|err| KVStoreErrors::InternalError(err.to_string()))?;
+        value
+            .parse::<u64>()
+            .map_err(|err| KVStoreErrors::InternalError(err.to_string()))
+    }
+
+    fn read_u64(db: &TransactionalTree, key: &str) -> TxResult<Option<u64>> {
+        let Some(raw_value) = db.get(key.as_bytes())? else {
+            return Ok(None);
+        };
+        Self::parse_u64(&raw_value)
+            .map(Some)
+            .map_err(ConflictableTransactionError::Abort)
+    }
+
+    fn current_revision(db: &TransactionalTree, key: &str) -> TxResult<u64> {
+        let revision_key = Self::revision_key(key);
+        Ok(Self::read_u64(db, &revision_key)?.unwrap_or(0))
     }
 
     fn next_revision(db: &TransactionalTree, key: &str) -> TxResult<u64> {
@@ -69,57 +110,113 @@ impl SledStore {
         db.insert(revision_key.as_bytes(), revision.to_string().as_bytes())?;
         Ok(())
     }
-}
 
-#[async_trait]
-impl KVStoreProvider for SledStore {
-    async fn get(&self, key: String) -> Result<Option<String>> {
-        Ok(self
-            .get_with_revision(key)
-            .await?
-            .map(|(value, _revision)| value))
-    }
+    // append one entry to the change log in the same transaction as the write,
+    // and drop the oldest entries once the log grows over capacity.
+    // `timestamp` comes from the caller: replicated stores pass the leader's time so
+    // every replica records the same change log.
+    // lease records only keep their per-key revision (for keep-alive CAS).
+    fn append_change(
+        &self,
+        db: &TransactionalTree,
+        key: &str,
+        action: KVChangeAction,
+        value: Option<&str>,
+        key_revision: u64,
+        timestamp: u64,
+    ) -> TxResult<()> {
+        if is_lease_key(key) {
+            return Ok(());
+        }
+        let head = Self::read_u64(db, Self::CHANGE_LOG_HEAD_KEY)?.unwrap_or(0);
+        let revision = head.checked_add(1).ok_or_else(|| {
+            ConflictableTransactionError::Abort(KVStoreErrors::InternalError(
+                "store revision overflow".to_string(),
+            ))
+        })?;
 
-    async fn get_with_revision(&self, key: String) -> Result<Option<(String, u64)>> {
-        let tx_result = self.db.transaction(|db| {
-            let raw_value = match db.get(key.as_bytes())? {
-                Some(value) => value,
-                None => return Ok(None),
-            };
+        let event = KVChangeEvent {
+            revision,
+            key: key.to_string(),
+            action,
+            value: value.map(|value| value.to_string()),
+            key_revision,
+            timestamp,
+        };
+        let raw_event = serde_json::to_vec(&event).map_err(|err| {
+            ConflictableTransactionError::Abort(KVStoreErrors::InternalError(err.to_string()))
+        })?;
+        db.insert(Self::change_log_key(revision).as_bytes(), raw_event.clone())?;
 
-            let value = String::from_utf8(raw_value.to_vec()).map_err(|_err| {
-                ConflictableTransactionError::Abort(KVStoreErrors::InternalError(
-                    "Invalid UTF-8 sequence".to_string(),
-                ))
-            })?;
-            let revision = Self::current_revision(db, &key)?;
+        // per-key revisions grow by one on every write, so only one old entry falls out each time
+        db.insert(Self::history_key(key, key_revision).as_bytes(), raw_event)?;
+        if key_revision > self.key_history_capacity {
+            let expired_revision = key_revision - self.key_history_capacity;
+            db.remove(Self::history_key(key, expired_revision).as_bytes())?;
+        }
+        db.insert(
+            Self::CHANGE_LOG_HEAD_KEY.as_bytes(),
+            revision.to_string().as_bytes(),
+        )?;
+
+        let mut first = Self::read_u64(db, Self::CHANGE_LOG_FIRST_KEY)?.unwrap_or(1);
+        while revision + 1 - first > self.change_log_capacity {
+            db.remove(Self::change_log_key(first).as_bytes())?;
+            first += 1;
+        }
+        db.insert(
+            Self::CHANGE_LOG_FIRST_KEY.as_bytes(),
+            first.to_string().as_bytes(),
+        )?;
+        Ok(())
+    }
 
-            Ok(Some((value, revision)))
-        });
+    fn read_meta_u64(&self, key: &str) -> Result<Option<u64>> {
+        let raw_value = self
+            .db
+            .get(key.as_bytes())
+            .map_err(|err| KVStoreErrors::InternalError(err.to_string()))?;
+        raw_value.map(|raw| Self::parse_u64(&raw)).transpose()
+    }
 
-        match tx_result {
-            Ok(Some((value, revision))) => {
-                debug!(
-                    "Sled Get key:[{}] value length:[{}] revision:[{}]",
-                    key,
-                    value.len(),
-                    revision
-                );
-                Ok(Some((value, revision)))
-            }
-            Ok(None) => Ok(None),
-            Err(TransactionError::Abort(err)) => Err(err),
-            Err(TransactionError::Storage(err)) => {
-                Err(KVStoreErrors::InternalError(err.to_string()))
-            }
+    // dump every entry (user keys and internal meta), used to build replication snapshots
+    pub(crate) fn export_entries(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.db
+            .iter()
+            .map(|item| {
+                item.map(|(key, value)| (key.to_vec(), value.to_vec()))
+                    .map_err(|err| KVStoreErrors::InternalError(err.to_string()))
+            })
+            .collect()
+    }
+
+    // replace the whole store content with entries from export_entries
+    pub(crate) fn import_entries(&self, entries: Vec<(Vec<u8>, Vec<u8>)>) -> Result<()> {
+        self.db
+            .clear()
+            .map_err(|err| KVStoreErrors::InternalError(err.to_string()))?;
+        let mut batch = sled::Batch::default();
+        for (key, value) in entries {
+            batch.insert(key, value);
         }
+        self.db
+            .apply_batch(batch)
+            .map_err(|err| KVStoreErrors::InternalError(err.to_string()))?;
+        Ok(())
     }
 
-    async fn set(&self, key: String, value: String) -> Result<()> {
+    // the write operations take the change log timestamp explicitly, see append_change
+    pub(crate) fn set_at(&self, key: String, value: String, timestamp: u64) -> Result<()> {
         let tx_result = self.db.transaction(|db| {
+            let action = if db.get(key.as_bytes())?.is_some() {
+                KVChangeAction::Set
+            } else {
+                KVChangeAction::Create
+            };
             let next_revision = Self::next_revision(db, &key)?;
             db.insert(key.as_bytes(), value.as_bytes())?;
             Self::write_revision(db, &key, next_revision)?;
+            self.append_change(db, &key, action, Some(&value), next_revision, timestamp)?;
             Ok(())
         });
 
@@ -138,7 +235,13 @@ impl KVStoreProvider for SledStore {
         Ok(())
     }
 
-    async fn set_by_path(&self, key: String, json_path: String, value: &Value) -> Result<()> {
+    pub(crate) fn set_by_path_at(
+        &self,
+        key: String,
+        json_path: String,
+        value: &Value,
+        timestamp: u64,
+    ) -> Result<()> {
         let tx_result = self.db.transaction(|db| {
             // Get the current value
             let current_value = match db.get(key.as_bytes())? {
@@ -161,14 +264,22 @@ impl KVStoreProvider for SledStore {
             // Update the value using json_path
             set_json_by_path(&mut current_value, &json_path, Some(value));
 
-            // Convert back to bytes
-            let updated_value = serde_json::to_vec(&current_value).map_err(|err| {
+            // Convert back to string
+            let updated_value = serde_json::to_string(&current_value).map_err(|err| {
                 ConflictableTransactionError::Abort(KVStoreErrors::InternalError(err.to_string()))
             })?;
 
             let next_revision = Self::next_revision(db, &key)?;
-            db.insert(key.as_bytes(), updated_value)?;
+            db.insert(key.as_bytes(), updated_value.as_bytes())?;
             Self::write_revision(db, &key, next_revision)?;
+            self.append_change(
+                db,
+                &key,
+                KVChangeAction::Set,
+                Some(&updated_value),
+                next_revision,
+                timestamp,
+            )?;
             Ok(())
         });
 
@@ -186,7 +297,7 @@ impl KVStoreProvider for SledStore {
         }
     }
 
-    async fn create(&self, key: &str, value: &str) -> Result<()> {
+    pub(crate) fn create_at(&self, key: &str, value: &str, timestamp: u64) -> Result<()> {
         let tx_result = self.db.transaction(|db| {
             if db.get(key.as_bytes())?.is_some() {
                 return Err(ConflictableTransactionError::Abort(
@@ -197,6 +308,14 @@ impl KVStoreProvider for SledStore {
             let next_revision = Self::next_revision(db, key)?;
             db.insert(key.as_bytes(), value.as_bytes())?;
             Self::write_revision(db, key, next_revision)?;
+            self.append_change(
+                db,
+                key,
+                KVChangeAction::Create,
+                Some(value),
+                next_revision,
+                timestamp,
+            )?;
             Ok(())
         });
 
@@ -222,7 +341,7 @@ impl KVStoreProvider for SledStore {
         }
     }
 
-    async fn delete(&self, key: &str) -> Result<()> {
+    pub(crate) fn delete_at(&self, key: &str, timestamp: u64) -> Result<()> {
         let tx_result = self.db.transaction(|db| {
             if db.get(key.as_bytes())?.is_none() {
                 return Err(ConflictableTransactionError::Abort(
@@ -233,6 +352,14 @@ impl KVStoreProvider for SledStore {
             let next_revision = Self::next_revision(db, key)?;
             db.remove(key.as_bytes())?;
             Self::write_revision(db, key, next_revision)?;
+            self.append_change(
+                db,
+                key,
+                KVChangeAction::Delete,
+                None,
+                next_revision,
+                timestamp,
+            )?;
             Ok(())
         });
 
@@ -251,79 +378,16 @@ impl KVStoreProvider for SledStore {
         Ok(())
     }
 
-    async fn list_data(&self, key_perfix: &str) -> Result<HashMap<String, String>> {
-        let mut result = HashMap::new();
-        let iter = self.db.scan_prefix(key_perfix.to_string());
-        for item in iter {
-            if item.is_ok() {
-                let (key, value) = item.unwrap();
-                let key_str = String::from_utf8(key.to_vec())
-                    .map_err(|err| KVStoreErrors::InternalError(err.to_string()))?;
-                if Self::is_internal_key(&key_str) {
-                    continue;
-                }
-                let value_str = String::from_utf8(value.to_vec())
-                    .map_err(|err| KVStoreErrors::InternalError(err.to_string()))?;
-                result.insert(key_str, value_str);
-            }
-        }
-        Ok(result)
-    }
-
-    async fn list_keys(&self, key_prefix: &str) -> Result<Vec<String>> {
-        let mut result = Vec::new();
-        let iter = self.db.scan_prefix(key_prefix.to_string()).keys();
-        for key in iter {
-            if let Ok(key) = key {
-                if let Ok(key_str) = String::from_utf8(key.to_vec()) {
-                    if Self::is_internal_key(&key_str) {
-                        continue;
-                    }
-                    result.push(key_str);
-                }
-            }
-        }
-        Ok(result)
-    }
-
-    async fn list_direct_children(&self, prefix: String) -> Result<Vec<String>> {
-        let mut result = Vec::new();
-        let prefix = if prefix.eq("") || prefix.ends_with("/") {
-            prefix
-        } else {
-            format!("{}/", prefix)
-        };
-        let iter = self.db.scan_prefix(prefix.clone()).keys();
-        for key in iter {
-            if let Ok(key) = key {
-                if let Ok(key_str) = String::from_utf8(key.to_vec()) {
-                    if Self::is_internal_key(&key_str) {
-                        continue;
-                    }
-                    let suffix = key_str.trim_start_matches(prefix.as_str());
-                    let splite_result: Vec<_> = if suffix.ends_with("/") {
-                        suffix[1..].split("/").collect()
-                    } else {
-                        suffix.split("/").collect()
-                    };
-                    let child = splite_result[0];
-                    if !result.contains(&child.to_string()) {
-                        result.push(child.to_string());
-                    }
-                }
-            }
-        }
-        Ok(result)
-    }
-
-    async fn exec_tx(
+    pub(crate) fn exec_tx_at(
         &self,
         tx: HashMap<String, KVAction>,
         main_key: Option<(String, u64)>,
+        timestamp: u64,
     ) -> Result<()> {
         let tx_result = self.db.transaction(|db| {
             let mut batch = sled::Batch::default();
             let mut revision_updates = HashMap::new();
+            let mut changes: Vec<(String, KVChangeAction, Option<String>)> = Vec::new();
 
             if let Some((key, expected_revision)) = main_key.as_ref() {
                 let actual_revision = Self::current_revision(db, key)?;
@@ -355,9 +419,16 @@ impl KVStoreProvider for SledStore {
                             ));
                         }
                         batch.insert(key.as_bytes(), value.as_bytes());
+                        changes.push((key.clone(), KVChangeAction::Create, Some(value.clone())));
                     }
                     KVAction::Update(value) => {
+                        let action = if db.get(key.as_bytes())?.is_some() {
+                            KVChangeAction::Set
+                        } else {
+                            KVChangeAction::Create
+                        };
                         batch.insert(key.as_bytes(), value.as_bytes());
+                        changes.push((key.clone(), action, Some(value.clone())));
                     }
                     KVAction::Append(value) => {
                         let existing_value = match db.get(key.as_bytes())? {
@@ -378,6 +449,7 @@ impl KVStoreProvider for SledStore {
 
                         let updated_value = format!("{}{}", existing_value, value);
                         batch.insert(key.as_bytes(), updated_value.as_bytes());
+                        changes.push((key.clone(), KVChangeAction::Set, Some(updated_value)));
                     }
                     KVAction::SetByJsonPath(value) => {
                         let existing_value = match db.get(key.as_bytes())? {
@@ -408,15 +480,20 @@ impl KVStoreProvider for SledStore {
                             }
                         }
 
-                        let updated_value = serde_json::to_vec(&existing_value).map_err(|err| {
-                            ConflictableTransactionError::Abort(KVStoreErrors::InternalError(
-                                err.to_string(),
-                            ))
-                        })?;
+                        let updated_value =
+                            serde_json::to_string(&existing_value).map_err(|err| {
+                                ConflictableTransactionError::Abort(KVStoreErrors::InternalError(
+                                    err.to_string(),
+                                ))
+                            })?;
 
-                        batch.insert(key.as_bytes(), updated_value);
+                        batch.insert(key.as_bytes(), updated_value.as_bytes());
+                        changes.push((key.clone(), KVChangeAction::Set, Some(updated_value)));
                     }
                     KVAction::Remove => {
+                        if db.get(key.as_bytes())?.is_some() {
+                            changes.push((key.clone(), KVChangeAction::Delete, None));
+                        }
                         batch.remove(key.as_bytes());
                     }
                 }
@@ -427,12 +504,18 @@ impl KVStoreProvider for SledStore {
                 }
             }
 
-            for (key, revision) in revision_updates.into_iter() {
-                let revision_key = Self::revision_key(&key);
+            for (key, revision) in revision_updates.iter() {
+                let revision_key = Self::revision_key(key);
                 batch.insert(revision_key.as_bytes(), revision.to_string().as_bytes());
             }
 
             db.apply_batch(&batch)?;
+
+            changes.sort_by(|left, right| left.0.cmp(&right.0));
+            for (key, action, value) in changes.iter() {
+                let key_revision = revision_updates.get(key).copied().unwrap_or(0);
+                self.append_change(db, key, *action, value.as_deref(), key_revision, timestamp)?;
+            }
             Ok(())
         });
 
@@ -451,6 +534,217 @@ impl KVStoreProvider for SledStore {
     }
 }
 
+#[async_trait]
+impl KVStoreProvider for SledStore {
+    async fn get(&self, key: String) -> Result<Option<String>> {
+        Ok(self
+            .get_with_revision(key)
+            .await?
+            .map(|(value, _revision)| value))
+    }
+
+    async fn get_with_revision(&self, key: String) -> Result<Option<(String, u64)>> {
+        let tx_result = self.db.transaction(|db| {
+            let raw_value = match db.get(key.as_bytes())? {
+                Some(value) => value,
+                None => return Ok(None),
+            };
+
+            let value = String::from_utf8(raw_value.to_vec()).map_err(|_err| {
+                ConflictableTransactionError::Abort(KVStoreErrors::InternalError(
+                    "Invalid UTF-8 sequence".to_string(),
+                ))
+            })?;
+            let revision = Self::current_revision(db, &key)?;
+
+            Ok(Some((value, revision)))
+        });
+
+        match tx_result {
+            Ok(Some((value, revision))) => {
+                debug!(
+                    "Sled Get key:[{}] value length:[{}] revision:[{}]",
+                    key,
+                    value.len(),
+                    revision
+                );
+                Ok(Some((value, revision)))
+            }
+            Ok(None) => Ok(None),
+            Err(TransactionError::Abort(err)) => Err(err),
+            Err(TransactionError::Storage(err)) => {
+                Err(KVStoreErrors::InternalError(err.to_string()))
+            }
+        }
+    }
+
+    async fn set(&self, key: String, value: String) -> Result<()> {
+        self.set_at(key, value, buckyos_get_unix_timestamp())
+    }
+
+    async fn set_by_path(&self, key: String, json_path: String, value: &Value) -> Result<()> {
+        self.set_by_path_at(key, json_path, value, buckyos_get_unix_timestamp())
+    }
+
+    async fn create(&self, key: &str, value: &str) -> Result<()> {
+        self.create_at(key, value, buckyos_get_unix_timestamp())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.delete_at(key, buckyos_get_unix_timestamp())
+    }
+
+    async fn list_data(&self, key_perfix: &str) -> Result<HashMap<String, String>> {
+        let mut result = HashMap::new();
+        let iter = self.db.scan_prefix(key_perfix.to_string());
+        for item in iter {
+            if item.is_ok() {
+                let (key, value) = item.unwrap();
+                let key_str = String::from_utf8(key.to_vec())
+                    .map_err(|err| KVStoreErrors::InternalError(err.to_string()))?;
+                if Self::is_internal_key(&key_str) {
+                    continue;
+                }
+                let value_str = String::from_utf8(value.to_vec())
+                    .map_err(|err| KVStoreErrors::InternalError(err.to_string()))?;
+                result.insert(key_str, value_str);
+            }
+        }
+        Ok(result)
+    }
+
+    async fn list_keys(&self, key_prefix: &str) -> Result<Vec<String>> {
+        let mut result = Vec::new();
+        let iter = self.db.scan_prefix(key_prefix.to_string()).keys();
+        for key in iter {
+            if let Ok(key) = key {
+                if let Ok(key_str) = String::from_utf8(key.to_vec()) {
+                    if Self::is_internal_key(&key_str) {
+                        continue;
+                    }
+                    result.push(key_str);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    async fn list_direct_children(&self, prefix: String) -> Result<Vec<String>> {
+        let mut result = Vec::new();
+        let prefix = if prefix.eq("") || prefix.ends_with("/") {
+            prefix
+        } else {
+            format!("{}/", prefix)
+        };
+        let iter = self.db.scan_prefix(prefix.clone()).keys();
+        for key in iter {
+            if let Ok(key) = key {
+                if let Ok(key_str) = String::from_utf8(key.to_vec()) {
+                    if Self::is_internal_key(&key_str) {
+                        continue;
+                    }
+                    let suffix = key_str.trim_start_matches(prefix.as_str());
+                    let splite_result: Vec<_> = if suffix.ends_with("/") {
+                        suffix[1..].split("/").collect()
+                    } else {
+                        suffix.split("/").collect()
+                    };
+                    let child = splite_result[0];
+                    if !result.contains(&child.to_string()) {
+                        result.push(child.to_string());
+                    }
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    async fn get_store_revision(&self) -> Result<u64> {
+        Ok(self.read_meta_u64(Self::CHANGE_LOG_HEAD_KEY)?.unwrap_or(0))
+    }
+
+    async fn list_changes(
+        &self,
+        key_prefix: &str,
+        from_revision: u64,
+        limit: usize,
+    ) -> Result<(Vec<KVChangeEvent>, u64)> {
+        let head = self.get_store_revision().await?;
+        let first = self.read_meta_u64(Self::CHANGE_LOG_FIRST_KEY)?.unwrap_or(1);
+        let from_revision = from_revision.max(1);
+        if from_revision < first {
+            return Err(KVStoreErrors::Compacted {
+                requested: from_revision,
+                oldest: first,
+            });
+        }
+        if from_revision > head {
+            return Ok((Vec::new(), from_revision));
+        }
+
+        let start_key = Self::change_log_key(from_revision);
+        let end_key = Self::change_log_key(head);
+        let mut events = Vec::new();
+        let mut next_revision = head + 1;
+        for item in self.db.range(start_key.as_bytes()..=end_key.as_bytes()) {
+            let (_key, raw_event) =
+                item.map_err(|err| KVStoreErrors::InternalError(err.to_string()))?;
+            let event: KVChangeEvent = serde_json::from_slice(&raw_event)
+                .map_err(|err| KVStoreErrors::InternalError(err.to_string()))?;
+            if event.key.starts_with(key_prefix) {
+                let revision = event.revision;
+                events.push(event);
+                if events.len() >= limit {
+                    next_revision = revision + 1;
+                    break;
+                }
+            }
+        }
+        Ok((events, next_revision))
+    }
+
+    async fn get_history(&self, key: &str, limit: usize) -> Result<Vec<KVChangeEvent>> {
+        let mut result = Vec::new();
+        let iter = self
+            .db
+            .scan_prefix(Self::history_prefix(key))
+            .values()
+            .rev();
+        for raw_event in iter.take(limit) {
+            let raw_event =
+                raw_event.map_err(|err| KVStoreErrors::InternalError(err.to_string()))?;
+            let event: KVChangeEvent = serde_json::from_slice(&raw_event)
+                .map_err(|err| KVStoreErrors::InternalError(err.to_string()))?;
+            if event.key != key {
+                continue;
+            }
+            result.push(event);
+        }
+        Ok(result)
+    }
+
+    async fn get_at_revision(&self, key: &str, key_revision: u64) -> Result<Option<KVChangeEvent>> {
+        let raw_event = self
+            .db
+            .get(Self::history_key(key, key_revision).as_bytes())
+            .map_err(|err| KVStoreErrors::InternalError(err.to_string()))?;
+        let Some(raw_event) = raw_event else {
+            return Ok(None);
+        };
+        let event = serde_json::from_slice(&raw_event)
+            .map_err(|err| KVStoreErrors::InternalError(err.to_string()))?;
+        Ok(Some(event))
+    }
+
+    async fn exec_tx(
+        &self,
+        tx: HashMap<String, KVAction>,
+        main_key: Option<(String, u64)>,
+    ) -> Result<()> {
+        self.exec_tx_at(tx, main_key, buckyos_get_unix_timestamp())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -620,4 +914,176 @@ mod tests {
         assert!(user_data.contains_key("users/alice/profile"));
         assert!(!user_data.contains_key("__meta/revision/users/alice/profile"));
     }
+
+    #[tokio::test]
+    async fn change_log_records_writes_in_revision_order() {
+        let store = setup_store();
+
+        store
+            .create("users/alice/profile", "v1")
+            .await
+            .expect("create profile");
+        store
+            .set("users/alice/profile".to_string(), "v2".to_string())
+            .await
+            .expect("set profile");
+        store
+            .set("services/demo/spec".to_string(), "spec".to_string())
+            .await
+            .expect("set spec");
+
+        let mut tx = HashMap::new();
+        tx.insert("users/alice/profile".to_string(), KVAction::Remove);
+        tx.insert(
+            "users/alice/settings".to_string(),
+            KVAction::Create("s1".to_string()),
+        );
+        store.exec_tx(tx, None).await.expect("exec tx");
+
+        assert_eq!(store.get_store_revision().await.expect("head"), 5);
+
+        let (events, next_revision) = store
+            .list_changes("users/", 0, 100)
+            .await
+            .expect("list changes");
+        assert_eq!(next_revision, 6);
+        let summary: Vec<_> = events
+            .iter()
+            .map(|event| (event.revision, event.key.as_str(), event.action))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (1, "users/alice/profile", KVChangeAction::Create),
+                (2, "users/alice/profile", KVChangeAction::Set),
+                (4, "users/alice/profile", KVChangeAction::Delete),
+                (5, "users/alice/settings", KVChangeAction::Create),
+            ]
+        );
+        assert_eq!(events[1].value.as_deref(), Some("v2"));
+        assert_eq!(events[1].key_revision, 2);
+        assert_eq!(events[2].value, None);
+
+        let (events, next_revision) = store
+            .list_changes("users/", 3, 1)
+            .await
+            .expect("list limited changes");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].revision, 4);
+        assert_eq!(next_revision, 5);
+
+        let (events, next_revision) = store
+            .list_changes("users/", 6, 100)
+            .await
+            .expect("list future changes");
+        assert!(events.is_empty());
+        assert_eq!(next_revision, 6);
+
+        let root_children = store
+            .list_direct_children("".to_string())
+            .await
+            .expect("list root");
+        assert!(!root_children.contains(&"__meta".to_string()));
+    }
+
+    #[tokio::test]
+    async fn change_log_reports_compacted_revisions() {
+        let mut store = setup_store();
+        store.change_log_capacity = 3;
+
+        for index in 0..5 {
+            store
+                .set("users/alice/counter".to_string(), index.to_string())
+                .await
+                .expect("set counter");
+        }
+
+        let err = store
+            .list_changes("users/", 2, 100)
+            .await
+            .expect_err("revision 2 should be compacted");
+        match err {
+            KVStoreErrors::Compacted { requested, oldest } => {
+                assert_eq!(requested, 2);
+                assert_eq!(oldest, 3);
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+
+        let (events, next_revision) = store
+            .list_changes("users/", 3, 100)
+            .await
+            .expect("list retained changes");
+        assert_eq!(
+            events
+                .iter()
+                .map(|event| event.revision)
+                .collect::<Vec<_>>(),
+            vec![3, 4, 5]
+        );
+        assert_eq!(next_revision, 6);
+    }
+
+    #[tokio::test]
+    async fn keeps_bounded_history_per_key() {
+        let mut store = setup_store();
+        store.key_history_capacity = 3;
+
+        store
+            .create("boot/config", "v1")
+            .await
+            .expect("create config");
+        for value in ["v2", "v3", "v4"] {
+            store
+                .set("boot/config".to_string(), value.to_string())
+                .await
+                .expect("set config");
+        }
+        store
+            .create("boot/config_backup", "other")
+            .await
+            .expect("create sibling key");
+        store.delete("boot/config").await.expect("delete config");
+
+        let history = store
+            .get_history("boot/config", 10)
+            .await
+            .expect("get history");
+        let summary: Vec<_> = history
+            .iter()
+            .map(|event| (event.key_revision, event.action, event.value.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (5, KVChangeAction::Delete, None),
+                (4, KVChangeAction::Set, Some("v4")),
+                (3, KVChangeAction::Set, Some("v3")),
+            ]
+        );
+
+        let limited = store
+            .get_history("boot/config", 1)
+            .await
+            .expect("get limited history");
+        assert_eq!(limited.len(), 1);
+        assert_eq!(limited[0].key_revision, 5);
+
+        let at_three = store
+            .get_at_revision("boot/config", 3)
+            .await
+            .expect("get at revision 3")
+            .expect("revision 3 retained");
+        assert_eq!(at_three.value.as_deref(), Some("v3"));
+        assert_eq!(
+            store
+                .get_at_revision("boot/config", 1)
+                .await
+                .expect("get at revision 1"),
+            None
+        );
+
+        let user_keys = store.list_keys("boot/").await.expect("list boot keys");
+        assert_eq!(user_keys, vec!["boot/config_backup".to_string()]);
+    }
 }
@@ -0,0 +1,584 @@
+use crate::kv_provider::*;
+use crate::lease::is_lease_key;
+use async_trait::async_trait;
+use buckyos_kit::*;
+use log::*;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde_json::Value;
+use std::{collections::HashMap, path::Path, sync::Mutex};
+
+use crate::sled_provider::{DEFAULT_CHANGE_LOG_CAPACITY, DEFAULT_KEY_HISTORY_CAPACITY};
+
+fn to_internal_error(err: impl std::fmt::Display) -> KVStoreErrors {
+    KVStoreErrors::InternalError(err.to_string())
+}
+
+// SQLite backend, same semantics as SledStore:
+// - every write bumps the per-key revision (kept after delete, so a re-created key continues)
+// - every value change is appended to the store-wide change log and the per-key history,
+//   except lease records (see lease::is_lease_key)
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+    change_log_capacity: u64,
+    key_history_capacity: u64,
+}
+
+impl SqliteStore {
+    const META_CHANGE_LOG_HEAD: &'static str = "changelog_head";
+    const META_CHANGE_LOG_FIRST: &'static str = "changelog_first";
+
+    fn from_conn(conn: Connection) -> Result<Self> {
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            PRAGMA synchronous = FULL;
+            CREATE TABLE IF NOT EXISTS kv (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS kv_revision (
+                key TEXT PRIMARY KEY,
+                revision INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS kv_meta (
+                name TEXT PRIMARY KEY,
+                value INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS change_log (
+                revision INTEGER PRIMARY KEY,
+                key TEXT NOT NULL,
+                event TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS key_history (
+                key TEXT NOT NULL,
+                key_revision INTEGER NOT NULL,
+                event TEXT NOT NULL,
+                PRIMARY KEY (key, key_revision)
+            );",
+        )
+        .map_err(to_internal_error)?;
+
+        Ok(SqliteStore {
+            conn: Mutex::new(conn),
+            change_log_capacity: DEFAULT_CHANGE_LOG_CAPACITY,
+            key_history_capacity: DEFAULT_KEY_HISTORY_CAPACITY,
+        })
+    }
+
+    pub fn new() -> std::result::Result<Self, Box<dyn std::error::Error>> {
+        let data_dir = get_buckyos_service_local_data_dir("system_config_sqlite");
+        std::fs::create_dir_all(&data_dir)?;
+        let store = Self::open(&data_dir.join("system_config.db"))?;
+        Ok(store)
+    }
+
+    pub fn open(db_path: &Path) -> Result<Self> {
+        let conn = Connection::open(db_path).map_err(to_internal_error)?;
+        Self::from_conn(conn)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        let conn = Connection::open_in_memory().map_err(to_internal_error)?;
+        Self::from_conn(conn)
+    }
+
+    pub fn set_retention(&mut self, change_log_capacity: u64, key_history_capacity: u64) {
+        self.change_log_capacity = change_log_capacity.max(1);
+        self.key_history_capacity = key_history_capacity.max(1);
+    }
+
+    fn lock_conn(&self) -> Result<std::sync::MutexGuard<'_, Connection>> {
+        self.conn
+            .lock()
+            .map_err(|_| KVStoreErrors::InternalError("sqlite connection poisoned".to_string()))
+    }
+
+    fn read_value(conn: &Connection, key: &str) -> Result<Option<String>> {
+        conn.query_row("SELECT value FROM kv WHERE key = ?1", params![key], |row| {
+            row.get::<_, String>(0)
+        })
+        .optional()
+        .map_err(to_internal_error)
+    }
+
+    fn read_revision(conn: &Connection, key: &str) -> Result<u64> {
+        let revision = conn
+            .query_row(
+                "SELECT revision FROM kv_revision WHERE key = ?1",
+                params![key],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()
+            .map_err(to_internal_error)?;
+        Ok(revision.unwrap_or(0) as u64)
+    }
+
+    fn read_meta(conn: &Connection, name: &str) -> Result<Option<u64>> {
+        let value = conn
+            .query_row(
+                "SELECT value FROM kv_meta WHERE name = ?1",
+                params![name],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()
+            .map_err(to_internal_error)?;
+        Ok(value.map(|value| value as u64))
+    }
+
+    fn write_meta(tx: &Transaction, name: &str, value: u64) -> Result<()> {
+        tx.execute(
+            "INSERT INTO kv_meta (name, value) VALUES (?1, ?2)
+            ON CONFLICT(name) DO UPDATE SET value = excluded.value",
+            params![name, value as i64],
+        )
+        .map_err(to_internal_error)?;
+        Ok(())
+    }
+
+    fn next_revision(tx: &Transaction, key: &str) -> Result<u64> {
+        let current_revision = Self::read_revision(tx, key)?;
+        current_revision.checked_add(1).ok_or_else(|| {
+            KVStoreErrors::InternalError(format!("revision overflow for key: {}", key))
+        })
+    }
+
+    fn write_value(tx: &Transaction, key: &str, value: &str) -> Result<()> {
+        tx.execute(
+            "INSERT INTO kv (key, value) VALUES (?1, ?2)
+            ON CONFLICT(key) DO UPDATE SET value = excluded.value",
+            params![key, value],
+        )
+        .map_err(to_internal_error)?;
+        Ok(())
+    }
+
+    fn write_revision(tx: &Transaction, key: &str, revision: u64) -> Result<()> {
+        tx.execute(
+            "INSERT INTO kv_revision (key, revision) VALUES (?1, ?2)
+            ON CONFLICT(key) DO UPDATE SET revision = excluded.revision",
+            params![key, revision as i64],
+        )
+        .map_err(to_internal_error)?;
+        Ok(())
+    }
+
+    fn remove_value(tx: &Transaction, key: &str) -> Result<()> {
+        tx.execute("DELETE FROM kv WHERE key = ?1", params![key])
+            .map_err(to_internal_error)?;
+        Ok(())
+    }
+
+    fn append_change(
+        &self,
+        tx: &Transaction,
+        key: &str,
+        action: KVChangeAction,
+        value: Option<&str>,
+        key_revision: u64,
+    ) -> Result<()> {
+        if is_lease_key(key) {
+            return Ok(());
+        }
+        let head = Self::read_meta(tx, Self::META_CHANGE_LOG_HEAD)?.unwrap_or(0);
+        let revision = head
+            .checked_add(1)
+            .ok_or_else(|| KVStoreErrors::InternalError("store revision overflow".to_string()))?;
+
+        let event = KVChangeEvent {
+            revision,
+            key: key.to_string(),
+            action,
+            value: value.map(|value| value.to_string()),
+            key_revision,
+            timestamp: buckyos_get_unix_timestamp(),
+        };
+        let raw_event = serde_json::to_string(&event).map_err(to_internal_error)?;
+
+        tx.execute(
+            "INSERT INTO change_log (revision, key, event) VALUES (?1, ?2, ?3)",
+            params![revision as i64, key, raw_event],
+        )
+        .map_err(to_internal_error)?;
+        Self::write_meta(tx, Self::META_CHANGE_LOG_HEAD, revision)?;
+
+        let first = Self::read_meta(tx, Self::META_CHANGE_LOG_FIRST)?.unwrap_or(1);
+        let new_first = if revision + 1 - first > self.change_log_capacity {
+            revision + 1 - self.change_log_capacity
+        } else {
+            first
+        };
+        if new_first != first {
+            tx.execute(
+                "DELETE FROM change_log WHERE revision < ?1",
+                params![new_first as i64],
+            )
+            .map_err(to_internal_error)?;
+        }
+        Self::write_meta(tx, Self::META_CHANGE_LOG_FIRST, new_first)?;
+
+        tx.execute(
+            "INSERT OR REPLACE INTO key_history (key, key_revision, event) VALUES (?1, ?2, ?3)",
+            params![key, key_revision as i64, raw_event],
+        )
+        .map_err(to_internal_error)?;
+        if key_revision > self.key_history_capacity {
+            tx.execute(
+                "DELETE FROM key_history WHERE key = ?1 AND key_revision <= ?2",
+                params![key, (key_revision - self.key_history_capacity) as i64],
+            )
+            .map_err(to_internal_error)?;
+        }
+        Ok(())
+    }
+
+    // run f in one sqlite transaction, commit only if it succeeds
+    fn with_tx<T>(&self, f: impl FnOnce(&Transaction) -> Result<T>) -> Result<T> {
+        let mut conn = self.lock_conn()?;
+        let tx = conn.transaction().map_err(to_internal_error)?;
+        let result = f(&tx)?;
+        tx.commit().map_err(to_internal_error)?;
+        Ok(result)
+    }
+
+    fn list_keys_with_prefix(conn: &Connection, key_prefix: &str) -> Result<Vec<String>> {
+        let mut stmt = conn
+            .prepare("SELECT key FROM kv WHERE substr(key, 1, length(?1)) = ?1 ORDER BY key")
+            .map_err(to_internal_error)?;
+        let rows = stmt
+            .query_map(params![key_prefix], |row| row.get::<_, String>(0))
+            .map_err(to_internal_error)?;
+        let mut result = Vec::new();
+        for row in rows {
+            result.push(row.map_err(to_internal_error)?);
+        }
+        Ok(result)
+    }
+
+    fn parse_event(raw_event: &str) -> Result<KVChangeEvent> {
+        serde_json::from_str(raw_event).map_err(to_internal_error)
+    }
+}
+
+#[async_trait]
+impl KVStoreProvider for SqliteStore {
+    async fn get(&self, key: String) -> Result<Option<String>> {
+        let conn = self.lock_conn()?;
+        Self::read_value(&conn, &key)
+    }
+
+    async fn get_with_revision(&self, key: String) -> Result<Option<(String, u64)>> {
+        let conn = self.lock_conn()?;
+        let Some(value) = Self::read_value(&conn, &key)? else {
+            return Ok(None);
+        };
+        let revision = Self::read_revision(&conn, &key)?;
+        debug!(
+            "Sqlite Get key:[{}] value length:[{}] revision:[{}]",
+            key,
+            value.len(),
+            revision
+        );
+        Ok(Some((value, revision)))
+    }
+
+    async fn set(&self, key: String, value: String) -> Result<()> {
+        self.with_tx(|tx| {
+            let action = if Self::read_value(tx, &key)?.is_some() {
+                KVChangeAction::Set
+            } else {
+                KVChangeAction::Create
+            };
+            let next_revision = Self::next_revision(tx, &key)?;
+            Self::write_value(tx, &key, &value)?;
+            Self::write_revision(tx, &key, next_revision)?;
+            self.append_change(tx, &key, action, Some(&value), next_revision)
+        })?;
+        debug!("Sqlite Set key:[{}] to value:[{}]", key, value);
+        Ok(())
+    }
+
+    async fn set_by_path(&self, key: String, json_path: String, value: &Value) -> Result<()> {
+        self.with_tx(|tx| {
+            let current_value = Self::read_value(tx, &key)?
+                .ok_or_else(|| KVStoreErrors::KeyNotFound(key.clone()))?;
+            let mut current_value: Value =
+                serde_json::from_str(&current_value).map_err(to_internal_error)?;
+            set_json_by_path(&mut current_value, &json_path, Some(value));
+            let updated_value = serde_json::to_string(&current_value).map_err(to_internal_error)?;
+
+            let next_revision = Self::next_revision(tx, &key)?;
+            Self::write_value(tx, &key, &updated_value)?;
+            Self::write_revision(tx, &key, next_revision)?;
+            self.append_change(
+                tx,
+                &key,
+                KVChangeAction::Set,
+                Some(&updated_value),
+                next_revision,
+            )
+        })
+    }
+
+    async fn exec_tx(
+        &self,
+        tx_actions: HashMap<String, KVAction>,
+        main_key: Option<(String, u64)>,
+    ) -> Result<()> {
+        self.with_tx(|tx| {
+            let mut revision_updates = HashMap::new();
+            let mut changes: Vec<(String, KVChangeAction, Option<String>)> = Vec::new();
+
+            if let Some((key, expected_revision)) = main_key.as_ref() {
+                let actual_revision = Self::read_revision(tx, key)?;
+                if actual_revision != *expected_revision {
+                    return Err(KVStoreErrors::RevisionMismatch {
+                        key: key.clone(),
+                        expected: *expected_revision,
+                        actual: actual_revision,
+                    });
+                }
+                revision_updates.insert(key.clone(), Self::next_revision(tx, key)?);
+            }
+
+            for (key, action) in tx_actions.iter() {
+                let existing_value = Self::read_value(tx, key)?;
+                match action {
+                    KVAction::Create(value) => {
+                        if existing_value.is_some() {
+                            return Err(KVStoreErrors::KeyExist(key.to_string()));
+                        }
+                        Self::write_value(tx, key, value)?;
+                        changes.push((key.clone(), KVChangeAction::Create, Some(value.clone())));
+                    }
+                    KVAction::Update(value) => {
+                        let action = if existing_value.is_some() {
+                            KVChangeAction::Set
+                        } else {
+                            KVChangeAction::Create
+                        };
+                        Self::write_value(tx, key, value)?;
+                        changes.push((key.clone(), action, Some(value.clone())));
+                    }
+                    KVAction::Append(value) => {
+                        let existing_value = existing_value
+                            .ok_or_else(|| KVStoreErrors::KeyNotFound(key.to_string()))?;
+                        let updated_value = format!("{}{}", existing_value, value);
+                        Self::write_value(tx, key, &updated_value)?;
+                        changes.push((key.clone(), KVChangeAction::Set, Some(updated_value)));
+                    }
+                    KVAction::SetByJsonPath(value) => {
+                        let existing_value = existing_value
+                            .ok_or_else(|| KVStoreErrors::KeyNotFound(key.to_string()))?;
+                        let mut existing_value: Value =
+                            serde_json::from_str(&existing_value).map_err(to_internal_error)?;
+                        for (path, sub_value) in value.iter() {
+                            set_json_by_path(&mut existing_value, path, sub_value.as_ref());
+                        }
+                        let updated_value =
+                            serde_json::to_string(&existing_value).map_err(to_internal_error)?;
+                        Self::write_value(tx, key, &updated_value)?;
+                        changes.push((key.clone(), KVChangeAction::Set, Some(updated_value)));
+                    }
+                    KVAction::Remove => {
+                        if existing_value.is_some() {
+                            changes.push((key.clone(), KVChangeAction::Delete, None));
+                        }
+                        Self::remove_value(tx, key)?;
+                    }
+                }
+
+                if !revision_updates.contains_key(key) {
+                    revision_updates.insert(key.clone(), Self::next_revision(tx, key)?);
+                }
+            }
+
+            for (key, revision) in revision_updates.iter() {
+                Self::write_revision(tx, key, *revision)?;
+            }
+
+            changes.sort_by(|left, right| left.0.cmp(&right.0));
+            for (key, action, value) in changes.iter() {
+                let key_revision = revision_updates.get(key).copied().unwrap_or(0);
+                self.append_change(tx, key, *action, value.as_deref(), key_revision)?;
+            }
+            Ok(())
+        })
+    }
+
+    async fn create(&self, key: &str, value: &str) -> Result<()> {
+        let result = self.with_tx(|tx| {
+            if Self::read_value(tx, key)?.is_some() {
+                return Err(KVStoreErrors::KeyExist(key.to_string()));
+            }
+            let next_revision = Self::next_revision(tx, key)?;
+            Self::write_value(tx, key, value)?;
+            Self::write_revision(tx, key, next_revision)?;
+            self.append_change(tx, key, KVChangeAction::Create, Some(value), next_revision)
+        });
+        match result {
+            Ok(_) => {
+                debug!("Sqlite Create key:[{}] to value:[{}]", key, value);
+                Ok(())
+            }
+            Err(KVStoreErrors::KeyExist(_)) => {
+                warn!(
+                    "Sqlite Create key:[{}] to value:[{}] failed, key already exist",
+                    key, value
+                );
+                Err(KVStoreErrors::KeyExist(key.to_string()))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.with_tx(|tx| {
+            if Self::read_value(tx, key)?.is_none() {
+                return Err(KVStoreErrors::KeyNotFound(key.to_string()));
+            }
+            let next_revision = Self::next_revision(tx, key)?;
+            Self::remove_value(tx, key)?;
+            Self::write_revision(tx, key, next_revision)?;
+            self.append_change(tx, key, KVChangeAction::Delete, None, next_revision)
+        })?;
+        debug!("Sqlite Delete key:[{}]", key);
+        Ok(())
+    }
+
+    async fn list_data(&self, key_perfix: &str) -> Result<HashMap<String, String>> {
+        let conn = self.lock_conn()?;
+        let mut stmt = conn
+            .prepare("SELECT key, value FROM kv WHERE substr(key, 1, length(?1)) = ?1")
+            .map_err(to_internal_error)?;
+        let rows = stmt
+            .query_map(params![key_perfix], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .map_err(to_internal_error)?;
+        let mut result = HashMap::new();
+        for row in rows {
+            let (key, value) = row.map_err(to_internal_error)?;
+            result.insert(key, value);
+        }
+        Ok(result)
+    }
+
+    async fn list_keys(&self, key_perfix: &str) -> Result<Vec<String>> {
+        let conn = self.lock_conn()?;
+        Self::list_keys_with_prefix(&conn, key_perfix)
+    }
+
+    async fn list_direct_children(&self, prefix: String) -> Result<Vec<String>> {
+        let prefix = if prefix.is_empty() || prefix.ends_with('/') {
+            prefix
+        } else {
+            format!("{}/", prefix)
+        };
+        let conn = self.lock_conn()?;
+        let keys = Self::list_keys_with_prefix(&conn, &prefix)?;
+        let mut result: Vec<String> = Vec::new();
+        for key in keys.iter() {
+            let suffix = key.trim_start_matches(prefix.as_str());
+            let splite_result: Vec<_> = if suffix.ends_with('/') {
+                suffix[1..].split('/').collect()
+            } else {
+                suffix.split('/').collect()
+            };
+            let child = splite_result[0];
+            if !result.contains(&child.to_string()) {
+                result.push(child.to_string());
+            }
+        }
+        Ok(result)
+    }
+
+    async fn get_store_revision(&self) -> Result<u64> {
+        let conn = self.lock_conn()?;
+        Ok(Self::read_meta(&conn, Self::META_CHANGE_LOG_HEAD)?.unwrap_or(0))
+    }
+
+    async fn list_changes(
+        &self,
+        key_prefix: &str,
+        from_revision: u64,
+        limit: usize,
+    ) -> Result<(Vec<KVChangeEvent>, u64)> {
+        let conn = self.lock_conn()?;
+        let head = Self::read_meta(&conn, Self::META_CHANGE_LOG_HEAD)?.unwrap_or(0);
+        let first = Self::read_meta(&conn, Self::META_CHANGE_LOG_FIRST)?.unwrap_or(1);
+        let from_revision = from_revision.max(1);
+        if from_revision < first {
+            return Err(KVStoreErrors::Compacted {
+                requested: from_revision,
+                oldest: first,
+            });
+        }
+        if from_revision > head {
+            return Ok((Vec::new(), from_revision));
+        }
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT revision, event FROM change_log
+                WHERE revision >= ?1 AND revision <= ?2 AND substr(key, 1, length(?3)) = ?3
+                ORDER BY revision LIMIT ?4",
+            )
+            .map_err(to_internal_error)?;
+        let rows = stmt
+            .query_map(
+                params![
+                    from_revision as i64,
+                    head as i64,
+                    key_prefix,
+                    limit.max(1) as i64
+                ],
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)),
+            )
+            .map_err(to_internal_error)?;
+        let mut events = Vec::new();
+        for row in rows {
+            let (_revision, raw_event) = row.map_err(to_internal_error)?;
+            events.push(Self::parse_event(&raw_event)?);
+        }
+
+        let next_revision = if events.len() >= limit.max(1) {
+            events
+                .last()
+                .map(|event| event.revision + 1)
+                .unwrap_or(head + 1)
+        } else {
+            head + 1
+        };
+        Ok((events, next_revision))
+    }
+
+    async fn get_history(&self, key: &str, limit: usize) -> Result<Vec<KVChangeEvent>> {
+        let conn = self.lock_conn()?;
+        let mut stmt = conn
+            .prepare(
+                "SELECT event FROM key_history WHERE key = ?1
+                ORDER BY key_revision DESC LIMIT ?2",
+            )
+            .map_err(to_internal_error)?;
+        let rows = stmt
+            .query_map(params![key, limit as i64], |row| row.get::<_, String>(0))
+            .map_err(to_internal_error)?;
+        let mut result = Vec::new();
+        for row in rows {
+            result.push(Self::parse_event(&row.map_err(to_internal_error)?)?);
+        }
+        Ok(result)
+    }
+
+    async fn get_at_revision(&self, key: &str, key_revision: u64) -> Result<Option<KVChangeEvent>> {
+        let conn = self.lock_conn()?;
+        let raw_event = conn
+            .query_row(
+                "SELECT event FROM key_history WHERE key = ?1 AND key_revision = ?2",
+                params![key, key_revision as i64],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()
+            .map_err(to_internal_error)?;
+        raw_event.map(|raw| Self::parse_event(&raw)).transpose()
+    }
+}
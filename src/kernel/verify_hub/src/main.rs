@@ -68,6 +68,7 @@ MC4CAQAwBQYDK2VwBCIEIMDp9endjUnT2o4ImedpgvhVFyZEunZqG+ca0mka8oRp
     static ref VERIFY_SERVICE_CONFIG: Arc<Mutex<Option<VerifyServiceConfig>>> =
         Arc::new(Mutex::new(None));
     static ref MY_RPC_TOKEN: Arc<Mutex<Option<RPCSessionToken>>> =  Arc::new(Mutex::new(None)) ;
+    static ref SERVICE_INSTANCE_LEASE: Arc<Mutex<Option<SystemConfigLease>>> = Arc::new(Mutex::new(None));
 }
 
 /// Generate a session token with specified parameters
@@ -298,17 +299,22 @@ async fn report_service_instance_info() -> Result<()> {
         last_update_time: buckyos_get_unix_timestamp(),
         start_time: service_config.start_time,
         pid: std::process::id(),
+        lease_expire_time: None,
     };
 
     let system_config_client = get_system_config_client().await?;
     let control_panel_client = ControlPanelClient::new(system_config_client);
-    control_panel_client
-        .update_service_instance_info(
+    let mut service_instance_lease = SERVICE_INSTANCE_LEASE.lock().await;
+    let lease = control_panel_client
+        .update_service_instance_info_with_lease(
             VERIFY_HUB_UNIQUE_ID,
             service_config.device_id.as_str(),
             &instance_info,
+            service_instance_lease.as_ref(),
         )
         .await?;
+    *service_instance_lease = Some(lease);
+    drop(service_instance_lease);
     info!(
         "verify_hub reported service instance info,node_id:{}",
         service_config.device_id
@@ -4,6 +4,7 @@ mod loader;
 mod ndn;
 #[allow(unused_mut, dead_code, unused_variables)]
 mod package_cmd;
+mod scheduler;
 mod sys_config;
 
 use buckyos_api::test_config;
@@ -189,7 +190,13 @@ async fn main() -> Result<(), String> {
                         .value_names(&["key", "value"])  // Define two placeholder names
                         .num_args(2)
                         .help("set system config,
-    buckycli sys_config --set $key $value")
+    buckycli sys_config --set $key $value [--validate]")
+                )
+                .arg(
+                    Arg::new("validate")
+                        .long("validate")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("with --set/--set_file, only check permission and schema, do not write")
                 )
                 .arg(
                     Arg::new("list")
@@ -203,7 +210,7 @@ async fn main() -> Result<(), String> {
                         .value_names(&["key", "$filename"])  // Define two placeholder names
                         .num_args(2)
                         .help("set system config with file content. filename = file path.
-    buckycli sys_config --set_file $key $filename")
+    buckycli sys_config --set_file $key $filename [--validate]")
                 )
                 .arg(
                     Arg::new("append")
@@ -213,6 +220,78 @@ async fn main() -> Result<(), String> {
                         .help("append system config,
     buckycli sys_config --append $key $value")
                 )
+                .arg(
+                    Arg::new("export")
+                        .long("export")
+                        .value_name("filename")
+                        .help("export config tree to an owner-signed archive file,
+    buckycli sys_config --export $filename [--prefix $key]")
+                )
+                .arg(
+                    Arg::new("import")
+                        .long("import")
+                        .value_name("filename")
+                        .help("import an owner-signed archive file in one transaction,
+    buckycli sys_config --import $filename [--replace]")
+                )
+                .arg(
+                    Arg::new("prefix")
+                        .long("prefix")
+                        .value_name("key")
+                        .help("only export keys under this prefix (default: all keys)")
+                )
+                .arg(
+                    Arg::new("replace")
+                        .long("replace")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("on import, also remove keys under the archive prefix that are not in the archive")
+                )
+        )
+        .subcommand(
+            Command::new("scheduler")
+                .about("Scheduler tools")
+                .arg(
+                    Arg::new("dry_run")
+                        .long("dry_run")
+                        .action(clap::ArgAction::SetTrue)
+                        .help("preview the actions the scheduler would take after the given mutations, nothing is applied,
+    buckycli scheduler --dry_run [--mutations $filename] [--remove_node $node_id] [--instance_count $spec_id $count]")
+                )
+                .arg(
+                    Arg::new("mutations")
+                        .long("mutations")
+                        .value_name("filename")
+                        .help("json file with a list of mutations, e.g.
+    [{\"type\":\"add_node\",\"node_id\":\"ood2\",\"state\":\"Ready\",\"cpu_mhz\":4000,\"memory\":8589934592}]")
+                )
+                .arg(
+                    Arg::new("remove_node")
+                        .long("remove_node")
+                        .value_name("node_id")
+                        .action(clap::ArgAction::Append)
+                        .help("mark a node as Removing")
+                )
+                .arg(
+                    Arg::new("instance_count")
+                        .long("instance_count")
+                        .value_names(&["spec_id", "count"])
+                        .num_args(2)
+                        .help("change best_instance_count of a spec")
+                )
+                .arg(
+                    Arg::new("resources")
+                        .long("resources")
+                        .value_names(&["spec_id", "cpu_mhz", "memory"])
+                        .num_args(3)
+                        .help("change required cpu (MHz) and memory (bytes) of a spec")
+                )
+                .arg(
+                    Arg::new("affinity")
+                        .long("affinity")
+                        .value_names(&["spec_id", "node_label"])
+                        .num_args(2)
+                        .help("require a node label for a spec, use --mutations for selector expressions")
+                )
         )
         .subcommand(
             Command::new("did")
@@ -696,6 +775,10 @@ oods look like this 'ood1,ood2'.")
                     .collect();
                 let key = config_values[0];
                 let value = config_values[1];
+                if matches.get_flag("validate") {
+                    sys_config::validate_config(key, value).await;
+                    return Ok(());
+                }
                 println!("Set system config, key[{}]: {}", key, value);
                 sys_config::set_config(key, value).await;
                 return Ok(());
@@ -725,9 +808,33 @@ oods look like this 'ood1,ood2'.")
                 let filepath = config_values[1];
                 let content = std::fs::read_to_string(filepath)
                     .unwrap_or_else(|_| panic!("Failed to read file: {}", filepath));
+                if matches.get_flag("validate") {
+                    sys_config::validate_config(key, &content).await;
+                    return Ok(());
+                }
                 sys_config::set_config(key, &content).await;
                 return Ok(());
             }
+            if let Some(filepath) = matches.get_one::<String>("export") {
+                let prefix = matches
+                    .get_one::<String>("prefix")
+                    .map(|prefix| prefix.as_str())
+                    .unwrap_or("");
+                sys_config::export_config(prefix, filepath).await;
+                return Ok(());
+            }
+            if let Some(filepath) = matches.get_one::<String>("import") {
+                let replace = matches.get_flag("replace");
+                sys_config::import_config(filepath, replace).await;
+                return Ok(());
+            }
+        }
+        Some(("scheduler", matches)) => {
+            if matches.get_flag("dry_run") {
+                scheduler::dry_run(matches).await;
+                return Ok(());
+            }
+            println!("Usage: buckycli scheduler --dry_run [mutations...]");
         }
         Some(("connect", _matches)) => {
             sys_config::connect_into().await;
@@ -0,0 +1,100 @@
+use buckyos_api::*;
+use clap::ArgMatches;
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .trim()
+        .parse::<T>()
+        .map_err(|_| format!("invalid {}: {}", name, value))
+}
+
+fn build_dry_run_mutations(matches: &ArgMatches) -> Result<Vec<SchedulerDryRunMutation>, String> {
+    let mut mutations = Vec::new();
+    if let Some(filepath) = matches.get_one::<String>("mutations") {
+        let content = std::fs::read_to_string(filepath)
+            .map_err(|e| format!("read {} failed: {}", filepath, e))?;
+        let file_mutations: Vec<SchedulerDryRunMutation> = serde_json::from_str(&content)
+            .map_err(|e| format!("parse {} failed: {}", filepath, e))?;
+        mutations.extend(file_mutations);
+    }
+
+    if let Some(node_ids) = matches.get_many::<String>("remove_node") {
+        for node_id in node_ids {
+            mutations.push(SchedulerDryRunMutation::RemoveNode {
+                node_id: node_id.clone(),
+            });
+        }
+    }
+
+    if let Some(values) = matches.get_many::<String>("instance_count") {
+        let values: Vec<&String> = values.collect();
+        mutations.push(SchedulerDryRunMutation::SetBestInstanceCount {
+            spec_id: values[0].clone(),
+            count: parse_number("count", values[1])?,
+        });
+    }
+
+    if let Some(values) = matches.get_many::<String>("resources") {
+        let values: Vec<&String> = values.collect();
+        mutations.push(SchedulerDryRunMutation::SetSpecResources {
+            spec_id: values[0].clone(),
+            required_cpu_mhz: Some(parse_number("cpu_mhz", values[1])?),
+            required_memory: Some(parse_number("memory", values[2])?),
+            required_gpu_tflops: None,
+            required_gpu_mem: None,
+        });
+    }
+
+    if let Some(values) = matches.get_many::<String>("affinity") {
+        let values: Vec<&String> = values.collect();
+        mutations.push(SchedulerDryRunMutation::SetSpecAffinity {
+            spec_id: values[0].clone(),
+            node_affinity: Some(NodeAffinity::required_label(values[1])),
+            network_affinity: None,
+            replica_spread: None,
+        });
+    }
+
+    Ok(mutations)
+}
+
+pub async fn dry_run(matches: &ArgMatches) {
+    let mutations = match build_dry_run_mutations(matches) {
+        Ok(mutations) => mutations,
+        Err(err) => {
+            println!("scheduler dry run error: {}", err);
+            return;
+        }
+    };
+
+    let api_runtime = get_buckyos_api_runtime().unwrap();
+    let scheduler_client = match api_runtime.get_scheduler_client().await {
+        Ok(client) => client,
+        Err(err) => {
+            println!("scheduler dry run error: {}", err);
+            return;
+        }
+    };
+    let result = match scheduler_client.dry_run(mutations).await {
+        Ok(result) => result,
+        Err(err) => {
+            println!("scheduler dry run error: {}", err);
+            return;
+        }
+    };
+
+    println!("mutations:");
+    if result.notes.is_empty() {
+        println!("  (none)");
+    }
+    for note in result.notes.iter() {
+        println!("  {}", note);
+    }
+    println!("planned actions:");
+    if result.actions.is_empty() {
+        println!("  (none)");
+    }
+    for action in result.actions.iter() {
+        println!("  [{}] {}: {}", action.action, action.target, action.reason);
+    }
+}
@@ -27,6 +27,17 @@ pub async fn set_config(key: &str, value: &str) {
     }
 }
 
+pub async fn validate_config(key: &str, value: &str) {
+    let api_runtime = get_buckyos_api_runtime().unwrap();
+    let syc_cfg_client = api_runtime.get_system_config_client().await.unwrap();
+    let result = syc_cfg_client.validate(key, value).await;
+    match result {
+        Ok(Some(schema)) => println!("{} is valid, schema: {}", key, schema),
+        Ok(None) => println!("{} is valid, no schema registered for this key", key),
+        Err(err) => println!("config validate error: {}", err),
+    }
+}
+
 pub async fn append_config(key: &str, value: &str) {
     let api_runtime = get_buckyos_api_runtime().unwrap();
     let syc_cfg_client = api_runtime.get_system_config_client().await.unwrap();
@@ -49,6 +60,63 @@ pub async fn list_config(key: &str) {
     }
 }
 
+pub async fn export_config(prefix: &str, filepath: &str) {
+    let api_runtime = get_buckyos_api_runtime().unwrap();
+    let Some(private_key) = api_runtime.user_private_key.as_ref() else {
+        println!("config export error: owner private key (user_private_key.pem) not found, can not sign archive");
+        return;
+    };
+    let syc_cfg_client = api_runtime.get_system_config_client().await.unwrap();
+    let archive = match syc_cfg_client.export_archive(prefix).await {
+        Ok(archive) => archive,
+        Err(err) => {
+            println!("config export error: {}", err);
+            return;
+        }
+    };
+
+    let signed_archive =
+        match sign_system_config_archive(&archive, api_runtime.user_id.clone(), private_key) {
+            Ok(signed_archive) => signed_archive,
+            Err(err) => {
+                println!("config export error: {}", err);
+                return;
+            }
+        };
+    if let Err(err) = std::fs::write(filepath, signed_archive) {
+        println!("config export error: write {} failed: {}", filepath, err);
+        return;
+    }
+    println!(
+        "{} keys exported to {}, store revision: {}",
+        archive.items.len(),
+        filepath,
+        archive.store_revision
+    );
+}
+
+pub async fn import_config(filepath: &str, replace: bool) {
+    let signed_archive = match std::fs::read_to_string(filepath) {
+        Ok(content) => content,
+        Err(err) => {
+            println!("config import error: read {} failed: {}", filepath, err);
+            return;
+        }
+    };
+    let api_runtime = get_buckyos_api_runtime().unwrap();
+    let syc_cfg_client = api_runtime.get_system_config_client().await.unwrap();
+    let result = syc_cfg_client
+        .import_archive(signed_archive.trim(), replace)
+        .await;
+    match result {
+        Ok(result) => println!(
+            "config imported from {}, updated: {}, removed: {}",
+            filepath, result.updated, result.removed
+        ),
+        Err(err) => println!("config import error: {}", err),
+    }
+}
+
 pub async fn connect_into() {
     let api_runtime = get_buckyos_api_runtime().unwrap();
     let syc_cfg_client = api_runtime.get_system_config_client().await.unwrap();